@@ -8,11 +8,14 @@ A high-performance, stateful, and structured conversation engine powered by Rust
 from _llm_core import (
     Chat,
     Message,
+    extract,
     run_sorter,
+    sort_records,
     SchemaItems,
     SchemaProperty,
     SimpleSchema,
     SortingInstructions,
+    CategoryDefinition,
     Tool,
     ToolDefinition,
     Ingestor,
@@ -23,11 +26,14 @@ from _llm_core import (
 __all__ = [
     "Chat",
     "Message",
+    "extract",
     "run_sorter",
+    "sort_records",
     "SchemaItems",
     "SchemaProperty",
     "SimpleSchema",
     "SortingInstructions",
+    "CategoryDefinition",
     "Tool",
     "ToolDefinition",
     "Ingestor",
@@ -8,19 +8,24 @@ use serde_json::{Value as JsonValue};
 use std::path::{Path, PathBuf};
 use tokio::runtime::Runtime;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
+use crate::client::Shutdown;
 use crate::config;
-use crate::convo::Chat;
+use crate::convo::{Attachment, Chat};
 use crate::lucky::{SchemaItems, SchemaProperty, SimpleSchema};
-use crate::orchestra::Orchestra;
-use crate::sorter::{Sorter, SortingInstructions};
-use crate::tools::{FunctionDefinition, Tool, ToolDefinition, ToolLibrary};
+use crate::orchestra::{self, ChatEvent, Orchestra};
+use crate::providers::SamplingPolicy;
+use crate::sorter::{CategoryDefinition, CostEstimate, Sorter, SortingInstructions};
+use crate::tools::{self, FunctionDefinition, Tool, ToolDefinition, ToolLibrary, ToolLibraryExt};
 use crate::usage::log_usage_turn;
 use serde_json::json;
-use crate::ingest::Ingestor;
-use crate::vector::KnowledgeBase;
+use crate::ingest::{IngestReport, Ingestor, OnConflict};
+use crate::vector::{KnowledgeBase, SearchMode};
 use crate::error::LLMCoreError;
+use crate::datam::{Message, Usage};
+use std::collections::{BTreeMap, HashMap};
 
 // --- Python Bindings for Tools ---
 
@@ -73,37 +78,103 @@ pub struct PySortingInstructions {
     item_sorting_guidelines: Vec<String>,
     #[pyo3(get, set)]
     provided_categories: Vec<String>,
+    #[pyo3(get, set)]
+    category_definitions: Vec<PyCategoryDefinition>,
+    #[pyo3(get, set)]
+    strict_categories: bool,
+    #[pyo3(get, set)]
+    capture_reasoning: bool,
 }
 
 #[pymethods]
 impl PySortingInstructions {
     #[new]
-    #[pyo3(signature = (data_item_name, data_profile_description, item_sorting_guidelines, provided_categories = Vec::new()))]
+    #[pyo3(signature = (data_item_name, data_profile_description, item_sorting_guidelines, provided_categories = Vec::new(), capture_reasoning = false, category_definitions = Vec::new(), strict_categories = false))]
     fn new(
             data_item_name: &str,
             data_profile_description: &str,
             item_sorting_guidelines: Vec<String>,
             provided_categories: Vec<String>,
+            capture_reasoning: bool,
+            category_definitions: Vec<PyCategoryDefinition>,
+            strict_categories: bool,
         ) -> Self {
         PySortingInstructions {
             data_item_name: data_item_name.to_string(),
             data_profile_description: data_profile_description.to_string(),
             item_sorting_guidelines,
             provided_categories,
+            category_definitions,
+            strict_categories,
+            capture_reasoning,
         }
     }
 }
 
+impl From<PySortingInstructions> for SortingInstructions {
+    fn from(instructions: PySortingInstructions) -> Self {
+        SortingInstructions {
+            data_item_name: instructions.data_item_name,
+            data_profile_description: instructions.data_profile_description,
+            item_sorting_guidelines: instructions.item_sorting_guidelines,
+            provided_categories: instructions.provided_categories,
+            category_definitions: instructions
+                .category_definitions
+                .into_iter()
+                .map(|d| CategoryDefinition { name: d.name, description: d.description })
+                .collect(),
+            strict_categories: instructions.strict_categories,
+            capture_reasoning: instructions.capture_reasoning,
+        }
+    }
+}
+
+/// A category with the description the sorter classifies by.
+#[pyclass(name = "CategoryDefinition")]
+#[derive(Clone)]
+pub struct PyCategoryDefinition {
+    #[pyo3(get, set)]
+    name: String,
+    #[pyo3(get, set)]
+    description: String,
+}
+
+#[pymethods]
+impl PyCategoryDefinition {
+    #[new]
+    fn new(name: String, description: String) -> Self {
+        PyCategoryDefinition { name, description }
+    }
+}
+
+enum SorterOutcome {
+    Sorted((BTreeMap<String, Vec<String>>, Vec<String>, Usage, usize)),
+    Estimate(CostEstimate, usize),
+}
+
+/// Sorts items into categories. `input_path` may be a `.json` list, a `.txt` file with
+/// one item per line, a `.csv` file (read from `csv_column`, else the first column) or
+/// a folder of them. With `estimate_only=True`, nothing is sent and a dict
+/// with the projected `request_count`, token counts and `estimated_cost` is returned.
+/// `on_result`, if given, is called with `(item, category)` as each item is sorted; an
+/// exception it raises stops the run, so no further items are sent, and is re-raised. `usage_metadata` tags the
+/// run's usage records (see `Orchestra.set_usage_metadata`).
 #[pyfunction]
-#[pyo3(signature = (model_name, instructions, *, input_path = None, items_list = None, output_path = None, swarm_size = 1, debug_out = false))]
+#[pyo3(signature = (model_name, instructions, *, input_path = None, items_list = None, output_path = None, system_prompt = None, csv_column = None, swarm_size = 1, debug_out = false, estimate_only = false, on_result = None, usage_metadata = None))]
+#[allow(clippy::too_many_arguments)]
 pub fn run_sorter(
         model_name: &str,
         instructions: PySortingInstructions,
         input_path: Option<&str>,
         items_list: Option<Vec<String>>,
         output_path: Option<String>,
+        system_prompt: Option<String>,
+        csv_column: Option<String>,
         swarm_size: usize,
         debug_out: bool,
+        estimate_only: bool,
+        on_result: Option<PyObject>,
+        usage_metadata: Option<HashMap<String, String>>,
     ) -> PyResult<PyObject> {
     if input_path.is_some() && items_list.is_some() {
         return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
@@ -118,30 +189,51 @@ pub fn run_sorter(
 
     let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
     let job_id = Uuid::new_v4();
+    let callback_error: Arc<std::sync::Mutex<Option<PyErr>>> = Arc::default();
     
+    let usage_metadata = usage_metadata.unwrap_or_default();
+
     let result = rt.block_on(async {
-        let orchestra = Orchestra::new(model_name, None, None, None, None, Some(debug_out))?;
-        let rust_instructions = SortingInstructions {
-            data_item_name: instructions.data_item_name,
-            data_profile_description: instructions.data_profile_description,
-            item_sorting_guidelines: instructions.item_sorting_guidelines,
-            provided_categories: instructions.provided_categories,
-        };
-        Sorter::run_sorting_task(
-            Arc::new(orchestra),
-            input_path.map(PathBuf::from),
-            items_list,
-            output_path.map(PathBuf::from),
-            rust_instructions,
-            swarm_size,
-            debug_out,
-        ).await
+        let mut orchestra = Orchestra::new(model_name, None, None, None, None, Some(debug_out))?;
+        orchestra.set_usage_metadata(usage_metadata.clone());
+        let rust_instructions = SortingInstructions::from(instructions);
+        let mut sorter = Sorter::new(Arc::new(orchestra), rust_instructions, output_path.map(PathBuf::from), system_prompt, debug_out)?
+            .with_csv_column(csv_column);
+        if estimate_only {
+            let items = sorter.collect_items(input_path.map(PathBuf::from), items_list).await?;
+            return Ok(SorterOutcome::Estimate(sorter.estimate(&items)?, items.len()));
+        }
+        if let Some(on_result) = on_result {
+            let callback_error = Arc::clone(&callback_error);
+            sorter = sorter.with_result_callback(Arc::new(move |item: &str, category: &str| {
+                // The Python exception itself is raised once the run has stopped.
+                Python::with_gil(|py| on_result.call1(py, (item, category))).map(|_| ()).map_err(|e| {
+                    let message = e.to_string();
+                    *callback_error.lock().unwrap() = Some(e);
+                    LLMCoreError::PythonError(message)
+                })
+            }));
+        }
+        sorter.run(input_path.map(PathBuf::from), items_list, swarm_size).await.map(SorterOutcome::Sorted)
     });
 
+    if let Some(e) = callback_error.lock().unwrap().take() {
+        return Err(e);
+    }
+
     match result {
-        Ok((sorted_data, _, usage_data, item_count)) => {
+        Ok(SorterOutcome::Estimate(estimate, item_count)) => Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("item_count", item_count)?;
+            dict.set_item("request_count", estimate.request_count)?;
+            dict.set_item("input_tokens", estimate.usage.prompt_tokens)?;
+            dict.set_item("output_tokens", estimate.usage.completion_tokens)?;
+            dict.set_item("estimated_cost", estimate.total_cost())?;
+            Ok(dict.into())
+        }),
+        Ok(SorterOutcome::Sorted((sorted_data, _, usage_data, item_count))) => {
             let label = format!("sort {} items", item_count);
-            if let Err(e) = log_usage_turn(job_id, &usage_data, &label, model_name) {
+            if let Err(e) = log_usage_turn(job_id, &usage_data, &label, model_name, &usage_metadata) {
                 eprintln!("[WARNING] Failed to log sorter usage: {}", e);
             }
             Python::with_gil(|py| {
@@ -156,6 +248,110 @@ pub fn run_sorter(
     }
 }
 
+/// Sorts dicts by the text in `text_field` and returns the full dicts grouped by
+/// category. Categories are generated first when none are provided. `usage_metadata`
+/// tags the run's usage records, as in `run_sorter`.
+#[pyfunction]
+#[pyo3(signature = (model_name, instructions, records, text_field, *, output_path = None, system_prompt = None, swarm_size = 1, debug_out = false, usage_metadata = None))]
+#[allow(clippy::too_many_arguments)]
+pub fn sort_records(
+        model_name: &str,
+        instructions: PySortingInstructions,
+        records: Vec<PyObject>,
+        text_field: &str,
+        output_path: Option<String>,
+        system_prompt: Option<String>,
+        swarm_size: usize,
+        debug_out: bool,
+        usage_metadata: Option<HashMap<String, String>>,
+    ) -> PyResult<PyObject> {
+    let records = Python::with_gil(|py| {
+        records.iter().map(|record| pyobject_to_json(py, record)).collect::<PyResult<Vec<JsonValue>>>()
+    })?;
+
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let job_id = Uuid::new_v4();
+
+    let usage_metadata = usage_metadata.unwrap_or_default();
+
+    let result = rt.block_on(async {
+        let mut orchestra = Orchestra::new(model_name, None, None, None, None, Some(debug_out))?;
+        orchestra.set_usage_metadata(usage_metadata.clone());
+        let rust_instructions = SortingInstructions::from(instructions);
+        let mut sorter = Sorter::new(Arc::new(orchestra), rust_instructions, output_path.map(PathBuf::from), system_prompt, debug_out)?;
+        sorter.run_records(&records, text_field, swarm_size).await
+    });
+
+    match result {
+        Ok((sorted_records, _, usage_data, item_count)) => {
+            let label = format!("sort {} records", item_count);
+            if let Err(e) = log_usage_turn(job_id, &usage_data, &label, model_name, &usage_metadata) {
+                eprintln!("[WARNING] Failed to log sorter usage: {}", e);
+            }
+            Python::with_gil(|py| {
+                let dict = PyDict::new(py);
+                for (key, value) in sorted_records {
+                    dict.set_item(key, json_to_pyobject(py, &JsonValue::Array(value))?)?;
+                }
+                Ok(dict.into())
+            })
+        }
+        Err(e) => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string())),
+    }
+}
+
+/// Caps the requests in flight to a provider across every chat, sorter and ingestor in
+/// the process. `None` removes the cap.
+#[pyfunction]
+#[pyo3(signature = (provider_name, max_in_flight = None))]
+pub fn set_provider_concurrency_limit(provider_name: &str, max_in_flight: Option<usize>) -> PyResult<()> {
+    Ok(Orchestra::set_provider_concurrency_limit(provider_name, max_in_flight)?)
+}
+
+/// Points `alias` (e.g. `"cheap"`) at a model name, or removes it with `None`. The
+/// alias `"default"` otherwise comes from the `LLM_CORE_DEFAULT_MODEL` env var.
+#[pyfunction]
+#[pyo3(signature = (alias, model_name = None))]
+pub fn set_model_alias(alias: &str, model_name: Option<&str>) -> PyResult<()> {
+    Ok(config::set_model_alias(alias, model_name)?)
+}
+
+/// Stops the process from starting new chat turns, swarms, sorter runs and requests.
+/// Work already in flight runs to completion; see `wait_idle`.
+#[pyfunction]
+pub fn begin_drain() {
+    Shutdown::global().begin_drain();
+}
+
+/// Blocks until no work is in flight, or until `timeout_secs` elapses. Returns `True`
+/// if the process went idle.
+#[pyfunction]
+#[pyo3(signature = (timeout_secs = None))]
+pub fn wait_idle(py: Python, timeout_secs: Option<f64>) -> PyResult<bool> {
+    py.allow_threads(|| {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(rt.block_on(async {
+            match timeout_secs {
+                Some(secs) => tokio::time::timeout(Duration::from_secs_f64(secs), Shutdown::global().wait_idle())
+                    .await
+                    .is_ok(),
+                None => {
+                    Shutdown::global().wait_idle().await;
+                    true
+                }
+            }
+        }))
+    })
+}
+
+/// Extracts structured data from `text` using `schema` and returns it as a dict.
+#[pyfunction]
+pub fn extract(model_name: &str, text: &str, schema: PySimpleSchema) -> PyResult<PyObject> {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let json_val: JsonValue = rt.block_on(orchestra::extract(model_name, text, schema.into()))?;
+    Python::with_gil(|py| json_to_pyobject(py, &json_val))
+}
+
 // --- Python Bindings for Chat and Schema ---
 
 #[pyclass(name = "SchemaItems")]
@@ -228,6 +424,29 @@ impl PySimpleSchema {
     }
 }
 
+impl From<PySimpleSchema> for SimpleSchema {
+    fn from(py_schema: PySimpleSchema) -> Self {
+        SimpleSchema {
+            name: py_schema.name,
+            description: py_schema.description,
+            properties: py_schema.properties.into_iter().map(SchemaProperty::from).collect(),
+        }
+    }
+}
+
+impl From<PySchemaProperty> for SchemaProperty {
+    fn from(py_prop: PySchemaProperty) -> Self {
+        SchemaProperty {
+            name: py_prop.name,
+            property_type: py_prop.property_type,
+            description: py_prop.description,
+            items: py_prop.items.map(|py_items| SchemaItems {
+                item_type: py_items.item_type,
+            }),
+        }
+    }
+}
+
 #[pyclass(name = "Message")]
 pub struct PyMessage {
     #[pyo3(get)]
@@ -238,6 +457,72 @@ pub struct PyMessage {
     reasoning_content: Option<String>,
 }
 
+// Combines the native Rust tools (if requested) with user-supplied Python tools.
+/// Combines the native tools with the manifest's HTTP and command tools and the Python ones. A tool
+/// named like one from an earlier source, or like another Python tool, is a `ValueError`
+/// rather than a silent override.
+fn build_tool_library(
+        native_tools: bool,
+        extra_tools: Option<Vec<PyTool>>,
+        tool_manifest: Option<&str>,
+    ) -> PyResult<Option<ToolLibrary>> {
+    let mut tool_library = ToolLibrary::new();
+    if native_tools {
+        tool_library.extend(config::get_rust_tool_library());
+    }
+    if let Some(path) = tool_manifest {
+        if let Err(collisions) = tool_library.merge(tools::load_manifest(Path::new(path))?) {
+            return Err(PyValueError::new_err(format!(
+                "Manifest tools {:?} have the same names as native tools; rename them.",
+                collisions
+            )));
+        }
+    }
+    if let Some(py_tools) = extra_tools {
+        let mut python_library = ToolLibrary::new();
+        let mut duplicates = Vec::new();
+        for py_tool in py_tools {
+            let params_schema = &py_tool.definition.parameters;
+            let parameters_json = json!({
+                "type": "object",
+                "properties": params_schema.properties.iter().map(|p| {
+                    (p.name.clone(), json!({
+                        "type": p.property_type,
+                        "description": p.description,
+                    }))
+                }).collect::<serde_json::Map<String, serde_json::Value>>(),
+                "required": params_schema.properties.iter().map(|p| p.name.clone()).collect::<Vec<String>>()
+            });
+
+            let definition = ToolDefinition {
+                tool_type: "function".to_string(),
+                function: FunctionDefinition {
+                    name: py_tool.definition.name.clone(),
+                    description: py_tool.definition.description.clone(),
+                    parameters: parameters_json,
+                },
+            };
+            let name = py_tool.definition.name.clone();
+            if python_library.insert(name.clone(), Tool::Python { definition, function: py_tool.function }).is_some() {
+                duplicates.push(name);
+            }
+        }
+        if !duplicates.is_empty() {
+            return Err(PyValueError::new_err(format!(
+                "Several extra tools are named {:?}; tool names must be unique.",
+                duplicates
+            )));
+        }
+        if let Err(collisions) = tool_library.merge(python_library) {
+            return Err(PyValueError::new_err(format!(
+                "Extra tools {:?} have the same names as native or manifest tools; rename them.",
+                collisions
+            )));
+        }
+    }
+    Ok(if tool_library.is_empty() { None } else { Some(tool_library) })
+}
+
 #[pyclass(name = "Chat", unsendable)]
 pub struct PyChat {
     chat: Chat,
@@ -246,8 +531,10 @@ pub struct PyChat {
 
 #[pymethods]
 impl PyChat {
+    /// `tool_manifest` is the path of a JSON or YAML manifest of HTTP and command tools to add.
     #[new]
-    #[pyo3(signature = (model_name, system_prompt = None, schema = None, native_tools = false, extra_tools = None, thinking_mode = None, debug_out = false))]
+    #[pyo3(signature = (model_name, system_prompt = None, schema = None, native_tools = false, extra_tools = None, thinking_mode = None, debug_out = false, tool_manifest = None))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
             model_name: &str,
             system_prompt: Option<String>,
@@ -256,72 +543,243 @@ impl PyChat {
             extra_tools: Option<Vec<PyTool>>,
             thinking_mode: Option<bool>,
             debug_out: bool,
+            tool_manifest: Option<String>,
         ) -> PyResult<Self> {
-        if schema.is_some() && (native_tools || extra_tools.is_some()) {
+        if schema.is_some() && (native_tools || extra_tools.is_some() || tool_manifest.is_some()) {
             return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
                 "Cannot use a schema and tools (native or extra) at the same time.",
             ));
         }
 
-        let rust_schema = schema.map(|py_schema| SimpleSchema {
-            name: py_schema.name,
-            description: py_schema.description,
-            properties: py_schema.properties.into_iter().map(|py_prop| SchemaProperty {
-                    name: py_prop.name,
-                    property_type: py_prop.property_type,
-                    description: py_prop.description,
-                    items: py_prop.items.map(|py_items| SchemaItems {
-                        item_type: py_items.item_type,
-                    }),
-            }).collect(),
-        });
+        let rust_schema = schema.map(SimpleSchema::from);
+        let final_tools = build_tool_library(native_tools, extra_tools, tool_manifest.as_deref())?;
+        let chat = Chat::new(model_name, system_prompt, final_tools, rust_schema, thinking_mode, Some(debug_out))?;
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(PyChat { chat, rt })
+    }
 
-        let mut tool_library = ToolLibrary::new();
-        if native_tools {
-            tool_library.extend(config::get_rust_tool_library());
-        }
-        if let Some(py_tools) = extra_tools {
-            for py_tool in py_tools {
-                let params_schema = &py_tool.definition.parameters;
-                let parameters_json = json!({
-                    "type": "object",
-                    "properties": params_schema.properties.iter().map(|p| {
-                        (p.name.clone(), json!({
-                            "type": p.property_type,
-                            "description": p.description,
-                        }))
-                    }).collect::<serde_json::Map<String, serde_json::Value>>(),
-                    "required": params_schema.properties.iter().map(|p| p.name.clone()).collect::<Vec<String>>()
-                });
-
-                let definition = ToolDefinition {
-                    tool_type: "function".to_string(),
-                    function: FunctionDefinition {
-                        name: py_tool.definition.name.clone(),
-                        description: py_tool.definition.description.clone(),
-                        parameters: parameters_json,
-                    },
-                };
-                tool_library.insert(
-                    py_tool.definition.name.clone(),
-                    Tool::Python { definition, function: py_tool.function },
-                );
-            }
+    /// Creates a chat pre-seeded with `messages`, a list of dicts in the OpenAI message
+    /// format (`{"role": ..., "content": ...}`), e.g. few-shot examples or a history
+    /// restored from your own storage.
+    #[staticmethod]
+    #[pyo3(signature = (model_name, messages, schema = None, native_tools = false, extra_tools = None, thinking_mode = None, debug_out = false, tool_manifest = None))]
+    #[allow(clippy::too_many_arguments)]
+    fn from_messages(
+            py: Python,
+            model_name: &str,
+            messages: Vec<PyObject>,
+            schema: Option<PySimpleSchema>,
+            native_tools: bool,
+            extra_tools: Option<Vec<PyTool>>,
+            thinking_mode: Option<bool>,
+            debug_out: bool,
+            tool_manifest: Option<String>,
+        ) -> PyResult<Self> {
+        if schema.is_some() && (native_tools || extra_tools.is_some() || tool_manifest.is_some()) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Cannot use a schema and tools (native or extra) at the same time.",
+            ));
         }
-        let final_tools = if tool_library.is_empty() { None } else { Some(tool_library) };
-        let chat = Chat::new(model_name, system_prompt, final_tools, rust_schema, thinking_mode, Some(debug_out))?;
+        let rust_messages = messages
+            .iter()
+            .map(|obj| {
+                let value = pyobject_to_json(py, obj)?;
+                serde_json::from_value::<Message>(value)
+                    .map_err(|e| PyValueError::new_err(format!("Invalid message: {}", e)))
+            })
+            .collect::<PyResult<Vec<Message>>>()?;
+
+        let rust_schema = schema.map(SimpleSchema::from);
+        let final_tools = build_tool_library(native_tools, extra_tools, tool_manifest.as_deref())?;
+        let chat = Chat::from_messages(model_name, rust_messages, final_tools, rust_schema, thinking_mode, Some(debug_out))?;
         let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
         Ok(PyChat { chat, rt })
     }
 
-    fn send(&mut self, user_prompt: &str) -> PyResult<PyMessage> {
-        let assistant_message = self.rt.block_on(self.chat.send(user_prompt))?;
+    /// Sends a prompt and returns the reply. `temperature` overrides the chat's own for
+    /// this turn only.
+    #[pyo3(signature = (user_prompt, temperature = None))]
+    fn send(&mut self, user_prompt: &str, temperature: Option<f32>) -> PyResult<PyMessage> {
+        let assistant_message = match temperature {
+            Some(temperature) => self.rt.block_on(self.chat.send_with_temperature(user_prompt, temperature))?,
+            None => self.rt.block_on(self.chat.send(user_prompt))?,
+        };
+        Ok(PyMessage {
+            role: assistant_message.role.clone(),
+            content: assistant_message.content.clone(),
+            reasoning_content: assistant_message.reasoning_content.clone(),
+        })
+    }
+
+    /// Sends a prompt and calls `on_event` with a dict for each step of the turn. Each
+    /// dict has a `type` of `tool_call_started` (`name`, `args`), `tool_result` (`name`,
+    /// `result`), `reasoning_delta` or `content_delta` (`text`), or `done` (`content`,
+    /// `reasoning_content`). An exception raised by `on_event` is re-raised after the turn.
+    fn send_events(&mut self, user_prompt: &str, on_event: PyObject) -> PyResult<PyMessage> {
+        let mut callback_error: Option<PyErr> = None;
+        let assistant_message = self.rt.block_on(self.chat.send_events(user_prompt, |event| {
+            if callback_error.is_some() {
+                return;
+            }
+            let result = Python::with_gil(|py| {
+                chat_event_to_dict(py, &event).and_then(|dict| on_event.call1(py, (dict,)))
+            });
+            if let Err(e) = result {
+                callback_error = Some(e);
+            }
+        }))?;
+        if let Some(e) = callback_error {
+            return Err(e);
+        }
         Ok(PyMessage {
             role: assistant_message.role.clone(),
             content: assistant_message.content.clone(),
             reasoning_content: assistant_message.reasoning_content.clone(),
         })
     }
+
+    /// Sends a prompt with files attached as `(filename, content)` pairs.
+    fn send_with_attachments(&mut self, user_prompt: &str, attachments: Vec<(String, String)>) -> PyResult<PyMessage> {
+        let attachments = attachments
+            .into_iter()
+            .map(|(filename, content)| Attachment::new(filename, content))
+            .collect();
+        let assistant_message = self.rt.block_on(self.chat.send_with_attachments(user_prompt, attachments))?;
+        Ok(PyMessage {
+            role: assistant_message.role.clone(),
+            content: assistant_message.content.clone(),
+            reasoning_content: assistant_message.reasoning_content.clone(),
+        })
+    }
+
+    /// Wipes the history and usage but keeps the model, tools and schema.
+    #[pyo3(signature = (keep_system_prompt = true))]
+    fn clear(&mut self, keep_system_prompt: bool) {
+        self.chat.clear(keep_system_prompt);
+    }
+
+    /// Returns `(estimated tokens used, context window or None)`.
+    fn context_usage(&self) -> (usize, Option<usize>) {
+        self.chat.context_usage()
+    }
+
+    /// Sets the tag names prompt-induced reasoning is wrapped in, e.g. `["reasoning"]`.
+    fn set_thinking_tags(&mut self, tags: Vec<String>) -> PyResult<()> {
+        Ok(self.chat.orchestra.set_thinking_tags(tags)?)
+    }
+
+    /// Receives responses as a server-sent event stream: `send_events` reports text as
+    /// it arrives, and streamed tool calls are assembled before they run. Raises
+    /// ValueError if the provider cannot stream.
+    fn set_streaming(&mut self, enabled: bool) -> PyResult<()> {
+        Ok(self.chat.orchestra.set_streaming(enabled)?)
+    }
+
+    /// Lists structural problems in the history this chat's provider would reject, such
+    /// as tool results with no matching call. Empty when the history is valid.
+    fn validate(&self) -> Vec<String> {
+        self.chat.validate().err().unwrap_or_default().iter().map(ToString::to_string).collect()
+    }
+
+    /// Drops orphaned tool results and merges consecutive same-role messages, returning
+    /// a description of each fix.
+    fn repair(&mut self) -> Vec<String> {
+        self.chat.conversation.repair().iter().map(ToString::to_string).collect()
+    }
+
+    /// Chooses what happens to a temperature outside the provider's range: `"clamp"`
+    /// (the default) clamps it with a warning, `"error"` raises ValueError instead.
+    fn set_sampling_policy(&mut self, policy: &str) -> PyResult<()> {
+        let policy = match policy {
+            "clamp" => SamplingPolicy::Clamp,
+            "error" => SamplingPolicy::Error,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown sampling policy '{}'; expected 'clamp' or 'error'.",
+                    other
+                )))
+            }
+        };
+        Ok(self.chat.orchestra.set_sampling_policy(policy)?)
+    }
+
+    /// Identifies the end user this chat serves to the provider for abuse monitoring
+    /// (`user` on OpenAI-style APIs, `metadata.user_id` on Anthropic). `None` omits it.
+    #[pyo3(signature = (user_id))]
+    fn set_end_user_id(&mut self, user_id: Option<String>) -> PyResult<()> {
+        Ok(self.chat.orchestra.set_end_user_id(user_id)?)
+    }
+
+    /// Saves failed requests as JSON, API key redacted, to `directory` (default
+    /// `~/.llm-core/failures`) while `enabled`. Re-send one with `replay_failure`.
+    #[pyo3(signature = (enabled, directory = None))]
+    fn set_failure_capture(&mut self, enabled: bool, directory: Option<PathBuf>) {
+        let dir = enabled.then(|| directory.unwrap_or_else(|| config::FAILURE_DATA_DIR.clone()));
+        self.chat.orchestra.set_failure_capture(dir);
+    }
+
+    /// Re-sends a saved failed request with this chat's API key and returns the raw body.
+    fn replay_failure(&self, path: PathBuf) -> PyResult<String> {
+        Ok(self.rt.block_on(self.chat.orchestra.replay_failure(&path))?)
+    }
+
+    /// Writes a JSON trace of each turn (requests, raw responses, tool calls, usage) to
+    /// `directory` (default `~/.llm-core/traces`), one file per job id, while `enabled`.
+    #[pyo3(signature = (enabled, directory = None))]
+    fn set_trace_dir(&mut self, enabled: bool, directory: Option<PathBuf>) {
+        let dir = enabled.then(|| directory.unwrap_or_else(|| config::TRACE_DATA_DIR.clone()));
+        self.chat.orchestra.set_trace_dir(dir);
+    }
+
+    /// Adds `prompt` (default: an instruction to answer the user from the tool results)
+    /// to the request that answers after tools run, while `enabled`. It is not kept in
+    /// the history.
+    #[pyo3(signature = (enabled, prompt = None))]
+    fn set_synthesis_prompt(&mut self, enabled: bool, prompt: Option<String>) -> PyResult<()> {
+        let prompt = enabled.then(|| prompt.unwrap_or_else(|| orchestra::SYNTHESIS_PROMPT.to_string()));
+        Ok(self.chat.orchestra.set_synthesis_prompt(prompt)?)
+    }
+
+    /// Tags the usage records of this chat's turns, e.g. `{"tenant": "acme"}`.
+    fn set_usage_metadata(&mut self, metadata: HashMap<String, String>) {
+        self.chat.orchestra.set_usage_metadata(metadata);
+    }
+
+    /// Stores `content` (and an optional system prompt) in the provider's context cache
+    /// for `ttl_secs` and makes later turns reference it instead of resending it. Returns
+    /// the cache's name. Gemini only; the content is inlined again if the cache expires.
+    #[pyo3(signature = (content, ttl_secs = 3600, system_prompt = None))]
+    fn cache_context(&mut self, content: &str, ttl_secs: u64, system_prompt: Option<&str>) -> PyResult<String> {
+        let context = self.rt.block_on(self.chat.orchestra.create_cached_context(
+            system_prompt,
+            content,
+            Duration::from_secs(ttl_secs),
+        ))?;
+        let name = context.name.clone();
+        self.chat.orchestra.set_cached_context(Some(context))?;
+        Ok(name)
+    }
+
+    /// The tools this chat offers the model, sorted by name, as dicts with `name`,
+    /// `description` and `parameters` (the JSON schema the model sees).
+    fn tools(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        self.chat
+            .orchestra
+            .tool_definitions()
+            .iter()
+            .map(|definition| json_to_pyobject(py, &serde_json::to_value(&definition.function).map_err(LLMCoreError::from)?))
+            .collect()
+    }
+
+    /// How tools are sent: `"native"`, `"lucky"` (prompted fallback) or `"none"`.
+    fn tool_mode(&self) -> &'static str {
+        self.chat.orchestra.tool_mode()
+    }
+
+    /// How the schema is enforced: `"native"`, `"lucky"` (prompted fallback) or `"none"`.
+    fn structured_mode(&self) -> &'static str {
+        self.chat.orchestra.structured_mode()
+    }
 }
 
 #[pyclass(name = "KnowledgeBase", unsendable)]
@@ -345,21 +803,70 @@ impl PyKnowledgeBase {
         })
     }
 
-    fn search(&mut self, query: &str, limit: usize) -> PyResult<Py<PyAny>> {
+    /// Returns the `limit` best-matching chunks, at most `max_per_source` of them from
+    /// any one document. With `neighbor_window > 0`, the chunks that many positions
+    /// around each hit in the same document are included too. `search_mode` is
+    /// `"content"`, `"summary"` or `"both"` (the default): which text each chunk carries.
+    #[pyo3(signature = (query, limit, neighbor_window = 0, max_per_source = None, search_mode = "both"))]
+    fn search(
+        &mut self,
+        query: &str,
+        limit: usize,
+        neighbor_window: usize,
+        max_per_source: Option<usize>,
+        search_mode: &str,
+    ) -> PyResult<Py<PyAny>> {
+        let search_mode: SearchMode = serde_json::from_value(json!(search_mode)).map_err(|_| {
+            PyValueError::new_err(format!(
+                "Invalid search_mode '{}'; expected 'content', 'summary' or 'both'.",
+                search_mode
+            ))
+        })?;
         let db_path = self.db_path.clone();
         let index_path = self.index_path.clone();
         let embedding_model = self.embedding_model.clone();
 
-        let search_results = self.runtime.block_on(async move {
+        let mut search_results = self.runtime.block_on(async move {
             let kb = KnowledgeBase::new(&db_path, &index_path, &embedding_model)?;
-            kb.search(query, limit).await
+            kb.search_with_context(query, limit, neighbor_window, max_per_source).await
         }).map_err(|e: LLMCoreError| PyValueError::new_err(e.to_string()))?;
+        search_results.iter_mut().for_each(|chunk| search_mode.apply(chunk));
         
         Python::with_gil(|py| {
             let json_val = serde_json::to_value(search_results).unwrap();
             json_to_pyobject(py, &json_val)
         })
     }
+
+    /// The dimensionality of the stored vectors, or `None` if nothing has been indexed.
+    fn embedding_dimensions(&self) -> PyResult<Option<usize>> {
+        let kb = KnowledgeBase::new(&self.db_path, &self.index_path, &self.embedding_model)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(kb.embedding_dimensions())
+    }
+
+    /// Returns a dict with `chunk_count`, `source_count`, `vector_count`,
+    /// `index_size_bytes`, `db_size_bytes` and `consistency_ok` (the index holds one
+    /// vector per stored chunk).
+    fn stats(&self, py: Python) -> PyResult<PyObject> {
+        let kb = KnowledgeBase::new(&self.db_path, &self.index_path, &self.embedding_model)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let stats = kb.stats().map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let dict = PyDict::new(py);
+        dict.set_item("chunk_count", stats.chunk_count)?;
+        dict.set_item("source_count", stats.source_count)?;
+        dict.set_item("vector_count", stats.vector_count)?;
+        dict.set_item("index_size_bytes", stats.index_size_bytes)?;
+        dict.set_item("db_size_bytes", stats.db_size_bytes)?;
+        dict.set_item("consistency_ok", stats.consistency_ok)?;
+        Ok(dict.into())
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        let kb = KnowledgeBase::new(&self.db_path, &self.index_path, &self.embedding_model)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(kb.len())
+    }
 }
 
 #[pyclass(name = "Ingestor", unsendable)]
@@ -371,21 +878,51 @@ pub struct PyIngestor {
 #[pymethods]
 impl PyIngestor {
     #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (db_path, index_path, embedding_model, enrichment_model, system_prompt = None, *, enrichment_retries = 2, concurrency = 5, extra_fields = None, url_timeout_secs = 120.0, max_content_bytes = 20 * 1024 * 1024, on_conflict = "error"))]
     fn new(
         db_path: &str,
         index_path: &str,
         embedding_model: &str,
         enrichment_model: &str,
+        system_prompt: Option<String>,
+        enrichment_retries: u32,
+        concurrency: usize,
+        extra_fields: Option<Vec<PySchemaProperty>>,
+        url_timeout_secs: f64,
+        max_content_bytes: usize,
+        on_conflict: &str,
     ) -> PyResult<Self> {
         let runtime =
             Runtime::new().map_err(|e| PyValueError::new_err(format!("Failed to create Tokio runtime: {}", e)))?;
-        let ingestor = Ingestor::new(
+        let mut ingestor = Ingestor::new(
             Path::new(db_path),
             Path::new(index_path),
             embedding_model,
             enrichment_model,
+            system_prompt,
+            extra_fields.map(|fields| fields.into_iter().map(SchemaProperty::from).collect()),
         )
         .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        ingestor.set_enrichment_retries(enrichment_retries);
+        ingestor
+            .set_concurrency(concurrency)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let url_timeout = Duration::try_from_secs_f64(url_timeout_secs)
+            .map_err(|_| PyValueError::new_err("url_timeout_secs must be a positive number."))?;
+        ingestor.set_url_timeout(url_timeout)?;
+        ingestor.set_max_content_bytes(max_content_bytes)?;
+        ingestor.set_on_conflict(match on_conflict {
+            "skip" => OnConflict::Skip,
+            "replace" => OnConflict::Replace,
+            "error" => OnConflict::Error,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown on_conflict '{}'; expected 'skip', 'replace' or 'error'.",
+                    other
+                )))
+            }
+        });
         
         Ok(Self {
             ingestor,
@@ -393,20 +930,66 @@ impl PyIngestor {
         })
     }
 
-    fn ingest_from_url(&mut self, url: &str, source_tag: &str) -> PyResult<()> {
-        self.runtime
+    /// Ingests a web page and returns the run's report as a dict.
+    fn ingest_from_url(&mut self, py: Python, url: &str, source_tag: &str) -> PyResult<PyObject> {
+        let report = self.runtime
             .block_on(self.ingestor.ingest_from_url(url, source_tag))
-            .map_err(|e| PyValueError::new_err(e.to_string()))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        ingest_report_to_dict(py, &report)
     }
 
-    fn ingest_from_file(&mut self, file_path: &str, source_tag: &str) -> PyResult<()> {
-        self.runtime
+    /// Ingests a local file and returns the run's report as a dict.
+    fn ingest_from_file(&mut self, py: Python, file_path: &str, source_tag: &str) -> PyResult<PyObject> {
+        let report = self.runtime
             .block_on(
                 self.ingestor
                     .ingest_from_file(Path::new(file_path), source_tag),
             )
-            .map_err(|e| PyValueError::new_err(e.to_string()))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        ingest_report_to_dict(py, &report)
+    }
+}
+
+fn ingest_report_to_dict(py: Python, report: &IngestReport) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("chunk_count", report.chunk_count)?;
+    dict.set_item("skipped", report.skipped)?;
+    dict.set_item("replaced_chunk_count", report.replaced_chunk_count)?;
+    dict.set_item("enrichment_retries", report.enrichment_retries)?;
+    dict.set_item("simple_prompt_count", report.simple_prompt_count)?;
+    dict.set_item("fallback_count", report.fallback_count)?;
+    Ok(dict.into())
+}
+
+fn chat_event_to_dict(py: Python, event: &ChatEvent) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    match event {
+        ChatEvent::ContentDelta(text) => {
+            dict.set_item("type", "content_delta")?;
+            dict.set_item("text", text)?;
+        }
+        ChatEvent::ReasoningDelta(text) => {
+            dict.set_item("type", "reasoning_delta")?;
+            dict.set_item("text", text)?;
+        }
+        ChatEvent::ToolCallStarted { name, args } => {
+            dict.set_item("type", "tool_call_started")?;
+            dict.set_item("name", name)?;
+            dict.set_item("args", json_to_pyobject(py, args)?)?;
+        }
+        ChatEvent::ToolResult { name, result } => {
+            dict.set_item("type", "tool_result")?;
+            dict.set_item("name", name)?;
+            dict.set_item("result", result)?;
+        }
+        ChatEvent::Done(payload) => {
+            let message = payload.choices.first().map(|c| &c.message);
+            dict.set_item("type", "done")?;
+            dict.set_item("content", message.and_then(|m| m.content.clone()))?;
+            dict.set_item("reasoning_content", message.and_then(|m| m.reasoning_content.clone()))?;
+        }
     }
+    Ok(dict.into())
 }
 
 // --- Python <-> Rust Data Conversion Helpers ---
@@ -1,11 +1,18 @@
-use reqwest::{header, Client, StatusCode};
+use reqwest::multipart::Form;
+use reqwest::{header, Client, Method, StatusCode};
 use serde_json::Value as JsonValue;
+use crate::config;
+use crate::datam::{truncate_chars, RateLimitInfo, ResponsePayload};
 use crate::error::LLMCoreError;
-use tokio::task::JoinHandle;
-use tokio::sync::Semaphore;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinError, JoinSet};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::time::sleep;
-use std::sync::Arc;
 use rand::Rng;
 
 /// Defines the retry strategy for API calls.
@@ -23,6 +30,299 @@ pub enum Jitter {
     None,
 }
 
+// Process-wide caps on in-flight requests, keyed by API host. Every outbound call
+// takes a slot, so independent Orchestra, Chat and Sorter instances share the cap.
+static HOST_LIMITS: Lazy<RwLock<HashMap<String, HostLimit>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+struct HostLimit {
+    semaphore: Arc<Semaphore>,
+    max_in_flight: usize,
+    // Permits still to retire after a lower cap was set while they were held.
+    excess: Arc<AtomicUsize>,
+}
+
+impl HostLimit {
+    fn new(max_in_flight: usize) -> Self {
+        HostLimit {
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            excess: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Changes the cap in place, so requests already waiting on the host keep their place.
+    fn resize(&mut self, max_in_flight: usize) {
+        if max_in_flight > self.max_in_flight {
+            let mut added = max_in_flight - self.max_in_flight;
+            let retired = take_up_to(&self.excess, added);
+            added -= retired;
+            self.semaphore.add_permits(added);
+        } else {
+            let removed = self.max_in_flight - max_in_flight;
+            let forgotten = self.semaphore.forget_permits(removed);
+            self.excess.fetch_add(removed - forgotten, Ordering::SeqCst);
+        }
+        self.max_in_flight = max_in_flight;
+    }
+}
+
+// Subtracts up to `n` from `counter` and returns how much was subtracted.
+fn take_up_to(counter: &AtomicUsize, n: usize) -> usize {
+    let previous = counter
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| Some(current - current.min(n)))
+        .unwrap_or(0);
+    previous.min(n)
+}
+
+/// A held slot under a host's concurrency cap, returned to the cap when dropped.
+pub(crate) struct HostSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    excess: Arc<AtomicUsize>,
+}
+
+impl Drop for HostSlot {
+    fn drop(&mut self) {
+        // A slot released after the cap was lowered is retired instead of reused.
+        if take_up_to(&self.excess, 1) == 1 {
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+/// Caps the number of requests in flight to `host` (e.g. `"api.openai.com"`) across the
+/// whole process, or removes the cap with `None`. Requests over the cap wait for a slot.
+/// Changing an existing cap takes effect for requests already waiting.
+pub fn set_host_concurrency_limit(host: &str, max_in_flight: Option<usize>) -> Result<(), LLMCoreError> {
+    let mut limits = HOST_LIMITS.write().unwrap_or_else(|e| e.into_inner());
+    let host = host.to_lowercase();
+    match max_in_flight {
+        Some(0) => {
+            return Err(LLMCoreError::ConfigError("The concurrency limit must be at least 1.".to_string()));
+        }
+        Some(max) => match limits.get_mut(&host) {
+            Some(limit) => limit.resize(max),
+            None => {
+                limits.insert(host, HostLimit::new(max));
+            }
+        },
+        None => {
+            // Closing the semaphore lets requests still waiting on it proceed uncapped.
+            if let Some(limit) = limits.remove(&host) {
+                limit.semaphore.close();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Waits for a slot under the host's concurrency cap. Returns `None` when the host has no cap.
+pub(crate) async fn acquire_host_slot(url: &str) -> Option<HostSlot> {
+    let host = reqwest::Url::parse(url).ok()?.host_str()?.to_lowercase();
+    let (semaphore, excess) = {
+        let limits = HOST_LIMITS.read().unwrap_or_else(|e| e.into_inner());
+        let limit = limits.get(&host)?;
+        (limit.semaphore.clone(), limit.excess.clone())
+    };
+    let permit = semaphore.acquire_owned().await.ok()?;
+    Some(HostSlot { permit: Some(permit), excess })
+}
+
+tokio::task_local! {
+    // Set while a task runs as part of admitted work, so its own requests are still let
+    // through once a drain has begun.
+    static IN_FLIGHT_SCOPE: bool;
+}
+
+static SHUTDOWN: Lazy<Shutdown> = Lazy::new(Shutdown::new);
+
+/// Tracks the work in flight to providers so a server can drain it before exiting.
+///
+/// Chat turns, swarms, sorter runs, ingests, batches, jobs and single requests each
+/// count as one unit of work. After `begin_drain`, new work is rejected with a
+/// `ConcurrencyError`, while work already admitted, including its tool calls and
+/// fanned-out requests, runs to the end.
+pub struct Shutdown {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks one unit of admitted work; the count drops when it is dropped.
+pub struct InFlightGuard<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self { draining: AtomicBool::new(false), in_flight: AtomicUsize::new(0), idle: Notify::new() }
+    }
+
+    /// The process-wide handle used by every outbound request.
+    pub fn global() -> &'static Shutdown {
+        &SHUTDOWN
+    }
+
+    /// Stops admitting new work. Work already in flight is unaffected.
+    pub fn begin_drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Admits new work again after a drain.
+    pub fn resume(&self) {
+        self.draining.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// The number of units of work currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once no work is in flight.
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.in_flight() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Admits one unit of work, unless a drain has begun and the caller is not already
+    /// part of admitted work.
+    pub fn enter(&self) -> Result<InFlightGuard<'_>, LLMCoreError> {
+        let in_scope = IN_FLIGHT_SCOPE.try_with(|scoped| *scoped).unwrap_or(false);
+        if self.is_draining() && !in_scope {
+            return Err(LLMCoreError::ConcurrencyError(
+                "Shutting down: no new requests are being accepted.".to_string(),
+            ));
+        }
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Ok(InFlightGuard { shutdown: self })
+    }
+
+    /// Runs `work` as one admitted unit, so that requests made inside it (and in tasks
+    /// spawned through `carry_in_flight`) are let through during a drain.
+    pub async fn run<F: Future>(&self, work: F) -> Result<F::Output, LLMCoreError> {
+        let _guard = self.enter()?;
+        Ok(IN_FLIGHT_SCOPE.scope(true, work).await)
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `work` as one unit of in-flight work on the global `Shutdown` handle.
+pub async fn in_flight<F: Future>(work: F) -> Result<F::Output, LLMCoreError> {
+    SHUTDOWN.run(work).await
+}
+
+/// Wraps a future about to be spawned so it stays part of the caller's admitted work.
+pub fn carry_in_flight<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    let in_scope = IN_FLIGHT_SCOPE.try_with(|scoped| *scoped).unwrap_or(false);
+    IN_FLIGHT_SCOPE.scope(in_scope, work)
+}
+
+// How much of a non-JSON body is kept in the error message.
+const NON_JSON_SNIPPET_CHARS: usize = 300;
+
+/// Returns `true` for a response rejecting the request's credentials: a 401 or 403, or
+/// Google's 400 for an invalid API key.
+fn is_auth_failure(status: StatusCode, body: &str) -> bool {
+    matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        || (status == StatusCode::BAD_REQUEST && body.contains("API_KEY_INVALID"))
+}
+
+/// Names the provider behind `url` for error messages: the `models.json` provider whose
+/// base URL has the same host, or else the host itself.
+fn provider_label(url: &str) -> String {
+    let host_of = |url: &str| reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_lowercase));
+    let Some(host) = host_of(url) else {
+        return url.to_string();
+    };
+    let mut names: Vec<&String> = config::MODEL_LIBRARY
+        .providers
+        .iter()
+        .filter(|(_, provider)| provider.resolve_base_url().ok().and_then(|base| host_of(&base)).as_deref() == Some(host.as_str()))
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    names.first().map_or(host, |name| name.to_string())
+}
+
+/// Detects HTML pages, such as the error pages served by gateways and proxies, and
+/// other non-JSON bodies, and turns them into a readable `ApiError` instead of a parse
+/// failure. The only non-JSON bodies that pass are empty successful ones (e.g. a `204`)
+/// and, when `streaming`, a successful `text/event-stream`.
+fn non_json_body_error(
+        status: StatusCode,
+        content_type: Option<&str>,
+        body: &str,
+        streaming: bool,
+    ) -> Option<LLMCoreError> {
+    let trimmed = body.trim_start();
+    let is_html = content_type.is_some_and(|ct| ct.contains("html"));
+    let is_json = trimmed.starts_with('{') || trimmed.starts_with('[');
+    let is_event_stream = streaming && content_type.is_some_and(|ct| ct.contains("text/event-stream"));
+    let is_expected = status.is_success() && (trimmed.is_empty() || is_event_stream);
+    if !is_html && (is_json || is_expected) {
+        return None;
+    }
+
+    let snippet = truncate_chars(trimmed, NON_JSON_SNIPPET_CHARS);
+    let ellipsis = if snippet.len() < trimmed.len() { "..." } else { "" };
+    Some(LLMCoreError::ApiError(format!(
+        "Provider returned a non-JSON response (status {}, content-type {}): {}{}",
+        status,
+        content_type.unwrap_or("unknown"),
+        snippet,
+        ellipsis
+    )))
+}
+
+fn header_str<'a>(headers: &'a header::HeaderMap, names: &[&str]) -> Option<&'a str> {
+    names.iter().find_map(|name| headers.get(*name)?.to_str().ok())
+}
+
+/// Reads rate-limit headers (OpenAI-style `x-ratelimit-*` or Anthropic's
+/// `anthropic-ratelimit-*`). Returns `None` when the provider sent none of them.
+pub fn rate_limit_from_headers(headers: &header::HeaderMap) -> Option<RateLimitInfo> {
+    let number = |names: &[&str]| header_str(headers, names).and_then(|v| v.trim().parse().ok());
+    let info = RateLimitInfo {
+        remaining_requests: number(&["x-ratelimit-remaining-requests", "anthropic-ratelimit-requests-remaining"]),
+        remaining_tokens: number(&["x-ratelimit-remaining-tokens", "anthropic-ratelimit-tokens-remaining"]),
+        reset_requests: header_str(headers, &["x-ratelimit-reset-requests", "anthropic-ratelimit-requests-reset"])
+            .map(str::to_string),
+        processing_ms: number(&["openai-processing-ms"]),
+    };
+    (info != RateLimitInfo::default()).then_some(info)
+}
+
+/// Copies the request id and rate-limit headers onto a parsed response.
+pub fn apply_response_headers(payload: &mut ResponsePayload, headers: &header::HeaderMap) {
+    payload.request_id = header_str(headers, &["x-request-id", "request-id"]).map(str::to_string);
+    payload.rate_limit = rate_limit_from_headers(headers);
+}
+
 /// Executes a single API call with retry logic.
 pub async fn execute_single_call(
         url: String,
@@ -30,25 +330,123 @@ pub async fn execute_single_call(
         body: JsonValue,
         retry_policy: &RetryPolicy,
     ) -> Result<String, LLMCoreError> {
+    execute_single_call_with_headers(url, headers, body, retry_policy)
+        .await
+        .map(|(text, _)| text)
+}
+
+/// Like `execute_single_call`, but also returns the successful response's headers.
+pub async fn execute_single_call_with_headers(
+        url: String,
+        headers: header::HeaderMap,
+        body: JsonValue,
+        retry_policy: &RetryPolicy,
+    ) -> Result<(String, header::HeaderMap), LLMCoreError> {
+    execute_request(Method::POST, url, headers, Some(&body), retry_policy).await
+}
+
+/// Like `execute_single_call_with_headers`, but hands each piece of a successful
+/// response body to `on_chunk` as it arrives, for streamed responses. The whole body is
+/// still returned. A request is not retried once part of its body has been handed on.
+pub async fn execute_streaming_call(
+        url: String,
+        headers: header::HeaderMap,
+        body: JsonValue,
+        retry_policy: &RetryPolicy,
+        on_chunk: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<(String, header::HeaderMap), LLMCoreError> {
+    send_request(Method::POST, url, headers, RequestBody::Json(&body), retry_policy, Some(on_chunk)).await
+}
+
+/// Sends a request with any method and an optional JSON body, with the same retry
+/// and error handling as chat calls. Returns the body and headers on success.
+pub async fn execute_request(
+        method: Method,
+        url: String,
+        headers: header::HeaderMap,
+        body: Option<&JsonValue>,
+        retry_policy: &RetryPolicy,
+    ) -> Result<(String, header::HeaderMap), LLMCoreError> {
+    let body = body.map_or(RequestBody::Empty, RequestBody::Json);
+    send_request(method, url, headers, body, retry_policy, None).await
+}
+
+/// Like `execute_request`, but POSTs a multipart form, such as a file upload. The form
+/// cannot be reused once sent, so `build_form` makes a new one for every attempt.
+pub async fn execute_multipart_request(
+        url: String,
+        headers: header::HeaderMap,
+        build_form: &(dyn Fn() -> Result<Form, reqwest::Error> + Send + Sync),
+        retry_policy: &RetryPolicy,
+    ) -> Result<(String, header::HeaderMap), LLMCoreError> {
+    send_request(Method::POST, url, headers, RequestBody::Multipart(build_form), retry_policy, None).await
+}
+
+enum RequestBody<'a> {
+    Empty,
+    Json(&'a JsonValue),
+    Multipart(&'a (dyn Fn() -> Result<Form, reqwest::Error> + Send + Sync)),
+}
+
+async fn send_request(
+        method: Method,
+        url: String,
+        headers: header::HeaderMap,
+        body: RequestBody<'_>,
+        retry_policy: &RetryPolicy,
+        mut on_chunk: Option<&mut (dyn FnMut(&str) + Send)>,
+    ) -> Result<(String, header::HeaderMap), LLMCoreError> {
+    let _in_flight = SHUTDOWN.enter()?;
+    // Held across retries, so backing off from a 429 does not free the slot for another caller.
+    let _slot = acquire_host_slot(&url).await;
     let client = Client::builder()
         .timeout(Duration::from_secs(30)) // Add a 30-second timeout to prevent stalling
         .build()?;
 
     for i in 0..retry_policy.max_retries {
-        let response_result = client
-            .post(&url)
-            .headers(headers.clone())
-            .json(&body)
-            .send()
-            .await;
+        let mut request = client.request(method.clone(), &url).headers(headers.clone());
+        request = match &body {
+            RequestBody::Empty => request,
+            RequestBody::Json(body) => request.json(body),
+            RequestBody::Multipart(build_form) => request.multipart(build_form()?),
+        };
+        let response_result = request.send().await;
 
         match response_result {
             Ok(response) => {
                 let status = response.status();
-                let response_text = response.text().await?;
+                let response_headers = response.headers().clone();
+                let content_type = response_headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+                let body_result = match on_chunk.as_deref_mut().filter(|_| status.is_success()) {
+                    Some(on_chunk) => read_body_in_chunks(response, on_chunk).await,
+                    None => response.text().await.map_err(|e| (e, false)),
+                };
+                let response_text = match body_result {
+                    Ok(text) => text,
+                    Err((e, delivered)) => {
+                        let err = LLMCoreError::from_transport(e);
+                        eprintln!(
+                            "Failed to read response body (Attempt {}/{}): {}",
+                            i + 1,
+                            retry_policy.max_retries,
+                            err
+                        );
+                        // Part of a streamed body was already handed on; a retry would repeat it.
+                        if !delivered && err.is_retryable_transport() && i < retry_policy.max_retries - 1 {
+                            continue;
+                        }
+                        return Err(err);
+                    }
+                };
 
                 if status.is_success() {
-                    return Ok(response_text);
+                    if let Some(err) = non_json_body_error(status, content_type.as_deref(), &response_text, on_chunk.is_some()) {
+                        return Err(err);
+                    }
+                    return Ok((response_text, response_headers));
                 }
 
                 if status == StatusCode::TOO_MANY_REQUESTS {
@@ -67,6 +465,18 @@ pub async fn execute_single_call(
                         continue; // Retry the loop
                     }
                 }
+                // An HTML page here is usually a gateway or proxy error, not the provider's own.
+                if let Some(err) = non_json_body_error(status, content_type.as_deref(), &response_text, on_chunk.is_some()) {
+                    return Err(err);
+                }
+                // A rejected key fails the same way on every attempt, so it is never retried.
+                if is_auth_failure(status, &response_text) {
+                    return Err(LLMCoreError::AuthenticationFailed {
+                        provider: provider_label(&url),
+                        status: status.as_u16(),
+                        body: response_text,
+                    });
+                }
                 // For other non-success statuses, return our new ApiError.
                 return Err(LLMCoreError::ApiErrorDetailed {
                     status: status.as_u16(),
@@ -74,15 +484,17 @@ pub async fn execute_single_call(
                 });
             }
             Err(e) => {
-                // Handle network-level errors
+                // Handle network-level errors. Timeouts and connection failures are
+                // retried, and the final error keeps its specific variant.
+                let err = LLMCoreError::from_transport(e);
                 eprintln!(
                     "Network request failed (Attempt {}/{}): {}",
                     i + 1,
                     retry_policy.max_retries,
-                    e
+                    err
                 );
-                if i >= retry_policy.max_retries - 1 {
-                    return Err(e.into());
+                if !err.is_retryable_transport() || i >= retry_policy.max_retries - 1 {
+                    return Err(err);
                 }
             }
         }
@@ -93,10 +505,46 @@ pub async fn execute_single_call(
     ))
 }
 
+/// Reads a response body as it arrives, handing each decoded piece to `on_chunk`. On
+/// failure, also tells whether any of the body had been handed on.
+async fn read_body_in_chunks(
+        mut response: reqwest::Response,
+        on_chunk: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<String, (reqwest::Error, bool)> {
+    let mut body = String::new();
+    // Bytes of a character split across chunks wait here for the rest of it.
+    let mut pending: Vec<u8> = Vec::new();
+    let mut hand_on = |bytes: &[u8], body: &mut String| {
+        let text = String::from_utf8_lossy(bytes);
+        if !text.is_empty() {
+            on_chunk(&text);
+            body.push_str(&text);
+        }
+    };
+    loop {
+        match response.chunk().await {
+            Ok(Some(bytes)) => {
+                pending.extend_from_slice(&bytes);
+                let complete = match std::str::from_utf8(&pending) {
+                    Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                    // Valid text, or invalid bytes that lossy decoding replaces.
+                    _ => pending.len(),
+                };
+                hand_on(&pending[..complete], &mut body);
+                pending.drain(..complete);
+            }
+            Ok(None) => break,
+            Err(e) => return Err((e, !body.is_empty())),
+        }
+    }
+    hand_on(&pending, &mut body);
+    Ok(body)
+}
+
 /// Executes a swarm of API calls concurrently, with a limit on concurrency.
 ///
-/// This is ideal for batch processing tasks. It spawns, runs, and awaits all
-/// tasks, returning a final vector of results.
+/// This is ideal for batch processing tasks. It runs all payloads and returns a final
+/// vector of results in payload order.
 pub async fn execute_swarm_call(
         url: String,
         headers: header::HeaderMap,
@@ -104,29 +552,66 @@ pub async fn execute_swarm_call(
         max_concurrent_requests: usize,
         retry_policy: RetryPolicy,
     ) -> Vec<Result<String, LLMCoreError>> {
-    let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
-    let mut tasks = Vec::new();
+    let mut results: Vec<Option<Result<String, LLMCoreError>>> = (0..payloads.len()).map(|_| None).collect();
+    execute_swarm_stream(url, headers, payloads, max_concurrent_requests, retry_policy, |index, result| {
+        results[index] = Some(result);
+    })
+    .await;
+    results
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err(LLMCoreError::ChatError("Swarm request produced no result.".to_string()))))
+        .collect()
+}
+
+/// Runs payloads with at most `max_concurrent_requests` in flight and passes each
+/// result to `on_result` with its payload index, in completion order.
+///
+/// Payloads are pulled from the iterator only when a slot frees up, so neither the
+/// requests nor the responses are all held in memory at once.
+pub async fn execute_swarm_stream<I, F>(
+        url: String,
+        headers: header::HeaderMap,
+        payloads: I,
+        max_concurrent_requests: usize,
+        retry_policy: RetryPolicy,
+        mut on_result: F,
+    )
+    where
+        I: IntoIterator<Item = JsonValue>,
+        F: FnMut(usize, Result<String, LLMCoreError>),
+    {
+    let limit = max_concurrent_requests.max(1);
+    let mut in_flight = JoinSet::new();
+    // Maps task ids back to payload indexes so a panicked task still reports its slot.
+    let mut indexes: HashMap<task::Id, usize> = HashMap::new();
+    let mut deliver = |joined: Result<(task::Id, Result<String, LLMCoreError>), JoinError>,
+                       indexes: &mut HashMap<task::Id, usize>| {
+        let (id, result) = match joined {
+            Ok((id, result)) => (id, result),
+            Err(e) => (e.id(), Err(e.into())),
+        };
+        if let Some(index) = indexes.remove(&id) {
+            on_result(index, result);
+        }
+    };
 
-    for payload in payloads {
-        let semaphore_clone = Arc::clone(&semaphore);
+    for (index, payload) in payloads.into_iter().enumerate() {
+        if in_flight.len() >= limit {
+            if let Some(joined) = in_flight.join_next_with_id().await {
+                deliver(joined, &mut indexes);
+            }
+        }
         let url_clone = url.clone();
         let headers_clone = headers.clone();
-
-        let task: JoinHandle<Result<String, LLMCoreError>> = tokio::spawn(async move {
-            let _permit = semaphore_clone
-                .acquire()
-                .await
-                .expect("Failed to acquire semaphore permit");
+        let handle = in_flight.spawn(carry_in_flight(async move {
             execute_single_call(url_clone, headers_clone, payload, &retry_policy).await
-        });
-        tasks.push(task);
+        }));
+        indexes.insert(handle.id(), index);
     }
 
-    let mut results = Vec::new();
-    for task in tasks {
-        results.push(task.await.unwrap_or_else(|e| Err(e.into())));
+    while let Some(joined) = in_flight.join_next_with_id().await {
+        deliver(joined, &mut indexes);
     }
-    results
 }
 
 pub struct LLMClient {}
@@ -136,3 +621,115 @@ impl LLMClient {
         LLMClient {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_bodies_become_api_errors() {
+        let page = format!("<!DOCTYPE html><html><title>502 Bad Gateway</title>{}</html>", "x".repeat(1000));
+        let err = non_json_body_error(StatusCode::BAD_GATEWAY, Some("text/html; charset=UTF-8"), &page, false).unwrap();
+        let message = err.to_string();
+        assert!(matches!(err, LLMCoreError::ApiError(_)));
+        assert!(message.contains("502 Bad Gateway"));
+        assert!(message.ends_with("..."));
+
+        assert!(non_json_body_error(StatusCode::BAD_GATEWAY, None, "upstream connect error", false).is_some());
+        assert!(non_json_body_error(StatusCode::OK, Some("text/html"), "<html></html>", false).is_some());
+        assert!(non_json_body_error(StatusCode::OK, Some("text/plain"), "quota exceeded", false).is_some());
+        assert!(non_json_body_error(StatusCode::NO_CONTENT, None, "", false).is_none());
+        assert!(non_json_body_error(StatusCode::OK, Some("application/json"), " {\"id\": 1}", false).is_none());
+        let events = "data: {\"choices\": []}\n\n";
+        assert!(non_json_body_error(StatusCode::OK, Some("text/event-stream"), events, true).is_none());
+        assert!(non_json_body_error(StatusCode::OK, Some("text/event-stream"), events, false).is_some());
+        assert!(non_json_body_error(StatusCode::BAD_REQUEST, None, "[{\"error\": {}}]", false).is_none());
+    }
+
+    #[tokio::test]
+    async fn host_limit_caps_in_flight_requests() {
+        set_host_concurrency_limit("limited.example", Some(1)).unwrap();
+        let first = acquire_host_slot("https://LIMITED.example/v1/chat").await;
+        assert!(first.is_some());
+        let second = tokio::time::timeout(Duration::from_millis(50), acquire_host_slot("https://limited.example/x")).await;
+        assert!(second.is_err(), "a second slot should wait while the first is held");
+        drop(first);
+        assert!(acquire_host_slot("https://limited.example/x").await.is_some());
+
+        assert!(acquire_host_slot("https://other.example/x").await.is_none());
+        assert!(set_host_concurrency_limit("limited.example", Some(0)).is_err());
+        set_host_concurrency_limit("limited.example", None).unwrap();
+        assert!(acquire_host_slot("https://limited.example/x").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn host_limit_is_resized_in_place() {
+        set_host_concurrency_limit("resized.example", Some(2)).unwrap();
+        let first = acquire_host_slot("https://resized.example/a").await;
+        let second = acquire_host_slot("https://resized.example/b").await;
+        let waiting = tokio::spawn(acquire_host_slot("https://resized.example/c"));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        // Raising the cap admits the request that was already waiting.
+        set_host_concurrency_limit("resized.example", Some(3)).unwrap();
+        let third = tokio::time::timeout(Duration::from_millis(500), waiting).await.unwrap().unwrap();
+        assert!(third.is_some());
+
+        // Lowering it retires held slots as they are released.
+        set_host_concurrency_limit("resized.example", Some(1)).unwrap();
+        drop(first);
+        drop(second);
+        let slow = tokio::time::timeout(Duration::from_millis(50), acquire_host_slot("https://resized.example/d")).await;
+        assert!(slow.is_err(), "only one slot remains and it is still held");
+        drop(third);
+        assert!(acquire_host_slot("https://resized.example/d").await.is_some());
+        set_host_concurrency_limit("resized.example", None).unwrap();
+    }
+
+    #[tokio::test]
+    async fn drain_rejects_new_work_and_waits_for_admitted_work() {
+        let shutdown: &'static Shutdown = Box::leak(Box::new(Shutdown::new()));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let turn = tokio::spawn(shutdown.run(async move {
+            released.await.unwrap();
+            // Requests made by admitted work, including spawned ones, still get through.
+            let nested = shutdown.enter().is_ok();
+            let spawned = tokio::spawn(carry_in_flight(async move { shutdown.enter().is_ok() }));
+            nested && spawned.await.unwrap()
+        }));
+        tokio::task::yield_now().await;
+        assert_eq!(shutdown.in_flight(), 1);
+
+        shutdown.begin_drain();
+        assert!(matches!(shutdown.enter(), Err(LLMCoreError::ConcurrencyError(_))));
+        assert!(tokio::time::timeout(Duration::from_millis(50), shutdown.wait_idle()).await.is_err());
+
+        release.send(()).unwrap();
+        assert!(turn.await.unwrap().unwrap());
+        shutdown.wait_idle().await;
+        assert_eq!(shutdown.in_flight(), 0);
+
+        shutdown.resume();
+        assert!(shutdown.enter().is_ok());
+    }
+
+    #[test]
+    fn rate_limit_headers_are_read() {
+        let mut headers = header::HeaderMap::new();
+        assert_eq!(rate_limit_from_headers(&headers), None);
+
+        headers.insert("x-ratelimit-remaining-requests", header::HeaderValue::from_static("59"));
+        headers.insert("x-ratelimit-reset-requests", header::HeaderValue::from_static("1s"));
+        headers.insert("openai-processing-ms", header::HeaderValue::from_static("412"));
+        assert_eq!(
+            rate_limit_from_headers(&headers),
+            Some(RateLimitInfo {
+                remaining_requests: Some(59),
+                remaining_tokens: None,
+                reset_requests: Some("1s".to_string()),
+                processing_ms: Some(412),
+            })
+        );
+    }
+}
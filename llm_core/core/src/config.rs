@@ -1,8 +1,11 @@
+use crate::datam::Role;
 use crate::error::LLMCoreError;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 use std::env;
 
 pub mod toolkit;
@@ -12,13 +15,36 @@ pub use toolkit::get_rust_tool_library;
 // --- Data Structures for models.json ---
 
 /// An enum to represent the different reasoning capabilities of a model.
+///
+/// `thinking_mode` is resolved against it when an `Orchestra` is built: `Never` models
+/// and `Toggle` models whose provider has no thinking switch have it forced off, with a
+/// warning when it was asked for.
 #[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum ReasoningCapability {
+    /// The model reasons natively on every request; no CoT prompt is added.
     Always,
+    /// Native reasoning the provider switches per request: Anthropic extended thinking
+    /// (`thinking` block), Gemini 2.5 `thinkingConfig`, and Ollama's `think` option.
+    /// OpenAI, xAI, OpenRouter and Inception Labs requests have no such switch.
     Toggle,
+    /// No native reasoning; `thinking_mode` adds a chain-of-thought system prompt.
     #[default]
     PromptInducible,
+    /// The model must never be asked to reason; `thinking_mode` is forced off.
+    Never,
+}
+
+/// The OpenAI endpoint a model's chat turns are sent to. Other providers ignore it.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatApi {
+    /// `/chat/completions`, with `messages` in and `choices` out.
+    #[default]
+    ChatCompletions,
+    /// The Responses API (`/responses`), with `input` items in and `output` items out.
+    /// Requests are sent with `store: false`; the history stays with the caller.
+    Responses,
 }
 
 /// Holds the specific details for an individual AI model.
@@ -28,12 +54,29 @@ pub struct ModelDetails {
     pub input_price: f32,
     #[serde(default)]
     pub output_price: f32,
-    #[serde(default)]
+    /// The model's context window in tokens. `0` means unknown.
+    #[serde(default, alias = "context_window")]
     pub token_window: u32,
     #[serde(rename = "reasoning", default)]
     pub reasoning_capability: ReasoningCapability,
     #[serde(default)]
     pub dimensions: usize,
+    /// USD per generated image, for image models. Sizes and qualities above the
+    /// standard square image cost more.
+    #[serde(default)]
+    pub image_price: f32,
+    /// The role the provider expects for the system prompt (e.g. "developer" for newer
+    /// OpenAI models). Defaults to "system".
+    #[serde(default)]
+    pub system_role: Role,
+    /// Which OpenAI API chat turns use: `"chat_completions"` (the default) or
+    /// `"responses"`.
+    #[serde(default)]
+    pub api: ChatApi,
+    /// Short names the model can also be found by, e.g. `"gemini-flash"`. Matched
+    /// case-insensitively and unique across the library.
+    #[serde(default)]
+    pub aliases: Vec<String>,
 }
 
 /// Holds the configuration for a specific provider, including API keys and models.
@@ -41,9 +84,27 @@ pub struct ModelDetails {
 pub struct ProviderConfig {
     pub api_key: String,
     pub base_url: String,
+    /// The provider's well-known endpoint, used when the `base_url` env var is not set.
+    #[serde(default)]
+    pub default_base_url: Option<String>,
     pub models: HashMap<String, ModelDetails>,
     #[serde(default)]
     pub embedders: HashMap<String, ModelDetails>,
+    /// Models used only through `Orchestra::generate_image`, kept apart from chat models.
+    #[serde(default)]
+    pub image_models: HashMap<String, ModelDetails>,
+}
+
+impl ProviderConfig {
+    /// Resolves `base_url` from the environment, falling back to `default_base_url`.
+    pub fn resolve_base_url(&self) -> Result<String, LLMCoreError> {
+        let from_env = get_env_var(&self.base_url).ok().filter(|url| !url.trim().is_empty());
+        match (from_env, &self.default_base_url) {
+            (Some(url), _) => Ok(url),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) => get_env_var(&self.base_url),
+        }
+    }
 }
 
 // --- Helper Function ---
@@ -70,41 +131,176 @@ pub fn get_env_var(key_ref: &str) -> Result<String, LLMCoreError> {
 
 pub struct ModelLibrary {
     pub providers: HashMap<String, ProviderConfig>,
+    /// Why the model file could not be loaded, in which case the library is empty.
+    load_error: Option<String>,
 }
 
 impl ModelLibrary {
-    fn new() -> Result<Self, LLMCoreError> {
-        // The `models.json` is included at compile time, making the library self-contained.
-        let json_str = include_str!("config/models.json");
+    /// Loads the bundled `models.json`. A malformed file gives an empty library that
+    /// reports the problem from `check` and every lookup, so a config typo fails the
+    /// first model lookup instead of the process.
+    fn load() -> Self {
+        // The file is included at compile time, making the library self-contained.
+        Self::from_json(include_str!("config/models.json"), "models.json (bundled)").unwrap_or_else(|e| {
+            let reason = match e {
+                LLMCoreError::ConfigError(reason) => reason,
+                other => other.to_string(),
+            };
+            Self { providers: HashMap::new(), load_error: Some(reason) }
+        })
+    }
+
+    /// Reads a model library from a `models.json`-format file.
+    pub fn from_path(path: &Path) -> Result<Self, LLMCoreError> {
+        let json_str = fs::read_to_string(path).map_err(|e| {
+            LLMCoreError::ConfigError(format!("Failed to read model file '{}': {}", path.display(), e))
+        })?;
+        Self::from_json(&json_str, &path.display().to_string())
+    }
 
+    /// Parses a model library in the `models.json` format. `source` names the file in errors.
+    pub fn from_json(json_str: &str, source: &str) -> Result<Self, LLMCoreError> {
         let providers: HashMap<String, ProviderConfig> = serde_json::from_str(json_str)
-            .map_err(|e| LLMCoreError::ConfigError(format!("Failed to parse models.json: {}", e)))?;
+            .map_err(|e| LLMCoreError::ConfigError(format!("Failed to parse '{}': {}", source, e)))?;
 
-        Ok(ModelLibrary { providers })
+        let library = ModelLibrary { providers, load_error: None };
+        library
+            .validate_aliases()
+            .map_err(|e| LLMCoreError::ConfigError(format!("Invalid '{}': {}", source, e)))?;
+        Ok(library)
+    }
+
+    /// Returns the error that left the library empty, if loading it failed.
+    pub fn check(&self) -> Result<(), LLMCoreError> {
+        match &self.load_error {
+            Some(reason) => Err(LLMCoreError::ConfigError(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // An alias must not match a model name or another model's alias.
+    fn validate_aliases(&self) -> Result<(), LLMCoreError> {
+        let models = || self.providers.values().flat_map(|p| p.models.iter());
+        let mut owners: HashMap<String, &str> = models().map(|(name, _)| (name.to_lowercase(), name.as_str())).collect();
+        for (name, details) in models() {
+            for alias in &details.aliases {
+                if let Some(other) = owners.insert(alias.to_lowercase(), name) {
+                    return Err(LLMCoreError::ConfigError(format!(
+                        "Alias '{}' of model '{}' is already used by '{}'.",
+                        alias, name, other
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a model name or alias to the model's name in `models.json`. Exact names
+    /// win, then `aliases` from `models.json`, then aliases set with `set_model_alias`.
+    /// `DEFAULT_MODEL_ALIAS` falls back to the `LLM_CORE_DEFAULT_MODEL` env var.
+    pub fn resolve_model_name(&self, model_name: &str) -> Option<&str> {
+        if let Some(name) = self.resolve_static_name(model_name) {
+            return Some(name);
+        }
+        let key = model_name.to_lowercase();
+        let target = MODEL_ALIASES
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .cloned()
+            .or_else(|| {
+                (key == DEFAULT_MODEL_ALIAS)
+                    .then(|| env::var(DEFAULT_MODEL_ENV_VAR).ok())
+                    .flatten()
+            })?;
+        self.resolve_static_name(&target)
+    }
+
+    /// Resolves the default model from the `"default"` alias, if set, else from the value
+    /// of `LLM_CORE_DEFAULT_MODEL`, explaining which of the two is missing or unknown.
+    fn resolve_default_model(&self, alias: Option<String>, env_value: Option<String>) -> Result<&str, LLMCoreError> {
+        let env_value = env_value.filter(|value| !value.trim().is_empty());
+        match (alias, env_value) {
+            (Some(target), _) => self.resolve_static_name(&target).ok_or_else(|| {
+                LLMCoreError::ConfigError(format!("Model '{}' not found in `models.json`", target))
+            }),
+            (None, Some(value)) => self.resolve_static_name(&value).ok_or_else(|| {
+                LLMCoreError::ConfigError(format!(
+                    "{} is set to '{}', which is not a model or alias in `models.json`.",
+                    DEFAULT_MODEL_ENV_VAR, value
+                ))
+            }),
+            (None, None) => Err(LLMCoreError::ConfigError(format!(
+                "No default model is configured: set the {} env var or call `set_model_alias(\"{}\", ...)`.",
+                DEFAULT_MODEL_ENV_VAR, DEFAULT_MODEL_ALIAS
+            ))),
+        }
+    }
+
+    fn resolve_static_name(&self, model_name: &str) -> Option<&str> {
+        let models = || self.providers.values().flat_map(|p| p.models.iter());
+        if let Some((name, _)) = models().find(|(name, _)| name.as_str() == model_name) {
+            return Some(name);
+        }
+        models()
+            .find(|(_, details)| details.aliases.iter().any(|a| a.eq_ignore_ascii_case(model_name)))
+            .map(|(name, _)| name.as_str())
     }
 
     // science: This lookup function efficiently finds model details by iterating through providers.
     // It now also returns the provider's friendly name (e.g., "OpenAI").
-    pub fn find_model(&self, model_name: &str) -> Option<(&str, &ProviderConfig, &ModelDetails)> {
+    // Aliases are resolved first (see `resolve_model_name`).
+    pub fn find_model(&self, model_name: &str) -> Result<(&str, &ProviderConfig, &ModelDetails), LLMCoreError> {
+        self.check()?;
+        let not_found = || LLMCoreError::ConfigError(format!("Model '{}' not found in `models.json`", model_name));
+        let resolved = self.resolve_model_name(model_name).ok_or_else(not_found)?;
         for (provider_name, provider_data) in &self.providers {
             // First, search in the standard chat models.
-            if let Some(model_details) = provider_data.models.get(model_name) {
-                return Some((provider_name, provider_data, model_details));
+            if let Some(model_details) = provider_data.models.get(resolved) {
+                return Ok((provider_name, provider_data, model_details));
             }
         }
-        None
+        Err(not_found())
+    }
+
+    /// Finds a chat model by its provider-specific `model_tag`. Adapters only see the
+    /// tag, so this is how they read per-model settings.
+    pub fn find_model_by_tag(&self, provider_name: &str, model_tag: &str) -> Option<&ModelDetails> {
+        self.providers
+            .get(provider_name)?
+            .models
+            .values()
+            .find(|details| details.model_tag == model_tag)
+    }
+
+    /// Finds an image generation model by name.
+    pub fn find_image_model(
+            &self,
+            model_name: &str,
+        ) -> Result<(&str, &ProviderConfig, &ModelDetails), LLMCoreError> {
+        self.check()?;
+        self.providers
+            .iter()
+            .find_map(|(provider_name, provider_data)| {
+                let details = provider_data.image_models.get(model_name)?;
+                Some((provider_name.as_str(), provider_data, details))
+            })
+            .ok_or_else(|| {
+                LLMCoreError::ConfigError(format!("Image model '{}' not found in `models.json`", model_name))
+            })
     }
 
     pub fn find_embedder(
             &self,
             embedder_name: &str,
-        ) -> Option<(&str, &ProviderConfig, &ModelDetails)> {
+        ) -> Result<(&str, &ProviderConfig, &ModelDetails), LLMCoreError> {
+        self.check()?;
         for (provider_name, provider_data) in &self.providers {
             if let Some(embedder_details) = provider_data.embedders.get(embedder_name) {
-                return Some((provider_name, provider_data, embedder_details));
+                return Ok((provider_name, provider_data, embedder_details));
             }
         }
-        None
+        Err(LLMCoreError::ConfigError(format!("Embedder '{}' not found in config", embedder_name)))
     }
 }
 
@@ -119,8 +315,56 @@ pub static MANIFEST_DIR: Lazy<String> = Lazy::new(|| {
     env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set.")
 });
 
-pub static MODEL_LIBRARY: Lazy<ModelLibrary> =
-    Lazy::new(|| ModelLibrary::new().expect("Failed to load model library from models.json"));
+pub static MODEL_LIBRARY: Lazy<ModelLibrary> = Lazy::new(ModelLibrary::load);
+
+/// The alias that names the deployment's default model.
+pub const DEFAULT_MODEL_ALIAS: &str = "default";
+/// Env var naming the model `DEFAULT_MODEL_ALIAS` resolves to when it is not set with
+/// `set_model_alias`.
+pub const DEFAULT_MODEL_ENV_VAR: &str = "LLM_CORE_DEFAULT_MODEL";
+
+// Aliases defined at runtime, e.g. `"cheap"` or `"smart"`, keyed in lowercase.
+static MODEL_ALIASES: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Points `alias` at `model_name` (a model name or a `models.json` alias), or removes it
+/// with `None`. Lets a deployment map names like `"cheap"` and `"smart"` to concrete
+/// models. An alias cannot shadow a model name or a `models.json` alias.
+pub fn set_model_alias(alias: &str, model_name: Option<&str>) -> Result<(), LLMCoreError> {
+    MODEL_LIBRARY.check()?;
+    if MODEL_LIBRARY.resolve_static_name(alias).is_some() {
+        return Err(LLMCoreError::ConfigError(format!(
+            "'{}' already names a model in `models.json` and cannot be redefined.",
+            alias
+        )));
+    }
+    let mut aliases = MODEL_ALIASES.write().unwrap_or_else(|e| e.into_inner());
+    match model_name {
+        Some(target) => {
+            let name = MODEL_LIBRARY.resolve_static_name(target).ok_or_else(|| {
+                LLMCoreError::ConfigError(format!("Model '{}' not found in `models.json`", target))
+            })?;
+            aliases.insert(alias.to_lowercase(), name.to_string());
+        }
+        None => {
+            aliases.remove(&alias.to_lowercase());
+        }
+    }
+    Ok(())
+}
+
+/// The model `DEFAULT_MODEL_ALIAS` resolves to: the one set with
+/// `set_model_alias("default", ...)`, else the one named by `LLM_CORE_DEFAULT_MODEL`.
+/// Lets a deployment pick its model centrally (a local model in development, a hosted
+/// one in production) instead of in application code.
+pub fn default_model_name() -> Result<&'static str, LLMCoreError> {
+    MODEL_LIBRARY.check()?;
+    let alias = MODEL_ALIASES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(DEFAULT_MODEL_ALIAS)
+        .cloned();
+    MODEL_LIBRARY.resolve_default_model(alias, env::var(DEFAULT_MODEL_ENV_VAR).ok())
+}
 
 pub static DEFAULT_SORTER_INPUT_DIR: Lazy<PathBuf> = Lazy::new(|| {
     let mut path = PathBuf::from(MANIFEST_DIR.as_str());
@@ -149,3 +393,91 @@ pub static USAGE_DATA_DIR: Lazy<PathBuf> = Lazy::new(|| {
     path.push("usage");
     path
 });
+
+pub static FAILURE_DATA_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let mut path = DEFAULT_APP_DIR.clone();
+    path.push("failures");
+    path
+});
+
+pub static TRACE_DATA_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let mut path = DEFAULT_APP_DIR.clone();
+    path.push("traces");
+    path
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(base_url: &str, default_base_url: Option<&str>) -> ProviderConfig {
+        ProviderConfig {
+            api_key: String::new(),
+            base_url: base_url.to_string(),
+            default_base_url: default_base_url.map(String::from),
+            models: HashMap::new(),
+            embedders: HashMap::new(),
+            image_models: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn models_resolve_by_alias() {
+        assert_eq!(MODEL_LIBRARY.resolve_model_name("GEMINI 2.0 FLASH"), Some("GEMINI 2.0 FLASH"));
+        assert_eq!(MODEL_LIBRARY.resolve_model_name("Gemini-Flash"), Some("GEMINI 2.0 FLASH"));
+        let (provider, _, details) = MODEL_LIBRARY.find_model("gpt-4o-mini").unwrap();
+        assert_eq!((provider, details.model_tag.as_str()), ("OpenAI", "gpt-4o-mini-2024-07-18"));
+
+        assert_eq!(MODEL_LIBRARY.resolve_model_name("llm-core-test-cheap"), None);
+        set_model_alias("llm-core-test-cheap", Some("gemini-flash")).unwrap();
+        assert_eq!(MODEL_LIBRARY.resolve_model_name("LLM-CORE-TEST-CHEAP"), Some("GEMINI 2.0 FLASH"));
+        set_model_alias("llm-core-test-cheap", None).unwrap();
+        assert_eq!(MODEL_LIBRARY.resolve_model_name("llm-core-test-cheap"), None);
+
+        assert!(set_model_alias("gpt-4o", Some("GPT 4.1")).is_err());
+        assert!(set_model_alias("llm-core-test-missing", Some("NO SUCH MODEL")).is_err());
+    }
+
+    #[test]
+    fn default_model_comes_from_the_alias_or_env_var() {
+        let resolve = |alias: Option<&str>, env_value: Option<&str>| {
+            MODEL_LIBRARY.resolve_default_model(alias.map(String::from), env_value.map(String::from))
+        };
+        assert_eq!(resolve(Some("qwen3"), Some("GEMINI 2.0 FLASH")).unwrap(), "QWEN 3:0.6B");
+        assert_eq!(resolve(None, Some("qwen3")).unwrap(), "QWEN 3:0.6B");
+
+        let err = resolve(None, Some(" ")).unwrap_err().to_string();
+        assert!(err.contains("No default model"), "{}", err);
+        let err = resolve(None, Some("no-such-model")).unwrap_err().to_string();
+        assert!(err.contains(DEFAULT_MODEL_ENV_VAR) && err.contains("no-such-model"), "{}", err);
+    }
+
+    #[test]
+    fn malformed_model_files_are_config_errors() {
+        let err = ModelLibrary::from_json(r#"{"OpenAI": {"api_key": "#, "custom-models.json").err().unwrap();
+        assert!(matches!(&err, LLMCoreError::ConfigError(msg) if msg.contains("custom-models.json")));
+
+        let err = ModelLibrary::from_path(Path::new("/nonexistent/models.json")).err().unwrap();
+        assert!(matches!(&err, LLMCoreError::ConfigError(msg) if msg.contains("/nonexistent/models.json")));
+
+        let empty = ModelLibrary { providers: HashMap::new(), load_error: Some("Failed to parse 'x'".to_string()) };
+        assert!(matches!(empty.check(), Err(LLMCoreError::ConfigError(msg)) if msg == "Failed to parse 'x'"));
+        assert!(matches!(empty.find_model("GPT 4o MINI"), Err(LLMCoreError::ConfigError(msg)) if msg == "Failed to parse 'x'"));
+        assert!(matches!(empty.find_embedder("x"), Err(LLMCoreError::ConfigError(msg)) if msg == "Failed to parse 'x'"));
+        assert!(MODEL_LIBRARY.check().is_ok());
+    }
+
+    #[test]
+    fn base_url_falls_back_to_provider_default() {
+        let unset = "env:LLM_CORE_TEST_UNSET_BASE_URL";
+        assert_eq!(
+            provider(unset, Some("https://api.example.com/v1")).resolve_base_url().unwrap(),
+            "https://api.example.com/v1"
+        );
+        assert!(provider(unset, None).resolve_base_url().is_err());
+        assert_eq!(
+            provider("http://localhost:8080", Some("https://api.example.com/v1")).resolve_base_url().unwrap(),
+            "http://localhost:8080"
+        );
+    }
+}
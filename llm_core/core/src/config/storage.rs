@@ -2,6 +2,8 @@ use crate::error::LLMCoreError;
 use serde::{Deserialize, Serialize};
 use rusqlite::{Connection, params};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 use chrono::{DateTime, Utc};
 use std::fs;
 
@@ -19,8 +21,12 @@ pub struct DocumentChunk {
 }
 
 /// Manages a SQLite database for storing and retrieving document chunks.
+///
+/// A single connection is opened in WAL mode and shared behind a mutex, so repeated
+/// queries reuse it (and its prepared-statement cache) instead of reopening the file.
 pub struct Storage {
     db_path: PathBuf,
+    conn: Mutex<Connection>,
 }
 
 impl Storage {
@@ -28,13 +34,21 @@ impl Storage {
         if let Some(parent) = db_path.parent() {
             fs::create_dir_all(parent)?;
         }
-        let storage = Self { db_path: db_path.to_path_buf() };
+        let conn = Connection::open(db_path)?;
+        // WAL lets other connections (e.g. a second KnowledgeBase on the same file) read
+        // while we write; the busy timeout covers brief writer contention.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        let storage = Self { db_path: db_path.to_path_buf(), conn: Mutex::new(conn) };
         storage.initialize_db()?;
         Ok(storage)
     }
 
-    fn get_conn(&self) -> Result<Connection, LLMCoreError> {
-        Connection::open(&self.db_path).map_err(Into::into)
+    fn get_conn(&self) -> Result<MutexGuard<'_, Connection>, LLMCoreError> {
+        self.conn.lock().map_err(|e| {
+            LLMCoreError::ConcurrencyError(format!("Storage connection lock poisoned: {}", e))
+        })
     }
     
     fn initialize_db(&self) -> Result<(), LLMCoreError> {
@@ -59,7 +73,7 @@ impl Storage {
         let metadata_str = serde_json::to_string(metadata)?;
         let conn = self.get_conn()?;
 
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             "INSERT INTO document_chunks (url, chunk_number, title, summary, content, metadata)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
         )?;
@@ -69,7 +83,7 @@ impl Storage {
 
     pub fn get_chunk_by_id(&self, id: i64) -> Result<Option<DocumentChunk>, LLMCoreError> {
         let conn = self.get_conn()?;
-        let mut stmt = conn.prepare("SELECT id, url, chunk_number, title, summary, content, metadata, created_at FROM document_chunks WHERE id = ?1")?;
+        let mut stmt = conn.prepare_cached("SELECT id, url, chunk_number, title, summary, content, metadata, created_at FROM document_chunks WHERE id = ?1")?;
         let mut chunk_iter = stmt.query_map(params![id], |row| {
             let metadata = serde_json::from_str(&row.get::<_, String>(6)?).unwrap_or(serde_json::Value::Null);
             let created_at = DateTime::parse_from_rfc3339(&row.get::<_, String>(7)?).unwrap().with_timezone(&Utc);
@@ -97,7 +111,9 @@ impl Storage {
             "SELECT id, url, chunk_number, title, summary, content, metadata, created_at FROM document_chunks WHERE id IN ({})",
             params_sql
         );
-        let mut stmt = conn.prepare(&sql)?;
+        // The statement text depends on the number of ids, so the cache also helps
+        // repeated searches that use the same `limit`.
+        let mut stmt = conn.prepare_cached(&sql)?;
 
         let chunk_iter = stmt.query_map(rusqlite::params_from_iter(ids.iter()), |row| {
             let metadata = serde_json::from_str(&row.get::<_, String>(6)?).unwrap_or(serde_json::Value::Null);
@@ -131,7 +147,7 @@ impl Storage {
     /// Retrieves all unique source URLs from the database.
     pub fn list_sources(&self) -> Result<Vec<String>, LLMCoreError> {
         let conn = self.get_conn()?;
-        let mut stmt = conn.prepare("SELECT DISTINCT url FROM document_chunks ORDER BY url")?;
+        let mut stmt = conn.prepare_cached("SELECT DISTINCT url FROM document_chunks ORDER BY url")?;
         let mut rows = stmt.query([])?;
         let mut urls = Vec::new();
         while let Some(row) = rows.next()? {
@@ -140,10 +156,29 @@ impl Storage {
         Ok(urls)
     }
 
+    /// Counts the stored chunks and the distinct source URLs they come from.
+    pub fn chunk_and_source_counts(&self) -> Result<(usize, usize), LLMCoreError> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare_cached("SELECT COUNT(*), COUNT(DISTINCT url) FROM document_chunks")?;
+        let (chunks, sources): (i64, i64) = stmt.query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok((chunks as usize, sources as usize))
+    }
+
+    /// The size of the database on disk in bytes, including its write-ahead log.
+    pub fn size_on_disk(&self) -> Result<u64, LLMCoreError> {
+        let mut size = fs::metadata(&self.db_path)?.len();
+        let mut wal = self.db_path.clone().into_os_string();
+        wal.push("-wal");
+        if let Ok(metadata) = fs::metadata(wal) {
+            size += metadata.len();
+        }
+        Ok(size)
+    }
+
     /// Retrieves all chunks for a specific URL, ordered by their chunk number.
     pub fn get_full_document(&self, url: &str) -> Result<Vec<DocumentChunk>, LLMCoreError> {
         let conn = self.get_conn()?;
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             "SELECT id, url, chunk_number, title, summary, content, metadata, created_at 
              FROM document_chunks WHERE url = ?1 ORDER BY chunk_number ASC"
         )?;
@@ -170,18 +205,54 @@ impl Storage {
         Ok(chunks)
     }
 
+    /// Retrieves the IDs of all chunks stored for `url`, in chunk order.
+    pub fn chunk_ids_for_url(&self, url: &str) -> Result<Vec<i64>, LLMCoreError> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare_cached("SELECT id FROM document_chunks WHERE url = ?1 ORDER BY chunk_number ASC")?;
+        let ids = stmt.query_map(params![url], |row| row.get(0))?;
+        Ok(ids.collect::<Result<_, _>>()?)
+    }
+
     /// Removes a document chunk from the database by its unique ID.
     pub fn remove_chunk(&self, id: i64) -> Result<usize, LLMCoreError> {
         let conn = self.get_conn()?;
-        let rows_affected = conn.execute("DELETE FROM document_chunks WHERE id = ?1", params![id])?;
+        let rows_affected = conn.prepare_cached("DELETE FROM document_chunks WHERE id = ?1")?.execute(params![id])?;
         Ok(rows_affected)
     }
 
+    /// Removes several chunks in a single transaction, returning the ids that existed.
+    pub fn remove_chunks(&self, ids: &[i64]) -> Result<std::collections::HashSet<i64>, LLMCoreError> {
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        let mut removed = std::collections::HashSet::new();
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM document_chunks WHERE id = ?1")?;
+            for &id in ids {
+                if stmt.execute(params![id])? > 0 {
+                    removed.insert(id);
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
     /// Deletes the entire SQLite database file from the filesystem.
     /// This method consumes the Storage object, ensuring the file lock is released.
     pub fn delete_database(self) -> Result<(), LLMCoreError> {
-        // The connection is no longer stored in self, so we can just delete the file.
-        fs::remove_file(&self.db_path)?;
+        let Self { db_path, conn } = self;
+        // Close the shared connection first so the file lock is released.
+        let conn = conn.into_inner().map_err(|e| {
+            LLMCoreError::ConcurrencyError(format!("Storage connection lock poisoned: {}", e))
+        })?;
+        conn.close().map_err(|(_, e)| LLMCoreError::from(e))?;
+        fs::remove_file(&db_path)?;
+        // WAL mode leaves sidecar files next to the database; remove them if present.
+        for suffix in ["-wal", "-shm"] {
+            let mut sidecar = db_path.clone().into_os_string();
+            sidecar.push(suffix);
+            let _ = fs::remove_file(sidecar);
+        }
         Ok(())
     }
 }
@@ -1,4 +1,4 @@
-use crate::tools::{FunctionDefinition, Tool, ToolDefinition, ToolLibrary};
+use crate::tools::{ParametersBuilder, Tool, ToolDefinition, ToolLibrary};
 use crate::config::{get_env_var, MODEL_LIBRARY};
 
 // Add back the necessary imports for a self-contained, blocking HTTP call.
@@ -15,7 +15,7 @@ use crate::sorter::sort_data_items_tool;
 
 // --- Imports for the new KnowledgeBase Tools ---
 use crate::retrieval::{
-    knowledge_base_get_full_document, knowledge_base_list_sources, knowledge_base_search,
+    knowledge_base_get_full_document, knowledge_base_list_sources, knowledge_base_multi_search, knowledge_base_search,
 };
 
 /// Returns a `ToolLibrary` containing all the natively implemented Rust tools.
@@ -43,17 +43,16 @@ pub fn get_rust_tool_library() -> ToolLibrary {
 
         // --- Logic is now self-contained within the tool ---
         let (_provider_name, provider_data, model_details) =
-            MODEL_LIBRARY.find_model("GEMINI 2.0 FLASH IMAGE GEN")
-            .ok_or_else(|| "Model 'GEMINI 2.0 FLASH IMAGE GEN' not found".to_string())?;
+            MODEL_LIBRARY.find_image_model("GEMINI 2.0 FLASH IMAGE GEN")
+            .map_err(|e| e.to_string())?;
 
         let api_key = get_env_var(&provider_data.api_key).map_err(|e| e.to_string())?;
-        let base_url = get_env_var(&provider_data.base_url).map_err(|e| e.to_string())?;
+        let base_url = provider_data.resolve_base_url().map_err(|e| e.to_string())?;
 
         let url = format!(
-            "{}/{}:generateContent?key={}",
+            "{}/{}:generateContent",
             base_url.trim_end_matches('/'),
-            &model_details.model_tag,
-            api_key
+            &model_details.model_tag
         );
 
         let payload = json!({
@@ -70,6 +69,7 @@ pub fn get_rust_tool_library() -> ToolLibrary {
         let res = client
             .post(&url)
             .header("Content-Type", "application/json")
+            .header("x-goog-api-key", api_key)
             .json(&payload)
             .send()
             .map_err(|e| format!("Request error: {}", e))?;
@@ -143,14 +143,7 @@ pub fn get_rust_tool_library() -> ToolLibrary {
     tool_library.insert(
         "get_current_time".to_string(),
         Tool::Rust {
-            definition: ToolDefinition {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "get_current_time".to_string(),
-                    description: "Get the current time.".to_string(),
-                    parameters: json!({"type": "object", "properties": {}}),
-                },
-            },
+            definition: ToolDefinition::builder("get_current_time", "Get the current time.").build(),
             function: get_current_time,
         },
     );
@@ -158,27 +151,20 @@ pub fn get_rust_tool_library() -> ToolLibrary {
     tool_library.insert(
         "generate_image".to_string(),
         Tool::Rust {
-            definition: ToolDefinition {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "generate_image".to_string(),
-                    description: "Generate an image from a text prompt using the Gemini API and save it to a file.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "prompt": {
-                                "type": "string",
-                                "description": "The text prompt for generating the image."
-                            },
-                            "output_path": {
-                                "type": "string",
-                                "description": "Optional. The full path, including filename and extension (e.g., 'images/my_lion.png'), where the image should be saved. If not provided, a unique filename will be generated in the current directory."
-                            }
-                        },
-                        "required": ["prompt"]
-                    }),
-                },
-            },
+            definition: ToolDefinition::builder(
+                "generate_image",
+                "Generate an image from a text prompt using the Gemini API and save it to a file.",
+            )
+            .parameters(
+                ParametersBuilder::new()
+                    .string("prompt", "The text prompt for generating the image.", true)
+                    .string(
+                        "output_path",
+                        "Optional. The full path, including filename and extension (e.g., 'images/my_lion.png'), where the image should be saved. If not provided, a unique filename will be generated in the current directory.",
+                        false,
+                    ),
+            )
+            .build(),
             function: generate_image,
         },
     );
@@ -186,58 +172,77 @@ pub fn get_rust_tool_library() -> ToolLibrary {
     tool_library.insert(
         "sort_data_items".to_string(),
         Tool::Rust {
-            definition: ToolDefinition {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "sort_data_items".to_string(),
-                    description: "Sorts a list of data items into categories based on provided guidelines. Saves the output to a JSON file.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "input_path": {
-                                "type": "string",
-                                "description": "Optional. The path to a file or folder containing a JSON list of strings to be sorted. One of 'input_path' or 'items_list' is required."
-                            },
-                            "items_list": {
-                                "type": "array",
-                                "items": { "type": "string" },
-                                "description": "Optional. A direct list of strings to be sorted. One of 'input_path' or 'items_list' is required."
-                            },
-                             "output_path": {
-                                "type": "string",
-                                "description": "Optional. The path to a directory where the sorted output file should be saved. Defaults to 'tests/output'."
-                            },
-                            "data_item_name": {
-                                "type": "string",
-                                "description": "A brief, descriptive name for the type of items being sorted (e.g., 'Customer Feedback', 'Product Titles'). Defaults to 'Data Item'."
-                            },
-                            "data_profile_description": {
-                                "type": "string",
-                                "description": "A detailed description of the data's characteristics or the user's interests to guide the sorting AI. Required if no categories are provided."
-                            },
-                            "item_sorting_guidelines": {
-                                "type": "array",
-                                "items": { "type": "string" },
-                                "description": "A list of rules or guidelines for the AI to follow when sorting each item."
-                            },
-                            "provided_categories": {
-                                "type": "array",
-                                "items": { "type": "string" },
-                                "description": "Optional. A predefined list of categories to sort items into. If not provided, the tool will attempt to generate categories automatically."
-                            },
-                            "model_name": {
-                                "type": "string",
-                                "description": "Optional. The name of the AI model to use for sorting (e.g., 'GPT 4o MINI'). Defaults to a capable model."
-                            },
-                            "swarm_size": {
-                                "type": "number",
-                                "description": "Optional. The number of concurrent requests to make to the AI. Defaults to 5."
-                            }
-                        },
-                        "required": []
-                    }),
-                },
-            },
+            definition: ToolDefinition::builder(
+                "sort_data_items",
+                "Sorts a list of data items into categories based on provided guidelines. Saves the output to a JSON file.",
+            )
+            .parameters(
+                ParametersBuilder::new()
+                    .string(
+                        "input_path",
+                        "Optional. The path to a file or folder of items to be sorted: a .json list of strings, a .txt file with one item per line, or a .csv file. One of 'input_path' or 'items_list' is required.",
+                        false,
+                    )
+                    .string(
+                        "csv_column",
+                        "Optional. The header of the CSV column holding the items. Defaults to the first column.",
+                        false,
+                    )
+                    .array(
+                        "items_list",
+                        "string",
+                        "Optional. A direct list of strings to be sorted. One of 'input_path' or 'items_list' is required.",
+                        false,
+                    )
+                    .string(
+                        "output_path",
+                        "Optional. The path to a directory where the sorted output file should be saved. Defaults to 'tests/output'.",
+                        false,
+                    )
+                    .string(
+                        "data_item_name",
+                        "A brief, descriptive name for the type of items being sorted (e.g., 'Customer Feedback', 'Product Titles'). Defaults to 'Data Item'.",
+                        false,
+                    )
+                    .string(
+                        "data_profile_description",
+                        "A detailed description of the data's characteristics or the user's interests to guide the sorting AI. Required if no categories are provided.",
+                        false,
+                    )
+                    .array(
+                        "item_sorting_guidelines",
+                        "string",
+                        "A list of rules or guidelines for the AI to follow when sorting each item.",
+                        false,
+                    )
+                    .array(
+                        "provided_categories",
+                        "string",
+                        "Optional. A predefined list of categories to sort items into. If not provided, the tool will attempt to generate categories automatically.",
+                        false,
+                    )
+                    .string(
+                        "system_prompt",
+                        "Optional. A custom system prompt for the sorter. Replaces the built-in prompt, or wraps it if it contains '{default_prompt}'. Guidelines and categories are still appended.",
+                        false,
+                    )
+                    .boolean(
+                        "capture_reasoning",
+                        "Optional. If true, the model explains each classification and the rationales are saved to an audit file next to the sorted output. Defaults to false.",
+                        false,
+                    )
+                    .string(
+                        "model_name",
+                        "Optional. The name of the AI model to use for sorting (e.g., 'GPT 4o MINI'). Defaults to a capable model.",
+                        false,
+                    )
+                    .number(
+                        "swarm_size",
+                        "Optional. The number of concurrent requests to make to the AI. Defaults to 5.",
+                        false,
+                    ),
+            )
+            .build(),
             function: sort_data_items_tool,
         },
     );
@@ -247,42 +252,49 @@ pub fn get_rust_tool_library() -> ToolLibrary {
     tool_library.insert(
         "knowledge_base_search".to_string(),
         Tool::Rust {
-            definition: ToolDefinition {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "knowledge_base_search".to_string(),
-                    description: "Searches the knowledge base for documents relevant to a query.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "query": {
-                                "type": "string",
-                                "description": "The natural language query to search for."
-                            },
-                            "limit": {
-                                "type": "number",
-                                "description": "Optional. The maximum number of results to return. Defaults to 5."
-                            }
-                        },
-                        "required": ["query"]
-                    }),
-                },
-            },
+            definition: ToolDefinition::builder(
+                "knowledge_base_search",
+                "Searches the knowledge base for documents relevant to a query. Each result has a `source_id`; cite it inline in square brackets when using that result.",
+            )
+            .parameters(
+                ParametersBuilder::new()
+                    .string("query", "The natural language query to search for.", true)
+                    .number("limit", "Optional. The maximum number of results to return. Defaults to 5.", false)
+                    .number("max_per_source", "Optional. The most results to return from any one document, so others are included too. Unlimited by default.", false)
+                    .string("search_mode", "Optional. Which text to return for each result: \"content\", \"summary\" or \"both\" (the default). Use \"summary\" for broad queries, then read promising documents with `knowledge_base_get_full_document`.", false),
+            )
+            .build(),
             function: knowledge_base_search,
         },
     );
 
+    tool_library.insert(
+        "knowledge_base_multi_search".to_string(),
+        Tool::Rust {
+            definition: ToolDefinition::builder(
+                "knowledge_base_multi_search",
+                "Searches the knowledge base for several queries in one call, e.g. the sub-questions of a complex question. Results are grouped per query. Each result has a `source_id`; cite it inline in square brackets when using that result.",
+            )
+            .parameters(
+                ParametersBuilder::new()
+                    .array("queries", "string", "The natural language queries to search for.", true)
+                    .number("limit", "Optional. The maximum number of results to return per query. Defaults to 5.", false)
+                    .number("max_per_source", "Optional. The most results to return from any one document for each query. Unlimited by default.", false)
+                    .string("search_mode", "Optional. Which text to return for each result: \"content\", \"summary\" or \"both\" (the default).", false),
+            )
+            .build(),
+            function: knowledge_base_multi_search,
+        },
+    );
+
     tool_library.insert(
         "knowledge_base_list_sources".to_string(),
         Tool::Rust {
-            definition: ToolDefinition {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "knowledge_base_list_sources".to_string(),
-                    description: "Lists all the unique document sources (URLs) available in the knowledge base.".to_string(),
-                    parameters: json!({"type": "object", "properties": {}}),
-                },
-            },
+            definition: ToolDefinition::builder(
+                "knowledge_base_list_sources",
+                "Lists all the unique document sources (URLs) available in the knowledge base.",
+            )
+            .build(),
             function: knowledge_base_list_sources,
         },
     );
@@ -290,23 +302,31 @@ pub fn get_rust_tool_library() -> ToolLibrary {
     tool_library.insert(
         "knowledge_base_get_full_document".to_string(),
         Tool::Rust {
-            definition: ToolDefinition {
-                tool_type: "function".to_string(),
-                function: FunctionDefinition {
-                    name: "knowledge_base_get_full_document".to_string(),
-                    description: "Retrieves the full, combined content of a specific document from the knowledge base.".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "url": {
-                                "type": "string",
-                                "description": "The exact URL of the document source to retrieve."
-                            }
-                        },
-                        "required": ["url"]
-                    }),
-                },
-            },
+            definition: ToolDefinition::builder(
+                "knowledge_base_get_full_document",
+                "Retrieves the full, combined content of a specific document from the knowledge base. Long documents can be read in pages using 'offset' and 'max_chunks'; the response includes 'next_offset' while chunks remain.",
+            )
+            .parameters(
+                ParametersBuilder::new()
+                    .string("url", "The exact URL of the document source to retrieve.", true)
+                    .string(
+                        "separator",
+                        "Optional. The text placed between chunks. Defaults to a horizontal rule ('\\n\\n---\\n\\n').",
+                        false,
+                    )
+                    .boolean(
+                        "include_headers",
+                        "Optional. If true, each chunk is preceded by its title and summary. Defaults to false.",
+                        false,
+                    )
+                    .number("offset", "Optional. The index of the first chunk to return. Defaults to 0.", false)
+                    .number(
+                        "max_chunks",
+                        "Optional. The maximum number of chunks to return. Defaults to all remaining chunks.",
+                        false,
+                    ),
+            )
+            .build(),
             function: knowledge_base_get_full_document,
         },
     );
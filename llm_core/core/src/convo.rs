@@ -1,19 +1,96 @@
-use crate::config::{DEFAULT_SORTER_OUTPUT_DIR};
-use crate::datam::{Message, Usage};
+use crate::config::{DEFAULT_SORTER_OUTPUT_DIR, MODEL_LIBRARY};
+use crate::datam::{count_message_tokens, Message, Usage};
 use crate::usage::log_usage_turn;
-use crate::orchestra::Orchestra;
+use crate::orchestra::{ChatEvent, Orchestra};
 use crate::lucky::SimpleSchema;
 use crate::tools::ToolLibrary;
+use crate::providers::ProviderAdapter;
 
 use crate::error::LLMCoreError;
 
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::fmt;
 use std::path::Path;
 use uuid::Uuid;
 use std::fs;
 use std::io;
 
+/// A file's text attached to a single chat turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub content: String,
+}
+
+impl Attachment {
+    pub fn new(filename: impl Into<String>, content: impl Into<String>) -> Self {
+        Self { filename: filename.into(), content: content.into() }
+    }
+
+    /// Reads a UTF-8 text file, using its file name as the attachment name.
+    pub fn from_path(path: &Path) -> Result<Self, LLMCoreError> {
+        let content = fs::read_to_string(path)?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Ok(Self { filename, content })
+    }
+}
+
+/// Builds the user message text for a turn with attachments. Each file is wrapped in
+/// `<attachment>` tags so the model can tell the files apart from the prompt.
+pub fn format_attachments(user_prompt: &str, attachments: &[Attachment]) -> String {
+    let mut text = String::new();
+    for attachment in attachments {
+        text.push_str(&format!(
+            "<attachment filename=\"{}\">\n{}\n</attachment>\n\n",
+            attachment.filename,
+            attachment.content.trim_end()
+        ));
+    }
+    text.push_str(user_prompt);
+    text
+}
+
+/// A structural problem in a conversation's history that a provider would reject.
+/// `index` is the position of the offending message in `Conversation::messages`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    /// A `tool` message that answers no tool call of the assistant message before it.
+    OrphanedToolResult { index: usize, tool_call_id: Option<String> },
+    /// An assistant tool call that no `tool` message answers.
+    MissingToolResult { index: usize, tool_call_id: String },
+    /// A `user` or `assistant` message following one with the same role, for providers
+    /// that need the two to alternate.
+    ConsecutiveRole { index: usize, role: String },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::OrphanedToolResult { index, tool_call_id } => write!(
+                f,
+                "message {}: tool result '{}' does not follow an assistant call with that id",
+                index,
+                tool_call_id.as_deref().unwrap_or("<none>")
+            ),
+            ValidationIssue::MissingToolResult { index, tool_call_id } => write!(
+                f,
+                "message {}: tool call '{}' has no tool result",
+                index, tool_call_id
+            ),
+            ValidationIssue::ConsecutiveRole { index, role } => write!(
+                f,
+                "message {}: second '{}' message in a row",
+                index, role
+            ),
+        }
+    }
+}
+
 /// Represents a single, stateful conversation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
@@ -69,6 +146,165 @@ impl Conversation {
             fs::write(path, data)
         }
     }
+
+    /// Estimates how many prompt tokens the full history uses when sent to the model.
+    pub fn estimated_tokens(&self) -> usize {
+        count_message_tokens(&self.messages)
+    }
+
+    /// Checks the history for structure `provider` would reject: tool results that answer
+    /// no preceding call, tool calls left unanswered and, where roles must alternate,
+    /// consecutive `user` or `assistant` messages. Meant for conversations loaded from a
+    /// file or built by hand, before they are sent.
+    pub fn validate(&self, provider: &dyn ProviderAdapter) -> Result<(), Vec<ValidationIssue>> {
+        let mut issues = tool_issues(&self.messages);
+        if provider.requires_alternating_roles() {
+            issues.extend(role_issues(&self.messages));
+        }
+        issues.sort_by_key(|issue| match issue {
+            ValidationIssue::OrphanedToolResult { index, .. }
+            | ValidationIssue::MissingToolResult { index, .. }
+            | ValidationIssue::ConsecutiveRole { index, .. } => *index,
+        });
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    /// Fixes the issues `validate` reports that have an obvious fix: orphaned tool results
+    /// are dropped, unanswered tool calls are removed from their assistant message (and the
+    /// message with them when nothing else is left), and consecutive `user` or `assistant`
+    /// messages are merged into one. A `user` message right after a tool result has no such
+    /// fix and is left in place. Returns the issues that were fixed, with indices into the
+    /// history as it was before.
+    pub fn repair(&mut self) -> Vec<ValidationIssue> {
+        let mut fixed = tool_issues(&self.messages);
+        let orphaned: HashSet<usize> = fixed
+            .iter()
+            .filter_map(|issue| match issue {
+                ValidationIssue::OrphanedToolResult { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect();
+        // Assistant messages left with neither calls nor text once their calls are removed.
+        let mut emptied: HashSet<usize> = HashSet::new();
+        for issue in &fixed {
+            if let ValidationIssue::MissingToolResult { index, tool_call_id } = issue {
+                let message = &mut self.messages[*index];
+                if let Some(calls) = &mut message.tool_calls {
+                    calls.retain(|call| &call.id != tool_call_id);
+                    if calls.is_empty() {
+                        message.tool_calls = None;
+                        if is_blank(&message.content) && is_blank(&message.reasoning_content) {
+                            emptied.insert(*index);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Indices from here on refer to the original history, for the returned issues.
+        let mut kept: Vec<(usize, Message)> = Vec::with_capacity(self.messages.len());
+        for (index, message) in std::mem::take(&mut self.messages).into_iter().enumerate() {
+            if orphaned.contains(&index) || emptied.contains(&index) {
+                continue;
+            }
+            match kept.last_mut() {
+                Some((_, previous)) if can_merge(previous, &message) => {
+                    fixed.push(ValidationIssue::ConsecutiveRole { index, role: message.role.clone() });
+                    merge_into(previous, message);
+                }
+                _ => kept.push((index, message)),
+            }
+        }
+        self.messages = kept.into_iter().map(|(_, message)| message).collect();
+        fixed
+    }
+}
+
+/// Finds tool results that answer no call of the assistant message before them, and
+/// calls that are never answered.
+fn tool_issues(messages: &[Message]) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    // The assistant message whose calls the following tool messages answer.
+    let mut pending: Option<(usize, Vec<String>)> = None;
+    for (index, message) in messages.iter().enumerate() {
+        if message.role == "tool" {
+            let answered = match (&mut pending, &message.tool_call_id) {
+                (Some((_, ids)), Some(id)) => ids.iter().position(|pending_id| pending_id == id).map(|i| ids.remove(i)),
+                _ => None,
+            };
+            if answered.is_none() {
+                issues.push(ValidationIssue::OrphanedToolResult {
+                    index,
+                    tool_call_id: message.tool_call_id.clone(),
+                });
+            }
+            continue;
+        }
+        if let Some((call_index, ids)) = pending.take() {
+            issues.extend(ids.into_iter().map(|tool_call_id| ValidationIssue::MissingToolResult {
+                index: call_index,
+                tool_call_id,
+            }));
+        }
+        if let Some(calls) = message.tool_calls.as_ref().filter(|calls| !calls.is_empty()) {
+            pending = Some((index, calls.iter().map(|call| call.id.clone()).collect()));
+        }
+    }
+    if let Some((call_index, ids)) = pending {
+        issues.extend(ids.into_iter().map(|tool_call_id| ValidationIssue::MissingToolResult {
+            index: call_index,
+            tool_call_id,
+        }));
+    }
+    issues
+}
+
+/// Finds `user` or `assistant` messages that follow one with the same role. Tool results
+/// count as `user` turns, since that is how alternating providers receive them.
+fn role_issues(messages: &[Message]) -> Vec<ValidationIssue> {
+    fn turn_role(message: &Message) -> &str {
+        match message.role.as_str() {
+            "tool" => "user",
+            role => role,
+        }
+    }
+    messages
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| {
+            matches!(pair[1].role.as_str(), "user" | "assistant") && turn_role(&pair[0]) == pair[1].role
+        })
+        .map(|(i, pair)| ValidationIssue::ConsecutiveRole { index: i + 1, role: pair[1].role.clone() })
+        .collect()
+}
+
+/// Returns `true` when `next` can be folded into `previous`: both are plain `user` or
+/// `assistant` messages with the same role and no tool calls between them.
+fn can_merge(previous: &Message, next: &Message) -> bool {
+    previous.role == next.role
+        && matches!(next.role.as_str(), "user" | "assistant")
+        && previous.tool_calls.is_none()
+        && next.tool_calls.is_none()
+}
+
+fn is_blank(text: &Option<String>) -> bool {
+    text.as_deref().map(|text| text.trim().is_empty()).unwrap_or(true)
+}
+
+/// Appends `next`'s content and reasoning to `previous`, separated by a blank line.
+fn merge_into(previous: &mut Message, next: Message) {
+    fn join(a: Option<String>, b: Option<String>) -> Option<String> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(format!("{}\n\n{}", a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+    previous.content = join(previous.content.take(), next.content);
+    previous.reasoning_content = join(previous.reasoning_content.take(), next.reasoning_content);
 }
 impl Default for Conversation {
     fn default() -> Self {
@@ -107,7 +343,9 @@ impl Chat {
         ) -> Result<Self, LLMCoreError> {
         let has_tools = tools.is_some();
         let has_schema = schema.is_some();
-        let orchestra = Orchestra::new(model_name, None, tools, schema, thinking_mode, debug_out)?;
+        let mut orchestra = Orchestra::new(model_name, None, tools, schema, thinking_mode, debug_out)?;
+        // Turns are logged by `send_message`, under the conversation id.
+        orchestra.set_turn_usage_logging(false);
         let final_thinking_mode = orchestra.thinking_mode(); // Get the final state from Orchestra
         let mut conversation = Conversation::new(orchestra.user_facing_model_name.clone());
 
@@ -126,6 +364,28 @@ impl Chat {
         })
     }
 
+    /// Creates a chat session with the configured default model (see
+    /// `config::default_model_name`) and no system prompt, tools or schema.
+    pub fn default_model() -> Result<Self, LLMCoreError> {
+        Self::new(crate::config::default_model_name()?, None, None, None, None, None)
+    }
+
+    /// Creates a chat session whose history is seeded with `messages`, for example
+    /// few-shot examples or a conversation restored from the caller's own storage.
+    /// Any system prompt should be included as the first message.
+    pub fn from_messages(
+            model_name: &str,
+            messages: Vec<Message>,
+            tools: Option<ToolLibrary>,
+            schema: Option<SimpleSchema>,
+            thinking_mode: Option<bool>,
+            debug_out: Option<bool>,
+        ) -> Result<Self, LLMCoreError> {
+        let mut chat = Self::new(model_name, None, tools, schema, thinking_mode, debug_out)?;
+        chat.conversation.messages = messages;
+        Ok(chat)
+    }
+
     /// Resumes a chat session from a previously saved conversation file.
     ///
     /// # Arguments
@@ -147,7 +407,8 @@ impl Chat {
         // Use the provided model name, or default to the one stored in the conversation file.
         let final_model_name = model_name.unwrap_or(&conversation.model_name);
 
-        let orchestra = Orchestra::new(final_model_name, None, tools, None, thinking_mode, debug_out)?;
+        let mut orchestra = Orchestra::new(final_model_name, None, tools, None, thinking_mode, debug_out)?;
+        orchestra.set_turn_usage_logging(false);
         let final_thinking_mode = orchestra.thinking_mode();
 
         Ok(Self {
@@ -171,6 +432,42 @@ impl Chat {
         self.conversation.save(&save_path).map_err(Into::into)
     }
 
+    /// Starts a fresh conversation in this session, keeping the model, tools and schema.
+    ///
+    /// The history and usage are wiped and a new conversation id is assigned. If
+    /// `keep_system_prompt` is true, the original system message is carried over.
+    pub fn clear(&mut self, keep_system_prompt: bool) {
+        let system_message = self
+            .conversation
+            .messages
+            .first()
+            .filter(|m| m.role == "system")
+            .cloned();
+
+        self.conversation = Conversation::new(self.conversation.model_name.clone());
+        if keep_system_prompt {
+            if let Some(message) = system_message {
+                self.conversation.messages.push(message);
+            }
+        }
+    }
+
+    /// Checks the history against this session's provider; see `Conversation::validate`.
+    pub fn validate(&self) -> Result<(), Vec<ValidationIssue>> {
+        self.conversation.validate(self.orchestra.provider_adapter())
+    }
+
+    /// Returns `(estimated tokens used, model context window)`. The window is `None`
+    /// when `models.json` does not list one for the model.
+    pub fn context_usage(&self) -> (usize, Option<usize>) {
+        let context_window = MODEL_LIBRARY
+            .find_model(&self.conversation.model_name)
+            .ok()
+            .map(|(_, _, details)| details.token_window as usize)
+            .filter(|&window| window > 0);
+        (self.conversation.estimated_tokens(), context_window)
+    }
+
     /// Sends a user prompt to the model and updates the conversation state.
     ///
     /// This is the primary method for driving a conversation. It appends the user's
@@ -179,13 +476,69 @@ impl Chat {
     ///
     /// Returns a reference to the assistant's message that was just added to the history.
     pub async fn send(&mut self, user_prompt: &str) -> Result<&Message, LLMCoreError> {
-        // 1. Prepare the messages for this specific turn without mutating state yet.
         let user_message = crate::datam::format_user_message(user_prompt.to_string());
+        self.send_message(user_message, None, |_| {}).await
+    }
+
+    /// Like `send`, but samples this turn at `temperature` (e.g. low for a factual
+    /// question, high for brainstorming). The chat's own temperature is unchanged.
+    pub async fn send_with_temperature(
+            &mut self,
+            user_prompt: &str,
+            temperature: f32,
+        ) -> Result<&Message, LLMCoreError> {
+        let user_message = crate::datam::format_user_message(user_prompt.to_string());
+        self.send_message(user_message, Some(temperature), |_| {}).await
+    }
+
+    /// Like `send`, but reports the turn's tool calls, tool results, answer text and
+    /// final payload to `on_event` as they happen (see `ChatEvent`), for UIs that show
+    /// the agent's steps.
+    pub async fn send_events(
+            &mut self,
+            user_prompt: &str,
+            on_event: impl FnMut(ChatEvent) + Send,
+        ) -> Result<&Message, LLMCoreError> {
+        let user_message = crate::datam::format_user_message(user_prompt.to_string());
+        self.send_message(user_message, None, on_event).await
+    }
+
+    /// Sends a user prompt with the text of one or more files attached to this turn.
+    ///
+    /// The attachments are inlined into the user message (see `format_attachments`), so
+    /// they stay in the history for follow-up questions. If the model's context window
+    /// is known and the turn would not fit, it is rejected with `ContextWindowExceeded`
+    /// before anything is sent.
+    pub async fn send_with_attachments(
+            &mut self,
+            user_prompt: &str,
+            attachments: Vec<Attachment>,
+        ) -> Result<&Message, LLMCoreError> {
+        let user_message = crate::datam::format_user_message(format_attachments(user_prompt, &attachments));
+        if let (used, Some(limit)) = self.context_usage() {
+            let tokens = used + count_message_tokens(std::slice::from_ref(&user_message));
+            if tokens > limit {
+                return Err(LLMCoreError::ContextWindowExceeded { tokens, limit });
+            }
+        }
+        self.send_message(user_message, None, |_| {}).await
+    }
+
+    async fn send_message(
+            &mut self,
+            user_message: Message,
+            temperature: Option<f32>,
+            on_event: impl FnMut(ChatEvent) + Send,
+        ) -> Result<&Message, LLMCoreError> {
+        // 1. Prepare the messages for this specific turn without mutating state yet.
         let mut messages_for_call = self.conversation.messages.clone();
         messages_for_call.push(user_message.clone());
 
         // 2. Call the stateless Orchestra engine.
-        let response = self.orchestra.call_ai(messages_for_call).await?;
+        let response = match temperature {
+            Some(temperature) => self.orchestra.call_ai_at_temperature(messages_for_call, temperature, on_event).await?,
+            None => self.orchestra.call_ai_with_events(messages_for_call, on_event).await?,
+        };
 
         // 3. On success, commit the changes to the conversation state.
         let assistant_message = response
@@ -211,7 +564,13 @@ impl Chat {
             };
 
             // Log the usage for this specific turn.
-            log_usage_turn(self.conversation.id, &usage, label, &self.conversation.model_name)?;
+            log_usage_turn(
+                self.conversation.id,
+                &usage,
+                label,
+                &self.conversation.model_name,
+                self.orchestra.usage_metadata(),
+            )?;
             // Still update the cumulative total for the stateful conversation object.
             self.conversation.usage += usage;
         }
@@ -224,3 +583,124 @@ impl Chat {
             .expect("Message was just added, so it must exist."))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datam::{format_assistant_message, format_tool_message, format_user_message};
+    use crate::providers::{anthropic::AnthropicAdapter, openai::OpenAIAdapter};
+    use crate::tools::{FunctionCall, ToolCall};
+
+    #[test]
+    fn attachments_are_delimited_before_the_prompt() {
+        let text = format_attachments(
+            "Summarize these.",
+            &[Attachment::new("a.txt", "first file\n"), Attachment::new("b.md", "second file")],
+        );
+        assert_eq!(
+            text,
+            "<attachment filename=\"a.txt\">\nfirst file\n</attachment>\n\n\
+             <attachment filename=\"b.md\">\nsecond file\n</attachment>\n\nSummarize these."
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_attachment_is_rejected_before_sending() {
+        let mut chat = Chat::new("QWEN 3:0.6B", None, None, None, None, None).unwrap();
+        let attachment = Attachment::new("big.txt", "word ".repeat(40_000));
+
+        let err = chat.send_with_attachments("Summarize this.", vec![attachment]).await.unwrap_err();
+        assert!(matches!(err, LLMCoreError::ContextWindowExceeded { limit: 40_960, .. }));
+        assert!(chat.conversation.messages.is_empty());
+    }
+
+    fn call(id: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: FunctionCall { name: "lookup".to_string(), arguments: serde_json::json!({}) },
+        }
+    }
+
+    #[test]
+    fn structural_issues_are_found_and_repaired() {
+        let mut conversation = Conversation::new("CLAUDE 3.5 HAIKU".to_string());
+        conversation.messages = vec![
+            format_user_message("Hi".to_string()),
+            format_user_message("Are you there?".to_string()),
+            Message { role: "assistant".to_string(), tool_calls: Some(vec![call("a"), call("b")]), ..Default::default() },
+            format_tool_message("found".to_string(), "a".to_string(), "lookup".to_string()),
+            format_tool_message("stale".to_string(), "z".to_string(), "lookup".to_string()),
+            format_assistant_message("Done.".to_string()),
+        ];
+
+        assert_eq!(
+            conversation.validate(&OpenAIAdapter).unwrap_err(),
+            vec![
+                ValidationIssue::MissingToolResult { index: 2, tool_call_id: "b".to_string() },
+                ValidationIssue::OrphanedToolResult { index: 4, tool_call_id: Some("z".to_string()) },
+            ]
+        );
+        let issues = conversation.validate(&AnthropicAdapter).unwrap_err();
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[0], ValidationIssue::ConsecutiveRole { index: 1, role: "user".to_string() });
+
+        assert_eq!(conversation.repair().len(), 3);
+        assert!(conversation.validate(&AnthropicAdapter).is_ok());
+        assert_eq!(conversation.messages.len(), 4);
+        assert_eq!(conversation.messages[0].content.as_deref(), Some("Hi\n\nAre you there?"));
+        assert_eq!(conversation.messages[1].tool_calls.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn user_message_after_a_tool_result_is_flagged_for_alternating_providers() {
+        let mut conversation = Conversation::new("CLAUDE 4 SONNET".to_string());
+        conversation.messages = vec![
+            format_user_message("Look it up.".to_string()),
+            Message { role: "assistant".to_string(), tool_calls: Some(vec![call("a")]), ..Default::default() },
+            format_tool_message("found".to_string(), "a".to_string(), "lookup".to_string()),
+            format_user_message("And then?".to_string()),
+        ];
+
+        assert!(conversation.validate(&OpenAIAdapter).is_ok());
+        assert_eq!(
+            conversation.validate(&AnthropicAdapter).unwrap_err(),
+            vec![ValidationIssue::ConsecutiveRole { index: 3, role: "user".to_string() }]
+        );
+        assert!(conversation.repair().is_empty());
+        assert_eq!(conversation.messages.len(), 4);
+    }
+
+    #[test]
+    fn repair_drops_assistant_messages_left_empty() {
+        let mut conversation = Conversation::new("CLAUDE 4 SONNET".to_string());
+        conversation.messages = vec![
+            format_user_message("Look it up.".to_string()),
+            Message { role: "assistant".to_string(), tool_calls: Some(vec![call("a")]), ..Default::default() },
+            format_user_message("Never mind.".to_string()),
+        ];
+
+        assert_eq!(
+            conversation.repair(),
+            vec![
+                ValidationIssue::MissingToolResult { index: 1, tool_call_id: "a".to_string() },
+                ValidationIssue::ConsecutiveRole { index: 2, role: "user".to_string() },
+            ]
+        );
+        assert_eq!(conversation.messages.len(), 1);
+        assert_eq!(conversation.messages[0].content.as_deref(), Some("Look it up.\n\nNever mind."));
+        assert!(conversation.validate(&AnthropicAdapter).is_ok());
+    }
+
+    #[test]
+    fn from_messages_seeds_the_history() {
+        let messages = vec![
+            crate::datam::format_system_message("Answer with one word.".to_string()),
+            crate::datam::format_user_message("Capital of France?".to_string()),
+            crate::datam::format_assistant_message("Paris".to_string()),
+        ];
+        let chat = Chat::from_messages("QWEN 3:0.6B", messages, None, None, None, None).unwrap();
+        assert_eq!(chat.conversation.messages.len(), 3);
+        assert_eq!(chat.conversation.messages[2].content.as_deref(), Some("Paris"));
+    }
+}
@@ -1,8 +1,36 @@
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, AddAssign}; // Added for Usage aggregation
+use std::time::Duration;
 use pyo3::prelude::*;
 
 use crate::tools::ToolCall;
+use crate::error::LLMCoreError;
+
+/// The roles a message can have. `Message::role` stays a plain string for
+/// compatibility; this enum names the values and lets adapters remap them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    System,
+    /// OpenAI's replacement for `system` on newer models.
+    Developer,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Developer => "developer",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
 
 /// Represents a single message in a conversation.
 /// This struct is compatible with OpenAI's format and serves as our standard.
@@ -27,12 +55,77 @@ pub struct Message {
     #[pyo3(get, set)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_content: Option<String>,
+    // Why the model declined to answer (OpenAI `refusal`, Anthropic `refusal` stop reason).
+    #[pyo3(get, set)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+}
+
+impl Message {
+    /// An `assistant` message requesting `tool_calls`, checked with `validate`.
+    pub fn assistant_tool_calls(content: Option<String>, tool_calls: Vec<ToolCall>) -> Result<Self, LLMCoreError> {
+        let message = Self {
+            role: "assistant".to_string(),
+            content,
+            tool_calls: Some(tool_calls),
+            ..Default::default()
+        };
+        message.validate()?;
+        Ok(message)
+    }
+
+    /// A `tool` message answering the call `tool_call_id` to the tool `name`, checked
+    /// with `validate`.
+    pub fn tool_result(content: String, tool_call_id: String, name: String) -> Result<Self, LLMCoreError> {
+        let message = format_tool_message(content, tool_call_id, name);
+        message.validate()?;
+        Ok(message)
+    }
+
+    /// Checks the fields whose presence depends on the role: a `tool` message needs a
+    /// `tool_call_id` and a `name`, `tool_calls` belong to `assistant` messages and need
+    /// distinct, non-empty ids, and only `tool` messages carry a `tool_call_id`.
+    pub fn validate(&self) -> Result<(), LLMCoreError> {
+        let invalid = |reason: String| Err(LLMCoreError::ChatError(format!("Invalid '{}' message: {}", self.role, reason)));
+        let is_blank = |field: &Option<String>| field.as_deref().map(|v| v.trim().is_empty()).unwrap_or(true);
+        if self.role == "tool" {
+            if is_blank(&self.tool_call_id) {
+                return invalid("a tool result needs the `tool_call_id` of the call it answers.".to_string());
+            }
+            if is_blank(&self.name) {
+                return invalid("a tool result needs the `name` of the tool.".to_string());
+            }
+        } else if self.tool_call_id.is_some() {
+            return invalid("only tool results carry a `tool_call_id`.".to_string());
+        }
+        if let Some(calls) = &self.tool_calls {
+            if self.role != "assistant" {
+                return invalid("only assistant messages carry `tool_calls`.".to_string());
+            }
+            if calls.is_empty() {
+                return invalid("`tool_calls` is empty; leave it unset instead.".to_string());
+            }
+            let mut ids = std::collections::HashSet::new();
+            for call in calls {
+                if call.id.trim().is_empty() {
+                    return invalid(format!("the call to '{}' has no id.", call.function.name));
+                }
+                if !ids.insert(call.id.as_str()) {
+                    return invalid(format!("the tool call id '{}' is used twice.", call.id));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A single choice within the API response.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Choice {
     pub message: Message,
+    /// Why generation stopped, in OpenAI terms (`"stop"`, `"length"`, `"tool_calls"`).
+    #[serde(default)]
+    pub finish_reason: Option<String>,
 }
 
 /// Cost details for a specific API call.
@@ -51,12 +144,33 @@ pub struct Usage {
     pub total_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cost: Option<Cost>,
+    /// Wall-clock time of the request(s), in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Completion tokens per second of wall-clock time, derived from `duration_ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f32>,
+    /// The part of `prompt_tokens` read from the provider's context cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<u32>,
+    /// Set when the provider reported no token counts and they were estimated from the
+    /// text with `count_tokens` instead (see `ResponsePayload::estimate_missing_usage`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
 }
 
 impl Usage {
     // Calculates the total cost of the API call and sets the `cost` field.
     pub fn calculate_cost(&mut self, input_price: f32, output_price: f32) {
-        let input_cost = (self.prompt_tokens as f32 / 1_000_000.0) * input_price;
+        self.calculate_cost_with_cache(input_price, input_price, output_price);
+    }
+
+    /// Like `calculate_cost`, but bills the `cached_tokens` part of the prompt at
+    /// `cached_input_price`.
+    pub fn calculate_cost_with_cache(&mut self, input_price: f32, cached_input_price: f32, output_price: f32) {
+        let cached = self.cached_tokens.unwrap_or(0).min(self.prompt_tokens);
+        let input_cost = ((self.prompt_tokens - cached) as f32 / 1_000_000.0) * input_price
+            + (cached as f32 / 1_000_000.0) * cached_input_price;
         let output_cost = (self.completion_tokens as f32 / 1_000_000.0) * output_price;
         self.cost = Some(Cost {
             input_price,
@@ -64,6 +178,34 @@ impl Usage {
             total: input_cost + output_cost,
         });
     }
+
+    /// Records how long the request took and derives `tokens_per_second` from it.
+    pub fn record_duration(&mut self, duration: Duration) {
+        self.duration_ms = Some(duration.as_millis() as u64);
+        self.update_tokens_per_second();
+    }
+
+    fn update_tokens_per_second(&mut self) {
+        self.tokens_per_second = match self.duration_ms {
+            Some(ms) if ms > 0 => Some(self.completion_tokens as f32 * 1000.0 / ms as f32),
+            _ => None,
+        };
+    }
+}
+
+// Durations add up like tokens, so a combined usage reports the overall throughput.
+fn add_durations(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
+}
+
+fn add_counts(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
 }
 
 impl Add for Usage {
@@ -80,12 +222,18 @@ impl Add for Usage {
             (None, None) => None,
         };
 
-        Self {
+        let mut usage = Self {
             prompt_tokens: self.prompt_tokens + other.prompt_tokens,
             completion_tokens: self.completion_tokens + other.completion_tokens,
             total_tokens: self.total_tokens + other.total_tokens,
             cost: new_cost,
-        }
+            duration_ms: add_durations(self.duration_ms, other.duration_ms),
+            tokens_per_second: None,
+            cached_tokens: add_counts(self.cached_tokens, other.cached_tokens),
+            estimated: self.estimated || other.estimated,
+        };
+        usage.update_tokens_per_second();
+        usage
     }
 }
 
@@ -101,12 +249,16 @@ impl AddAssign for Usage {
                 self.cost = Some(other_cost);
             }
         }
+        self.duration_ms = add_durations(self.duration_ms, other.duration_ms);
+        self.cached_tokens = add_counts(self.cached_tokens, other.cached_tokens);
+        self.estimated |= other.estimated;
+        self.update_tokens_per_second();
     }
 }
 
 /// Represents the overall structure of a response from a chat completion API.
 /// This is based on the standard OpenAI response format.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponsePayload {
     pub id: String,
     pub object: String,
@@ -115,6 +267,187 @@ pub struct ResponsePayload {
     pub choices: Vec<Choice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usage: Option<Usage>,
+    /// Sources the answer cited inline, resolved from knowledge base tool results.
+    #[serde(default)]
+    pub citations: Option<Vec<Citation>>,
+    /// The provider's id for this request, from the `x-request-id`/`request-id` header.
+    /// Useful when reporting issues to the provider.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Rate-limit state reported in the response headers, when the provider sends it.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitInfo>,
+    /// The `models.json` name of the fallback model that answered, when the primary
+    /// model failed. `None` when the requested model served the call.
+    #[serde(default)]
+    pub served_by: Option<String>,
+}
+
+impl ResponsePayload {
+    /// Fills in `usage` when the provider reported no token counts (no usage, or all
+    /// zero): `prompt_tokens` is the caller's estimate of the request (e.g. from
+    /// `count_message_tokens`) and the completion is estimated from the returned messages.
+    /// The result is priced at the given rates and marked `estimated`. Reported counts
+    /// are left untouched.
+    pub fn estimate_missing_usage(&mut self, prompt_tokens: usize, input_price: f32, output_price: f32) {
+        let reported = self.usage.as_ref().is_some_and(|u| u.prompt_tokens > 0 || u.completion_tokens > 0 || u.total_tokens > 0);
+        if reported {
+            return;
+        }
+        let prompt_tokens = prompt_tokens as u32;
+        let completion_tokens: u32 = self
+            .choices
+            .iter()
+            .map(|choice| {
+                let message = &choice.message;
+                let mut tokens = message.content.as_deref().map_or(0, count_tokens);
+                tokens += message.reasoning_content.as_deref().map_or(0, count_tokens);
+                for call in message.tool_calls.iter().flatten() {
+                    tokens += count_tokens(&call.function.name) + count_tokens(&call.function.arguments.to_string());
+                }
+                tokens as u32
+            })
+            .sum();
+        let usage = self.usage.get_or_insert_with(Usage::default);
+        usage.prompt_tokens = prompt_tokens;
+        usage.completion_tokens = completion_tokens;
+        usage.total_tokens = prompt_tokens + completion_tokens;
+        usage.estimated = true;
+        usage.calculate_cost(input_price, output_price);
+    }
+
+    /// Merges a later response (a continuation or a stream delta) into this one.
+    ///
+    /// Choices are merged by index: content, reasoning and refusals are concatenated,
+    /// tool-call deltas extend the call with the same id (or the last call when the delta
+    /// has no id), and the later finish reason wins. Usage is summed and citations appended.
+    /// `id`, `model` and `created` stay from the first response; `request_id` and
+    /// `rate_limit` take the later values when present.
+    pub fn merge(mut self, other: ResponsePayload) -> ResponsePayload {
+        for (index, choice) in other.choices.into_iter().enumerate() {
+            match self.choices.get_mut(index) {
+                Some(existing) => existing.merge(choice),
+                None => self.choices.push(choice),
+            }
+        }
+
+        self.usage = match (self.usage, other.usage) {
+            (Some(mut usage), Some(more)) => {
+                usage += more;
+                Some(usage)
+            }
+            (usage, more) => usage.or(more),
+        };
+        self.citations = match (self.citations, other.citations) {
+            (Some(mut citations), Some(more)) => {
+                citations.extend(more);
+                Some(citations)
+            }
+            (citations, more) => citations.or(more),
+        };
+        self.request_id = other.request_id.or(self.request_id);
+        self.rate_limit = other.rate_limit.or(self.rate_limit);
+        self
+    }
+}
+
+impl Choice {
+    fn merge(&mut self, other: Choice) {
+        let message = &mut self.message;
+        append_text(&mut message.content, other.message.content);
+        append_text(&mut message.reasoning_content, other.message.reasoning_content);
+        append_text(&mut message.refusal, other.message.refusal);
+        for delta in other.message.tool_calls.into_iter().flatten() {
+            let calls = message.tool_calls.get_or_insert_with(Vec::new);
+            let target = if delta.id.is_empty() {
+                calls.last_mut()
+            } else {
+                calls.iter_mut().find(|call| call.id == delta.id)
+            };
+            match target {
+                Some(call) => merge_tool_call(call, delta),
+                None => calls.push(delta),
+            }
+        }
+        if other.finish_reason.is_some() {
+            self.finish_reason = other.finish_reason;
+        }
+    }
+}
+
+fn append_text(target: &mut Option<String>, more: Option<String>) {
+    match (target.as_mut(), more) {
+        (Some(text), Some(more)) => text.push_str(&more),
+        (None, more) => *target = more,
+        (Some(_), None) => {}
+    }
+}
+
+// Streamed arguments arrive as string fragments; anything else replaces the old value.
+fn merge_tool_call(call: &mut ToolCall, delta: ToolCall) {
+    if call.function.name.is_empty() {
+        call.function.name = delta.function.name;
+    }
+    match (&mut call.function.arguments, delta.function.arguments) {
+        (_, serde_json::Value::Null) => {}
+        (serde_json::Value::String(arguments), serde_json::Value::String(fragment)) => arguments.push_str(&fragment),
+        (arguments, replacement) => *arguments = replacement,
+    }
+}
+
+/// Rate-limit and timing details read from a provider's response headers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitInfo {
+    pub remaining_requests: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    /// When the request budget resets, as sent by the provider (e.g. `"1s"` or a timestamp).
+    pub reset_requests: Option<String>,
+    /// Server-side processing time in milliseconds (`openai-processing-ms`).
+    pub processing_ms: Option<u64>,
+}
+
+/// The outcome of a `swarm_call`: one result per prompt, in prompt order, with the
+/// success/failure tallies and usage summed across the successful responses.
+#[derive(Debug)]
+pub struct SwarmSummary {
+    pub results: Vec<Result<ResponsePayload, LLMCoreError>>,
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub usage: Usage,
+}
+
+impl SwarmSummary {
+    pub fn from_results(results: Vec<Result<ResponsePayload, LLMCoreError>>) -> Self {
+        let mut usage = Usage::default();
+        let mut success_count = 0;
+        for response in results.iter().flatten() {
+            success_count += 1;
+            if let Some(turn_usage) = &response.usage {
+                usage += turn_usage.clone();
+            }
+        }
+        let failure_count = results.len() - success_count;
+        Self { results, success_count, failure_count, usage }
+    }
+
+    /// The content of each successful response, in prompt order. Failed prompts and
+    /// responses without content are skipped.
+    pub fn successful_contents(&self) -> Vec<&str> {
+        self.results
+            .iter()
+            .flatten()
+            .filter_map(|response| response.choices.first()?.message.content.as_deref())
+            .collect()
+    }
+}
+
+/// A knowledge base source referenced in an answer as `[source_id]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    pub source_id: String,
+    pub url: String,
+    pub chunk_number: i32,
+    pub title: String,
 }
 
 // --- Message Formatting Helpers ---
@@ -128,6 +461,7 @@ pub fn format_system_message(content: String) -> Message {
         tool_call_id: None,
         tool_calls: None,
         reasoning_content: None, // Will be None and not serialized
+        refusal: None,
     }
 }
 
@@ -140,6 +474,7 @@ pub fn format_user_message(content: String) -> Message {
         tool_call_id: None,
         tool_calls: None,
         reasoning_content: None, // Will be None and not serialized
+        refusal: None,
     }
 }
 
@@ -152,6 +487,7 @@ pub fn format_assistant_message(content: String) -> Message {
         tool_call_id: None,
         tool_calls: None,
         reasoning_content: None, // Will be None and not serialized
+        refusal: None,
     }
 }
 
@@ -166,3 +502,410 @@ pub fn format_tool_message(content: String, tool_call_id: String, name: String)
     }
 }
 
+/// Tidies tool fields that histories loaded from a file or built by hand often get
+/// slightly wrong: an empty `tool_calls` list is unset, and a tool result without a
+/// `name` takes the name of the call it answers. A nameless result that answers no
+/// earlier call is an error, since providers such as Gemini match results by name.
+pub fn normalize_tool_messages(messages: &mut [Message]) -> Result<(), LLMCoreError> {
+    let mut call_names: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    for (index, message) in messages.iter_mut().enumerate() {
+        if message.tool_calls.as_ref().map(|calls| calls.is_empty()).unwrap_or(false) {
+            message.tool_calls = None;
+        }
+        if let Some(calls) = &message.tool_calls {
+            call_names.extend(calls.iter().map(|call| (call.id.clone(), call.function.name.clone())));
+        }
+        if message.role == "tool" && message.name.as_deref().map(str::is_empty).unwrap_or(true) {
+            match message.tool_call_id.as_ref().and_then(|id| call_names.get(id)) {
+                Some(name) => message.name = Some(name.clone()),
+                None => {
+                    return Err(LLMCoreError::ChatError(format!(
+                        "Message {}: tool result '{}' has no `name` and answers no earlier tool call.",
+                        index,
+                        message.tool_call_id.as_deref().unwrap_or("<none>")
+                    )))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// --- Token Estimation ---
+
+// Per-message formatting overhead and the tokens that prime the reply, following
+// OpenAI's published counting guidance. Other providers are close enough for estimates.
+const TOKENS_PER_MESSAGE: usize = 4;
+const TOKENS_PER_REPLY: usize = 3;
+
+/// Estimates the token count of a text without a tokenizer, at roughly four characters
+/// per token. Good enough for context-usage displays, not for billing.
+pub fn count_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimates the prompt tokens a list of messages will use, including formatting overhead.
+pub fn count_message_tokens(messages: &[Message]) -> usize {
+    let per_message: usize = messages
+        .iter()
+        .map(|m| {
+            let mut tokens = TOKENS_PER_MESSAGE + count_tokens(&m.role);
+            tokens += m.content.as_deref().map_or(0, count_tokens);
+            tokens += m.name.as_deref().map_or(0, count_tokens);
+            for call in m.tool_calls.iter().flatten() {
+                tokens += count_tokens(&call.function.name) + count_tokens(&call.function.arguments.to_string());
+            }
+            tokens
+        })
+        .sum();
+    if messages.is_empty() { 0 } else { per_message + TOKENS_PER_REPLY }
+}
+
+// --- UTF-8 Safe Truncation ---
+
+/// Returns at most the first `max_chars` characters of `s`.
+pub fn truncate_chars(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((byte_index, _)) => &s[..byte_index],
+        None => s,
+    }
+}
+
+/// Returns the longest prefix of `s` that fits in `max_bytes` without splitting a
+/// character. Use this instead of `&s[..max_bytes]`, which panics on multi-byte text.
+pub fn truncate_at_boundary(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// The tag prompt-induced reasoning is wrapped in unless an `Orchestra` is configured
+/// with others (`<think>...</think>`).
+pub const DEFAULT_THINKING_TAG: &str = "think";
+
+fn starts_with_tag(text: &str, at: usize, tag: &str) -> bool {
+    text.as_bytes()
+        .get(at..at + tag.len())
+        .is_some_and(|bytes| bytes.eq_ignore_ascii_case(tag.as_bytes()))
+}
+
+/// Moves prompt-induced `<think>` reasoning out of `content` and returns it.
+///
+/// Tags are matched case-insensitively and with nesting, so an inner `</think>` does not
+/// end the block early. A block left open by a truncated response takes the rest of the
+/// text, and a leading `</think>` with no opener (the block started in an earlier chunk)
+/// makes everything before it reasoning. Several blocks are joined with blank lines.
+/// `content` is left untouched when it has no think tags; `None` means no reasoning text.
+/// Parsers holding `Option<String>` content use `extract_reasoning`.
+pub fn extract_think_blocks(content: &mut String) -> Option<String> {
+    extract_tagged_blocks(content, &[DEFAULT_THINKING_TAG])
+}
+
+/// `extract_think_blocks` for any set of tag names, e.g. `["reasoning", "scratchpad"]`
+/// for `<reasoning>` and `<scratchpad>` blocks. An opening tag from the set is closed by
+/// any closing tag from it.
+pub fn extract_tagged_blocks<S: AsRef<str>>(content: &mut String, tags: &[S]) -> Option<String> {
+    let opens: Vec<String> = tags.iter().map(|tag| format!("<{}>", tag.as_ref())).collect();
+    let closes: Vec<String> = tags.iter().map(|tag| format!("</{}>", tag.as_ref())).collect();
+    let mut thoughts: Vec<&str> = Vec::new();
+    let mut answer = String::new();
+    let mut found_tag = false;
+    let mut depth = 0;
+    let mut block_start = 0;
+    let mut answer_start = 0;
+    let mut i = 0;
+
+    while i < content.len() {
+        if let Some(open) = opens.iter().find(|open| starts_with_tag(content, i, open)) {
+            if depth == 0 {
+                answer.push_str(&content[answer_start..i]);
+                block_start = i + open.len();
+            }
+            found_tag = true;
+            depth += 1;
+            i += open.len();
+        } else if let Some(close) = closes.iter().find(|close| starts_with_tag(content, i, close)) {
+            if depth > 0 {
+                depth -= 1;
+                if depth == 0 {
+                    thoughts.push(&content[block_start..i]);
+                    answer_start = i + close.len();
+                }
+            } else if !found_tag {
+                thoughts.push(&content[..i]);
+                answer_start = i + close.len();
+            }
+            found_tag = true;
+            i += close.len();
+        } else {
+            i += 1;
+        }
+    }
+
+    if !found_tag {
+        return None;
+    }
+    if depth > 0 {
+        thoughts.push(&content[block_start..]);
+    } else {
+        answer.push_str(&content[answer_start..]);
+    }
+
+    let reasoning = thoughts
+        .iter()
+        .map(|thought| thought.trim())
+        .filter(|thought| !thought.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    *content = answer.trim().to_string();
+    Some(reasoning).filter(|r| !r.is_empty())
+}
+
+/// `extract_think_blocks` for optional message content: strips the think blocks from
+/// `content`, if any, and returns the reasoning.
+pub fn extract_reasoning(content: &mut Option<String>) -> Option<String> {
+    content.as_mut().and_then(extract_think_blocks)
+}
+
+/// Returns the text after the last closing tag from `tags`, or all of `content` when
+/// there is none.
+pub fn answer_after_reasoning<'a, S: AsRef<str>>(content: &'a str, tags: &[S]) -> &'a str {
+    tags.iter()
+        .filter_map(|tag| {
+            let close = format!("</{}>", tag.as_ref());
+            content.rfind(&close).map(|end| end + close.len())
+        })
+        .max()
+        .map_or(content, |start| &content[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_tags_are_extracted() {
+        let mut content = "<reasoning>plan</reasoning><SCRATCHPAD>sums</scratchpad> Answer <think>kept</think>".to_string();
+        let reasoning = extract_tagged_blocks(&mut content, &["reasoning", "scratchpad"]);
+        assert_eq!(reasoning.as_deref(), Some("plan\n\nsums"));
+        assert_eq!(content, "Answer <think>kept</think>");
+        assert_eq!(answer_after_reasoning("<reasoning>x</reasoning>{}", &["think", "reasoning"]), "{}");
+    }
+
+    #[test]
+    fn role_dependent_fields_are_checked() {
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: crate::tools::FunctionCall { name: "lookup".to_string(), arguments: serde_json::json!({}) },
+        };
+        assert!(Message::assistant_tool_calls(None, vec![call("a"), call("b")]).is_ok());
+        assert!(Message::assistant_tool_calls(None, vec![call("a"), call("a")]).is_err());
+        assert!(Message::assistant_tool_calls(None, vec![call(" ")]).is_err());
+        assert!(Message::assistant_tool_calls(None, Vec::new()).is_err());
+
+        assert!(Message::tool_result("42".to_string(), "a".to_string(), "lookup".to_string()).is_ok());
+        assert!(Message::tool_result("42".to_string(), String::new(), "lookup".to_string()).is_err());
+        let unnamed = Message { role: "tool".to_string(), tool_call_id: Some("a".to_string()), ..Default::default() };
+        assert!(matches!(unnamed.validate(), Err(LLMCoreError::ChatError(msg)) if msg.contains("`name`")));
+
+        let mut user = format_user_message("Hi".to_string());
+        assert!(user.validate().is_ok());
+        user.tool_calls = Some(vec![call("a")]);
+        assert!(user.validate().is_err());
+    }
+
+    #[test]
+    fn tool_fields_are_normalized() {
+        let call = ToolCall {
+            id: "a".to_string(),
+            tool_type: "function".to_string(),
+            function: crate::tools::FunctionCall { name: "lookup".to_string(), arguments: serde_json::json!({}) },
+        };
+        let mut messages = vec![
+            Message { role: "assistant".to_string(), tool_calls: Some(Vec::new()), ..Default::default() },
+            Message::assistant_tool_calls(None, vec![call]).unwrap(),
+            Message { role: "tool".to_string(), tool_call_id: Some("a".to_string()), ..Default::default() },
+        ];
+        normalize_tool_messages(&mut messages).unwrap();
+        assert!(messages[0].tool_calls.is_none());
+        assert_eq!(messages[2].name.as_deref(), Some("lookup"));
+        assert!(messages[2].validate().is_ok());
+
+        messages.push(Message { role: "tool".to_string(), tool_call_id: Some("z".to_string()), ..Default::default() });
+        let err = normalize_tool_messages(&mut messages).unwrap_err();
+        assert!(matches!(err, LLMCoreError::ChatError(msg) if msg.starts_with("Message 3:")));
+    }
+
+    #[test]
+    fn missing_usage_is_estimated() {
+        let mut payload = ResponsePayload {
+            id: "r".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "m".to_string(),
+            choices: vec![Choice { message: format_assistant_message("12345678".to_string()), finish_reason: None }],
+            usage: Some(Usage { duration_ms: Some(10), ..Default::default() }),
+            citations: None,
+            request_id: None,
+            rate_limit: None,
+            served_by: None,
+        };
+        payload.estimate_missing_usage(40, 1_000_000.0, 2_000_000.0);
+        let usage = payload.usage.clone().unwrap();
+        assert!(usage.estimated);
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (40, 2, 42));
+        assert_eq!(usage.duration_ms, Some(10));
+        assert!((usage.cost.unwrap().total - 44.0).abs() < 1e-3);
+
+        let reported = Usage { prompt_tokens: 7, completion_tokens: 1, total_tokens: 8, ..Default::default() };
+        payload.usage = Some(reported);
+        payload.estimate_missing_usage(40, 1.0, 1.0);
+        assert!(!payload.usage.as_ref().unwrap().estimated);
+        assert_eq!(payload.usage.unwrap().prompt_tokens, 7);
+    }
+
+    #[test]
+    fn truncation_never_splits_characters() {
+        let text = "日本語😀abc";
+        assert_eq!(truncate_chars(text, 4), "日本語😀");
+        assert_eq!(truncate_chars(text, 100), text);
+        assert_eq!(truncate_chars(text, 0), "");
+
+        // Each CJK character is 3 bytes and the emoji is 4.
+        assert_eq!(truncate_at_boundary(text, 4), "日");
+        assert_eq!(truncate_at_boundary(text, 12), "日本語");
+        assert_eq!(truncate_at_boundary(text, 13), "日本語😀");
+        assert_eq!(truncate_at_boundary(text, 2), "");
+        assert_eq!(truncate_at_boundary(text, 100), text);
+    }
+
+    fn payload(content: &str, tokens: u32, finish_reason: Option<&str>, tool_calls: Option<Vec<ToolCall>>) -> ResponsePayload {
+        ResponsePayload {
+            id: format!("resp-{}", tokens),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "mock".to_string(),
+            choices: vec![Choice {
+                message: Message {
+                    role: "assistant".to_string(),
+                    content: Some(content.to_string()),
+                    tool_calls,
+                    ..Default::default()
+                },
+                finish_reason: finish_reason.map(String::from),
+            }],
+            usage: Some(Usage { prompt_tokens: tokens, completion_tokens: tokens, total_tokens: 2 * tokens, ..Default::default() }),
+            citations: None,
+            request_id: None,
+            rate_limit: None,
+            served_by: None,
+        }
+    }
+
+    fn tool_call(id: &str, name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: crate::tools::FunctionCall {
+                name: name.to_string(),
+                arguments: serde_json::Value::String(arguments.to_string()),
+            },
+        }
+    }
+
+    #[test]
+    fn merge_concatenates_content_and_sums_usage() {
+        let merged = payload("The answer is", 10, Some("length"), None)
+            .merge(payload(" 42.", 4, Some("stop"), None));
+
+        let choice = &merged.choices[0];
+        assert_eq!(choice.message.content.as_deref(), Some("The answer is 42."));
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(merged.id, "resp-10");
+
+        let usage = merged.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 14);
+        assert_eq!(usage.completion_tokens, 14);
+        assert_eq!(usage.total_tokens, 28);
+    }
+
+    #[test]
+    fn durations_give_tokens_per_second() {
+        let mut fast = Usage { completion_tokens: 100, ..Default::default() };
+        fast.record_duration(Duration::from_millis(500));
+        assert_eq!(fast.duration_ms, Some(500));
+        assert_eq!(fast.tokens_per_second, Some(200.0));
+
+        let mut slow = Usage { completion_tokens: 50, ..Default::default() };
+        slow.record_duration(Duration::from_millis(1500));
+        let total = fast.clone() + slow;
+        assert_eq!(total.duration_ms, Some(2000));
+        assert_eq!(total.tokens_per_second, Some(75.0));
+
+        let untimed = Usage { completion_tokens: 10, ..Default::default() };
+        assert_eq!((untimed.clone() + untimed).tokens_per_second, None);
+    }
+
+    #[test]
+    fn merge_joins_tool_call_argument_deltas() {
+        let first = payload("", 1, None, Some(vec![tool_call("call_1", "get_weather", "{\"city\":")]));
+        let second = payload("", 1, None, Some(vec![tool_call("", "", "\"Paris\"}")]));
+        let third = payload("", 1, Some("tool_calls"), Some(vec![tool_call("call_2", "get_time", "{}")]));
+
+        let merged = first.merge(second).merge(third);
+        let calls = merged.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, serde_json::json!("{\"city\":\"Paris\"}"));
+        assert_eq!(calls[1].id, "call_2");
+        assert_eq!(merged.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+    }
+
+    fn split(text: &str) -> (Option<String>, String) {
+        let mut content = text.to_string();
+        let reasoning = extract_think_blocks(&mut content);
+        (reasoning, content)
+    }
+
+    #[test]
+    fn complete_blocks_are_extracted() {
+        assert_eq!(split("<think> plan </think>\n\nAnswer"), (Some("plan".into()), "Answer".into()));
+        assert_eq!(
+            split("<THINK>one</THINK>A <think>two</think>B"),
+            (Some("one\n\ntwo".into()), "A B".into())
+        );
+        assert_eq!(split("<think>\n\n</think>\n\nAnswer"), (None, "Answer".into()));
+        assert_eq!(split("  no tags here "), (None, "  no tags here ".into()));
+    }
+
+    #[test]
+    fn truncated_and_split_blocks_are_recovered() {
+        // Cut off mid-thought: everything after the opener is reasoning.
+        assert_eq!(split("Intro <think>still going"), (Some("still going".into()), "Intro".into()));
+        // The opener was in an earlier chunk.
+        assert_eq!(split("rest of plan</think>Answer"), (Some("rest of plan".into()), "Answer".into()));
+    }
+
+    #[test]
+    fn nested_blocks_do_not_end_early() {
+        assert_eq!(
+            split("<think>outer <think>inner</think> tail</think>Answer"),
+            (Some("outer <think>inner</think> tail".into()), "Answer".into())
+        );
+        assert_eq!(split("<think>日本語 😀</think>答え"), (Some("日本語 😀".into()), "答え".into()));
+    }
+
+    #[test]
+    fn reasoning_is_extracted_from_optional_content() {
+        let mut content = Some("<think>plan</think>Answer".to_string());
+        assert_eq!(extract_reasoning(&mut content).as_deref(), Some("plan"));
+        assert_eq!(content.as_deref(), Some("Answer"));
+        assert_eq!(extract_reasoning(&mut None), None);
+    }
+}
@@ -4,6 +4,8 @@ use crate::client::{self, Jitter, RetryPolicy};
 use crate::config;
 use crate::error::LLMCoreError;
 use crate::providers::{
+    self,
+    gemini::{GoogleAdapter, GoogleParser},
     openai::{OpenAIAdapter, OpenAIParser},
     unsupported::{UnsupportedAdapter, UnsupportedParser},
     ProviderAdapter, ResponseParser,
@@ -24,26 +26,32 @@ pub struct Embedder {
 impl Embedder {
     /// Creates a new `Embedder` instance for a specific embedding model.
     pub fn new(model_name: &str, debug: Option<bool>) -> Result<Self, LLMCoreError> {
-        let (provider_name, provider_data, model_details) =
-            config::MODEL_LIBRARY.find_embedder(model_name).ok_or_else(|| {
-                LLMCoreError::ConfigError(format!("Embedder '{}' not found in config", model_name))
-            })?;
+        let (provider_name, provider_data, model_details) = config::MODEL_LIBRARY.find_embedder(model_name)?;
 
         let api_key = config::get_env_var(&provider_data.api_key)?;
-        let base_url = config::get_env_var(&provider_data.base_url)?;
+        providers::check_api_key(provider_name, &api_key)?;
+        let base_url = provider_data.resolve_base_url()?;
 
-        let provider_adapter: Arc<dyn ProviderAdapter> = match provider_name {
-            "OpenAI" => Arc::new(OpenAIAdapter),
-            _ => Arc::new(UnsupportedAdapter {
-                provider_name: provider_name.to_string(),
-            }),
-        };
+        let (provider_adapter, response_parser) = match providers::registered_provider(provider_name) {
+            Some(registered) => registered,
+            None => {
+                let provider_adapter: Arc<dyn ProviderAdapter> = match provider_name {
+                    "OpenAI" => Arc::new(OpenAIAdapter),
+                    "Google" => Arc::new(GoogleAdapter),
+                    _ => Arc::new(UnsupportedAdapter {
+                        provider_name: provider_name.to_string(),
+                    }),
+                };
 
-        let response_parser: Arc<dyn ResponseParser> = match provider_name {
-            "OpenAI" => Arc::new(OpenAIParser),
-            _ => Arc::new(UnsupportedParser {
-                provider_name: provider_name.to_string(),
-            }),
+                let response_parser: Arc<dyn ResponseParser> = match provider_name {
+                    "OpenAI" => Arc::new(OpenAIParser),
+                    "Google" => Arc::new(GoogleParser),
+                    _ => Arc::new(UnsupportedParser {
+                        provider_name: provider_name.to_string(),
+                    }),
+                };
+                (provider_adapter, response_parser)
+            }
         };
 
         if !provider_adapter.supports_embeddings(&model_details.model_tag) {
@@ -77,13 +85,15 @@ impl Embedder {
         let payload = self
             .provider_adapter
             .prepare_embedding_request(&self.model_tag, texts);
-        let headers = self.provider_adapter.get_request_headers(&self.api_key);
+        let headers = self.provider_adapter.get_embedding_headers(&self.api_key);
 
         let provider_name = self.provider_adapter.get_provider_name();
         let provider_config = config::MODEL_LIBRARY
             .providers
             .get(provider_name)
-            .unwrap();
+            .ok_or_else(|| {
+                LLMCoreError::ConfigError(format!("Provider '{}' not found in config", provider_name))
+            })?;
 
         let url = self.provider_adapter.get_embedding_url(
             &self.base_url,
@@ -1,4 +1,7 @@
-use pyo3::{exceptions::PyValueError, PyErr};
+use pyo3::{
+    exceptions::{PyConnectionError, PyPermissionError, PyTimeoutError, PyValueError},
+    PyErr,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,6 +12,9 @@ pub enum LLMCoreError {
     #[error("API call failed with status {status}: {body}")]
     ApiErrorDetailed { status: u16, body: String },
 
+    #[error("Authentication with {provider} failed (status {status}); check its API key: {body}")]
+    AuthenticationFailed { provider: String, status: u16, body: String },
+
     #[error("Failed to parse response from AI: {0}")]
     ResponseParseError(String),
 
@@ -42,6 +48,15 @@ pub enum LLMCoreError {
     #[error("An error occurred during a request: {0}")]
     RequestError(#[from] reqwest::Error),
 
+    #[error("The request timed out: {0}")]
+    Timeout(String),
+
+    #[error("Failed to connect to the API: {0}")]
+    Connect(String),
+
+    #[error("A network error occurred: {0}")]
+    Network(String),
+
     #[error("Vector index error: {0}")]
     HeedError(#[from] heed::Error),
 
@@ -59,11 +74,61 @@ pub enum LLMCoreError {
 
     #[error("Image generation error: {0}")]
     ImageGenerationError(String),
+
+    #[error("The request was blocked by a content filter: {0}")]
+    ContentFiltered(String),
+
+    #[error("The prompt is estimated at {tokens} tokens, which exceeds the model's context window of {limit}")]
+    ContextWindowExceeded { tokens: usize, limit: usize },
+
+    #[error("The document is {size} bytes, which exceeds the limit of {limit} bytes")]
+    ContentTooLarge { size: usize, limit: usize },
 }
 
 impl From<LLMCoreError> for PyErr {
     fn from(err: LLMCoreError) -> PyErr {
-        PyValueError::new_err(err.to_string())
+        match err {
+            LLMCoreError::Timeout(_) => PyTimeoutError::new_err(err.to_string()),
+            LLMCoreError::Connect(_) | LLMCoreError::Network(_) => {
+                PyConnectionError::new_err(err.to_string())
+            }
+            LLMCoreError::AuthenticationFailed { .. } => PyPermissionError::new_err(err.to_string()),
+            _ => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+impl LLMCoreError {
+    /// Classifies a transport-level `reqwest::Error` into `Timeout`, `Connect` or `Network`,
+    /// so callers can decide whether to retry with a longer timeout or fail fast.
+    /// Builder and other non-transport errors are kept as `RequestError`.
+    pub fn from_transport(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            LLMCoreError::Timeout(err.to_string())
+        } else if err.is_connect() {
+            LLMCoreError::Connect(err.to_string())
+        } else if err.is_request() || err.is_body() || err.is_decode() {
+            LLMCoreError::Network(err.to_string())
+        } else {
+            LLMCoreError::RequestError(err)
+        }
+    }
+
+    /// Returns true for transport errors that are worth retrying.
+    pub fn is_retryable_transport(&self) -> bool {
+        matches!(
+            self,
+            LLMCoreError::Timeout(_) | LLMCoreError::Connect(_) | LLMCoreError::Network(_)
+        )
+    }
+
+    /// Returns true when the model or its provider is unavailable (a transport failure,
+    /// rate limit or server error), so another model may still answer.
+    pub fn warrants_fallback(&self) -> bool {
+        match self {
+            LLMCoreError::ApiErrorDetailed { status, .. } => *status == 429 || *status >= 500,
+            other => other.is_retryable_transport(),
+        }
     }
 }
 
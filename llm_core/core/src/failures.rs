@@ -0,0 +1,156 @@
+use crate::error::LLMCoreError;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Stands in for the API key in a saved URL or header; put back on replay.
+pub const REDACTED_KEY: &str = "REDACTED_API_KEY";
+
+// Headers that carry credentials other than the API key itself.
+const SECRET_HEADERS: [&str; 3] = ["authorization", "x-api-key", "x-goog-api-key"];
+
+/// A request that failed, as written by `Orchestra::set_failure_capture`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailedRequest {
+    pub captured_at: DateTime<Utc>,
+    pub model: String,
+    pub provider: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub payload: JsonValue,
+    pub error: String,
+    pub status: Option<u16>,
+    pub response_body: Option<String>,
+}
+
+impl FailedRequest {
+    /// Records a failed request with `api_key` redacted from the URL and headers.
+    pub fn new(
+            model: &str,
+            provider: &str,
+            url: &str,
+            headers: &HeaderMap,
+            payload: JsonValue,
+            error: &LLMCoreError,
+            api_key: &str,
+        ) -> Self {
+        let (status, response_body) = match error {
+            LLMCoreError::ApiErrorDetailed { status, body } | LLMCoreError::AuthenticationFailed { status, body, .. } => {
+                (Some(*status), Some(body.clone()))
+            }
+            _ => (None, None),
+        };
+        FailedRequest {
+            captured_at: Utc::now(),
+            model: model.to_string(),
+            provider: provider.to_string(),
+            url: redact(url, api_key),
+            headers: redact_headers(headers, api_key),
+            payload,
+            error: error.to_string(),
+            status,
+            response_body,
+        }
+    }
+
+    /// Writes the record to a new JSON file in `dir` and returns its path.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, LLMCoreError> {
+        fs::create_dir_all(dir)?;
+        let name = format!("{}-{}.json", self.captured_at.format("%Y%m%dT%H%M%S%.3fZ"), Uuid::new_v4());
+        let path = dir.join(name);
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    /// Reads a record written by `save`.
+    pub fn load(path: &Path) -> Result<Self, LLMCoreError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// The URL and headers to re-send, with `api_key` in place of the redaction marker.
+    pub fn restore(&self, api_key: &str) -> Result<(String, HeaderMap), LLMCoreError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| LLMCoreError::ConfigError(format!("Invalid header name '{}': {}", name, e)))?;
+            let value = if value == REDACTED_KEY && name == header::AUTHORIZATION {
+                format!("Bearer {}", api_key)
+            } else {
+                value.replace(REDACTED_KEY, api_key)
+            };
+            let value = HeaderValue::from_str(&value)
+                .map_err(|e| LLMCoreError::ConfigError(format!("Invalid value for header '{}': {}", name, e)))?;
+            headers.insert(name, value);
+        }
+        Ok((self.url.replace(REDACTED_KEY, api_key), headers))
+    }
+}
+
+/// `headers` as text, with `api_key` and any credential header replaced by `REDACTED_KEY`.
+pub(crate) fn redact_headers(headers: &HeaderMap, api_key: &str) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = value.to_str().unwrap_or_default();
+            let value = if SECRET_HEADERS.contains(&name.as_str()) && (api_key.is_empty() || !value.contains(api_key)) {
+                REDACTED_KEY.to_string()
+            } else {
+                redact(value, api_key)
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// `text` with every occurrence of `api_key` replaced by `REDACTED_KEY`.
+pub(crate) fn redact(text: &str, api_key: &str) -> String {
+    if api_key.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(api_key, REDACTED_KEY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn saved_requests_hold_no_api_key_and_restore_it() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer sk-secret"));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let error = LLMCoreError::ApiErrorDetailed { status: 400, body: "bad request".to_string() };
+        let failed = FailedRequest::new(
+            "GPT 4o",
+            "OpenAI",
+            "https://example.test/v1/chat?key=sk-secret",
+            &headers,
+            json!({"model": "gpt-4o"}),
+            &error,
+            "sk-secret",
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = failed.save(dir.path()).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("sk-secret"));
+        assert!(saved.contains("bad request"));
+
+        let loaded = FailedRequest::load(&path).unwrap();
+        assert_eq!(loaded.status, Some(400));
+        assert_eq!(loaded.payload, json!({"model": "gpt-4o"}));
+        let (url, headers) = loaded.restore("sk-other").unwrap();
+        assert_eq!(url, "https://example.test/v1/chat?key=sk-other");
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer sk-other");
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+    }
+}
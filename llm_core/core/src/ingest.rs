@@ -1,90 +1,316 @@
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use pyo3::prelude::*;
-use pyo3::types::PyModule;
+use pyo3::types::{PyDict, PyModule};
 use tokio::task;
 use futures::{stream, StreamExt};
 use serde::Deserialize;
 
+use crate::client;
 use crate::error::LLMCoreError;
 use crate::vector::{KnowledgeBase, DocumentSource};
 use crate::orchestra::Orchestra;
-use crate::datam::{format_user_message};
+use crate::datam::{format_system_message, format_user_message, truncate_at_boundary, truncate_chars};
 use crate::lucky::{SimpleSchema, SchemaProperty};
 
+/// Built-in prompt used to enrich each chunk. `{chunk}` is replaced with the chunk text.
+pub const ENRICHMENT_PROMPT: &str = r#"Please analyze the following text chunk and provide a concise title and a detailed summary.
+
+            Text to analyze:
+            ---
+            {chunk}
+            ---"#;
+
+/// Shorter prompt used once after the full prompt has failed every attempt.
+pub const SIMPLE_ENRICHMENT_PROMPT: &str = r#"Give this text a short title and a one-paragraph summary.
+
+{chunk}"#;
+
+const DEFAULT_ENRICHMENT_RETRIES: u32 = 2;
+const DEFAULT_CONCURRENCY: usize = 5;
+/// How long fetching and converting one URL may take.
+pub const DEFAULT_URL_TIMEOUT: Duration = Duration::from_secs(120);
+/// The largest document (downloaded file and converted markdown) that is ingested.
+pub const DEFAULT_MAX_CONTENT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Field names the enrichment schema and chunk metadata already use.
+const RESERVED_ENRICHMENT_FIELDS: [&str; 3] = ["title", "summary", "source"];
+
 #[derive(Deserialize)]
 struct EnrichedContent {
     title: String,
     summary: String,
+    /// Values for the extra enrichment fields, stored in the chunk metadata.
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl EnrichedContent {
+    /// Title and summary taken from the chunk itself, used when enrichment fails.
+    fn fallback(chunk: &str) -> Self {
+        let title = chunk
+            .lines()
+            .map(|line| line.trim().trim_start_matches('#').trim())
+            .find(|line| !line.is_empty())
+            .unwrap_or("Untitled");
+        Self {
+            title: truncate_chars(title, 80).to_string(),
+            summary: truncate_chars(chunk, 300).to_string(),
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+/// How a chunk's enrichment went: the content plus how much retrying it took.
+struct ChunkEnrichment {
+    content: EnrichedContent,
+    retries: u32,
+    used_simple_prompt: bool,
+    used_fallback: bool,
+}
+
+/// What an ingest does when the knowledge base already holds the URL (or file path).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Leave the stored document alone and ingest nothing.
+    Skip,
+    /// Delete the stored chunks and ingest the document again.
+    Replace,
+    /// Fail with a `ConfigError` before anything is fetched.
+    #[default]
+    Error,
+}
+
+/// A summary of one ingestion run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestReport {
+    pub chunk_count: usize,
+    /// Set when the document was already stored and `OnConflict::Skip` left it alone.
+    pub skipped: bool,
+    /// Chunks of an earlier ingest removed by `OnConflict::Replace`.
+    pub replaced_chunk_count: usize,
+    /// Enrichment calls made after a chunk's first attempt failed, including the
+    /// simpler-prompt attempt.
+    pub enrichment_retries: u32,
+    /// Chunks enriched only by the simpler prompt.
+    pub simple_prompt_count: usize,
+    /// Chunks stored with a title and summary taken from the text itself.
+    pub fallback_count: usize,
+}
+
+/// Waits for `fut` for at most `timeout`, failing with `Timeout` naming `source`.
+async fn within_timeout<F: std::future::Future>(fut: F, timeout: Duration, source: &str) -> Result<F::Output, LLMCoreError> {
+    tokio::time::timeout(timeout, fut).await.map_err(|_| {
+        LLMCoreError::Timeout(format!("Extracting '{}' took longer than {:?}", source, timeout))
+    })
+}
+
+/// Rejects converted text larger than `limit` bytes.
+fn check_content_size(content: String, limit: usize) -> Result<String, LLMCoreError> {
+    if content.len() > limit {
+        return Err(LLMCoreError::ContentTooLarge { size: content.len(), limit });
+    }
+    Ok(content)
+}
+
+/// Builds the `enrich_content` schema: the required title and summary plus any extra fields.
+fn enrichment_schema(extra_fields: Vec<SchemaProperty>) -> Result<SimpleSchema, LLMCoreError> {
+    if let Some(field) = extra_fields.iter().find(|f| RESERVED_ENRICHMENT_FIELDS.contains(&f.name.as_str())) {
+        return Err(LLMCoreError::ConfigError(format!(
+            "Extra enrichment field '{}' clashes with a built-in field ({}).",
+            field.name,
+            RESERVED_ENRICHMENT_FIELDS.join(", ")
+        )));
+    }
+
+    let mut properties = vec![
+        SchemaProperty {
+            name: "title".to_string(),
+            property_type: "string".to_string(),
+            description: "A concise title for the text chunk.".to_string(),
+            items: None,
+        },
+        SchemaProperty {
+            name: "summary".to_string(),
+            property_type: "string".to_string(),
+            description: "A detailed summary of the text chunk.".to_string(),
+            items: None,
+        },
+    ];
+    properties.extend(extra_fields);
+    Ok(SimpleSchema {
+        name: "enrich_content".to_string(),
+        description: "A title and summary for a chunk of text.".to_string(),
+        properties,
+    })
 }
 
 pub struct Ingestor {
     kb: KnowledgeBase,
     orchestra: Arc<Orchestra>,
+    system_prompt: Option<Arc<String>>,
+    enrichment_retries: u32,
+    concurrency: usize,
+    url_timeout: Duration,
+    max_content_bytes: usize,
+    on_conflict: OnConflict,
 }
 
 impl Ingestor {
+    /// Creates an ingestor. `extra_fields` are requested from the enrichment model next
+    /// to the title and summary (e.g. keywords, document type) and stored in each
+    /// chunk's metadata; they may not be named `title`, `summary` or `source`.
     pub fn new(
             db_path: &Path,
             index_path: &Path,
             embedding_model: &str,
             enrichment_model: &str,
+            system_prompt_override: Option<String>,
+            extra_fields: Option<Vec<SchemaProperty>>,
         ) -> Result<Self, LLMCoreError> {
         let kb = KnowledgeBase::new(db_path, index_path, embedding_model)?;
         
-        let schema = SimpleSchema {
-            name: "enrich_content".to_string(),
-            description: "A title and summary for a chunk of text.".to_string(),
-            properties: vec![
-                SchemaProperty {
-                    name: "title".to_string(),
-                    property_type: "string".to_string(),
-                    description: "A concise title for the text chunk.".to_string(),
-                    items: None,
-                },
-                SchemaProperty {
-                    name: "summary".to_string(),
-                    property_type: "string".to_string(),
-                    description: "A detailed summary of the text chunk.".to_string(),
-                    items: None,
-                },
-            ],
-        };
+        let schema = enrichment_schema(extra_fields.unwrap_or_default())?;
 
         let orchestra = Arc::new(Orchestra::new(enrichment_model, None, None, Some(schema), None, None)?);
-        Ok(Self { kb, orchestra })
+        Ok(Self {
+            kb,
+            orchestra,
+            system_prompt: system_prompt_override.map(Arc::new),
+            enrichment_retries: DEFAULT_ENRICHMENT_RETRIES,
+            concurrency: DEFAULT_CONCURRENCY,
+            url_timeout: DEFAULT_URL_TIMEOUT,
+            max_content_bytes: DEFAULT_MAX_CONTENT_BYTES,
+            on_conflict: OnConflict::default(),
+        })
     }
 
-    pub async fn ingest_from_url(&self, url: &str, source_tag: &str) -> Result<(), LLMCoreError> {
-        let markdown_content = self.extract_content_from_url(url).await?;
-        let documents = self.process_markdown(markdown_content, url, source_tag).await?;
-        self.kb.add_documents_and_build(documents).await?;
+    /// Sets how many times a failed enrichment call is retried with the full prompt
+    /// (default 2). After that, one attempt is made with `SIMPLE_ENRICHMENT_PROMPT`
+    /// before the chunk falls back to a title and summary taken from its own text.
+    pub fn set_enrichment_retries(&mut self, retries: u32) {
+        self.enrichment_retries = retries;
+    }
+
+    /// Sets how many chunks are enriched at once (default 5).
+    pub fn set_concurrency(&mut self, concurrency: usize) -> Result<(), LLMCoreError> {
+        if concurrency == 0 {
+            return Err(LLMCoreError::ConfigError("Ingestor concurrency must be at least 1.".to_string()));
+        }
+        self.concurrency = concurrency;
         Ok(())
     }
 
-    pub async fn ingest_from_file(&self, file_path: &Path, source_tag: &str) -> Result<(), LLMCoreError> {
-        let markdown_content = self.extract_content_from_file(file_path).await?;
-        let documents = self.process_markdown(markdown_content, &file_path.to_string_lossy(), source_tag).await?;
-        self.kb.add_documents_and_build(documents).await?;
+    /// Sets how long fetching and converting a URL may take (default 120 seconds) before
+    /// `ingest_from_url` fails with `Timeout`. The conversion cannot be interrupted, so it
+    /// finishes in the background and its result is discarded.
+    pub fn set_url_timeout(&mut self, timeout: Duration) -> Result<(), LLMCoreError> {
+        if timeout.is_zero() {
+            return Err(LLMCoreError::ConfigError("The URL timeout must be greater than zero.".to_string()));
+        }
+        self.url_timeout = timeout;
+        Ok(())
+    }
+
+    /// Sets the largest document that is ingested (default 20 MiB). Larger downloads are
+    /// refused by the converter and larger converted text fails with `ContentTooLarge`
+    /// before any chunk is enriched.
+    pub fn set_max_content_bytes(&mut self, max_bytes: usize) -> Result<(), LLMCoreError> {
+        if max_bytes == 0 {
+            return Err(LLMCoreError::ConfigError("The content size limit must be greater than zero.".to_string()));
+        }
+        self.max_content_bytes = max_bytes;
         Ok(())
     }
 
+    /// Sets what happens when the URL or file being ingested is already stored (default
+    /// `OnConflict::Error`). `Replace` only removes the old chunks once the new ones are
+    /// enriched, so a failed re-ingest keeps the stored version.
+    pub fn set_on_conflict(&mut self, on_conflict: OnConflict) {
+        self.on_conflict = on_conflict;
+    }
+
+    /// Ingests one URL as a single unit of in-flight work (see `client::Shutdown`): it is
+    /// refused once a drain has begun, and finished if already under way.
+    pub async fn ingest_from_url(&self, url: &str, source_tag: &str) -> Result<IngestReport, LLMCoreError> {
+        client::in_flight(async {
+            if self.skip_existing(url)? {
+                return Ok(IngestReport { skipped: true, ..Default::default() });
+            }
+            let markdown_content = self.extract_content_from_url(url).await?;
+            let (documents, report) = self.process_markdown(markdown_content, url, source_tag).await?;
+            self.store(url, documents, report).await
+        })
+        .await?
+    }
+
+    /// Ingests one file as a single unit of in-flight work, like `ingest_from_url`.
+    pub async fn ingest_from_file(&self, file_path: &Path, source_tag: &str) -> Result<IngestReport, LLMCoreError> {
+        client::in_flight(async {
+            let url = file_path.to_string_lossy();
+            if self.skip_existing(&url)? {
+                return Ok(IngestReport { skipped: true, ..Default::default() });
+            }
+            let markdown_content = check_content_size(self.extract_content_from_file(file_path).await?, self.max_content_bytes)?;
+            let (documents, report) = self.process_markdown(markdown_content, &url, source_tag).await?;
+            self.store(&url, documents, report).await
+        })
+        .await?
+    }
+
+    /// Applies `on_conflict` before an ingest: returns `true` if the stored document
+    /// should be kept and nothing ingested, and fails under `OnConflict::Error`.
+    fn skip_existing(&self, url: &str) -> Result<bool, LLMCoreError> {
+        if !self.kb.contains_source(url)? {
+            return Ok(false);
+        }
+        match self.on_conflict {
+            OnConflict::Skip => Ok(true),
+            OnConflict::Replace => Ok(false),
+            OnConflict::Error => Err(LLMCoreError::ConfigError(format!(
+                "'{}' is already in the knowledge base; set `on_conflict` to skip or replace it.",
+                url
+            ))),
+        }
+    }
+
+    /// Stores freshly enriched `documents`, first removing the chunks of an earlier
+    /// ingest of `url` when replacing.
+    async fn store(
+            &self,
+            url: &str,
+            documents: Vec<DocumentSource>,
+            mut report: IngestReport,
+        ) -> Result<IngestReport, LLMCoreError> {
+        if self.on_conflict == OnConflict::Replace {
+            report.replaced_chunk_count = self.kb.remove_source(url)?;
+        }
+        self.kb.add_documents_and_build(documents).await?;
+        Ok(report)
+    }
+
     async fn extract_content_from_url(&self, url: &str) -> Result<String, LLMCoreError> {
-        let url = url.to_string();
-        task::spawn_blocking(move || {
+        let source = url.to_string();
+        let max_file_size = self.max_content_bytes;
+        let conversion = task::spawn_blocking(move || {
             Python::with_gil(|py| {
                 let converter_class = PyModule::import(py, "docling.document_converter")?
                     .getattr("DocumentConverter")?;
                 let converter = converter_class.call0()?;
-                let result = converter.call_method1("convert", (url,))?;
+                let kwargs = PyDict::new(py);
+                kwargs.set_item("max_file_size", max_file_size)?;
+                let result = converter.call_method("convert", (source,), Some(&kwargs))?;
                 let document = result.getattr("document")?;
                 let markdown = document.call_method0("export_to_markdown")?;
                 markdown.extract()
             })
-        })
-        .await
-        .map_err(|e| LLMCoreError::PythonError(e.to_string()))? // Handles JoinError
-        .map_err(|e: PyErr| LLMCoreError::PythonError(e.to_string())) // Handles PyErr
+        });
+        let markdown = within_timeout(conversion, self.url_timeout, url)
+            .await?
+            .map_err(|e| LLMCoreError::PythonError(e.to_string()))? // Handles JoinError
+            .map_err(|e: PyErr| LLMCoreError::PythonError(e.to_string()))?; // Handles PyErr
+        check_content_size(markdown, self.max_content_bytes)
     }
 
     async fn extract_content_from_file(&self, file_path: &Path) -> Result<String, LLMCoreError> {
@@ -105,56 +331,115 @@ impl Ingestor {
         .map_err(|e: PyErr| LLMCoreError::PythonError(e.to_string())) // Handles PyErr
     }
 
-    async fn process_markdown(&self, markdown: String, url: &str, source_tag: &str) -> Result<Vec<DocumentSource>, LLMCoreError> {
+    async fn process_markdown(
+            &self,
+            markdown: String,
+            url: &str,
+            source_tag: &str,
+        ) -> Result<(Vec<DocumentSource>, IngestReport), LLMCoreError> {
         let chunks = chunk_text(&markdown, 4000);
-        const CONCURRENT_REQUESTS: usize = 5;
-        
+        let retries = self.enrichment_retries;
+
         let documents_futures = chunks.into_iter().enumerate().map(|(i, chunk)| {
             let url = url.to_string();
             let source_tag = source_tag.to_string();
             let orchestra = Arc::clone(&self.orchestra);
+            let system_prompt = self.system_prompt.clone();
             
-            tokio::spawn(async move {
-                let enriched = Ingestor::enrich_chunk(&orchestra, &chunk).await?;
-                
-                Ok(DocumentSource {
+            tokio::spawn(client::carry_in_flight(async move {
+                let enrichment = Ingestor::enrich_with_retries(
+                    &orchestra, system_prompt.as_deref().map(String::as_str), &chunk, i + 1, retries,
+                ).await?;
+
+                let mut metadata = enrichment.content.extra;
+                metadata.insert("source".to_string(), serde_json::Value::String(source_tag));
+                let document = DocumentSource {
                     url,
                     chunk_number: (i + 1) as i32,
-                    title: enriched.title,
-                    summary: enriched.summary,
+                    title: enrichment.content.title,
+                    summary: enrichment.content.summary,
                     content: chunk,
-                    metadata: serde_json::json!({ "source": source_tag }),
-                })
-            })
+                    metadata: serde_json::Value::Object(metadata),
+                };
+                Ok::<_, LLMCoreError>((document, enrichment.retries, enrichment.used_simple_prompt, enrichment.used_fallback))
+            }))
         });
 
         let stream = stream::iter(documents_futures);
-        let results: Vec<_> = stream.buffer_unordered(CONCURRENT_REQUESTS).collect().await;
+        let results: Vec<_> = stream.buffer_unordered(self.concurrency).collect().await;
 
         let mut documents = Vec::new();
+        let mut report = IngestReport::default();
         for result in results {
-            match result {
-                Ok(Ok(doc)) => documents.push(doc),
-                Ok(Err(e)) => return Err(e),
-                Err(e) => return Err(LLMCoreError::ConcurrencyError(e.to_string())),
-            }
+            let (doc, retries, used_simple_prompt, used_fallback) =
+                result.map_err(|e| LLMCoreError::ConcurrencyError(e.to_string()))??;
+            documents.push(doc);
+            report.chunk_count += 1;
+            report.enrichment_retries += retries;
+            report.simple_prompt_count += used_simple_prompt as usize;
+            report.fallback_count += used_fallback as usize;
         }
-        
-        Ok(documents)
+
+        Ok((documents, report))
     }
 
-    async fn enrich_chunk(orchestra: &Arc<Orchestra>, chunk: &str) -> Result<EnrichedContent, LLMCoreError> {
-        let prompt = format!(
-            r#"Please analyze the following text chunk and provide a concise title and a detailed summary.
+    /// Enriches a chunk, retrying with the full prompt up to `retries` times, then once
+    /// with the simpler prompt, and finally falling back to the chunk's own text. A
+    /// `ConcurrencyError` (a shutdown drain) is returned rather than retried or papered over.
+    async fn enrich_with_retries(
+            orchestra: &Arc<Orchestra>,
+            system_prompt: Option<&str>,
+            chunk: &str,
+            chunk_number: usize,
+            retries: u32,
+        ) -> Result<ChunkEnrichment, LLMCoreError> {
+        let mut last_error = None;
+        for attempt in 0..=retries {
+            match Ingestor::enrich_chunk(orchestra, system_prompt, chunk, ENRICHMENT_PROMPT).await {
+                Ok(content) => {
+                    return Ok(ChunkEnrichment { content, retries: attempt, used_simple_prompt: false, used_fallback: false });
+                }
+                Err(e @ LLMCoreError::ConcurrencyError(_)) => return Err(e),
+                Err(e) => last_error = Some(e),
+            }
+        }
 
-            Text to analyze:
-            ---
-            {}
-            ---"#,
-            chunk
-        );
+        let retries = retries + 1;
+        match Ingestor::enrich_chunk(orchestra, system_prompt, chunk, SIMPLE_ENRICHMENT_PROMPT).await {
+            Ok(content) => Ok(ChunkEnrichment { content, retries, used_simple_prompt: true, used_fallback: false }),
+            Err(e @ LLMCoreError::ConcurrencyError(_)) => Err(e),
+            Err(e) => {
+                eprintln!(
+                    "[WARNING] Enrichment failed for chunk {} ({}; simple prompt: {}). Using a title and summary from the text.",
+                    chunk_number,
+                    last_error.map_or_else(String::new, |e| e.to_string()),
+                    e
+                );
+                Ok(ChunkEnrichment {
+                    content: EnrichedContent::fallback(chunk),
+                    retries,
+                    used_simple_prompt: false,
+                    used_fallback: true,
+                })
+            }
+        }
+    }
 
-        let messages = vec![format_user_message(prompt)];
+    async fn enrich_chunk(
+            orchestra: &Arc<Orchestra>,
+            system_prompt: Option<&str>,
+            chunk: &str,
+            prompt_template: &str,
+        ) -> Result<EnrichedContent, LLMCoreError> {
+        let prompt = prompt_template.replace("{chunk}", chunk);
+
+        // The custom system prompt sets tone/language; the user prompt and the
+        // `enrich_content` schema still define what must be returned.
+        let mut messages = Vec::new();
+        if let Some(system_prompt) = system_prompt {
+            messages.push(format_system_message(system_prompt.to_string()));
+        }
+        messages.push(format_user_message(prompt));
         let response = orchestra.call_ai(messages).await?;
 
         let content = response.choices.get(0)
@@ -176,7 +461,12 @@ fn chunk_text(text: &str, chunk_size: usize) -> Vec<String> {
     let text_len = text.len();
 
     while current_pos < text_len {
-        let end_pos = std::cmp::min(current_pos + chunk_size, text_len);
+        let remaining = &text[current_pos..];
+        let mut end_pos = current_pos + truncate_at_boundary(remaining, chunk_size).len();
+        if end_pos == current_pos {
+            // `chunk_size` is smaller than the next character; take it whole.
+            end_pos += remaining.chars().next().map_or(0, char::len_utf8);
+        }
         let mut chunk_end = end_pos;
 
         if end_pos < text_len {
@@ -204,4 +494,63 @@ fn chunk_text(text: &str, chunk_size: usize) -> Vec<String> {
     }
 
     chunks
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunking_handles_multi_byte_text() {
+        let text = "これは日本語の文章です。😀 Emoji and ASCII mixed together. ".repeat(20);
+        let chunks = chunk_text(&text, 50);
+        assert!(chunks.len() > 1);
+        let rejoined: String = chunks.concat();
+        assert_eq!(rejoined.replace(' ', ""), text.replace(' ', ""));
+
+        assert_eq!(chunk_text("😀😀", 1), vec!["😀", "😀"]);
+    }
+
+    #[tokio::test]
+    async fn slow_and_oversized_content_is_refused() {
+        let slow = tokio::time::sleep(Duration::from_secs(5));
+        let err = within_timeout(slow, Duration::from_millis(20), "https://example.com").await.unwrap_err();
+        assert!(matches!(err, LLMCoreError::Timeout(msg) if msg.contains("https://example.com")));
+        assert_eq!(within_timeout(async { 7 }, Duration::from_secs(1), "x").await.unwrap(), 7);
+
+        assert_eq!(check_content_size("short".to_string(), 5).unwrap(), "short");
+        assert!(matches!(
+            check_content_size("too long".to_string(), 5),
+            Err(LLMCoreError::ContentTooLarge { size: 8, limit: 5 })
+        ));
+    }
+
+    #[test]
+    fn fallback_enrichment_comes_from_the_chunk() {
+        let chunk = format!("\n## Installing the CLI\n\n{}", "Run the installer. ".repeat(30));
+        let fallback = EnrichedContent::fallback(&chunk);
+        assert_eq!(fallback.title, "Installing the CLI");
+        assert_eq!(fallback.summary.chars().count(), 300);
+
+        assert_eq!(EnrichedContent::fallback("   ").title, "Untitled");
+    }
+
+    #[test]
+    fn extra_enrichment_fields_extend_the_schema() {
+        let field = |name: &str| SchemaProperty {
+            name: name.to_string(),
+            property_type: "string".to_string(),
+            description: String::new(),
+            items: None,
+        };
+        let schema = enrichment_schema(vec![field("document_type")]).unwrap();
+        let names: Vec<_> = schema.properties.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["title", "summary", "document_type"]);
+        assert!(matches!(enrichment_schema(vec![field("source")]), Err(LLMCoreError::ConfigError(_))));
+
+        let enriched: EnrichedContent =
+            serde_json::from_str(r#"{"title": "T", "summary": "S", "document_type": "guide"}"#).unwrap();
+        assert_eq!(enriched.extra.get("document_type"), Some(&serde_json::json!("guide")));
+        assert!(!enriched.extra.contains_key("title"));
+    }
+}
@@ -0,0 +1,232 @@
+// llm-core/src/jobs.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::datam::Usage;
+use crate::error::LLMCoreError;
+use crate::orchestra::Orchestra;
+use crate::sorter::{Sorter, SortingInstructions};
+
+/// The output of a finished sorting job: sorted items by category, the final category
+/// list, the accumulated usage and the number of items processed.
+pub type SortingJobOutput = (BTreeMap<String, Vec<String>>, Vec<String>, Usage, usize);
+
+/// The lifecycle state of a background job.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed(String),
+}
+
+/// Counters updated by the running task so callers can poll progress.
+#[derive(Debug, Default)]
+pub struct JobProgress {
+    total: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+impl JobProgress {
+    pub fn set_total(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn increment(&self) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns `(completed, total)`.
+    pub fn snapshot(&self) -> (usize, usize) {
+        (self.completed.load(Ordering::Relaxed), self.total.load(Ordering::Relaxed))
+    }
+}
+
+struct JobState {
+    status: JobStatus,
+    progress: Arc<JobProgress>,
+    result: Option<SortingJobOutput>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// A point-in-time view of a job, returned by `job_info`.
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: Uuid,
+    pub status: JobStatus,
+    pub completed: usize,
+    pub total: usize,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// In-memory registry of all jobs started in this process, keyed by job id.
+static JOBS: Lazy<Mutex<HashMap<Uuid, Arc<Mutex<JobState>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A handle to a job running in the background.
+///
+/// The handle can be polled (`status`, `progress`) or awaited (`wait`). Dropping it does
+/// not cancel the job; its state and result stay available through `job_info` and
+/// `take_job_result` using the job id.
+pub struct JobHandle {
+    id: Uuid,
+    state: Arc<Mutex<JobState>>,
+    task: JoinHandle<Result<SortingJobOutput, LLMCoreError>>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state.lock().unwrap().status.clone()
+    }
+
+    /// Returns `(completed, total)` items.
+    pub fn progress(&self) -> (usize, usize) {
+        self.state.lock().unwrap().progress.snapshot()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the job to complete and returns its output.
+    pub async fn wait(self) -> Result<SortingJobOutput, LLMCoreError> {
+        self.task.await?
+    }
+}
+
+/// Returns a snapshot of the job's state, or `None` if the id is unknown.
+pub fn job_info(id: Uuid) -> Option<JobInfo> {
+    let jobs = JOBS.lock().unwrap();
+    let state = jobs.get(&id)?.lock().unwrap();
+    let (completed, total) = state.progress.snapshot();
+    Some(JobInfo {
+        id,
+        status: state.status.clone(),
+        completed,
+        total,
+        created_at: state.created_at,
+        finished_at: state.finished_at,
+    })
+}
+
+/// Removes a finished job from the registry and returns its outcome: the output, or an
+/// error carrying the message it failed with. Returns `None`, keeping the job, if the
+/// job is unknown or has not finished yet.
+pub fn take_job_result(id: Uuid) -> Option<Result<SortingJobOutput, LLMCoreError>> {
+    let mut jobs = JOBS.lock().unwrap();
+    let outcome = {
+        let mut state = jobs.get(&id)?.lock().unwrap();
+        match state.status.clone() {
+            JobStatus::Completed => Ok(state.result.take()?),
+            JobStatus::Failed(message) => {
+                Err(LLMCoreError::ChatError(format!("Sorting job {} failed: {}", id, message)))
+            }
+            JobStatus::Pending | JobStatus::Running => return None,
+        }
+    };
+    jobs.remove(&id);
+    Some(outcome)
+}
+
+/// Forgets a job. Finished jobs stay in memory until removed or their result is taken.
+pub fn remove_job(id: Uuid) -> bool {
+    JOBS.lock().unwrap().remove(&id).is_some()
+}
+
+/// Starts a sorting task in the background. Must be called from within a Tokio runtime.
+pub(crate) fn spawn_sorting_job(
+        orchestra: Arc<Orchestra>,
+        sorting_instructions: SortingInstructions,
+        input_path: Option<PathBuf>,
+        items_list: Option<Vec<String>>,
+        output_path: Option<PathBuf>,
+        swarm_size: usize,
+        debug: bool,
+    ) -> JobHandle {
+    let id = Uuid::new_v4();
+    let progress = Arc::new(JobProgress::default());
+    let state = Arc::new(Mutex::new(JobState {
+        status: JobStatus::Pending,
+        progress: Arc::clone(&progress),
+        result: None,
+        created_at: Utc::now(),
+        finished_at: None,
+    }));
+    JOBS.lock().unwrap().insert(id, Arc::clone(&state));
+
+    let task_state = Arc::clone(&state);
+    let task = tokio::spawn(async move {
+        task_state.lock().unwrap().status = JobStatus::Running;
+
+        let result = async {
+            let mut sorter = Sorter::new(orchestra, sorting_instructions, output_path, None, debug)?
+                .with_progress(progress);
+            sorter.run(input_path, items_list, swarm_size).await
+        }
+        .await;
+
+        let mut state = task_state.lock().unwrap();
+        state.finished_at = Some(Utc::now());
+        match &result {
+            Ok(output) => {
+                state.status = JobStatus::Completed;
+                state.result = Some(output.clone());
+            }
+            Err(e) => state.status = JobStatus::Failed(e.to_string()),
+        }
+        result
+    });
+
+    JobHandle { id, state, task }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Registers a finished job directly, without running a sorter.
+    fn finished_job(status: JobStatus, result: Option<SortingJobOutput>) -> Uuid {
+        let id = Uuid::new_v4();
+        let state = JobState {
+            status,
+            progress: Arc::new(JobProgress::default()),
+            result,
+            created_at: Utc::now(),
+            finished_at: Some(Utc::now()),
+        };
+        JOBS.lock().unwrap().insert(id, Arc::new(Mutex::new(state)));
+        id
+    }
+
+    #[test]
+    fn taking_a_result_removes_the_job_either_way() {
+        let output: SortingJobOutput = (BTreeMap::new(), vec!["animal".to_string()], Usage::default(), 0);
+        let completed = finished_job(JobStatus::Completed, Some(output));
+        assert_eq!(take_job_result(completed).unwrap().unwrap().1, vec!["animal".to_string()]);
+        assert!(job_info(completed).is_none());
+
+        let failed = finished_job(JobStatus::Failed("no input".to_string()), None);
+        let err = take_job_result(failed).unwrap().unwrap_err();
+        assert!(err.to_string().contains("no input"));
+        assert!(job_info(failed).is_none());
+        assert!(take_job_result(failed).is_none());
+
+        let running = finished_job(JobStatus::Running, None);
+        assert!(take_job_result(running).is_none());
+        assert!(remove_job(running));
+    }
+}
@@ -8,7 +8,9 @@ pub mod convo;
 pub mod datam;
 pub mod embed;
 pub mod error;
+pub mod failures;
 pub mod ingest;
+pub mod jobs;
 pub mod lucky;
 pub mod modes;
 pub mod orchestra;
@@ -16,6 +18,7 @@ pub mod providers;
 pub mod retrieval;
 pub mod sorter;
 pub mod tools;
+pub mod trace;
 pub mod usage;
 pub mod vector;
 
@@ -32,8 +35,15 @@ fn _llm_core(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<bindings::python_b::PySchemaProperty>()?;
     m.add_class::<bindings::python_b::PySchemaItems>()?;
     m.add_class::<bindings::python_b::PySortingInstructions>()?;
+    m.add_class::<bindings::python_b::PyCategoryDefinition>()?;
     m.add_class::<bindings::python_b::PyKnowledgeBase>()?;
     m.add_class::<bindings::python_b::PyIngestor>()?;
     m.add_function(wrap_pyfunction!(bindings::python_b::run_sorter, m)?)?;
+    m.add_function(wrap_pyfunction!(bindings::python_b::sort_records, m)?)?;
+    m.add_function(wrap_pyfunction!(bindings::python_b::extract, m)?)?;
+    m.add_function(wrap_pyfunction!(bindings::python_b::set_provider_concurrency_limit, m)?)?;
+    m.add_function(wrap_pyfunction!(bindings::python_b::set_model_alias, m)?)?;
+    m.add_function(wrap_pyfunction!(bindings::python_b::begin_drain, m)?)?;
+    m.add_function(wrap_pyfunction!(bindings::python_b::wait_idle, m)?)?;
     Ok(())
 }
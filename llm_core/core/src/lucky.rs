@@ -4,6 +4,11 @@ use std::collections::HashMap;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
+use crate::error::LLMCoreError;
+
+/// The JSON Schema types a `SchemaProperty` (or its array items) may declare.
+pub const JSON_SCHEMA_TYPES: [&str; 6] = ["string", "number", "integer", "boolean", "array", "object"];
+
 /// Represents a simplified, serializable JSON schema for guiding model responses.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SimpleSchema {
@@ -12,6 +17,29 @@ pub struct SimpleSchema {
     pub properties: Vec<SchemaProperty>,
 }
 
+impl SimpleSchema {
+    /// Checks that every property (and array item) type is a JSON Schema type, so a typo
+    /// such as Lucky's `"str"` fails at setup instead of as a provider error.
+    pub fn validate(&self) -> Result<(), LLMCoreError> {
+        for property in &self.properties {
+            let item_type = property.items.as_ref().map(|items| items.item_type.as_str());
+            for (label, value) in [("type", Some(property.property_type.as_str())), ("items type", item_type)] {
+                if let Some(value) = value.filter(|v| !JSON_SCHEMA_TYPES.contains(v)) {
+                    return Err(LLMCoreError::ConfigError(format!(
+                        "Schema '{}': property '{}' has invalid {} '{}'; expected one of {}.",
+                        self.name,
+                        property.name,
+                        label,
+                        value,
+                        JSON_SCHEMA_TYPES.join(", ")
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Defines a single property within a SimpleSchema.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SchemaProperty {
@@ -95,6 +123,54 @@ fn polish_json_values(value: JsonValue) -> JsonValue {
     }
 }
 
+/// Coerces tool-call arguments towards the JSON schema a tool declared: string-encoded
+/// numbers and booleans become the declared type, stringified objects and arrays are
+/// parsed, and numbers or booleans given for a string become text. Values that cannot
+/// be converted are left as they are for the tool to reject.
+pub fn coerce_to_schema(value: JsonValue, schema: &JsonValue) -> JsonValue {
+    // `"type"` may be a list such as `["integer", "null"]`; the first non-null type wins.
+    let declared = match &schema["type"] {
+        JsonValue::String(t) => Some(t.as_str()),
+        JsonValue::Array(types) => types.iter().filter_map(JsonValue::as_str).find(|t| *t != "null"),
+        _ => None,
+    };
+    match (declared, value) {
+        (Some("object" | "array"), JsonValue::String(s)) => match serde_json::from_str::<JsonValue>(&s) {
+            Ok(parsed @ (JsonValue::Object(_) | JsonValue::Array(_))) => coerce_to_schema(parsed, schema),
+            _ => JsonValue::String(s),
+        },
+        (Some("integer" | "number" | "boolean"), JsonValue::String(s)) => {
+            let polished = polish_json_values(JsonValue::String(s.trim().to_string()));
+            let matches = match declared {
+                Some("integer") => polished.is_i64() || polished.is_u64(),
+                Some("number") => polished.is_number(),
+                _ => polished.is_boolean(),
+            };
+            if matches { polished } else { JsonValue::String(s) }
+        }
+        (Some("string"), value @ (JsonValue::Number(_) | JsonValue::Bool(_))) => JsonValue::String(value.to_string()),
+        (_, JsonValue::Object(map)) => JsonValue::Object(
+            map.into_iter()
+                .map(|(k, v)| {
+                    let v = match schema["properties"].get(&k) {
+                        Some(property) => coerce_to_schema(v, property),
+                        None => v,
+                    };
+                    (k, v)
+                })
+                .collect(),
+        ),
+        (_, JsonValue::Array(items)) => match schema.get("items") {
+            Some(item_schema) => JsonValue::Array(items.into_iter().map(|v| coerce_to_schema(v, item_schema)).collect()),
+            None => JsonValue::Array(items),
+        },
+        (_, value) => value,
+    }
+}
+
+/// The key delimiter used by Lucky mode unless the `Orchestra` is configured otherwise.
+pub const DEFAULT_DELIMITER: &str = "###";
+
 /// Recursively wraps the keys of a JSON-like structure with delimiters.
 /// This prepares the template for the LLM.
 fn wrap_with_delimiters_recursive(
@@ -281,6 +357,8 @@ pub fn parse_lucky_response(
     // Now, parse the cleaned, standard JSON.
     match serde_json::from_str::<JsonValue>(&cleaned_json_text) {
         Ok(standard_json) => {
+            // Weaker models drift on key spelling; map near-miss keys back to the schema's.
+            let standard_json = align_keys_to_format(standard_json, output_format);
             // Validate the structure against the original format.
             match validate_structure(&standard_json, output_format) {
                 Ok(_) => Ok(polish_json_values(standard_json)),
@@ -354,6 +432,56 @@ fn validate_and_convert_type(field_text: &str, format_str: &str) -> Result<JsonV
     }
 }
 
+/// Lowercases a key and drops all whitespace, so "Tool Name " matches "toolname".
+fn normalize_key(key: &str) -> String {
+    key.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Recursively renames keys that don't exactly match the format to the format key they
+/// match after case and whitespace normalization. Exact matches always win.
+fn align_keys_to_format(value: JsonValue, format: &JsonValue) -> JsonValue {
+    match (value, format) {
+        (JsonValue::Object(value_map), JsonValue::Object(format_map)) => {
+            let mut aligned = serde_json::Map::new();
+            // Exact keys go first so a fuzzy match never displaces them.
+            let (exact, inexact): (Vec<_>, Vec<_>) =
+                value_map.into_iter().partition(|(key, _)| format_map.contains_key(key));
+            for (key, val) in exact.into_iter().chain(inexact) {
+                let target_key = if format_map.contains_key(&key) {
+                    key
+                } else {
+                    let normalized = normalize_key(&key);
+                    format_map
+                        .keys()
+                        .find(|k| normalize_key(k) == normalized)
+                        .cloned()
+                        .unwrap_or(key)
+                };
+                // Don't let a fuzzy match overwrite a value that came with the exact key.
+                if aligned.contains_key(&target_key) {
+                    continue;
+                }
+                let val = match format_map.get(&target_key) {
+                    Some(sub_format) => align_keys_to_format(val, sub_format),
+                    None => val,
+                };
+                aligned.insert(target_key, val);
+            }
+            JsonValue::Object(aligned)
+        }
+        (JsonValue::Array(items), JsonValue::Array(format_arr)) => match format_arr.first() {
+            Some(template) => JsonValue::Array(
+                items.into_iter().map(|item| align_keys_to_format(item, template)).collect(),
+            ),
+            None => JsonValue::Array(items),
+        },
+        (value, _) => value,
+    }
+}
+
 /// Checks if a parsed `JsonValue` conforms to the structure of the `output_format`.
 fn validate_structure(value: &JsonValue, format: &JsonValue) -> Result<(), String> {
     match format {
@@ -391,7 +519,8 @@ fn validate_structure(value: &JsonValue, format: &JsonValue) -> Result<(), Strin
 #[cfg(test)]
 mod tests {
     // Import the function we want to test
-    use super::clean_code_block;
+    use super::{clean_code_block, coerce_to_schema, parse_lucky_response, prepare_lucky_prompt, SchemaItems, SchemaProperty, SimpleSchema};
+    use serde_json::json;
 
     #[test]
     fn test_clean_code_block_removes_tags() {
@@ -400,8 +529,89 @@ mod tests {
         assert_eq!(clean_code_block(input, Some("json")), expected);
     }
 
+    #[test]
+    fn test_custom_delimiter_keeps_hashes_in_values() {
+        let format = json!({ "title": "<type:str>", "body": "<type:str>" });
+        let (system, _) = prepare_lucky_prompt("", "Write a note.", &format, "@@@", None, false);
+        assert!(system.contains("@@@title@@@"));
+
+        let response = r###"{"@@@title@@@": "# Notes", "@@@body@@@": "## Step 1"}"###;
+        let parsed = parse_lucky_response(response, &format, "@@@").unwrap();
+        assert_eq!(parsed["title"], json!("# Notes"));
+        assert_eq!(parsed["body"], json!("## Step 1"));
+    }
+
+    #[test]
+    fn test_near_miss_keys_are_matched() {
+        let format = json!({ "category": "<type:str>", "details": { "confidence": "<type:int>" } });
+        let response = r#"{"Category ": "fruit", "  DETAILS": {"Confidence": 3}}"#;
+        let parsed = parse_lucky_response(response, &format, "###").unwrap();
+        assert_eq!(parsed["category"], json!("fruit"));
+        assert_eq!(parsed["details"]["confidence"], json!(3));
+    }
+
     #[test]
     fn test_another_case() {
         // ... more tests ...
     }
+
+    #[test]
+    fn schema_types_are_validated() {
+        let property = |name: &str, property_type: &str, item_type: Option<&str>| SchemaProperty {
+            name: name.to_string(),
+            property_type: property_type.to_string(),
+            description: String::new(),
+            items: item_type.map(|t| SchemaItems { item_type: t.to_string() }),
+        };
+        let schema = |properties| SimpleSchema { name: "s".to_string(), description: String::new(), properties };
+
+        assert!(schema(vec![property("title", "string", None), property("tags", "array", Some("string"))])
+            .validate()
+            .is_ok());
+
+        let err = schema(vec![property("title", "str", None)]).validate().unwrap_err().to_string();
+        assert!(err.contains("'title'") && err.contains("'str'"));
+        let err = schema(vec![property("tags", "array", Some("int"))]).validate().unwrap_err().to_string();
+        assert!(err.contains("items type 'int'"));
+    }
+
+    #[test]
+    fn tool_arguments_are_coerced_to_the_declared_types() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "limit": { "type": "number" },
+                "page": { "type": ["integer", "null"] },
+                "exact": { "type": "boolean" },
+                "zip": { "type": "string" },
+                "filters": { "type": "object", "properties": { "max": { "type": "integer" } } },
+                "ids": { "type": "array", "items": { "type": "integer" } },
+                "query": { "type": "string" }
+            }
+        });
+        let args = json!({
+            "limit": "5",
+            "page": "2.5",
+            "exact": "true",
+            "zip": 90210,
+            "filters": "{\"max\": \"10\"}",
+            "ids": ["1", 2, "x"],
+            "query": "5",
+            "extra": "7"
+        });
+        assert_eq!(
+            coerce_to_schema(args, &schema),
+            json!({
+                "limit": 5,
+                "page": "2.5",
+                "exact": true,
+                "zip": "90210",
+                "filters": { "max": 10 },
+                "ids": [1, 2, "x"],
+                "query": "5",
+                "extra": "7"
+            })
+        );
+        assert_eq!(coerce_to_schema(json!("{\"limit\": \"3\"}"), &schema), json!({ "limit": 3 }));
+    }
 }
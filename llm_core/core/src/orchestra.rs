@@ -4,26 +4,43 @@ use crate::bindings;
 use crate::config::{self, ReasoningCapability};
 use crate::client::{self, Jitter, RetryPolicy};
 use crate::datam::{
-    format_system_message, format_tool_message, format_user_message, Message, ResponsePayload,
+    answer_after_reasoning, count_message_tokens, count_tokens, extract_tagged_blocks,
+    format_system_message, format_user_message, normalize_tool_messages, truncate_chars, Message,
+    ResponsePayload, SwarmSummary, Usage, DEFAULT_THINKING_TAG,
 };
-use crate::tools::{Tool, ToolDefinition, ToolLibrary};
+use crate::tools::{run_command_tool, run_http_tool, Tool, ToolDefinition, ToolLibrary, ToolLibraryExt};
 use crate::lucky::{self, SimpleSchema};
 use crate::error::LLMCoreError;
+use crate::failures::FailedRequest;
+use crate::trace::{self, JobTrace};
+use crate::jobs::{self, JobHandle};
+use crate::retrieval;
+use crate::sorter::SortingInstructions;
 use crate::providers::{
     gemini::{GoogleAdapter, GoogleParser},
     grok::{GrokAdapter, GrokParser},
     anthropic::{AnthropicAdapter, AnthropicParser},
-    openai::{OpenAIAdapter, OpenAIParser},
+    openai::{OpenAIAdapter, OpenAIParser, BATCH_PRICE_FACTOR},
     mercury::{MercuryAdapter, MercuryParser},
     ollama::{OllamaAdapter, OllamaParser},
-    openrouter::{OpenRouterAdapter, OpenRouterParser},
+    openrouter::{OpenRouterAdapter, OpenRouterParser, OpenRouterRouting},
+    GenerationLimits, ImageOptions, SamplingPolicy,
     unsupported::{UnsupportedAdapter, UnsupportedParser},
-    ProviderAdapter, ResponseParser,
+    sse, ProviderAdapter, ResponseParser,
 };
+use crate::providers;
 
+use reqwest::Method;
+use serde::de::DeserializeOwned;
 use serde_json::Value as JsonValue;
 use serde_json::{json};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use base64::engine::{general_purpose::STANDARD as BASE64, Engine as _};
 use uuid::Uuid;
 
 const PROMPT_INDUCED_REASONING_PROMPT: &str = r#"# **COGNITION INSTRUCTIONS**
@@ -131,8 +148,90 @@ pub struct ImageGenerationResult {
     pub text_response: Option<String>,
     #[pyo3(get)]
     pub image_data_b64: Option<String>,
+    /// The model's per-image price from `models.json`, in USD.
+    #[pyo3(get)]
+    pub cost: f32,
+}
+
+impl ImageGenerationResult {
+    /// Decodes the image bytes. Parsers have already checked and normalized the base64.
+    pub fn decode(&self) -> Result<Vec<u8>, LLMCoreError> {
+        let data = self.image_data_b64.as_deref().ok_or_else(|| {
+            LLMCoreError::ImageGenerationError("The result has no image data.".to_string())
+        })?;
+        BASE64.decode(data).map_err(|e| {
+            LLMCoreError::ImageGenerationError(format!("Image data is not valid base64: {}", e))
+        })
+    }
+}
+
+/// A step of a turn, reported by `Orchestra::call_ai_with_events` as it happens.
+///
+/// With streaming on, the deltas carry the text as the provider sends it, before any
+/// post-processing such as prefill restoration; `Done` has the final payload. Otherwise
+/// each delta carries the whole text of the final answer (or its reasoning) in one event.
+#[derive(Debug, Clone)]
+pub enum ChatEvent {
+    ContentDelta(String),
+    ReasoningDelta(String),
+    ToolCallStarted { name: String, args: JsonValue },
+    ToolResult { name: String, result: String },
+    Done(Box<ResponsePayload>),
+}
+
+/// Cached contexts this close to expiry are inlined rather than risk a rejected request.
+const CACHE_EXPIRY_MARGIN_SECS: i64 = 30;
+
+/// A handle to a large prompt stored in the provider's context cache, created by
+/// `Orchestra::create_cached_context`. While it is usable, requests reference it by
+/// `name` instead of resending the content; once it expires or the provider rejects it,
+/// the content is inlined into the system prompt again.
+#[derive(Debug, Clone)]
+pub struct CachedContext {
+    pub name: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    system_prompt: Option<String>,
+    content: String,
+    invalidated: Arc<AtomicBool>,
+}
+
+impl CachedContext {
+    /// Returns `false` once the handle was rejected by the provider or is about to expire.
+    pub fn is_usable(&self) -> bool {
+        if self.invalidated.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.expires_at
+            .map(|at| at - chrono::Duration::seconds(CACHE_EXPIRY_MARGIN_SECS) > Utc::now())
+            .unwrap_or(true)
+    }
+
+    /// Stops referencing the handle, so the content is inlined from now on.
+    pub fn invalidate(&self) {
+        self.invalidated.store(true, Ordering::SeqCst);
+    }
+
+    /// The cached system prompt and content as one block of text, for inlining.
+    fn inline_text(&self) -> String {
+        match self.system_prompt.as_deref().filter(|s| !s.trim().is_empty()) {
+            Some(system_prompt) => format!("{}\n\n{}", system_prompt.trim(), self.content),
+            None => self.content.clone(),
+        }
+    }
+
+    /// A copy that is never referenced by name, for models that cannot use the cache.
+    fn inline_only(&self) -> Self {
+        Self {
+            invalidated: Arc::new(AtomicBool::new(true)),
+            ..self.clone()
+        }
+    }
 }
 
+/// A check run on the outgoing messages before every call. Returning an error (usually
+/// `LLMCoreError::ContentFiltered`) stops the call before anything is sent.
+pub type PreSendFilter = Arc<dyn Fn(&[Message]) -> Result<(), LLMCoreError> + Send + Sync>;
+
 /// The main orchestrator for making LLM calls.
 /// This struct holds the configuration for a specific model and provider.
 #[derive(Clone)]
@@ -155,6 +254,30 @@ pub struct Orchestra {
     // NEW: Reasoning capability fields
     reasoning_capability: ReasoningCapability,
     thinking_mode: bool,
+    pre_send_filter: Option<PreSendFilter>,
+    context_window: Option<usize>,
+    check_context_window: bool,
+    lucky_delimiter: String,
+    dedupe_swarm: bool,
+    generation_limits: GenerationLimits,
+    json_mode: bool,
+    assistant_prefill: Option<String>,
+    usage_metadata: HashMap<String, String>,
+    // Off when the caller (e.g. `Chat`) logs each turn under its own id.
+    log_turn_usage: bool,
+    cached_context: Option<CachedContext>,
+    thinking_tags: Vec<String>,
+    streaming: bool,
+    sampling_policy: SamplingPolicy,
+    end_user_id: Option<String>,
+    openrouter_routing: Option<OpenRouterRouting>,
+    failure_dir: Option<PathBuf>,
+    trace_dir: Option<PathBuf>,
+    synthesis_prompt: Option<String>,
+    // The inputs that depend on no particular provider, kept to build fallback models.
+    schema: Option<SimpleSchema>,
+    requested_thinking_mode: Option<bool>,
+    fallbacks: Vec<Orchestra>,
 }
 
 impl Orchestra {
@@ -171,54 +294,74 @@ impl Orchestra {
             thinking_mode: Option<bool>,
             debug: Option<bool>,
         ) -> Result<Self, LLMCoreError> {
+        let orchestra = Self::build(model_name, temperature, tools.map(Arc::new), schema, thinking_mode, debug)?;
+        orchestra.check_temperature(orchestra.temperature)?;
+        Ok(orchestra)
+    }
+
+    /// Creates an `Orchestra` for the configured default model (see
+    /// `config::default_model_name`), with default settings.
+    pub fn default_model() -> Result<Self, LLMCoreError> {
+        Self::new(config::default_model_name()?, None, None, None, None, None)
+    }
+
+    fn build(
+            model_name: &str,
+            temperature: Option<f32>,
+            tools: Option<Arc<ToolLibrary>>,
+            schema: Option<SimpleSchema>,
+            thinking_mode: Option<bool>,
+            debug: Option<bool>,
+        ) -> Result<Self, LLMCoreError> {
         // --- Configuration Validation ---
         if tools.is_some() && schema.is_some() {
             return Err(LLMCoreError::ConfigError(
                 "Unsupported configuration: Cannot provide both a tool library and a schema simultaneously. To enforce a structured output, please define it as a single tool in the tool library.".to_string()
             ));
         }
+        if let Some(schema) = &schema {
+            schema.validate()?;
+        }
 
         let debug_mode = debug.unwrap_or(false);
 
-        let (provider_name, provider_data, model_details) = config::MODEL_LIBRARY
-            .find_model(model_name)
-            .ok_or_else(|| {
-                LLMCoreError::ConfigError(format!("Model '{}' not found in `models.json`", model_name))
-            })?;
+        config::MODEL_LIBRARY.check()?;
+        // Models supplied with `register_provider` are found before those in `models.json`.
+        let registered = providers::registered_model(model_name);
+        let (model_name, provider_name, provider_data, model_details) = match &registered {
+            Some((name, provider_name, provider_data, model_details)) => {
+                (name.as_str(), provider_name.as_str(), provider_data, model_details)
+            }
+            None => {
+                let not_found = || LLMCoreError::ConfigError(format!("Model '{}' not found in `models.json`", model_name));
+                // Aliases are replaced by the model's own name, which usage logs and errors report.
+                let model_name = match config::MODEL_LIBRARY.resolve_model_name(model_name) {
+                    Some(name) => name,
+                    // Explains whether the default model is unset or names an unknown model.
+                    None if model_name.eq_ignore_ascii_case(config::DEFAULT_MODEL_ALIAS) => config::default_model_name()?,
+                    None => return Err(not_found()),
+                };
+                let (provider_name, provider_data, model_details) = config::MODEL_LIBRARY.find_model(model_name)?;
+                (model_name, provider_name, provider_data, model_details)
+            }
+        };
 
         let reasoning_capability = model_details.reasoning_capability.clone();
 
+        let (provider_adapter, response_parser) = provider_for(provider_name);
+
         // Determine the final thinking_mode state. It can be overridden by the user,
         // otherwise it defaults to true only if the model's reasoning is always on.
-        let final_thinking_mode = thinking_mode.unwrap_or_else(|| {
-            reasoning_capability == ReasoningCapability::Always
-        });
-
-        let provider_adapter: Arc<dyn ProviderAdapter> = match provider_name {
-            "OpenAI" => Arc::new(OpenAIAdapter),
-            "Google" => Arc::new(GoogleAdapter),
-            "xAI" => Arc::new(GrokAdapter),
-            "Inception Labs" => Arc::new(MercuryAdapter),
-            "OpenRouter" => Arc::new(OpenRouterAdapter),
-            "Ollama" => Arc::new(OllamaAdapter),
-            "Anthropic" => Arc::new(AnthropicAdapter),
-            _ => Arc::new(UnsupportedAdapter { provider_name: provider_name.to_string() }),
-        };
-        let response_parser: Arc<dyn ResponseParser> = match provider_name {
-            "OpenAI" => Arc::new(OpenAIParser),
-            "Inception Labs" => Arc::new(MercuryParser),
-            "OpenRouter" => Arc::new(OpenRouterParser),
-            "Google" => Arc::new(GoogleParser),
-            "xAI" => Arc::new(GrokParser),
-            "Ollama" => Arc::new(OllamaParser),
-            "Anthropic" => Arc::new(AnthropicParser),
-            _ => Arc::new(UnsupportedParser { provider_name: provider_name.to_string() }),
-        };
+        let final_thinking_mode = resolve_thinking_mode(
+            &reasoning_capability,
+            thinking_mode,
+            model_name,
+            provider_adapter.supports_thinking_toggle(&model_details.model_tag),
+        );
 
         // --- Determine Strategy based on Provider Capabilities ---
         
-        let tool_strategy = if let Some(tool_lib) = tools {
-            let arc_tool_lib = Arc::new(tool_lib);
+        let tool_strategy = if let Some(arc_tool_lib) = tools {
             if provider_adapter.supports_tools(&model_details.model_tag) {
                 if debug_mode {
                     println!("[Orchestra] Model supports native tools. Using Payload strategy.");
@@ -238,7 +381,7 @@ impl Orchestra {
             InternalToolStrategy::None
         };
         
-        let structured_strategy = if let Some(s) = schema {
+        let structured_strategy = if let Some(s) = schema.clone() {
             if provider_adapter.supports_native_schema(&model_details.model_tag) {
                 if debug_mode {
                     println!("[ORCHESTRA DEBUG] Model supports native schema. Using Schema strategy.");
@@ -263,7 +406,8 @@ impl Orchestra {
         };
 
         let api_key = config::get_env_var(&provider_data.api_key)?;
-        let base_url = config::get_env_var(&provider_data.base_url)?;
+        providers::check_api_key(provider_name, &api_key)?;
+        let base_url = provider_data.resolve_base_url()?;
 
         Ok(Self {
             api_key,
@@ -285,26 +429,517 @@ impl Orchestra {
             debug: debug_mode,
             reasoning_capability,
             thinking_mode: final_thinking_mode,
+            pre_send_filter: None,
+            context_window: Some(model_details.token_window as usize).filter(|&w| w > 0),
+            check_context_window: false,
+            lucky_delimiter: lucky::DEFAULT_DELIMITER.to_string(),
+            dedupe_swarm: false,
+            generation_limits: GenerationLimits::default(),
+            json_mode: false,
+            assistant_prefill: None,
+            usage_metadata: HashMap::new(),
+            log_turn_usage: true,
+            cached_context: None,
+            thinking_tags: vec![DEFAULT_THINKING_TAG.to_string()],
+            streaming: false,
+            sampling_policy: SamplingPolicy::default(),
+            end_user_id: None,
+            openrouter_routing: None,
+            failure_dir: None,
+            trace_dir: None,
+            synthesis_prompt: None,
+            schema,
+            requested_thinking_mode: thinking_mode,
+            fallbacks: Vec::new(),
+        })
+    }
+
+    /// Sends requests to `base_url` instead of the provider's configured endpoint, e.g. a
+    /// proxy or an observability gateway that speaks the provider's own API. Headers and
+    /// payloads are unchanged.
+    pub fn with_base_url_override(mut self, base_url: &str) -> Result<Self, LLMCoreError> {
+        let base_url = base_url.trim();
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Invalid base URL override '{}': it must start with http:// or https://.",
+                base_url
+            )));
+        }
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        Ok(self)
+    }
+
+    /// Adds models that `call_ai` tries in order when a call fails with a transport error,
+    /// a 429 or a 5xx after exhausting its retries. Each fallback keeps this `Orchestra`'s
+    /// tools, schema, temperature and settings, as they are when the call is made, but uses
+    /// its own provider, key and pricing. A response from a fallback has `served_by` set to
+    /// that model's name.
+    ///
+    /// A turn only falls back before any tool has run, so tools are never run twice.
+    /// Provider-specific settings (`with_base_url_override`, OpenRouter routing) apply to
+    /// the primary model only. Swarm and batch calls do not fall back.
+    pub fn with_fallbacks(mut self, model_names: Vec<&str>) -> Result<Self, LLMCoreError> {
+        let tools = match &self.tool_strategy {
+            InternalToolStrategy::Payload(tools) | InternalToolStrategy::Lucky(tools, _) => Some(Arc::clone(tools)),
+            InternalToolStrategy::None => None,
+        };
+        for model_name in model_names {
+            // Only the provider is resolved here; `fallback_for` copies the settings.
+            let mut fallback = Self::build(
+                model_name,
+                Some(self.temperature),
+                tools.clone(),
+                self.schema.clone(),
+                self.requested_thinking_mode,
+                Some(self.debug),
+            )?;
+            fallback.sampling_policy = self.sampling_policy;
+            fallback.check_temperature(self.temperature)?;
+            self.fallbacks.push(fallback);
+        }
+        Ok(self)
+    }
+
+    /// Returns `fallback` with this instance's current settings.
+    fn fallback_for(&self, fallback: &Orchestra) -> Orchestra {
+        let mut fallback = fallback.clone();
+        fallback.temperature = self.temperature;
+        fallback.sampling_policy = self.sampling_policy;
+        fallback.end_user_id = self.end_user_id.clone();
+        // Routing preferences mean nothing to other providers.
+        fallback.openrouter_routing = self
+            .openrouter_routing
+            .clone()
+            .filter(|_| fallback.provider_adapter.get_provider_name() == "OpenRouter");
+        fallback.failure_dir = self.failure_dir.clone();
+        fallback.trace_dir = self.trace_dir.clone();
+        fallback.synthesis_prompt = self.synthesis_prompt.clone();
+        fallback.retry_policy = self.retry_policy;
+        fallback.debug = self.debug;
+        fallback.pre_send_filter = self.pre_send_filter.clone();
+        fallback.check_context_window = self.check_context_window;
+        fallback.lucky_delimiter = self.lucky_delimiter.clone();
+        fallback.dedupe_swarm = self.dedupe_swarm;
+        fallback.generation_limits = self.generation_limits;
+        fallback.json_mode = self.json_mode;
+        fallback.assistant_prefill = self.assistant_prefill.clone();
+        fallback.usage_metadata = self.usage_metadata.clone();
+        fallback.log_turn_usage = self.log_turn_usage;
+        fallback.cached_context = self.cached_context.as_ref().map(CachedContext::inline_only);
+        fallback.thinking_tags = self.thinking_tags.clone();
+        fallback.streaming = self.streaming && fallback.provider_adapter.supports_streaming(&fallback.model_tag);
+        fallback
+    }
+
+    /// Sets the key delimiter used by Lucky mode prompts and parsing (default `"###"`).
+    /// Useful when responses contain markdown headings that collide with `#`.
+    pub fn set_lucky_delimiter(&mut self, delimiter: &str) -> Result<(), LLMCoreError> {
+        if delimiter.is_empty() || delimiter.contains(['"', '\\']) || delimiter.contains(char::is_whitespace) {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Invalid Lucky delimiter '{}': it must be non-empty and contain no quotes, backslashes or whitespace.",
+                delimiter
+            )));
+        }
+        self.lucky_delimiter = delimiter.to_string();
+        Ok(())
+    }
+
+    /// Enables a local check that rejects prompts whose estimated size exceeds the
+    /// model's `token_window` with `ContextWindowExceeded`, instead of sending them.
+    /// The estimate is approximate, so prompts right at the limit may still be refused
+    /// by the provider. Off by default.
+    pub fn set_context_window_check(&mut self, enabled: bool) {
+        self.check_context_window = enabled;
+    }
+
+    /// Makes `swarm_call` send identical prompts only once and copy the answer to every
+    /// duplicate. Results stay in prompt order; copies carry no `usage`, so the summary
+    /// reflects what was actually billed. Off by default.
+    pub fn set_swarm_dedup(&mut self, enabled: bool) {
+        self.dedupe_swarm = enabled;
+    }
+
+    /// Chooses what happens to a `temperature` outside the range the provider accepts
+    /// (Anthropic takes 0–1, most others 0–2): `Clamp` (the default) clamps it with a
+    /// warning, `Error` rejects it. Switching to `Error` fails if the current temperature
+    /// is out of range for this model or one of its fallbacks.
+    pub fn set_sampling_policy(&mut self, policy: SamplingPolicy) -> Result<(), LLMCoreError> {
+        if policy == SamplingPolicy::Error {
+            for orchestra in std::iter::once(&*self).chain(&self.fallbacks) {
+                let (min, max) = orchestra.provider_adapter.temperature_range(&orchestra.model_tag);
+                if !(min..=max).contains(&self.temperature) {
+                    return Err(orchestra.temperature_error(self.temperature, min, max));
+                }
+            }
+        }
+        self.sampling_policy = policy;
+        Ok(())
+    }
+
+    // Checks `temperature` against the provider's range: out of range, it is rejected
+    // under `SamplingPolicy::Error` and otherwise reported as about to be clamped.
+    fn check_temperature(&self, temperature: f32) -> Result<(), LLMCoreError> {
+        let (min, max) = self.provider_adapter.temperature_range(&self.model_tag);
+        if (min..=max).contains(&temperature) {
+            return Ok(());
+        }
+        match self.sampling_policy {
+            SamplingPolicy::Error => Err(self.temperature_error(temperature, min, max)),
+            SamplingPolicy::Clamp => {
+                eprintln!(
+                    "[WARNING] Temperature {} is outside the range {} accepts for '{}' ({}–{}); clamping it to {}.",
+                    temperature,
+                    self.provider_adapter.get_provider_name(),
+                    self.user_facing_model_name,
+                    min,
+                    max,
+                    temperature.clamp(min, max)
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn temperature_error(&self, temperature: f32, min: f32, max: f32) -> LLMCoreError {
+        LLMCoreError::ConfigError(format!(
+            "Temperature {} is outside the range {} accepts for '{}' ({}–{}).",
+            temperature,
+            self.provider_adapter.get_provider_name(),
+            self.user_facing_model_name,
+            min,
+            max
+        ))
+    }
+
+    /// The temperature sent to the provider, clamped into the range it accepts.
+    fn request_temperature(&self) -> f32 {
+        let (min, max) = self.provider_adapter.temperature_range(&self.model_tag);
+        self.temperature.clamp(min, max)
+    }
+
+    /// Sets OpenRouter provider-routing preferences (upstream order, fallbacks, data
+    /// collection) for every request. Only valid for OpenRouter models.
+    pub fn set_openrouter_routing(&mut self, routing: OpenRouterRouting) -> Result<(), LLMCoreError> {
+        if self.provider_adapter.get_provider_name() != "OpenRouter" {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Provider routing only applies to OpenRouter models, not '{}'.",
+                self.user_facing_model_name
+            )));
+        }
+        self.openrouter_routing = Some(routing);
+        Ok(())
+    }
+
+    /// Sets the output cap and, for thinking models, the reasoning budget for every request.
+    /// Reasoning counts toward `max_tokens`, so with only a budget set the cap is raised to
+    /// leave room for the answer. A `max_tokens` that does not exceed the budget is rejected.
+    pub fn set_generation_limits(
+            &mut self,
+            max_tokens: Option<u32>,
+            thinking_budget: Option<u32>,
+        ) -> Result<(), LLMCoreError> {
+        if let (Some(max), Some(budget)) = (max_tokens, thinking_budget) {
+            if max <= budget {
+                return Err(LLMCoreError::ConfigError(format!(
+                    "max_tokens ({}) must be greater than the thinking budget ({}), or the answer gets no tokens.",
+                    max, budget
+                )));
+            }
+        }
+        if max_tokens == Some(0) {
+            return Err(LLMCoreError::ConfigError("max_tokens must be greater than 0.".to_string()));
+        }
+        self.generation_limits = GenerationLimits { max_tokens, thinking_budget };
+        Ok(())
+    }
+
+    /// Asks for a JSON object answer without a schema, using the provider's JSON mode
+    /// (`response_format`, `format: "json"`, `response_mime_type`) where it has one.
+    /// A JSON instruction is added to the system prompt when no message mentions JSON,
+    /// which OpenAI requires and which is all that providers without the mode get.
+    /// Not available together with a schema or tools.
+    pub fn set_json_mode(&mut self, enabled: bool) -> Result<(), LLMCoreError> {
+        if enabled && (self.schema.is_some() || !matches!(self.tool_strategy, InternalToolStrategy::None)) {
+            return Err(LLMCoreError::ConfigError(
+                "JSON mode cannot be combined with a schema or tools.".to_string(),
+            ));
+        }
+        if enabled && !self.provider_adapter.supports_json_mode(&self.model_tag) {
+            eprintln!(
+                "[WARNING] {} has no native JSON mode for '{}'; relying on the prompt instruction only.",
+                self.provider_adapter.get_provider_name(),
+                self.user_facing_model_name
+            );
+        }
+        self.json_mode = enabled;
+        Ok(())
+    }
+
+    /// Starts every reply with `prefill`, e.g. `{` to get bare JSON. Providers that continue
+    /// a trailing assistant message (Anthropic) are sent it as one and the prefill is put
+    /// back in front of the returned content; others get a system instruction to begin
+    /// with it. Trailing whitespace is dropped, as Anthropic rejects it. Not available
+    /// together with a schema or tools.
+    pub fn set_assistant_prefill(&mut self, prefill: Option<String>) -> Result<(), LLMCoreError> {
+        let prefill = prefill
+            .map(|p| p.trim_end().to_string())
+            .filter(|p| !p.is_empty());
+        if prefill.is_some() && (self.schema.is_some() || !matches!(self.tool_strategy, InternalToolStrategy::None)) {
+            return Err(LLMCoreError::ConfigError(
+                "An assistant prefill cannot be combined with a schema or tools.".to_string(),
+            ));
+        }
+        self.assistant_prefill = prefill;
+        Ok(())
+    }
+
+    /// Sets the tag names prompt-induced reasoning is wrapped in, e.g. `["reasoning"]` for
+    /// models that emit `<reasoning>...</reasoning>`. The reasoning prompt asks for the
+    /// first tag, and blocks in any of them are moved to `reasoning_content`. Default `["think"]`.
+    pub fn set_thinking_tags(&mut self, tags: Vec<String>) -> Result<(), LLMCoreError> {
+        if tags.is_empty() {
+            return Err(LLMCoreError::ConfigError("At least one thinking tag is required.".to_string()));
+        }
+        if let Some(tag) = tags
+            .iter()
+            .find(|tag| tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+        {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Invalid thinking tag '{}': use a bare name such as 'reasoning', without angle brackets.",
+                tag
+            )));
+        }
+        self.thinking_tags = tags;
+        Ok(())
+    }
+
+    /// Receives responses as a server-sent event stream. Text is reported to
+    /// `call_ai_with_events` as it arrives, while text and tool-call argument fragments
+    /// are also assembled into a complete response before parsing, so tool cycles run
+    /// as usual. Fails if the provider cannot stream.
+    pub fn set_streaming(&mut self, enabled: bool) -> Result<(), LLMCoreError> {
+        if enabled && !self.provider_adapter.supports_streaming(&self.model_tag) {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Model '{}' does not support streaming.",
+                self.user_facing_model_name
+            )));
+        }
+        self.streaming = enabled;
+        Ok(())
+    }
+
+    /// Identifies the end user requests are made for, sent as `user` (OpenAI, Grok,
+    /// OpenRouter) or `metadata.user_id` (Anthropic) so the provider can attribute abuse
+    /// to one user rather than the whole account. Use an opaque id, not an email address.
+    /// Gemini and Ollama have no such field and ignore it. `None` (the default) omits it.
+    pub fn set_end_user_id(&mut self, user_id: Option<String>) -> Result<(), LLMCoreError> {
+        if user_id.as_deref().is_some_and(|id| id.trim().is_empty()) {
+            return Err(LLMCoreError::ConfigError("The end user id must not be empty.".to_string()));
+        }
+        self.end_user_id = user_id;
+        Ok(())
+    }
+
+    /// Saves every request that fails (URL, headers, payload and the error or error
+    /// response) as a JSON file in `dir`, usually `config::FAILURE_DATA_DIR`, with the
+    /// API key redacted. `None` (the default) turns capture off. See `replay_failure`.
+    pub fn set_failure_capture(&mut self, dir: Option<PathBuf>) {
+        self.failure_dir = dir;
+    }
+
+    /// Writes a trace of every `call_ai` turn to `<dir>/<job_id>.json`, usually under
+    /// `config::TRACE_DATA_DIR`: each request's payload and raw response (API key
+    /// redacted from the URL and headers), the tools called, the parsed payload and its
+    /// usage. `None` (the default) turns tracing off.
+    pub fn set_trace_dir(&mut self, dir: Option<PathBuf>) {
+        self.trace_dir = dir;
+    }
+
+    /// Adds `prompt` to the system message of the request that answers the user after
+    /// tools have run, usually `SYNTHESIS_PROMPT`. It is sent with that request only and
+    /// never kept in the conversation. `None` (the default) adds nothing.
+    pub fn set_synthesis_prompt(&mut self, prompt: Option<String>) -> Result<(), LLMCoreError> {
+        if prompt.as_deref().is_some_and(|p| p.trim().is_empty()) {
+            return Err(LLMCoreError::ConfigError("The synthesis prompt must not be empty.".to_string()));
+        }
+        self.synthesis_prompt = prompt;
+        Ok(())
+    }
+
+    /// Re-sends a request saved by `set_failure_capture` with this instance's API key
+    /// and returns the raw response body. The saved URL and payload are used unchanged.
+    pub async fn replay_failure(&self, path: &std::path::Path) -> Result<String, LLMCoreError> {
+        let failed = FailedRequest::load(path)?;
+        let (url, headers) = failed.restore(&self.api_key)?;
+        let (body, _) = client::execute_single_call_with_headers(url, headers, failed.payload, &self.retry_policy).await?;
+        Ok(body)
+    }
+
+    /// Tags every usage record logged by this instance (e.g. `tenant`, `feature`,
+    /// `user_id`), so spend can be grouped with `usage::aggregate_usage_by_metadata`.
+    pub fn set_usage_metadata(&mut self, metadata: HashMap<String, String>) {
+        self.usage_metadata = metadata;
+    }
+
+    /// The tags written into this instance's usage records.
+    pub fn usage_metadata(&self) -> &HashMap<String, String> {
+        &self.usage_metadata
+    }
+
+    /// Stops `call_ai` from logging each turn's usage, for callers that log it themselves.
+    pub(crate) fn set_turn_usage_logging(&mut self, enabled: bool) {
+        self.log_turn_usage = enabled;
+    }
+
+    /// Stores `system_prompt` and `content` (e.g. a large reference document) in the
+    /// provider's context cache for `ttl` and returns a handle to pass to
+    /// `set_cached_context`. Only providers with explicit caching (Gemini) support this,
+    /// and they reject content below a minimum size.
+    pub async fn create_cached_context(
+            &self,
+            system_prompt: Option<&str>,
+            content: &str,
+            ttl: Duration,
+        ) -> Result<CachedContext, LLMCoreError> {
+        if !self.provider_adapter.supports_context_caching(&self.model_tag) {
+            return Err(LLMCoreError::ConfigError(format!(
+                "{} does not support context caching for '{}'.",
+                self.provider_adapter.get_provider_name(),
+                self.user_facing_model_name
+            )));
+        }
+        let url = self.provider_adapter.get_cached_content_url(&self.base_url, &self.api_key);
+        let headers = self.provider_adapter.get_request_headers(&self.api_key);
+        let payload = self.provider_adapter
+            .prepare_cached_content_request(&self.model_tag, system_prompt, content, ttl);
+        let (response_text, _) =
+            client::execute_request(Method::POST, url, headers, Some(&payload), &self.retry_policy).await?;
+        if self.debug {
+            println!("[ORCHESTRA DEBUG] Raw cached content response: {}", response_text);
+        }
+        let (name, expires_at) = self.response_parser.parse_cached_content_response(&response_text)?;
+        Ok(CachedContext {
+            name,
+            expires_at,
+            system_prompt: system_prompt.map(str::to_string),
+            content: content.to_string(),
+            invalidated: Arc::new(AtomicBool::new(false)),
         })
     }
 
-    /// Generates an image based on a prompt using a specified image model.
+    /// Makes every request reference `context` in place of its content, which is otherwise
+    /// added to the system prompt. The content is inlined instead once the handle expires
+    /// or the provider rejects it, and always for fallback models. Not available together
+    /// with tools, as a cached request cannot declare its own.
+    pub fn set_cached_context(&mut self, context: Option<CachedContext>) -> Result<(), LLMCoreError> {
+        if let Some(context) = &context {
+            if !matches!(self.tool_strategy, InternalToolStrategy::None) {
+                return Err(LLMCoreError::ConfigError(
+                    "A cached context cannot be combined with tools.".to_string(),
+                ));
+            }
+            if !self.provider_adapter.supports_context_caching(&self.model_tag) {
+                return Err(LLMCoreError::ConfigError(format!(
+                    "{} does not support context caching for '{}'.",
+                    self.provider_adapter.get_provider_name(),
+                    self.user_facing_model_name
+                )));
+            }
+            if !context.is_usable() {
+                eprintln!(
+                    "[WARNING] Cached context '{}' has expired; its content will be inlined.",
+                    context.name
+                );
+            }
+        }
+        self.cached_context = context;
+        Ok(())
+    }
+
+    /// The cached context referenced by this instance's requests, if any.
+    pub fn cached_context(&self) -> Option<&CachedContext> {
+        self.cached_context.as_ref()
+    }
+
+    /// Returns the cached context when requests can reference it by name.
+    fn usable_cache(&self) -> Option<&CachedContext> {
+        self.cached_context.as_ref().filter(|c| c.is_usable())
+    }
+
+    /// Adds the cached context's content to the system prompt when the handle cannot be used.
+    fn inline_cached_context(&self, messages: &mut Vec<Message>) {
+        if let Some(context) = &self.cached_context {
+            if !context.is_usable() {
+                append_system_instruction(messages, context.inline_text());
+            }
+        }
+    }
+
+    /// Sets (or clears) the filter that screens messages before each call.
+    pub fn set_pre_send_filter(&mut self, filter: Option<PreSendFilter>) {
+        self.pre_send_filter = filter;
+    }
+
+    /// Plugs in a custom adapter and parser for a provider without editing this crate.
+    ///
+    /// The registration is process-wide and is consulted before the built-in providers,
+    /// so it can also replace one of them. `config` lists the provider's models, API key
+    /// and endpoint as a `models.json` entry would, and its models are found by name
+    /// before those in `models.json`. Without it, the provider name must match the one
+    /// its models are listed under in `models.json`.
+    pub fn register_provider(
+            name: &str,
+            adapter: Arc<dyn ProviderAdapter>,
+            parser: Arc<dyn ResponseParser>,
+            config: Option<config::ProviderConfig>,
+        ) {
+        providers::register_provider(name, adapter, parser, config);
+    }
+
+    /// Removes a provider registered with `register_provider`, restoring the built-in
+    /// adapter if there is one. Returns `false` if nothing was registered under `name`.
+    pub fn unregister_provider(name: &str) -> bool {
+        providers::unregister_provider(name)
+    }
+
+    /// Caps the requests in flight to a provider (as named in `models.json`) across every
+    /// `Orchestra`, `Chat` and `Sorter` in the process, so together they stay under an
+    /// account-wide limit. `None` removes the cap. The cap applies to the provider's
+    /// host, so it is shared by everything sent to that base URL.
+    pub fn set_provider_concurrency_limit(
+            provider_name: &str,
+            max_in_flight: Option<usize>,
+        ) -> Result<(), LLMCoreError> {
+        let provider = config::MODEL_LIBRARY.providers.get(provider_name).ok_or_else(|| {
+            LLMCoreError::ConfigError(format!("Provider '{}' not found in `models.json`", provider_name))
+        })?;
+        let base_url = provider.resolve_base_url()?;
+        let host = reqwest::Url::parse(&base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .ok_or_else(|| {
+                LLMCoreError::ConfigError(format!("Base URL '{}' for '{}' has no host.", base_url, provider_name))
+            })?;
+        client::set_host_concurrency_limit(&host, max_in_flight)
+    }
+
+    /// Generates images based on a prompt using a specified image model.
     ///
     /// This function is separate from the main chat flow and uses the new
     /// `prepare_image_request_payload` and `parse_image_response` methods
-    /// on the provider adapters.
+    /// on the provider adapters. `options` sets the image count, size, quality and
+    /// style; one result is returned per generated image.
     pub async fn generate_image(
             &self,
             prompt: &str,
             image_model_name: &str, // e.g., "GEMINI 2.0 FLASH IMAGE GEN"
-        ) -> Result<ImageGenerationResult, LLMCoreError> {
+            options: &ImageOptions,
+        ) -> Result<Vec<ImageGenerationResult>, LLMCoreError> {
+        if options.n == Some(0) {
+            return Err(LLMCoreError::ConfigError("The number of images must be at least 1.".to_string()));
+        }
         let (_provider_name, _provider_data, model_details) =
-            config::MODEL_LIBRARY.find_model(image_model_name).ok_or_else(|| {
-                LLMCoreError::ConfigError(format!(
-                    "Image model '{}' not found in `models.json`",
-                    image_model_name
-                ))
-            })?;
+            config::MODEL_LIBRARY.find_image_model(image_model_name)?;
         
         // We can reuse the existing provider adapter and parser from the Orchestra instance
         // as long as the image model belongs to the same provider. This is a reasonable
@@ -317,7 +952,7 @@ impl Orchestra {
         );
         let headers = self.provider_adapter.get_request_headers(&self.api_key);
         let payload = self.provider_adapter
-            .prepare_image_request_payload(prompt, &model_details.model_tag);
+            .prepare_image_request_payload(prompt, &model_details.model_tag, options);
 
         if self.debug {
             println!("[ORCHESTRA DEBUG] Image generation payload: {:?}", payload);
@@ -330,32 +965,323 @@ impl Orchestra {
             println!("[ORCHESTRA DEBUG] Raw image response: {}", response_text);
         }
 
-        let (text_response, image_data_b64) = self
+        let images = self
             .response_parser
             .parse_image_response(&response_text)?;
             
-        Ok(ImageGenerationResult {
-            text_response,
-            image_data_b64,
-        })
+        Ok(images
+            .into_iter()
+            .map(|(text_response, image_data_b64)| ImageGenerationResult {
+                text_response,
+                image_data_b64,
+                cost: model_details.image_price,
+            })
+            .collect())
+    }
+
+    /// Embeds `texts` with this model's provider, reusing its key, base URL and retry
+    /// policy instead of building an `Embedder`. Uses the provider's cheapest embedder in
+    /// `models.json`; build an `Embedder` to pick a specific one.
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, LLMCoreError> {
+        let provider_name = self.provider_adapter.get_provider_name();
+        let provider_config = config::MODEL_LIBRARY.providers.get(provider_name);
+        let embedder = provider_config
+            .and_then(default_embedder)
+            .filter(|details| self.provider_adapter.supports_embeddings(&details.model_tag));
+        let (Some(provider_config), Some(embedder)) = (provider_config, embedder) else {
+            return Err(LLMCoreError::ConfigError(format!(
+                "{} has no embedding model for '{}'.",
+                provider_name, self.user_facing_model_name
+            )));
+        };
+
+        let url = self.provider_adapter.get_embedding_url(&self.base_url, &embedder.model_tag, provider_config);
+        let headers = self.provider_adapter.get_embedding_headers(&self.api_key);
+        let payload = self.provider_adapter.prepare_embedding_request(&embedder.model_tag, texts);
+        if self.debug {
+            println!("[ORCHESTRA DEBUG] Embedding with '{}' at {}", embedder.model_tag, url);
+        }
+        let response_text = client::execute_single_call(url, headers, payload, &self.retry_policy).await?;
+        self.response_parser.parse_embedding_response(&response_text)
+    }
+
+    /// Sends `body` to an arbitrary `path` under the model's base URL, for provider
+    /// endpoints this crate does not wrap (files, fine-tuning, ...). Uses the configured
+    /// auth headers and returns the response as JSON; an empty body (e.g. a `204`) is
+    /// `null`.
+    ///
+    /// Only idempotent methods (`GET`, `HEAD`, `PUT`, `DELETE`) are retried with the
+    /// retry policy; other methods are sent once. Use `raw_request_with_retries` to
+    /// retry them as well.
+    pub async fn raw_request(
+            &self,
+            method: &str,
+            path: &str,
+            body: Option<JsonValue>,
+        ) -> Result<JsonValue, LLMCoreError> {
+        self.send_raw_request(method, path, body, false).await
+    }
+
+    /// Like `raw_request`, but retries every method, for endpoints where repeating a
+    /// `POST` is known to be safe.
+    pub async fn raw_request_with_retries(
+            &self,
+            method: &str,
+            path: &str,
+            body: Option<JsonValue>,
+        ) -> Result<JsonValue, LLMCoreError> {
+        self.send_raw_request(method, path, body, true).await
+    }
+
+    async fn send_raw_request(
+            &self,
+            method: &str,
+            path: &str,
+            body: Option<JsonValue>,
+            retry_any_method: bool,
+        ) -> Result<JsonValue, LLMCoreError> {
+        let method = Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| LLMCoreError::ConfigError(format!("Invalid HTTP method '{}'.", method)))?;
+        let url = self.provider_adapter.get_raw_request_url(&self.base_url, path, &self.api_key);
+        let headers = self.provider_adapter.get_request_headers(&self.api_key);
+
+        if self.debug {
+            println!("[ORCHESTRA DEBUG] Raw {} request to {}", method, path);
+        }
+
+        let idempotent = matches!(method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE);
+        let retry_policy = if idempotent || retry_any_method {
+            self.retry_policy
+        } else {
+            RetryPolicy { max_retries: 1, ..self.retry_policy }
+        };
+        let (response_text, _) =
+            client::execute_request(method, url, headers, body.as_ref(), &retry_policy).await?;
+        if response_text.trim().is_empty() {
+            return Ok(JsonValue::Null);
+        }
+        Ok(serde_json::from_str(&response_text)?)
     }
 
     pub fn model_tag(&self) -> &str {
         &self.model_tag
     }
 
+    /// The adapter that builds this instance's requests.
+    pub fn provider_adapter(&self) -> &dyn ProviderAdapter {
+        self.provider_adapter.as_ref()
+    }
+
+    /// How tools are sent to the model: `"native"` (the provider's tool calling),
+    /// `"lucky"` (a prompted JSON fallback) or `"none"` when no tools were given.
+    pub fn tool_mode(&self) -> &'static str {
+        match self.tool_strategy {
+            InternalToolStrategy::Payload(_) => "native",
+            InternalToolStrategy::Lucky(..) => "lucky",
+            InternalToolStrategy::None => "none",
+        }
+    }
+
+    /// The definitions of the tools this instance offers the model, sorted by name.
+    pub fn tool_definitions(&self) -> Vec<ToolDefinition> {
+        match &self.tool_strategy {
+            InternalToolStrategy::Payload(tools) | InternalToolStrategy::Lucky(tools, _) => tools.descriptions(),
+            InternalToolStrategy::None => Vec::new(),
+        }
+    }
+
+    /// How a schema is enforced: `"native"` (the provider's schema or tool-forcing mode),
+    /// `"lucky"` (a prompted JSON fallback) or `"none"` when no schema was given.
+    pub fn structured_mode(&self) -> &'static str {
+        match self.structured_strategy {
+            InternalStructuredStrategy::Schema(_) => "native",
+            InternalStructuredStrategy::Lucky(_) => "lucky",
+            InternalStructuredStrategy::None => "none",
+        }
+    }
+
     pub fn thinking_mode(&self) -> bool {
         self.thinking_mode
     }
 
+    /// Starts a sorting task in the background and returns a handle to poll or await it.
+    ///
+    /// Job state is kept in memory keyed by the job id, so the result can also be
+    /// retrieved later with `jobs::job_info` / `jobs::take_job_result`.
+    /// Must be called from within a Tokio runtime.
+    pub fn spawn_sorting_job(
+            &self,
+            sorting_instructions: SortingInstructions,
+            input_path: Option<PathBuf>,
+            items_list: Option<Vec<String>>,
+            output_path: Option<PathBuf>,
+            swarm_size: usize,
+        ) -> JobHandle {
+        jobs::spawn_sorting_job(
+            Arc::new(self.clone()),
+            sorting_instructions,
+            input_path,
+            items_list,
+            output_path,
+            swarm_size,
+            self.debug,
+        )
+    }
+
     /// Executes the first API call in a potential multi-step conversation.
     /// It prepares the prompt according to the determined strategy (Native vs. Lucky)
     /// and parses the initial response.
+    ///
+    /// When a structured reply cannot be parsed (Lucky) or is not JSON (schema), the
+    /// request is sent once more with a reminder appended to the user prompt. This
+    /// content retry is separate from the network retries in `RetryPolicy`. A schema
+    /// reply that is still prose afterwards fails with `ResponseParseError`.
     async fn execute_initial_turn(
             &self,
             messages: Vec<Message>,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
         ) -> Result<(ResponsePayload, Vec<Message>), LLMCoreError> {
-        let mut final_messages = messages.clone();
+        let result = self.send_turn(&messages, on_event).await;
+        let nudge = match &result {
+            Err(LLMCoreError::ResponseParseError(_)) if self.uses_lucky() => LUCKY_RETRY_NUDGE,
+            Ok(payload) if self.is_non_json_schema_reply(payload) => JSON_RETRY_NUDGE,
+            _ => return result.map(|payload| (payload, messages)),
+        };
+
+        eprintln!(
+            "[WARNING] Model '{}' did not return the requested structure; retrying once with a reminder.",
+            self.user_facing_model_name
+        );
+        let mut retry_messages = messages.clone();
+        match retry_messages.iter_mut().rfind(|m| m.role == "user") {
+            Some(user) => {
+                let prompt = user.content.as_deref().unwrap_or("");
+                user.content = Some(format!("{}\n\n{}", prompt, nudge));
+            }
+            None => retry_messages.push(format_user_message(nudge.to_string())),
+        }
+
+        // Prose that survives the reminder is an error rather than content callers would
+        // later fail to parse. If the retry itself fails, its own error is returned.
+        match self.send_turn(&retry_messages, on_event).await {
+            Ok(payload) if self.is_non_json_schema_reply(&payload) => Err(self.prose_under_schema_error(&payload)),
+            Ok(mut payload) => {
+                // The first attempt was billed too.
+                if let Some(first) = result.as_ref().ok().and_then(|first| first.usage.clone()) {
+                    match payload.usage.as_mut() {
+                        Some(usage) => *usage += first,
+                        None => payload.usage = Some(first),
+                    }
+                }
+                Ok((payload, messages))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends one prepared turn and parses the response.
+    /// When streaming, the text of each event is reported to `on_event` as it arrives.
+    async fn send_turn(
+            &self,
+            messages: &[Message],
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
+        ) -> Result<ResponsePayload, LLMCoreError> {
+        let payload = self.stream_payload(self.prepare_turn_payload(messages)?);
+
+        // --- Execute API Call ---
+        let url = self.provider_adapter.get_request_url(&self.base_url, &self.model_tag, &self.api_key);
+        let headers = self.provider_adapter.get_request_headers(&self.api_key);
+        let started = Instant::now();
+        let result = self.post_turn(url.clone(), headers.clone(), payload, on_event).await;
+        let (response_text, response_headers) = match result {
+            Err(e) if self.is_rejected_cache(&e) => {
+                // The handle expired early or was deleted: resend once with the content inlined.
+                let context = self.cached_context.as_ref().expect("checked by is_rejected_cache");
+                eprintln!(
+                    "[WARNING] Cached context '{}' was rejected; inlining its content instead.",
+                    context.name
+                );
+                context.invalidate();
+                let payload = self.stream_payload(self.prepare_turn_payload(messages)?);
+                self.post_turn(url, headers, payload, on_event).await?
+            }
+            result => result?,
+        };
+        let response_text = self.assemble_streamed_body(response_text)?;
+        let elapsed = started.elapsed();
+        if self.debug {
+            println!("[ORCHESTRA DEBUG] Raw response from model: {}", response_text);
+        }
+
+        let mut final_payload = self.process_turn_response(&response_text, self.input_price, self.output_price)?;
+        final_payload.estimate_missing_usage(count_message_tokens(messages), self.input_price, self.output_price);
+        client::apply_response_headers(&mut final_payload, &response_headers);
+        if let Some(usage) = &mut final_payload.usage {
+            usage.record_duration(elapsed);
+        }
+        if self.debug {
+            println!(
+                "[ORCHESTRA DEBUG] Request ID: {:?}, Rate limit: {:?}",
+                final_payload.request_id, final_payload.rate_limit
+            );
+        }
+        Ok(final_payload)
+    }
+
+    /// Returns `true` when `error` is the provider refusing the cached context in use.
+    fn is_rejected_cache(&self, error: &LLMCoreError) -> bool {
+        self.usable_cache().is_some_and(|context| rejects_cached_context(error, &context.name))
+    }
+
+    fn uses_lucky(&self) -> bool {
+        matches!(self.structured_strategy, InternalStructuredStrategy::Lucky(_))
+            || matches!(self.tool_strategy, InternalToolStrategy::Lucky(..))
+    }
+
+    /// Returns `true` for a native-schema reply whose answer is prose instead of JSON.
+    fn is_non_json_schema_reply(&self, payload: &ResponsePayload) -> bool {
+        if !matches!(self.structured_strategy, InternalStructuredStrategy::Schema(_)) {
+            return false;
+        }
+        let Some(message) = payload.choices.first().map(|c| &c.message) else {
+            return false;
+        };
+        if message.tool_calls.is_some() {
+            return false;
+        }
+        let content = message.content.as_deref().unwrap_or("");
+        let answer = answer_after_reasoning(content, &self.thinking_tags);
+        serde_json::from_str::<JsonValue>(answer.trim()).is_err()
+    }
+
+    fn prose_under_schema_error(&self, payload: &ResponsePayload) -> LLMCoreError {
+        let schema_name = match &self.structured_strategy {
+            InternalStructuredStrategy::Schema(schema) => schema.name.as_str(),
+            _ => "",
+        };
+        let content = payload.choices.first().and_then(|c| c.message.content.as_deref()).unwrap_or("");
+        LLMCoreError::ResponseParseError(format!(
+            "Model '{}' answered in prose instead of JSON for schema '{}': {}",
+            self.user_facing_model_name,
+            schema_name,
+            truncate_chars(content.trim(), 200)
+        ))
+    }
+
+    /// Builds the provider payload for a turn: runs the pre-send checks and applies the
+    /// reasoning, Lucky and native tool/schema strategies to the messages.
+    fn prepare_turn_payload(&self, messages: &[Message]) -> Result<JsonValue, LLMCoreError> {
+        if let Some(filter) = &self.pre_send_filter {
+            if let Err(e) = filter(messages) {
+                if self.debug {
+                    println!("[ORCHESTRA DEBUG] Pre-send filter blocked the request: {}", e);
+                }
+                return Err(e);
+            }
+        }
+
+        let mut final_messages = messages.to_vec();
+        normalize_tool_messages(&mut final_messages)?;
         let mut schema_for_provider: Option<SimpleSchema> = None;
         let mut tools_for_provider: Option<Vec<ToolDefinition>> = None;
 
@@ -373,26 +1299,29 @@ impl Orchestra {
                 (false, false)
             };
 
+        // Only prompt-inducible models get the CoT prompt; native reasoners (`Always`,
+        // `Toggle`) and `Never` models have it stripped so no tokens are wasted on it.
+        let reasoning_prompt = self.reasoning_prompt();
         if self.thinking_mode && self.reasoning_capability == ReasoningCapability::PromptInducible {
             if !has_cot_prompt {
                 if system_message_exists {
                     let content = final_messages[0].content.as_deref().unwrap_or("").trim();
                     let new_content = if content.is_empty() {
-                        PROMPT_INDUCED_REASONING_PROMPT.to_string()
+                        reasoning_prompt.clone()
                     } else {
-                        format!("{}\n\n{}", content, PROMPT_INDUCED_REASONING_PROMPT)
+                        format!("{}\n\n{}", content, reasoning_prompt)
                     };
                     final_messages[0].content = Some(new_content);
                 } else {
-                    final_messages.insert(0, format_system_message(PROMPT_INDUCED_REASONING_PROMPT.to_string()));
+                    final_messages.insert(0, format_system_message(reasoning_prompt.clone()));
                 }
             }
         } else {
             if has_cot_prompt {
                 let content = final_messages[0].content.as_ref().unwrap();
                 let new_content = content
-                    .replace(&format!("\n\n{}", PROMPT_INDUCED_REASONING_PROMPT), "")
-                    .replace(PROMPT_INDUCED_REASONING_PROMPT, "")
+                    .replace(&format!("\n\n{}", reasoning_prompt), "")
+                    .replace(&reasoning_prompt, "")
                     .trim()
                     .to_string();
                 
@@ -411,7 +1340,7 @@ impl Orchestra {
         // Handle structured response strategy
         if let InternalStructuredStrategy::Lucky(output_format) = &self.structured_strategy {
             let (system, user) = self.get_prompts_from_messages(&final_messages);
-            let (lucky_system, lucky_user) = lucky::prepare_lucky_prompt(system, user, output_format, "###", None, is_synthesis_turn);
+            let (lucky_system, lucky_user) = lucky::prepare_lucky_prompt(system, user, output_format, &self.lucky_delimiter, None, is_synthesis_turn);
             final_messages = vec![format_system_message(lucky_system), format_user_message(lucky_user)];
         } else if let InternalStructuredStrategy::Schema(s) = &self.structured_strategy {
             schema_for_provider = Some(s.clone());
@@ -421,7 +1350,7 @@ impl Orchestra {
         if let InternalToolStrategy::Lucky(tool_lib, output_format) = &self.tool_strategy {
             let tool_defs = tool_lib.values().map(|t| t.definition().clone()).collect::<Vec<_>>();
             let (system, user) = self.get_prompts_from_messages(&final_messages);
-            let (lucky_system, lucky_user) = lucky::prepare_lucky_prompt(system, user, output_format, "###", Some(&tool_defs), is_synthesis_turn);
+            let (lucky_system, lucky_user) = lucky::prepare_lucky_prompt(system, user, output_format, &self.lucky_delimiter, Some(&tool_defs), is_synthesis_turn);
             final_messages = vec![format_system_message(lucky_system), format_user_message(lucky_user)];
         } else if let InternalToolStrategy::Payload(tool_lib) = &self.tool_strategy {
              if !is_synthesis_turn {
@@ -429,30 +1358,207 @@ impl Orchestra {
              }
         }
         
-        // --- Execute API Call ---
-        let url = self.provider_adapter.get_request_url(&self.base_url, &self.model_tag, &self.api_key);
-        let headers = self.provider_adapter.get_request_headers(&self.api_key);
+        self.inline_cached_context(&mut final_messages);
+        if self.json_mode {
+            ensure_json_instruction(&mut final_messages);
+        }
+        self.add_prefill_instruction(&mut final_messages);
+
+        if let (true, Some(limit)) = (self.check_context_window, self.context_window) {
+            let tool_tokens = tools_for_provider
+                .as_ref()
+                .map_or(0, |tools| count_tokens(&serde_json::to_string(tools).unwrap_or_default()));
+            let tokens = count_message_tokens(&final_messages) + tool_tokens;
+            if tokens > limit {
+                return Err(LLMCoreError::ContextWindowExceeded { tokens, limit });
+            }
+        }
+
         let payload = self.provider_adapter.prepare_request_payload(
             &self.model_tag,
-            final_messages.clone(),
-            self.temperature,
+            final_messages,
+            self.request_temperature(),
             schema_for_provider,
             tools_for_provider.as_ref(),
             self.thinking_mode,
             self.debug,
         );
+        Ok(self.finish_payload(payload))
+    }
 
-        let response_text = client::execute_single_call(url, headers, payload, &self.retry_policy).await?;
-        if self.debug {
-            println!("[ORCHESTRA DEBUG] Raw response from model: {}", response_text);
+    /// Returns the assistant prefill when the provider takes it as a trailing message.
+    fn native_prefill(&self) -> Option<&str> {
+        self.assistant_prefill
+            .as_deref()
+            .filter(|_| self.provider_adapter.supports_assistant_prefill(&self.model_tag, self.thinking_mode))
+    }
+
+    /// Tells the model to begin with the assistant prefill when the provider cannot be
+    /// sent it as the start of the reply.
+    fn add_prefill_instruction(&self, messages: &mut Vec<Message>) {
+        let Some(prefill) = &self.assistant_prefill else { return };
+        if self.native_prefill().is_some() {
+            return;
         }
+        append_system_instruction(messages, format!("Begin your reply with exactly: {}", prefill));
+    }
+
+    /// The prompt-induced reasoning prompt, asking for the first configured thinking tag.
+    fn reasoning_prompt(&self) -> String {
+        match self.thinking_tags.first().map(String::as_str) {
+            Some(tag) if tag != DEFAULT_THINKING_TAG => PROMPT_INDUCED_REASONING_PROMPT
+                .replace("<think>", &format!("<{}>", tag))
+                .replace("</think>", &format!("</{}>", tag)),
+            _ => PROMPT_INDUCED_REASONING_PROMPT.to_string(),
+        }
+    }
 
-        let initial_payload = self.response_parser.parse_response(
-            &response_text,
+    /// Moves blocks in configured thinking tags other than `<think>`, which the parsers
+    /// already handle, out of the reply and into `reasoning_content`.
+    fn extract_configured_reasoning(&self, payload: &mut ResponsePayload) {
+        if self.thinking_tags.iter().all(|tag| tag == DEFAULT_THINKING_TAG) {
+            return;
+        }
+        for choice in &mut payload.choices {
+            let message = &mut choice.message;
+            let Some(reasoning) = message.content.as_mut().and_then(|c| extract_tagged_blocks(c, &self.thinking_tags)) else {
+                continue;
+            };
+            message.reasoning_content = Some(match message.reasoning_content.take() {
+                Some(existing) => format!("{}\n\n{}", existing, reasoning),
+                None => reasoning,
+            });
+        }
+    }
+
+    /// Puts a natively sent prefill back in front of the reply, which continues after it.
+    fn restore_prefill(&self, payload: &mut ResponsePayload) {
+        if let (Some(prefill), Some(choice)) = (self.native_prefill(), payload.choices.get_mut(0)) {
+            // The reply never repeats the prefill, even when it happens to start with the
+            // same text, so it is always prepended.
+            let content = choice.message.content.as_deref().unwrap_or("");
+            choice.message.content = Some(format!("{}{}", prefill, content));
+        }
+    }
+
+    /// Applies the configured `GenerationLimits`, JSON mode, native assistant prefill and
+    /// cached context to a prepared payload.
+    fn finish_payload(&self, mut payload: JsonValue) -> JsonValue {
+        self.provider_adapter.apply_generation_limits(
+            &mut payload,
+            &self.model_tag,
+            &self.generation_limits,
+            self.thinking_mode,
+        );
+        if self.json_mode && self.provider_adapter.supports_json_mode(&self.model_tag) {
+            self.provider_adapter.apply_json_mode(&mut payload, &self.model_tag);
+        }
+        if let Some(prefill) = self.native_prefill() {
+            self.provider_adapter.apply_assistant_prefill(&mut payload, prefill);
+        }
+        if let Some(context) = self.usable_cache() {
+            self.provider_adapter.apply_cached_content(&mut payload, &context.name);
+        }
+        if let Some(user_id) = &self.end_user_id {
+            self.provider_adapter.apply_end_user_id(&mut payload, user_id);
+        }
+        if let Some(routing) = &self.openrouter_routing {
+            payload["provider"] = json!(routing);
+        }
+        payload
+    }
+
+    /// Asks for a streamed response when streaming is enabled.
+    fn stream_payload(&self, mut payload: JsonValue) -> JsonValue {
+        if self.streaming {
+            self.provider_adapter.apply_streaming(&mut payload);
+        }
+        payload
+    }
+
+    /// Sends a turn's request, saving it if it fails while failure capture is on and
+    /// adding it to the job's trace while tracing is on.
+    async fn post_turn(
+            &self,
+            url: String,
+            headers: reqwest::header::HeaderMap,
+            payload: JsonValue,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
+        ) -> Result<(String, reqwest::header::HeaderMap), LLMCoreError> {
+        if self.failure_dir.is_none() && !trace::is_active() {
+            return self.post_turn_once(url, headers, payload, on_event).await;
+        }
+        let capture = (url.clone(), headers.clone(), payload.clone());
+        let result = self.post_turn_once(url, headers, payload, on_event).await;
+        let (url, headers, payload) = capture;
+        trace::record_request(&url, &headers, &payload, &result, &self.api_key);
+        if let (Err(error), Some(dir)) = (&result, &self.failure_dir) {
+            let failed = FailedRequest::new(
+                &self.user_facing_model_name,
+                self.provider_adapter.get_provider_name(),
+                &url,
+                &headers,
+                payload,
+                error,
+                &self.api_key,
+            );
+            if let Err(e) = failed.save(dir) {
+                eprintln!("[WARNING] Could not save the failed request to '{}': {}", dir.display(), e);
+            }
+        }
+        result
+    }
+
+    /// When streaming, the text of each event is reported to `on_event` as it arrives.
+    async fn post_turn_once(
+            &self,
+            url: String,
+            headers: reqwest::header::HeaderMap,
+            payload: JsonValue,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
+        ) -> Result<(String, reqwest::header::HeaderMap), LLMCoreError> {
+        if !self.streaming {
+            return client::execute_single_call_with_headers(url, headers, payload, &self.retry_policy).await;
+        }
+        let mut decoder = sse::SseDecoder::default();
+        let mut on_chunk = |text: &str| {
+            for data in decoder.push(text) {
+                for (field, delta) in sse::text_deltas(&data) {
+                    match field.as_str() {
+                        "content" => on_event(ChatEvent::ContentDelta(delta)),
+                        "reasoning_content" | "reasoning" => on_event(ChatEvent::ReasoningDelta(delta)),
+                        _ => {}
+                    }
+                }
+            }
+        };
+        client::execute_streaming_call(url, headers, payload, &self.retry_policy, &mut on_chunk).await
+    }
+
+    /// Reassembles a streamed response body into the provider's non-streamed shape.
+    /// A plain JSON body, from a provider that ignored the stream flag, is kept as is.
+    fn assemble_streamed_body(&self, body: String) -> Result<String, LLMCoreError> {
+        if !self.streaming || body.trim_start().starts_with('{') {
+            return Ok(body);
+        }
+        Ok(sse::assemble_stream(&body)?.to_string())
+    }
+
+    /// Parses a raw turn response and normalizes schema and Lucky results into `content`.
+    fn process_turn_response(
+            &self,
+            response_text: &str,
+            input_price: f32,
+            output_price: f32,
+        ) -> Result<ResponsePayload, LLMCoreError> {
+        let mut initial_payload = self.response_parser.parse_response(
+            response_text,
             &self.user_facing_model_name,
-            self.input_price,
-            self.output_price,
+            input_price,
+            output_price,
         )?;
+        self.extract_configured_reasoning(&mut initial_payload);
+        self.restore_prefill(&mut initial_payload);
 
         // --- Normalize response for different provider behaviors ---
         // OpenAI-style providers return schema results in `tool_calls`. We normalize this
@@ -460,6 +1566,10 @@ impl Orchestra {
         let mut processed_payload = initial_payload;
         if let InternalStructuredStrategy::Schema(schema) = &self.structured_strategy {
             if let Some(choice) = processed_payload.choices.get_mut(0) {
+                // A refused request has no schema result to normalize.
+                if let Some(refusal) = &choice.message.refusal {
+                    return Err(LLMCoreError::ContentFiltered(refusal.clone()));
+                }
                 let mut extracted_args: Option<JsonValue> = None;
 
                 // First, check tool_calls for a matching schema name (for OpenAI, Grok, etc.).
@@ -506,7 +1616,7 @@ impl Orchestra {
                     .ok_or(LLMCoreError::ResponseParseError(
                         "No content for Lucky parsing".to_string(),
                     ))?;
-                let lucky_json = lucky::parse_lucky_response(content, fmt, "###")?;
+                let lucky_json = lucky::parse_lucky_response(content, fmt, &self.lucky_delimiter)?;
                 let mut new_payload = processed_payload;
                 if let Some(choice) = new_payload.choices.get_mut(0) {
                     choice.message.content = Some(serde_json::to_string(&lucky_json)?);
@@ -516,7 +1626,7 @@ impl Orchestra {
             _ => processed_payload,
         };
 
-        Ok((final_payload, messages))
+        Ok(final_payload)
     }
 
     /// Handles the multi-step tool execution cycle if the initial response contained a tool call.
@@ -524,6 +1634,7 @@ impl Orchestra {
             &self,
             initial_payload: ResponsePayload,
             mut messages: Vec<Message>,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
         ) -> Result<ResponsePayload, LLMCoreError> {
         let tool_library_arc = match &self.tool_strategy {
             InternalToolStrategy::Payload(lib) | InternalToolStrategy::Lucky(lib, _) => Some(lib),
@@ -546,6 +1657,7 @@ impl Orchestra {
         }
         
         let tool_library = tool_library_arc.unwrap();
+        let history_len = messages.len();
         let mut assistant_message = initial_payload.choices.get(0).unwrap().message.clone();
         
         // --- Extract and Execute Tool Calls ---
@@ -557,13 +1669,14 @@ impl Orchestra {
             let tool_calls = assistant_message.tool_calls.take().unwrap(); // Now take the tool calls to run them.
             for call in tool_calls {
                 let result = self
-                    .execute_tool(
+                    .execute_tool_with_events(
                         Arc::clone(tool_library),
                         &call.function.name,
                         call.function.arguments,
+                        on_event,
                     )
                     .await;
-                messages.push(format_tool_message(result, call.id, call.function.name));
+                messages.push(Message::tool_result(result, call.id, call.function.name)?);
             }
         } else if let Some(content) = assistant_message.content.take() {
             let content_trimmed = content.trim();
@@ -577,9 +1690,9 @@ impl Orchestra {
                         for call in calls {
                             let tool_id = format!("granite-tool-{}", uuid::Uuid::new_v4());
                             let result = self
-                                .execute_tool(Arc::clone(tool_library), &call.name, call.arguments)
+                                .execute_tool_with_events(Arc::clone(tool_library), &call.name, call.arguments, on_event)
                                 .await;
-                            messages.push(format_tool_message(result, tool_id, call.name));
+                            messages.push(Message::tool_result(result, tool_id, call.name)?);
                         }
                     }
                 }
@@ -603,12 +1716,17 @@ impl Orchestra {
                 let tool_id = format!("lucky-tool-{}", uuid::Uuid::new_v4());
                 
                 let result = self
-                    .execute_tool(Arc::clone(tool_library), &name, args.clone())
+                    .execute_tool_with_events(Arc::clone(tool_library), &name, args.clone(), on_event)
                     .await;
                 
                 // Add assistant's "thought" (the tool call) and the result to history
-                messages.push(Message { role: "assistant".to_string(), tool_calls: Some(vec![crate::tools::ToolCall { id: tool_id.clone(), tool_type: "function".to_string(), function: crate::tools::FunctionCall { name: name.clone(), arguments: args }}]), ..Default::default()});
-                messages.push(format_tool_message(result, tool_id, name));
+                let call = crate::tools::ToolCall {
+                    id: tool_id.clone(),
+                    tool_type: "function".to_string(),
+                    function: crate::tools::FunctionCall { name: name.clone(), arguments: args },
+                };
+                messages.push(Message::assistant_tool_calls(None, vec![call])?);
+                messages.push(Message::tool_result(result, tool_id, name)?);
             }
         }
         
@@ -617,37 +1735,103 @@ impl Orchestra {
             println!("[ORCHESTRA DEBUG] Synthesizing tool results...");
         }
 
-        // Add a final system message to guide the synthesis turn.
-        // This is a strong prompt engineering technique that places the final instruction
-        // at the end of the context, which is often more effective for some models.
-        // let synthesis_system_prompt = "You have just received the result from a tool. Your task is to respond to the user's original query in a natural, conversational way based on the tool's output.";
-        // messages.push(format_system_message(synthesis_system_prompt.to_string()));
-        
-        let synthesis_messages_for_debug = messages.clone(); // Clone for debugging.
+        // The tool results of this cycle, for citation lookup. Taken before the synthesis
+        // prompt is added, since that can insert a system message and shift the history.
+        let tool_outputs: Vec<String> = messages[history_len..]
+            .iter()
+            .filter(|m| m.role == "tool")
+            .filter_map(|m| m.content.clone())
+            .collect();
+
+        // The synthesis prompt goes into the system message rather than a trailing one,
+        // which Anthropic would drop. `messages` is this turn's copy, so it is not persisted.
+        if let Some(prompt) = &self.synthesis_prompt {
+            append_system_instruction(&mut messages, prompt.clone());
+        }
+
+        let synthesis_messages = messages.clone(); // Kept for citation lookup and debugging.
         let url = self.provider_adapter.get_request_url(&self.base_url, &self.model_tag, &self.api_key);
         let headers = self.provider_adapter.get_request_headers(&self.api_key);
-        let payload = self.provider_adapter.prepare_request_payload(&self.model_tag, messages, self.temperature, None, None, self.thinking_mode, self.debug);
-        let final_text = client::execute_single_call(url, headers, payload, &self.retry_policy).await?;
+        let payload = self.stream_payload(self.finish_payload(
+            self.provider_adapter.prepare_request_payload(&self.model_tag, messages, self.request_temperature(), None, None, self.thinking_mode, self.debug)
+        ));
+        let started = Instant::now();
+        let (final_text, response_headers) = self.post_turn(url, headers, payload, on_event).await?;
+        let final_text = self.assemble_streamed_body(final_text)?;
+        let elapsed = started.elapsed();
         
-        let final_payload = self.response_parser.parse_response(
+        let mut final_payload = self.response_parser.parse_response(
             &final_text,
             &self.user_facing_model_name,
             self.input_price,
             self.output_price,
         )?;
+        final_payload.estimate_missing_usage(count_message_tokens(&synthesis_messages), self.input_price, self.output_price);
+        client::apply_response_headers(&mut final_payload, &response_headers);
+        if let Some(usage) = &mut final_payload.usage {
+            usage.record_duration(elapsed);
+        }
+
+        // Map any `[source_id]` citations in the answer back to the knowledge base
+        // sources returned by the tools in this cycle.
+        if let Some(content) = final_payload.choices.first().and_then(|c| c.message.content.as_deref()) {
+            let citations = retrieval::extract_citations(content, &tool_outputs);
+            if !citations.is_empty() {
+                final_payload.citations = Some(citations);
+            }
+        }
 
         if self.debug {
-            println!("[ORCHESTRA DEBUG] Final message history sent for synthesis:\n{:#?}", synthesis_messages_for_debug);
+            println!("[ORCHESTRA DEBUG] Final message history sent for synthesis:\n{:#?}", synthesis_messages);
         }
 
         Ok(final_payload)
     }
 
+    /// Runs `execute_tool`, reporting the call and its result to `on_event`.
+    async fn execute_tool_with_events(
+            &self,
+            library: Arc<ToolLibrary>,
+            name: &str,
+            args: JsonValue,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
+        ) -> String {
+        on_event(ChatEvent::ToolCallStarted { name: name.to_string(), args: args.clone() });
+        let result = self.execute_tool(library, name, args).await;
+        on_event(ChatEvent::ToolResult { name: name.to_string(), result: result.clone() });
+        result
+    }
+
     /// Executes a single tool function and returns the result as a string.
     /// This is now async and uses `spawn_blocking` to avoid stalling the runtime.
     async fn execute_tool(&self, library: Arc<ToolLibrary>, name: &str, args: JsonValue) -> String {
         let tool_name = name.to_string();
         let debug_mode = self.debug;
+        // Models often send numbers as strings or objects as JSON text; match the schema first.
+        let args = match library.get(&tool_name) {
+            Some(tool) => lucky::coerce_to_schema(args, &tool.definition().function.parameters),
+            None => args,
+        };
+
+        // Command and HTTP tools are awaited directly; they don't need a blocking thread.
+        if let Some(Tool::Command { program, args_template, timeout, .. }) = library.get(&tool_name) {
+            if debug_mode {
+                println!("[ORCHESTRA DEBUG] Executing command tool: {} ({})", &tool_name, program);
+            }
+            return match run_command_tool(program, args_template, *timeout, &args).await {
+                Ok(res) => serde_json::to_string(&res).unwrap_or_else(|e| e.to_string()),
+                Err(e) => e,
+            };
+        }
+        if let Some(Tool::Http { method, url, headers, timeout, .. }) = library.get(&tool_name) {
+            if debug_mode {
+                println!("[ORCHESTRA DEBUG] Executing HTTP tool: {} ({} {})", &tool_name, method, url);
+            }
+            return match run_http_tool(method, url, headers, *timeout, &args).await {
+                Ok(res) => serde_json::to_string(&res).unwrap_or_else(|e| e.to_string()),
+                Err(e) => e,
+            };
+        }
     
         // Use spawn_blocking to run the synchronous tool code on a dedicated thread.
         let result = tokio::task::spawn_blocking(move || {
@@ -685,6 +1869,7 @@ impl Orchestra {
                             Err(e) => format!("Python tool execution failed: {}", e),
                         }
                     }),
+                    Tool::Command { .. } | Tool::Http { .. } => unreachable!("command and HTTP tools are handled above"),
                 },
                 None => format!("Tool '{}' not found in library.", &tool_name),
             }
@@ -707,6 +1892,97 @@ impl Orchestra {
     /// This function orchestrates the entire process, including prompt preparation,
     /// making the API call, and handling multi-step tool execution.
     pub async fn call_ai(&self, messages: Vec<Message>) -> Result<ResponsePayload, LLMCoreError> {
+        self.call_ai_with_events(messages, |_| {}).await
+    }
+
+    /// Like `call_ai`, but reports tool calls, tool results, the answer and the final
+    /// payload to `on_event` as the turn progresses.
+    ///
+    /// The turn counts as in-flight work for `client::Shutdown`: it is refused once a
+    /// drain has begun, but a turn already under way finishes its tool cycle.
+    pub async fn call_ai_with_events(
+            &self,
+            messages: Vec<Message>,
+            mut on_event: impl FnMut(ChatEvent) + Send,
+        ) -> Result<ResponsePayload, LLMCoreError> {
+        client::in_flight(self.call_with_fallbacks(messages, &mut on_event)).await?
+    }
+
+    /// Like `call_ai_with_events`, but samples this one turn at `temperature` instead of
+    /// the instance's own, which is left unchanged. The temperature is checked against
+    /// each model's range as `set_sampling_policy` describes.
+    pub async fn call_ai_at_temperature(
+            &self,
+            messages: Vec<Message>,
+            temperature: f32,
+            on_event: impl FnMut(ChatEvent) + Send,
+        ) -> Result<ResponsePayload, LLMCoreError> {
+        self.check_temperature(temperature)?;
+        for fallback in &self.fallbacks {
+            self.fallback_for(fallback).check_temperature(temperature)?;
+        }
+        let mut orchestra = self.clone();
+        orchestra.temperature = temperature;
+        orchestra.call_ai_with_events(messages, on_event).await
+    }
+
+    /// Runs the turn on this model, then on each fallback while the failure warrants it.
+    async fn call_with_fallbacks(
+            &self,
+            messages: Vec<Message>,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
+        ) -> Result<ResponsePayload, LLMCoreError> {
+        if self.fallbacks.is_empty() {
+            return self.call_model(messages, on_event).await;
+        }
+
+        // Once a tool has run, retrying the turn elsewhere would run it again.
+        let mut tool_ran = false;
+        let mut result = {
+            let mut watch_tools = |event: ChatEvent| {
+                tool_ran |= matches!(event, ChatEvent::ToolCallStarted { .. });
+                on_event(event);
+            };
+            self.call_model(messages.clone(), &mut watch_tools).await
+        };
+        let mut failed_model = self.user_facing_model_name.clone();
+        for fallback in &self.fallbacks {
+            match &result {
+                Err(e) if e.warrants_fallback() && !tool_ran => {
+                    eprintln!(
+                        "[WARNING] Model '{}' failed ({}); falling back to '{}'.",
+                        failed_model, e, fallback.user_facing_model_name
+                    );
+                    let fallback = self.fallback_for(fallback);
+                    let mut watch_tools = |event: ChatEvent| {
+                        tool_ran |= matches!(event, ChatEvent::ToolCallStarted { .. });
+                        on_event(event);
+                    };
+                    result = fallback.call_model(messages.clone(), &mut watch_tools).await.map(|mut payload| {
+                        payload.served_by = Some(fallback.user_facing_model_name.clone());
+                        payload
+                    });
+                    failed_model = fallback.user_facing_model_name;
+                }
+                Err(e) if e.warrants_fallback() => {
+                    eprintln!(
+                        "[WARNING] Model '{}' failed ({}) after running tools; not falling back.",
+                        failed_model, e
+                    );
+                    break;
+                }
+                _ => break,
+            }
+        }
+        result
+    }
+
+    /// Runs one full turn (including any tool cycle) against this model only.
+    async fn call_model(
+            &self,
+            messages: Vec<Message>,
+            on_event: &mut (dyn FnMut(ChatEvent) + Send),
+        ) -> Result<ResponsePayload, LLMCoreError> {
         let job_id = Uuid::new_v4();
         if self.debug {
             println!("\n[ORCHESTRA DEBUG]");
@@ -717,82 +1993,1654 @@ impl Orchestra {
             println!("Thinking Mode: {}", self.thinking_mode);
             println!("Reasoning Capability: {:?}", self.reasoning_capability);
         }
-        let (initial_payload, updated_messages) = self.execute_initial_turn(messages).await?;
-        let final_payload = self.handle_tool_cycle(initial_payload, updated_messages).await?;
+        let job_trace = self.trace_dir.as_ref().map(|_| Arc::new(Mutex::new(JobTrace::new(job_id, &self.user_facing_model_name))));
+        // Whether the answer's text already reached `on_event` as streamed deltas.
+        let mut streamed = false;
+        let result = {
+            let mut forward = |event: ChatEvent| {
+                streamed |= matches!(event, ChatEvent::ContentDelta(_) | ChatEvent::ReasoningDelta(_));
+                if let Some(job_trace) = &job_trace {
+                    let mut job_trace = job_trace.lock().unwrap_or_else(|e| e.into_inner());
+                    match &event {
+                        ChatEvent::ToolCallStarted { name, args } => job_trace.tool_call_started(name, args),
+                        ChatEvent::ToolResult { name, result } => job_trace.tool_result(name, result),
+                        _ => {}
+                    }
+                }
+                on_event(event)
+            };
+            let turn = async {
+                let (initial_payload, updated_messages) = self.execute_initial_turn(messages, &mut forward).await?;
+                self.handle_tool_cycle(initial_payload, updated_messages, &mut forward).await
+            };
+            match &job_trace {
+                Some(job_trace) => trace::scope(Arc::clone(job_trace), turn).await,
+                None => turn.await,
+            }
+        };
+        if let (Some(dir), Some(job_trace)) = (&self.trace_dir, &job_trace) {
+            let mut job_trace = job_trace.lock().unwrap_or_else(|e| e.into_inner());
+            job_trace.finish(&result);
+            if let Err(e) = job_trace.save(dir) {
+                eprintln!("[WARNING] Could not save the trace of job {} to '{}': {}", job_id, dir.display(), e);
+            }
+        }
+        let final_payload = result?;
 
-        if let Some(usage) = &final_payload.usage {
+        if let Some(usage) = final_payload.usage.as_ref().filter(|_| self.log_turn_usage) {
             let label = "chat_turn";
-            if let Err(e) = crate::usage::log_usage_turn(job_id, usage, label, &self.user_facing_model_name) {
+            if let Err(e) = crate::usage::log_usage_turn(job_id, usage, label, &self.user_facing_model_name, &self.usage_metadata) {
                 eprintln!("[WARNING] Failed to log usage for chat turn: {}", e);
             }
         }
 
+        if let Some(message) = final_payload.choices.first().map(|c| &c.message).filter(|_| !streamed) {
+            if let Some(reasoning) = message.reasoning_content.clone().filter(|r| !r.is_empty()) {
+                on_event(ChatEvent::ReasoningDelta(reasoning));
+            }
+            if let Some(content) = message.content.clone().filter(|c| !c.is_empty()) {
+                on_event(ChatEvent::ContentDelta(content));
+            }
+        }
+        on_event(ChatEvent::Done(Box::new(final_payload.clone())));
         Ok(final_payload)
     }
 
-    /// Executes a swarm of concurrent API calls.
+    /// Executes a swarm of concurrent API calls and summarizes the outcome.
     /// Note: Swarm calls do not support multi-step tool execution.
+    ///
+    /// This collects every result in memory; use `swarm_stream` for very large runs.
     pub async fn swarm_call(
             &self,
             system_prompt: &str,
             prompts: Vec<String>,
             swarm_size: usize,
-        ) -> Vec<Result<ResponsePayload, LLMCoreError>> {
-        let mut all_payloads = Vec::new();
-
-        for user_prompt in &prompts {
-            let (final_system_prompt, final_user_prompt, schema_for_provider) =
-                match &self.structured_strategy {
-                    InternalStructuredStrategy::Lucky(output_format) => {
-                        let (s, u) = lucky::prepare_lucky_prompt(system_prompt, user_prompt, output_format, "###", None, false);
-                        (s, u, None)
-                    }
-                    InternalStructuredStrategy::Schema(s) => (system_prompt.to_string(), user_prompt.to_string(), Some(s.clone())),
-                    InternalStructuredStrategy::None => (system_prompt.to_string(), user_prompt.to_string(), None),
-                };
+        ) -> SwarmSummary {
+        // For each prompt, the index of the request that answers it.
+        let mut slots = Vec::with_capacity(prompts.len());
+        let mut unique: Vec<&str> = Vec::new();
+        let mut first_seen: HashMap<&str, usize> = HashMap::new();
+        for prompt in &prompts {
+            let slot = if self.dedupe_swarm {
+                *first_seen.entry(prompt).or_insert_with(|| {
+                    unique.push(prompt);
+                    unique.len() - 1
+                })
+            } else {
+                unique.push(prompt);
+                unique.len() - 1
+            };
+            slots.push(slot);
+        }
+        if self.debug && unique.len() < prompts.len() {
+            println!("[ORCHESTRA DEBUG] Deduplicated {} prompts into {} calls.", prompts.len(), unique.len());
+        }
 
-            let messages = vec![
-                format_system_message(final_system_prompt),
-                format_user_message(final_user_prompt),
-            ];
+        let started = Instant::now();
+        let mut responses: Vec<Option<Result<ResponsePayload, LLMCoreError>>> = (0..unique.len()).map(|_| None).collect();
+        self.swarm_stream(system_prompt, unique.iter().map(|p| p.to_string()), swarm_size, |index, result| {
+            responses[index] = Some(result);
+        })
+        .await;
+        let responses = responses
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(LLMCoreError::ChatError("Swarm request produced no result.".to_string()))))
+            .collect();
 
-            let payload = self.provider_adapter.prepare_request_payload(
-                &self.model_tag, messages, self.temperature, schema_for_provider, None, self.thinking_mode, self.debug
-            );
-            all_payloads.push(payload);
+        let mut summary = SwarmSummary::from_results(fan_out_responses(responses, &slots));
+        // The calls overlap, so their summed durations would overstate the swarm's time.
+        summary.usage.record_duration(started.elapsed());
+        summary
+    }
+
+    /// Streams prompts through the swarm and hands each parsed result to `on_result`
+    /// as soon as it completes, together with the prompt's index. Results arrive in
+    /// completion order, not prompt order.
+    ///
+    /// Prompts are pulled lazily and at most `swarm_size` requests are in flight, so
+    /// memory use does not grow with the number of prompts. The total usage is logged
+    /// as one entry and returned.
+    pub async fn swarm_stream<I, F>(
+            &self,
+            system_prompt: &str,
+            prompts: I,
+            swarm_size: usize,
+            mut on_result: F,
+        ) -> Usage
+        where
+            I: IntoIterator<Item = String>,
+            F: FnMut(usize, Result<ResponsePayload, LLMCoreError>),
+        {
+        // One job id covers the whole swarm so its spend shows up as a single usage entry.
+        let job_id = Uuid::new_v4();
+        if self.debug {
+            println!("\n[ORCHESTRA DEBUG]");
+            println!("Swarm Job ID: {}", job_id);
+            println!("Model: {}", self.user_facing_model_name);
+            println!("Swarm size: {}", swarm_size);
         }
 
         let url = self.provider_adapter.get_request_url(&self.base_url, &self.model_tag, &self.api_key);
         let headers = self.provider_adapter.get_request_headers(&self.api_key);
+        let mut prompts = prompts.into_iter();
+        // While a cached context is referenced, the prompts in flight are kept so that a
+        // request whose cache the provider rejects can be resent with the content inlined.
+        let cache = self.usable_cache().cloned();
+        let pending_prompts: Mutex<HashMap<usize, String>> = Mutex::default();
+        // Prompt token estimates of the requests in flight, for responses without usage.
+        let prompt_estimates: Mutex<HashMap<usize, usize>> = Mutex::default();
+        let mut rejected: Vec<(usize, String)> = Vec::new();
+        let payloads = prompts.by_ref().enumerate().map(|(index, user_prompt)| {
+            let payload = self.swarm_payload(system_prompt, &user_prompt);
+            prompt_estimates.lock().unwrap().insert(index, count_tokens(system_prompt) + count_tokens(&user_prompt));
+            if cache.is_some() {
+                pending_prompts.lock().unwrap().insert(index, user_prompt);
+            }
+            payload
+        });
 
-        let raw_responses =
-            client::execute_swarm_call(url, headers, all_payloads, swarm_size, self.retry_policy)
+        let started = Instant::now();
+        let mut usage = Usage::default();
+        let (mut success_count, mut failure_count) = (0, 0);
+        let mut record = |index: usize, raw: Result<String, LLMCoreError>| {
+            let prompt_tokens = prompt_estimates.lock().unwrap().remove(&index).unwrap_or(0);
+            let result = raw.and_then(|text| self.parse_swarm_response(&text)).map(|mut payload| {
+                payload.estimate_missing_usage(prompt_tokens, self.input_price, self.output_price);
+                payload
+            });
+            match &result {
+                Ok(payload) => {
+                    success_count += 1;
+                    if let Some(turn_usage) = &payload.usage {
+                        usage += turn_usage.clone();
+                    }
+                }
+                Err(_) => failure_count += 1,
+            }
+            on_result(index, result);
+        };
+        // The whole swarm, resends included, is one unit of in-flight work, so a drain
+        // does not cut it short.
+        let streamed = client::in_flight(async {
+            client::execute_swarm_stream(url.clone(), headers.clone(), payloads, swarm_size, self.retry_policy, |index, raw| {
+                let prompt = pending_prompts.lock().unwrap().remove(&index);
+                if let (Err(e), Some(context), Some(prompt)) = (&raw, &cache, prompt) {
+                    if rejects_cached_context(e, &context.name) {
+                        context.invalidate();
+                        rejected.push((index, prompt));
+                        return;
+                    }
+                }
+                record(index, raw);
+            })
+            .await;
+
+            if let Some(context) = cache.as_ref().filter(|_| !rejected.is_empty()) {
+                eprintln!(
+                    "[WARNING] Cached context '{}' was rejected; resending {} swarm requests with its content inlined.",
+                    context.name,
+                    rejected.len()
+                );
+                let (indexes, resent): (Vec<usize>, Vec<String>) = rejected.into_iter().unzip();
+                let payloads: Vec<JsonValue> = resent.iter().map(|user_prompt| self.swarm_payload(system_prompt, user_prompt)).collect();
+                client::execute_swarm_stream(url, headers, payloads, swarm_size, self.retry_policy, |i, raw| {
+                    record(indexes[i], raw)
+                })
                 .await;
+            }
+        })
+        .await;
+        if let Err(LLMCoreError::ConcurrencyError(reason)) = streamed {
+            for (index, _) in prompts.enumerate() {
+                record(index, Err(LLMCoreError::ConcurrencyError(reason.clone())));
+            }
+        }
 
-        raw_responses
-            .into_iter()
-            .map(|res_result| {
-                res_result.and_then(|text| {
-                    let initial_payload = self.response_parser.parse_response(
-                        &text,
-                        &self.user_facing_model_name,
-                        self.input_price,
-                        self.output_price,
-                    )?;
-                    match &self.structured_strategy {
-                        InternalStructuredStrategy::Lucky(fmt) => {
-                            let content = initial_payload.choices.get(0).and_then(|c| c.message.content.as_ref()).ok_or_else(|| LLMCoreError::ResponseParseError("No content for Lucky parsing".to_string()))?;
-                            let lucky_json = lucky::parse_lucky_response(content, fmt, "###")?;
-                            let mut new_payload = initial_payload;
-                            if let Some(choice) = new_payload.choices.get_mut(0) {
-                                choice.message.content = Some(serde_json::to_string(&lucky_json)?);
-                            }
-                            Ok(new_payload)
-                        }
-                        _ => Ok(initial_payload),
-                    }
+        // Wall-clock time of the whole swarm, since its calls run concurrently.
+        usage.record_duration(started.elapsed());
+        if self.debug {
+            println!(
+                "[ORCHESTRA DEBUG] Swarm finished: {} succeeded, {} failed.",
+                success_count, failure_count
+            );
+        }
+        if success_count > 0 {
+            let label = format!("swarm {} calls", success_count + failure_count);
+            if let Err(e) = crate::usage::log_usage_turn(job_id, &usage, &label, &self.user_facing_model_name, &self.usage_metadata) {
+                eprintln!("[WARNING] Failed to log usage for swarm call: {}", e);
+            }
+        }
+
+        usage
+    }
+
+    /// Builds the request payload for one swarm prompt.
+    fn swarm_payload(&self, system_prompt: &str, user_prompt: &str) -> JsonValue {
+        let (final_system_prompt, final_user_prompt, schema_for_provider) =
+            match &self.structured_strategy {
+                InternalStructuredStrategy::Lucky(output_format) => {
+                    let (s, u) = lucky::prepare_lucky_prompt(system_prompt, user_prompt, output_format, &self.lucky_delimiter, None, false);
+                    (s, u, None)
+                }
+                InternalStructuredStrategy::Schema(s) => (system_prompt.to_string(), user_prompt.to_string(), Some(s.clone())),
+                InternalStructuredStrategy::None => (system_prompt.to_string(), user_prompt.to_string(), None),
+            };
+
+        let mut messages = vec![
+            format_system_message(final_system_prompt),
+            format_user_message(final_user_prompt),
+        ];
+        self.inline_cached_context(&mut messages);
+        if self.json_mode {
+            ensure_json_instruction(&mut messages);
+        }
+        self.add_prefill_instruction(&mut messages);
+
+        self.finish_payload(self.provider_adapter.prepare_request_payload(
+            &self.model_tag, messages, self.request_temperature(), schema_for_provider, None, self.thinking_mode, self.debug
+        ))
+    }
+
+    /// Parses one swarm response, applying Lucky parsing when that strategy is active.
+    fn parse_swarm_response(&self, text: &str) -> Result<ResponsePayload, LLMCoreError> {
+        let mut initial_payload = self.response_parser.parse_response(
+            text,
+            &self.user_facing_model_name,
+            self.input_price,
+            self.output_price,
+        )?;
+        self.extract_configured_reasoning(&mut initial_payload);
+        self.restore_prefill(&mut initial_payload);
+        match &self.structured_strategy {
+            InternalStructuredStrategy::Lucky(fmt) => {
+                let content = initial_payload.choices.get(0).and_then(|c| c.message.content.as_ref()).ok_or_else(|| LLMCoreError::ResponseParseError("No content for Lucky parsing".to_string()))?;
+                let lucky_json = lucky::parse_lucky_response(content, fmt, &self.lucky_delimiter)?;
+                let mut new_payload = initial_payload;
+                if let Some(choice) = new_payload.choices.get_mut(0) {
+                    choice.message.content = Some(serde_json::to_string(&lucky_json)?);
+                }
+                Ok(new_payload)
+            }
+            _ => Ok(initial_payload),
+        }
+    }
+
+    /// Sends independent requests through OpenAI's Batch API, which is billed at half
+    /// price but can take up to 24 hours. Polls every `poll_interval` until the batch
+    /// finishes and returns one result per request, in order.
+    ///
+    /// Only available for OpenAI models. Structured output is supported; tools are not.
+    /// The whole batch, polling included, is one unit of in-flight work, so a drain waits
+    /// for it (see `client::Shutdown`).
+    pub async fn batch_call(
+            &self,
+            requests: Vec<Vec<Message>>,
+            poll_interval: Duration,
+        ) -> Result<Vec<Result<ResponsePayload, LLMCoreError>>, LLMCoreError> {
+        client::in_flight(self.run_batch(requests, poll_interval)).await?
+    }
+
+    async fn run_batch(
+            &self,
+            requests: Vec<Vec<Message>>,
+            poll_interval: Duration,
+        ) -> Result<Vec<Result<ResponsePayload, LLMCoreError>>, LLMCoreError> {
+        if self.provider_adapter.get_provider_name() != "OpenAI" {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Batch calls are only supported for OpenAI models, not '{}'.",
+                self.user_facing_model_name
+            )));
+        }
+        if !matches!(self.tool_strategy, InternalToolStrategy::None) {
+            return Err(LLMCoreError::ConfigError("Batch calls do not support tools.".to_string()));
+        }
+        if requests.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let adapter = OpenAIAdapter;
+        let endpoint = adapter.batch_endpoint(&self.model_tag);
+        let mut jsonl = String::new();
+        for (i, messages) in requests.iter().enumerate() {
+            let payload = self.prepare_turn_payload(messages)?;
+            jsonl.push_str(&OpenAIAdapter::batch_request_line(&format!("request-{}", i), endpoint, payload).to_string());
+            jsonl.push('\n');
+        }
+
+        let input_file_id = adapter.upload_batch_file(&self.base_url, &self.api_key, jsonl, &self.retry_policy).await?;
+        let mut batch = adapter.create_batch(&self.base_url, &self.api_key, &input_file_id, endpoint, &self.retry_policy).await?;
+        if self.debug {
+            println!("[ORCHESTRA DEBUG] Submitted batch {} with {} requests.", batch.id, requests.len());
+        }
+
+        while !batch.is_finished() {
+            tokio::time::sleep(poll_interval).await;
+            // A batch can take hours, so a transient failure while polling must not lose it.
+            batch = match adapter.retrieve_batch(&self.base_url, &self.api_key, &batch.id, &self.retry_policy).await {
+                Ok(updated) => updated,
+                Err(e) if e.warrants_fallback() => {
+                    eprintln!("[WARNING] Polling batch {} failed ({}); retrying.", batch.id, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if self.debug {
+                println!("[ORCHESTRA DEBUG] Batch {} status: {}", batch.id, batch.status);
+            }
+        }
+        if batch.status != "completed" {
+            return Err(LLMCoreError::ApiError(format!(
+                "Batch {} ended with status '{}'.",
+                batch.id, batch.status
+            )));
+        }
+
+        let mut outputs = HashMap::new();
+        for file_id in [&batch.output_file_id, &batch.error_file_id].into_iter().flatten() {
+            let content = adapter.download_file(&self.base_url, &self.api_key, file_id, &self.retry_policy).await?;
+            outputs.extend(OpenAIAdapter::parse_batch_output(&content));
+        }
+
+        let input_price = self.input_price * BATCH_PRICE_FACTOR;
+        let output_price = self.output_price * BATCH_PRICE_FACTOR;
+        Ok((0..requests.len())
+            .map(|i| match outputs.remove(&format!("request-{}", i)) {
+                Some(Ok(body)) => self.process_turn_response(&body, input_price, output_price).map(|mut payload| {
+                    payload.estimate_missing_usage(count_message_tokens(&requests[i]), input_price, output_price);
+                    payload
+                }),
+                Some(Err(e)) => Err(e),
+                None => Err(LLMCoreError::ApiError(format!("Batch {} returned no result for request {}.", batch.id, i))),
+            })
+            .collect())
+    }
+}
+
+/// Returns `true` when `error` is the provider refusing the cached context `name`.
+fn rejects_cached_context(error: &LLMCoreError, name: &str) -> bool {
+    match error {
+        // Gemini answers a missing cache with 403, which reads as an authentication failure.
+        LLMCoreError::ApiErrorDetailed { status, body } | LLMCoreError::AuthenticationFailed { status, body, .. } => {
+            matches!(status, 400 | 403 | 404) && (body.to_lowercase().contains("cachedcontent") || body.contains(name))
+        }
+        _ => false,
+    }
+}
+
+/// Returns the adapter and parser for a provider. Providers registered through
+/// `Orchestra::register_provider` take precedence over the built-in ones.
+fn provider_for(provider_name: &str) -> providers::RegisteredProvider {
+    if let Some(registered) = providers::registered_provider(provider_name) {
+        return registered;
+    }
+    let provider_adapter: Arc<dyn ProviderAdapter> = match provider_name {
+        "OpenAI" => Arc::new(OpenAIAdapter),
+        "Google" => Arc::new(GoogleAdapter),
+        "xAI" => Arc::new(GrokAdapter),
+        "Inception Labs" => Arc::new(MercuryAdapter),
+        "OpenRouter" => Arc::new(OpenRouterAdapter),
+        "Ollama" => Arc::new(OllamaAdapter),
+        "Anthropic" => Arc::new(AnthropicAdapter),
+        _ => Arc::new(UnsupportedAdapter { provider_name: provider_name.to_string() }),
+    };
+    let response_parser: Arc<dyn ResponseParser> = match provider_name {
+        "OpenAI" => Arc::new(OpenAIParser),
+        "Inception Labs" => Arc::new(MercuryParser),
+        "OpenRouter" => Arc::new(OpenRouterParser),
+        "Google" => Arc::new(GoogleParser),
+        "xAI" => Arc::new(GrokParser),
+        "Ollama" => Arc::new(OllamaParser),
+        "Anthropic" => Arc::new(AnthropicParser),
+        _ => Arc::new(UnsupportedParser { provider_name: provider_name.to_string() }),
+    };
+    (provider_adapter, response_parser)
+}
+
+/// Resolves the effective thinking mode from the model's capability and the caller's
+/// request. `Never` models, and `Toggle` models whose provider cannot switch thinking
+/// (`can_toggle`), always resolve to `false`, with a warning if `true` was asked.
+fn resolve_thinking_mode(
+        capability: &ReasoningCapability,
+        requested: Option<bool>,
+        model_name: &str,
+        can_toggle: bool,
+    ) -> bool {
+    match capability {
+        ReasoningCapability::Never => {
+            if requested == Some(true) {
+                eprintln!(
+                    "[WARNING] Model '{}' does not support reasoning; ignoring thinking_mode=true.",
+                    model_name
+                );
+            }
+            false
+        }
+        ReasoningCapability::Toggle if !can_toggle => {
+            if requested == Some(true) {
+                eprintln!(
+                    "[WARNING] The provider of model '{}' has no way to switch on its reasoning; ignoring thinking_mode=true.",
+                    model_name
+                );
+            }
+            false
+        }
+        _ => requested.unwrap_or(*capability == ReasoningCapability::Always),
+    }
+}
+
+const JSON_RETRY_NUDGE: &str =
+    "Your last reply was not valid JSON. Output only the JSON object, with no other text.";
+const LUCKY_RETRY_NUDGE: &str =
+    "Your last reply did not follow the required output format. Reply again using exactly that format, with no other text.";
+
+/// A prompt for `Orchestra::set_synthesis_prompt`.
+pub const SYNTHESIS_PROMPT: &str = "You have just received the results of the tools you called. Now answer the user's original request directly, using those results. Do not call tools or describe the calls you made.";
+
+const JSON_MODE_INSTRUCTION: &str = "Respond with a single valid JSON object.";
+
+/// Adds the JSON mode instruction to the system message unless some message
+/// already mentions JSON.
+fn ensure_json_instruction(messages: &mut Vec<Message>) {
+    let mentions_json = messages
+        .iter()
+        .any(|m| m.content.as_deref().is_some_and(|c| c.to_lowercase().contains("json")));
+    if !mentions_json {
+        append_system_instruction(messages, JSON_MODE_INSTRUCTION.to_string());
+    }
+}
+
+/// The cheapest embedder a provider lists, ties broken by name.
+fn default_embedder(provider: &config::ProviderConfig) -> Option<&config::ModelDetails> {
+    provider
+        .embedders
+        .iter()
+        .min_by(|(a_name, a), (b_name, b)| a.input_price.total_cmp(&b.input_price).then_with(|| a_name.cmp(b_name)))
+        .map(|(_, details)| details)
+}
+
+/// Appends `instruction` to the system message, creating one if needed.
+fn append_system_instruction(messages: &mut Vec<Message>, instruction: String) {
+    match messages.first_mut() {
+        Some(first) if first.role == "system" => {
+            let content = first.content.as_deref().unwrap_or("").trim();
+            first.content = Some(if content.is_empty() {
+                instruction
+            } else {
+                format!("{}\n\n{}", content, instruction)
+            });
+        }
+        _ => messages.insert(0, format_system_message(instruction)),
+    }
+}
+
+/// Expands one response per sent request into one per prompt, following `slots`.
+/// The first prompt for each request gets the original result; later duplicates get a
+/// copy without `usage` (errors are copied as `ApiError` with the same message).
+fn fan_out_responses(
+        responses: Vec<Result<ResponsePayload, LLMCoreError>>,
+        slots: &[usize],
+    ) -> Vec<Result<ResponsePayload, LLMCoreError>> {
+    if slots.len() == responses.len() {
+        return responses;
+    }
+    let copies: Vec<Result<ResponsePayload, String>> = responses
+        .iter()
+        .map(|r| match r {
+            Ok(payload) => {
+                let mut copy = payload.clone();
+                copy.usage = None;
+                Ok(copy)
+            }
+            Err(e) => Err(e.to_string()),
+        })
+        .collect();
+    let mut originals: Vec<Option<Result<ResponsePayload, LLMCoreError>>> = responses.into_iter().map(Some).collect();
+    slots
+        .iter()
+        .map(|&slot| originals[slot].take().unwrap_or_else(|| copies[slot].clone().map_err(LLMCoreError::ApiError)))
+        .collect()
+}
+
+/// Extracts structured data from `text` in one call.
+///
+/// Builds a schema-enforced `Orchestra` for `model_name`, sends `text` as the user
+/// message and deserializes the JSON answer into `T`.
+pub async fn extract<T: DeserializeOwned>(
+        model_name: &str,
+        text: &str,
+        schema: SimpleSchema,
+    ) -> Result<T, LLMCoreError> {
+    let orchestra = Orchestra::new(model_name, Some(0.0), None, Some(schema), None, None)?;
+    let response = orchestra.call_ai(vec![format_user_message(text.to_string())]).await?;
+    let content = response
+        .choices
+        .first()
+        .and_then(|c| c.message.content.as_deref())
+        .ok_or_else(|| LLMCoreError::ResponseParseError("No content in extraction response".to_string()))?;
+    // Some models put a <think> block ahead of the JSON.
+    let json_text = answer_after_reasoning(content, &orchestra.thinking_tags);
+    Ok(serde_json::from_str(json_text.trim())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datam::Choice;
+    use reqwest::header;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    struct MockAdapter {
+        url: String,
+    }
+
+    impl ProviderAdapter for MockAdapter {
+        fn get_provider_name(&self) -> &str {
+            "Ollama"
+        }
+
+        fn prepare_request_payload(
+            &self,
+            model_tag: &str,
+            messages: Vec<Message>,
+            temperature: f32,
+            _schema: Option<SimpleSchema>,
+            _tools: Option<&Vec<ToolDefinition>>,
+            _thinking_mode: bool,
+            _debug: bool,
+        ) -> JsonValue {
+            json!({ "model": model_tag, "messages": messages, "temperature": temperature })
+        }
+
+        fn get_request_url(&self, _base_url: &str, _model_tag: &str, _api_key: &str) -> String {
+            self.url.clone()
+        }
+
+        fn get_request_headers(&self, _api_key: &str) -> header::HeaderMap {
+            header::HeaderMap::new()
+        }
+
+        fn supports_native_schema(&self, _model_tag: &str) -> bool {
+            false
+        }
+
+        fn supports_tools(&self, _model_tag: &str) -> bool {
+            false
+        }
+    }
+
+    struct MockParser;
+
+    impl ResponseParser for MockParser {
+        fn parse_response(
+                &self,
+                raw_response_text: &str,
+                model_name: &str,
+                _input_price: f32,
+                _output_price: f32,
+            ) -> Result<ResponsePayload, LLMCoreError> {
+            let raw: JsonValue = serde_json::from_str(raw_response_text)?;
+            Ok(ResponsePayload {
+                id: "mock-1".to_string(),
+                object: "chat.completion".to_string(),
+                created: 0,
+                model: model_name.to_string(),
+                choices: vec![Choice {
+                    message: Message {
+                        role: "assistant".to_string(),
+                        content: raw["reply"].as_str().map(String::from),
+                        tool_calls: raw["call"].as_str().map(|name| {
+                            vec![crate::tools::ToolCall {
+                                id: "call-1".to_string(),
+                                tool_type: "function".to_string(),
+                                function: crate::tools::FunctionCall { name: name.to_string(), arguments: raw["args"].clone() },
+                            }]
+                        }),
+                        ..Default::default()
+                    },
+                    finish_reason: None,
+                }],
+                usage: serde_json::from_value(raw["usage"].clone()).ok(),
+                citations: None,
+                request_id: None,
+                rate_limit: None,
+                served_by: None,
+            })
+        }
+    }
+
+    // Answers one HTTP request on `stream` with `body` and returns the request body.
+    fn answer(stream: std::net::TcpStream, body: &str) -> String {
+        let text = answer_request(stream, body);
+        text[text.find("\r\n\r\n").unwrap() + 4..].to_string()
+    }
+
+    // Like `answer`, but returns the whole request, including the request line and headers.
+    fn answer_request(stream: std::net::TcpStream, body: &str) -> String {
+        answer_with_status(stream, "200 OK", body)
+    }
+
+    fn answer_with_status(stream: std::net::TcpStream, status: &str, body: &str) -> String {
+        answer_with_content_type(stream, status, "application/json", body)
+    }
+
+    // Answers with a server-sent event body and returns the request body.
+    fn answer_stream(stream: std::net::TcpStream, body: &str) -> String {
+        let text = answer_with_content_type(stream, "200 OK", "text/event-stream", body);
+        text[text.find("\r\n\r\n").unwrap() + 4..].to_string()
+    }
+
+    fn answer_with_content_type(mut stream: std::net::TcpStream, status: &str, content_type: &str, body: &str) -> String {
+        let request = read_request(&mut stream);
+        respond(&mut stream, status, content_type, body);
+        request
+    }
+
+    // Reads one whole HTTP request, headers and body.
+    fn read_request(stream: &mut std::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let content_length = text[..header_end]
+                    .lines()
+                    .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                if request.len() >= header_end + 4 + content_length {
+                    break;
+                }
+            }
+            if n == 0 {
+                break;
+            }
+        }
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    fn respond(stream: &mut std::net::TcpStream, status: &str, content_type: &str, body: &str) {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).unwrap();
+    }
+
+    // Answers a single HTTP request with `body` and returns the request body it received.
+    fn serve_once(listener: TcpListener, body: &'static str) -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || answer(listener.accept().unwrap().0, body))
+    }
+
+    #[tokio::test]
+    async fn registered_provider_is_used_for_calls() {
+        // A name of its own, so tests running alongside keep the built-in providers.
+        let name = "Mock Provider (registry test)";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = serve_once(listener, r#"{"reply": "hello from the mock"}"#);
+
+        let config: config::ProviderConfig = serde_json::from_value(json!({
+            "api_key": "mock-key",
+            "base_url": url,
+            "models": {
+                "MOCK MODEL (registry test)": {"model_tag": "mock-model-1", "input_price": 0.0, "aliases": ["mock-registry"]}
+            }
+        }))
+        .unwrap();
+        Orchestra::register_provider(name, Arc::new(MockAdapter { url }), Arc::new(MockParser), Some(config));
+        let orchestra = Orchestra::new("MOCK-REGISTRY", None, None, None, Some(false), None).unwrap();
+        assert_eq!(orchestra.user_facing_model_name, "MOCK MODEL (registry test)");
+
+        let response = orchestra
+            .call_ai(vec![format_user_message("Hi".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(response.id, "mock-1");
+        assert_eq!(response.choices[0].message.content.as_deref(), Some("hello from the mock"));
+
+        let request: JsonValue = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(request["model"], json!("mock-model-1"));
+
+        assert!(Orchestra::unregister_provider(name));
+        assert!(!Orchestra::unregister_provider(name));
+        assert_eq!(provider_for(name).0.get_provider_name(), name);
+        assert!(Orchestra::new("MOCK-REGISTRY", None, None, None, Some(false), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn embed_uses_the_providers_cheapest_embedder() {
+        let openai = &config::MODEL_LIBRARY.providers["OpenAI"];
+        assert_eq!(default_embedder(openai).unwrap().model_tag, "text-embedding-3-small");
+
+        let orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        let err = orchestra.embed(vec!["hello".to_string()]).await.unwrap_err();
+        assert!(matches!(err, LLMCoreError::ConfigError(msg) if msg.contains("no embedding model")));
+    }
+
+    #[test]
+    fn configured_thinking_tags_shape_prompt_and_reply() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.response_parser = Arc::new(MockParser);
+        assert!(orchestra.set_thinking_tags(vec!["<reasoning>".to_string()]).is_err());
+        assert!(orchestra.set_thinking_tags(Vec::new()).is_err());
+        orchestra.set_thinking_tags(vec!["reasoning".to_string(), "scratchpad".to_string()]).unwrap();
+
+        let prompt = orchestra.reasoning_prompt();
+        assert!(prompt.contains("<reasoning>") && prompt.contains("</reasoning>"));
+        assert!(!prompt.contains("think>"));
+
+        let reply = r#"{"reply": "<reasoning>plan</reasoning><scratchpad>sums</scratchpad>Paris"}"#;
+        let payload = orchestra.process_turn_response(reply, 0.0, 0.0).unwrap();
+        let message = &payload.choices[0].message;
+        assert_eq!(message.content.as_deref(), Some("Paris"));
+        assert_eq!(message.reasoning_content.as_deref(), Some("plan\n\nsums"));
+    }
+
+    #[tokio::test]
+    async fn batch_polling_survives_a_transient_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer(listener.accept().unwrap().0, r#"{"id": "file-in"}"#);
+            answer(listener.accept().unwrap().0, r#"{"id": "batch-1", "status": "in_progress"}"#);
+            answer_with_status(listener.accept().unwrap().0, "503 Service Unavailable", r#"{"error": "busy"}"#);
+            answer(
+                listener.accept().unwrap().0,
+                r#"{"id": "batch-1", "status": "completed", "output_file_id": "file-out"}"#,
+            );
+            let line = json!({
+                "custom_id": "request-0",
+                "response": {
+                    "status_code": 200,
+                    "body": {
+                        "id": "chatcmpl-1",
+                        "object": "chat.completion",
+                        "created": 0,
+                        "model": "gpt-4o-mini",
+                        "choices": [{"message": {"role": "assistant", "content": "Paris"}}],
+                    },
+                },
+            });
+            answer(listener.accept().unwrap().0, &line.to_string());
+        });
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(OpenAIAdapter);
+        orchestra.response_parser = Arc::new(OpenAIParser);
+        orchestra.base_url = base_url;
+        let results = orchestra
+            .batch_call(vec![vec![format_user_message("Capital of France?".to_string())]], Duration::from_millis(1))
+            .await
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap().choices[0].message.content.as_deref(), Some("Paris"));
+    }
+
+    #[tokio::test]
+    async fn rejected_cached_context_is_inlined_and_resent() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let rejected = answer_with_status(
+                listener.accept().unwrap().0,
+                "404 Not Found",
+                r#"{"error": {"code": 404, "message": "CachedContent not found (or permission denied)"}}"#,
+            );
+            let resent = answer(
+                listener.accept().unwrap().0,
+                r#"{"candidates": [{"content": {"parts": [{"text": "Paris"}]}}]}"#,
+            );
+            (rejected, resent)
+        });
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(GoogleAdapter);
+        orchestra.response_parser = Arc::new(GoogleParser);
+        orchestra.base_url = base_url;
+        let context = CachedContext {
+            name: "cachedContents/abc".to_string(),
+            expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            system_prompt: Some("Answer from the atlas.".to_string()),
+            content: "France: capital Paris.".to_string(),
+            invalidated: Arc::new(AtomicBool::new(false)),
+        };
+        orchestra.set_cached_context(Some(context.clone())).unwrap();
+
+        let response = orchestra
+            .call_ai(vec![format_user_message("Capital of France?".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(response.choices[0].message.content.as_deref(), Some("Paris"));
+        assert!(!context.is_usable());
+
+        let (rejected, resent) = server.join().unwrap();
+        let rejected: JsonValue = serde_json::from_str(&rejected[rejected.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!(rejected["cachedContent"], json!("cachedContents/abc"));
+        assert!(!rejected.to_string().contains("France: capital Paris."));
+        let resent: JsonValue = serde_json::from_str(&resent).unwrap();
+        assert!(resent.get("cachedContent").is_none());
+        let system = resent["systemInstruction"]["parts"][0]["text"].as_str().unwrap();
+        assert!(system.contains("Answer from the atlas.\n\nFrance: capital Paris."));
+    }
+
+    #[tokio::test]
+    async fn swarm_resends_requests_whose_cache_was_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer_with_status(
+                listener.accept().unwrap().0,
+                "403 Forbidden",
+                r#"{"error": {"code": 403, "message": "CachedContent not found (or permission denied)"}}"#,
+            );
+            answer(
+                listener.accept().unwrap().0,
+                r#"{"candidates": [{"content": {"parts": [{"text": "Paris"}]}}]}"#,
+            )
+        });
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(GoogleAdapter);
+        orchestra.response_parser = Arc::new(GoogleParser);
+        orchestra.base_url = base_url;
+        let context = CachedContext {
+            name: "cachedContents/abc".to_string(),
+            expires_at: None,
+            system_prompt: None,
+            content: "France: capital Paris.".to_string(),
+            invalidated: Arc::new(AtomicBool::new(false)),
+        };
+        orchestra.set_cached_context(Some(context.clone())).unwrap();
+
+        let summary = orchestra.swarm_call("Be brief.", vec!["Capital of France?".to_string()], 1).await;
+        assert_eq!(summary.successful_contents(), vec!["Paris"]);
+        assert!(!context.is_usable());
+
+        let resent: JsonValue = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert!(resent.get("cachedContent").is_none());
+        assert!(resent.to_string().contains("France: capital Paris."));
+    }
+
+    #[tokio::test]
+    async fn tool_cycle_reports_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer(listener.accept().unwrap().0, r#"{"call": "shout", "args": {"text": "hi"}}"#);
+            answer(listener.accept().unwrap().0, r#"{"reply": "They said HI."}"#);
+        });
+
+        fn shout(args: JsonValue) -> Result<JsonValue, String> {
+            Ok(json!(args["text"].as_str().unwrap_or_default().to_uppercase()))
+        }
+        let definition = ToolDefinition::builder("shout", "Upper-cases text.").build();
+        let tools = ToolLibrary::from([("shout".to_string(), Tool::Rust { definition, function: shout })]);
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, Some(tools), None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let mut events = Vec::new();
+        let response = orchestra
+            .call_ai_with_events(vec![format_user_message("Shout hi".to_string())], |event| events.push(event))
+            .await
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(response.choices[0].message.content.as_deref(), Some("They said HI."));
+        assert!(matches!(&events[0], ChatEvent::ToolCallStarted { name, args } if name == "shout" && args["text"] == "hi"));
+        assert!(matches!(&events[1], ChatEvent::ToolResult { result, .. } if result == "\"HI\""));
+        assert!(matches!(&events[2], ChatEvent::ContentDelta(text) if text == "They said HI."));
+        assert!(matches!(&events[3], ChatEvent::Done(_)));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn streamed_tool_call_fragments_run_the_tool_cycle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let first = answer_stream(listener.accept().unwrap().0, concat!(
+                "data: {\"id\":\"s1\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"shout\",\"arguments\":\"{\\\"te\"}}]}}]}\n\n",
+                "data: {\"id\":\"s1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"xt\\\": \\\"hi\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n",
+                "data: [DONE]\n\n",
+            ));
+            let second = answer_stream(listener.accept().unwrap().0, concat!(
+                "data: {\"id\":\"s2\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"They said \"}}]}\n\n",
+                "data: {\"id\":\"s2\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"HI.\"},\"finish_reason\":\"stop\"}]}\n\n",
+                "data: [DONE]\n\n",
+            ));
+            (first, second)
+        });
+
+        fn shout(args: JsonValue) -> Result<JsonValue, String> {
+            Ok(json!(args["text"].as_str().unwrap_or_default().to_uppercase()))
+        }
+        let definition = ToolDefinition::builder("shout", "Upper-cases text.").build();
+        let tools = ToolLibrary::from([("shout".to_string(), Tool::Rust { definition, function: shout })]);
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, Some(tools), None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(OpenAIParser);
+        orchestra.streaming = true;
+
+        let mut deltas = Vec::new();
+        let response = orchestra
+            .call_ai_with_events(vec![format_user_message("Shout hi".to_string())], |event| {
+                if let ChatEvent::ContentDelta(text) = event {
+                    deltas.push(text);
+                }
+            })
+            .await
+            .unwrap();
+        let (first, second) = server.join().unwrap();
+
+        assert_eq!(response.choices[0].message.content.as_deref(), Some("They said HI."));
+        assert_eq!(deltas, vec!["They said ", "HI."]);
+        let first: JsonValue = serde_json::from_str(&first).unwrap();
+        assert_eq!(first["stream"], json!(true));
+        let second: JsonValue = serde_json::from_str(&second).unwrap();
+        let tool_result = second["messages"].as_array().unwrap().last().unwrap();
+        assert_eq!(tool_result["content"], json!("\"HI\""));
+    }
+
+    #[tokio::test]
+    async fn prose_schema_reply_is_retried_with_a_reminder() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer(
+                listener.accept().unwrap().0,
+                r#"{"reply": "Sure! The color is blue.", "usage": {"prompt_tokens": 10, "completion_tokens": 6, "total_tokens": 16}}"#,
+            );
+            answer(
+                listener.accept().unwrap().0,
+                r#"{"reply": "{\"color\": \"blue\"}", "usage": {"prompt_tokens": 14, "completion_tokens": 4, "total_tokens": 18}}"#,
+            )
+        });
+
+        let schema = SimpleSchema { name: "color".to_string(), description: "A color".to_string(), properties: vec![] };
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema), Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let response = orchestra.call_ai(vec![format_user_message("Pick a color".to_string())]).await.unwrap();
+        assert_eq!(response.choices[0].message.content.as_deref(), Some(r#"{"color": "blue"}"#));
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (24, 10, 34));
+
+        let retry: JsonValue = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(retry["messages"][0]["content"], json!(format!("Pick a color\n\n{}", JSON_RETRY_NUDGE)));
+    }
+
+    #[tokio::test]
+    async fn persistent_prose_under_schema_is_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                answer(listener.accept().unwrap().0, r#"{"reply": "I think blue is a lovely color."}"#);
+            }
+        });
+
+        let schema = SimpleSchema { name: "color".to_string(), description: "A color".to_string(), properties: vec![] };
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema), Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let err = orchestra.call_ai(vec![format_user_message("Pick a color".to_string())]).await.unwrap_err();
+        server.join().unwrap();
+        match err {
+            LLMCoreError::ResponseParseError(message) => {
+                assert!(message.contains("prose instead of JSON for schema 'color'"));
+                assert!(message.contains("lovely color"));
+            }
+            other => panic!("expected ResponseParseError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_schema_retry_reports_its_own_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer(listener.accept().unwrap().0, r#"{"reply": "I think blue is a lovely color."}"#);
+            answer_with_status(listener.accept().unwrap().0, "500 Internal Server Error", r#"{"error": "backend down"}"#);
+        });
+
+        let schema = SimpleSchema { name: "color".to_string(), description: "A color".to_string(), properties: vec![] };
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema), Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let err = orchestra.call_ai(vec![format_user_message("Pick a color".to_string())]).await.unwrap_err();
+        server.join().unwrap();
+        assert!(!matches!(err, LLMCoreError::ResponseParseError(_)), "got {:?}", err);
+        assert!(err.to_string().contains("backend down"), "got {:?}", err);
+    }
+
+    #[tokio::test]
+    async fn pre_send_filter_blocks_before_sending() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None).unwrap();
+        orchestra.set_pre_send_filter(Some(Arc::new(|messages: &[Message]| {
+            let blocked = messages
+                .iter()
+                .any(|m| m.content.as_deref().is_some_and(|c| c.contains("forbidden")));
+            if blocked {
+                return Err(LLMCoreError::ContentFiltered("prompt mentions a forbidden topic".to_string()));
+            }
+            Ok(())
+        })));
+
+        let err = orchestra
+            .call_ai(vec![format_user_message("Tell me about the forbidden topic".to_string())])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, LLMCoreError::ContentFiltered(_)));
+    }
+
+    #[tokio::test]
+    async fn oversized_prompt_is_rejected_locally() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None).unwrap();
+        orchestra.set_context_window_check(true);
+
+        // QWEN 3:0.6B lists a 40,960-token window; this prompt is estimated at ~50,000.
+        let prompt = "word ".repeat(40_000);
+        let err = orchestra.call_ai(vec![format_user_message(prompt)]).await.unwrap_err();
+        match err {
+            LLMCoreError::ContextWindowExceeded { tokens, limit } => {
+                assert_eq!(limit, 40_960);
+                assert!(tokens > limit);
+            }
+            other => panic!("expected ContextWindowExceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn swarm_summary_tallies_results() {
+        let mut ok = MockParser.parse_response(r#"{"reply": "first"}"#, "mock", 0.0, 0.0).unwrap();
+        ok.usage = Some(Usage { prompt_tokens: 10, completion_tokens: 5, total_tokens: 15, ..Default::default() });
+        let empty = MockParser.parse_response("{}", "mock", 0.0, 0.0).unwrap();
+        let summary = SwarmSummary::from_results(vec![
+            Ok(ok),
+            Err(LLMCoreError::ApiError("rate limited".to_string())),
+            Ok(empty),
+        ]);
+
+        assert_eq!(summary.success_count, 2);
+        assert_eq!(summary.failure_count, 1);
+        assert_eq!(summary.usage.total_tokens, 15);
+        assert_eq!(summary.successful_contents(), vec!["first"]);
+    }
+
+    #[tokio::test]
+    async fn swarm_dedup_sends_duplicates_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        // The server answers exactly one request, so a second call would fail.
+        let server = serve_once(listener, r#"{"reply": "same answer"}"#);
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+        orchestra.set_swarm_dedup(true);
+
+        let summary = orchestra
+            .swarm_call("Be brief.", vec!["repeat".to_string(), "repeat".to_string()], 2)
+            .await;
+        server.join().unwrap();
+
+        assert_eq!(summary.success_count, 2);
+        assert_eq!(summary.successful_contents(), vec!["same answer", "same answer"]);
+    }
+
+    #[test]
+    fn never_reasoning_models_ignore_thinking_mode() {
+        assert!(!resolve_thinking_mode(&ReasoningCapability::Never, Some(true), "mock", true));
+        assert!(!resolve_thinking_mode(&ReasoningCapability::Never, None, "mock", true));
+        assert!(resolve_thinking_mode(&ReasoningCapability::Always, None, "mock", false));
+        assert!(!resolve_thinking_mode(&ReasoningCapability::PromptInducible, None, "mock", false));
+        assert!(resolve_thinking_mode(&ReasoningCapability::Toggle, Some(true), "mock", true));
+        assert!(!resolve_thinking_mode(&ReasoningCapability::Toggle, Some(true), "mock", false));
+
+        let (_, _, details) = config::MODEL_LIBRARY.find_image_model("GEMINI 2.0 FLASH IMAGE GEN").unwrap();
+        assert_eq!(details.reasoning_capability, ReasoningCapability::Never);
+        assert!(config::MODEL_LIBRARY.find_model("GEMINI 2.0 FLASH IMAGE GEN").is_err());
+        assert_eq!(config::MODEL_LIBRARY.find_image_model("DALL-E 3").unwrap().2.image_price, 0.04);
+    }
+
+    #[tokio::test]
+    async fn fallback_model_answers_when_the_primary_is_down() {
+        let down = TcpListener::bind("127.0.0.1:0").unwrap();
+        let down_url = format!("http://{}/chat", down.local_addr().unwrap());
+        let down_server = std::thread::spawn(move || {
+            let (mut stream, _) = down.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).unwrap();
+            let body = r#"{"error": "overloaded"}"#;
+            let response = format!(
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        let up = TcpListener::bind("127.0.0.1:0").unwrap();
+        let up_url = format!("http://{}/chat", up.local_addr().unwrap());
+        let up_server = serve_once(up, r#"{"reply": "from the fallback"}"#);
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None)
+            .unwrap()
+            .with_fallbacks(vec!["QWEN 3:0.6B"])
+            .unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url: down_url });
+        orchestra.response_parser = Arc::new(MockParser);
+        orchestra.fallbacks[0].provider_adapter = Arc::new(MockAdapter { url: up_url });
+        orchestra.fallbacks[0].response_parser = Arc::new(MockParser);
+        // Settings changed after `with_fallbacks` still reach the fallback.
+        orchestra.set_thinking_tags(vec!["scratchpad".to_string()]).unwrap();
+        assert_eq!(orchestra.fallback_for(&orchestra.fallbacks[0]).thinking_tags, vec!["scratchpad"]);
+
+        let response = orchestra.call_ai(vec![format_user_message("Hi".to_string())]).await.unwrap();
+        assert_eq!(response.choices[0].message.content.as_deref(), Some("from the fallback"));
+        assert_eq!(response.served_by.as_deref(), Some("QWEN 3:0.6B"));
+        down_server.join().unwrap();
+        up_server.join().unwrap();
+
+        assert!(!LLMCoreError::ApiErrorDetailed { status: 400, body: String::new() }.warrants_fallback());
+    }
+
+    #[tokio::test]
+    async fn a_rejected_key_fails_at_once_with_a_typed_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        // Only one request is answered; a retry would find nothing listening.
+        let server = std::thread::spawn(move || {
+            answer_with_status(listener.accept().unwrap().0, "401 Unauthorized", r#"{"error": "invalid api key"}"#);
+        });
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let err = orchestra.call_ai(vec![format_user_message("Hi".to_string())]).await.unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(err, LLMCoreError::AuthenticationFailed { status: 401, .. }), "{:?}", err);
+        assert!(!err.warrants_fallback());
+    }
+
+    #[tokio::test]
+    async fn no_fallback_after_a_tool_has_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer(listener.accept().unwrap().0, r#"{"call": "shout", "args": {"text": "hi"}}"#);
+            answer_with_status(listener.accept().unwrap().0, "503 Service Unavailable", r#"{"error": "overloaded"}"#);
+        });
+
+        fn shout(args: JsonValue) -> Result<JsonValue, String> {
+            Ok(json!(args["text"].as_str().unwrap_or_default().to_uppercase()))
+        }
+        let definition = ToolDefinition::builder("shout", "Upper-cases text.").build();
+        let tools = ToolLibrary::from([("shout".to_string(), Tool::Rust { definition, function: shout })]);
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, Some(tools), None, Some(false), None)
+            .unwrap()
+            .with_fallbacks(vec!["QWEN 3:0.6B"])
+            .unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+        // Nothing listens here, so reaching the fallback would fail with a connection error.
+        orchestra.fallbacks[0].provider_adapter = Arc::new(MockAdapter { url: "http://127.0.0.1:9/chat".to_string() });
+        orchestra.fallbacks[0].response_parser = Arc::new(MockParser);
+
+        let err = orchestra.call_ai(vec![format_user_message("Shout hi".to_string())]).await.unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(err, LLMCoreError::ApiErrorDetailed { status: 503, .. }));
+    }
+
+    #[tokio::test]
+    async fn each_turn_is_traced_to_its_own_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer(listener.accept().unwrap().0, r#"{"call": "shout", "args": {"text": "hi"}}"#);
+            answer(listener.accept().unwrap().0, r#"{"reply": "It said HI.", "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}}"#);
+        });
+
+        fn shout(args: JsonValue) -> Result<JsonValue, String> {
+            Ok(json!(args["text"].as_str().unwrap_or_default().to_uppercase()))
+        }
+        let definition = ToolDefinition::builder("shout", "Upper-cases text.").build();
+        let tools = ToolLibrary::from([("shout".to_string(), Tool::Rust { definition, function: shout })]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, Some(tools), None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+        orchestra.log_turn_usage = false;
+        orchestra.set_trace_dir(Some(dir.path().to_path_buf()));
+
+        orchestra.call_ai(vec![format_user_message("Shout hi".to_string())]).await.unwrap();
+        server.join().unwrap();
+
+        let path = std::fs::read_dir(dir.path()).unwrap().next().unwrap().unwrap().path();
+        let trace: JobTrace = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(trace.requests.len(), 2);
+        assert_eq!(trace.requests[0].payload["messages"][0]["content"], json!("Shout hi"));
+        assert_eq!(trace.requests[1].raw_response.as_deref(), Some(r#"{"reply": "It said HI.", "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}}"#));
+        assert_eq!(trace.tool_calls[0].name, "shout");
+        assert_eq!(trace.tool_calls[0].result.as_deref(), Some("\"HI\""));
+        assert_eq!(trace.response.unwrap().choices[0].message.content.as_deref(), Some("It said HI."));
+        assert_eq!(trace.usage.unwrap().total_tokens, 8);
+    }
+
+    #[tokio::test]
+    async fn the_synthesis_prompt_is_sent_with_the_synthesis_request_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let first = answer(listener.accept().unwrap().0, r#"{"call": "shout", "args": {"text": "hi"}}"#);
+            let second = answer(listener.accept().unwrap().0, r#"{"reply": "It said HI [old#1]."}"#);
+            (first, second)
+        });
+
+        fn shout(args: JsonValue) -> Result<JsonValue, String> {
+            Ok(json!(args["text"].as_str().unwrap_or_default().to_uppercase()))
+        }
+        let definition = ToolDefinition::builder("shout", "Upper-cases text.").build();
+        let tools = ToolLibrary::from([("shout".to_string(), Tool::Rust { definition, function: shout })]);
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, Some(tools), None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+        orchestra.log_turn_usage = false;
+        assert!(orchestra.set_synthesis_prompt(Some(" ".to_string())).is_err());
+        orchestra.set_synthesis_prompt(Some(SYNTHESIS_PROMPT.to_string())).unwrap();
+
+        // A result from an earlier cycle, which this cycle's answer must not be credited with.
+        let earlier_result = json!({ "results": [{ "source_id": "old#1", "title": "Old", "url": "old", "chunk_number": 1 }] });
+        let history = vec![
+            format_user_message("Shout hi".to_string()),
+            crate::datam::format_tool_message(earlier_result.to_string(), "call_0".to_string(), "knowledge_base_search".to_string()),
+        ];
+        let response = orchestra.call_ai(history).await.unwrap();
+        assert!(response.citations.is_none());
+        let (first, second) = server.join().unwrap();
+        let first: JsonValue = serde_json::from_str(&first).unwrap();
+        let second: JsonValue = serde_json::from_str(&second).unwrap();
+
+        assert_eq!(first["messages"][0]["role"], json!("user"));
+        assert_eq!(second["messages"][0]["role"], json!("system"));
+        assert_eq!(second["messages"][0]["content"], json!(SYNTHESIS_PROMPT));
+        assert_eq!(second["messages"][1]["content"], json!("Shout hi"));
+    }
+
+    #[test]
+    fn temperature_is_clamped_or_rejected_per_provider() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", Some(1.5), None, None, Some(false), None).unwrap();
+        assert_eq!(orchestra.request_temperature(), 1.5);
+
+        orchestra.provider_adapter = Arc::new(AnthropicAdapter);
+        assert_eq!(orchestra.request_temperature(), 1.0);
+        let payload = orchestra.swarm_payload("system", "user");
+        assert_eq!(payload["temperature"], json!(1.0));
+
+        assert!(matches!(orchestra.set_sampling_policy(SamplingPolicy::Error), Err(LLMCoreError::ConfigError(_))));
+        assert_eq!(orchestra.sampling_policy, SamplingPolicy::Clamp);
+        orchestra.provider_adapter = Arc::new(OpenAIAdapter);
+        orchestra.set_sampling_policy(SamplingPolicy::Error).unwrap();
+        assert!(orchestra.check_temperature(2.5).is_err());
+        assert!(orchestra.check_temperature(0.2).is_ok());
+    }
+
+    #[tokio::test]
+    async fn temperature_can_be_overridden_for_one_turn() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = serve_once(listener, r#"{"reply": "creative"}"#);
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", Some(0.25), None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+        orchestra.set_sampling_policy(SamplingPolicy::Error).unwrap();
+        let messages = vec![format_user_message("Brainstorm".to_string())];
+        assert!(orchestra.call_ai_at_temperature(messages.clone(), 2.5, |_| {}).await.is_err());
+
+        orchestra.call_ai_at_temperature(messages, 1.5, |_| {}).await.unwrap();
+        let request: JsonValue = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(request["temperature"], json!(1.5));
+        assert_eq!(orchestra.temperature, 0.25);
+    }
+
+    #[test]
+    fn end_user_id_is_sent_in_the_providers_field() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(OpenAIAdapter);
+        assert!(orchestra.swarm_payload("system", "user").get("user").is_none());
+        assert!(orchestra.set_end_user_id(Some(" ".to_string())).is_err());
+
+        orchestra.set_end_user_id(Some("user-42".to_string())).unwrap();
+        assert_eq!(orchestra.swarm_payload("system", "user")["user"], json!("user-42"));
+        orchestra.provider_adapter = Arc::new(AnthropicAdapter);
+        assert_eq!(orchestra.swarm_payload("system", "user")["metadata"]["user_id"], json!("user-42"));
+        orchestra.provider_adapter = Arc::new(GoogleAdapter);
+        let payload = orchestra.swarm_payload("system", "user");
+        assert!(payload.get("user").is_none() && payload.get("metadata").is_none());
+    }
+
+    #[test]
+    fn openrouter_routing_keeps_the_adapter() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        let routing = OpenRouterRouting {
+            order: vec!["Chutes".to_string()],
+            allow_fallbacks: Some(false),
+            data_collection: Some(crate::providers::openrouter::DataCollectionPolicy::Deny),
+            ..Default::default()
+        };
+        assert!(orchestra.set_openrouter_routing(routing.clone()).is_err());
+
+        // Stands in for an adapter registered under the OpenRouter name.
+        let adapter: Arc<dyn ProviderAdapter> = Arc::new(OpenRouterAdapter);
+        orchestra.provider_adapter = adapter.clone();
+        assert!(orchestra.swarm_payload("system", "user").get("provider").is_none());
+        orchestra.set_openrouter_routing(routing).unwrap();
+        assert!(Arc::ptr_eq(&orchestra.provider_adapter, &adapter));
+        assert_eq!(
+            orchestra.swarm_payload("system", "user")["provider"],
+            json!({"order": ["Chutes"], "allow_fallbacks": false, "data_collection": "deny"})
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_saved_and_replayed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer_with_status(listener.accept().unwrap().0, "400 Bad Request", r#"{"error": "bad field"}"#);
+            answer(listener.accept().unwrap().0, r#"{"reply": "fixed"}"#)
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+        orchestra.set_failure_capture(Some(dir.path().to_path_buf()));
+        assert!(orchestra.call_ai(vec![format_user_message("Hi".to_string())]).await.is_err());
+
+        let path = std::fs::read_dir(dir.path()).unwrap().next().unwrap().unwrap().path();
+        let failed = FailedRequest::load(&path).unwrap();
+        assert_eq!(failed.status, Some(400));
+        assert_eq!(failed.response_body.as_deref(), Some(r#"{"error": "bad field"}"#));
+        assert_eq!(failed.payload["messages"][0]["content"], json!("Hi"));
+
+        let body = orchestra.replay_failure(&path).await.unwrap();
+        assert_eq!(body, r#"{"reply": "fixed"}"#);
+        assert!(server.join().unwrap().contains("\"Hi\""));
+    }
+
+    #[test]
+    fn resolved_strategies_are_reported() {
+        let schema = SimpleSchema {
+            name: "answer".to_string(),
+            description: String::new(),
+            properties: vec![lucky::SchemaProperty {
+                name: "text".to_string(),
+                property_type: "string".to_string(),
+                description: String::new(),
+                items: None,
+            }],
+        };
+
+        let native = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema.clone()), None, None).unwrap();
+        assert_eq!((native.structured_mode(), native.tool_mode()), ("native", "none"));
+
+        let lucky = Orchestra::new("GRANITE 3.3:2B", None, None, Some(schema), None, None).unwrap();
+        assert_eq!(lucky.structured_mode(), "lucky");
+
+        let tools = Orchestra::new("DEEPSEEK R1-0528:1.5B", None, Some(ToolLibrary::new()), None, None, None).unwrap();
+        assert_eq!((tools.structured_mode(), tools.tool_mode()), ("none", "lucky"));
+    }
+
+    #[test]
+    fn a_refused_schema_request_is_reported_as_filtered() {
+        let schema = SimpleSchema {
+            name: "answer".to_string(),
+            description: String::new(),
+            properties: vec![lucky::SchemaProperty {
+                name: "text".to_string(),
+                property_type: "string".to_string(),
+                description: String::new(),
+                items: None,
+            }],
+        };
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema), None, None).unwrap();
+        orchestra.response_parser = Arc::new(OpenAIParser);
+        let raw = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "message": {"role": "assistant", "content": null, "refusal": "I can't help with that."},
+                "finish_reason": "stop",
+            }],
+        });
+
+        let err = orchestra.process_turn_response(&raw.to_string(), 0.0, 0.0).unwrap_err();
+        assert!(matches!(err, LLMCoreError::ContentFiltered(text) if text == "I can't help with that."));
+    }
+
+    #[test]
+    fn base_url_override_replaces_the_provider_endpoint() {
+        let orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None)
+            .unwrap()
+            .with_base_url_override("https://gateway.example.com/ollama/")
+            .unwrap();
+        assert_eq!(
+            orchestra.provider_adapter.get_request_url(&orchestra.base_url, &orchestra.model_tag, &orchestra.api_key),
+            "https://gateway.example.com/ollama/api/chat"
+        );
+
+        let invalid = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None)
+            .unwrap()
+            .with_base_url_override("gateway.example.com");
+        assert!(matches!(invalid, Err(LLMCoreError::ConfigError(_))));
+    }
+
+    #[test]
+    fn generation_limits_are_validated_and_applied() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(true), None).unwrap();
+        assert!(matches!(
+            orchestra.set_generation_limits(Some(1024), Some(2048)),
+            Err(LLMCoreError::ConfigError(_))
+        ));
+        assert!(orchestra.swarm_payload("system", "user")["options"].get("num_predict").is_none());
+
+        orchestra.set_generation_limits(Some(512), None).unwrap();
+        assert_eq!(orchestra.swarm_payload("system", "user")["options"]["num_predict"], json!(512));
+    }
+
+    #[tokio::test]
+    async fn raw_request_uses_method_and_path() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/api", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || answer_request(listener.accept().unwrap().0, r#"{"models": []}"#));
+
+        let orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None)
+            .unwrap()
+            .with_base_url_override(&base_url)
+            .unwrap();
+        let response = orchestra.raw_request("get", "/tags", None).await.unwrap();
+        assert_eq!(response, json!({ "models": [] }));
+        assert!(server.join().unwrap().starts_with("GET /api/tags "));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/api", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || answer_with_content_type(listener.accept().unwrap().0, "200 OK", "text/plain", ""));
+        let orchestra = orchestra.with_base_url_override(&base_url).unwrap();
+        assert_eq!(orchestra.raw_request("DELETE", "delete", None).await.unwrap(), JsonValue::Null);
+        server.join().unwrap();
+
+        assert!(matches!(
+            orchestra.raw_request("NOT A METHOD", "tags", None).await,
+            Err(LLMCoreError::ConfigError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn raw_posts_are_only_retried_on_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/api", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            answer_with_status(listener.accept().unwrap().0, "429 Too Many Requests", "{}");
+            answer_with_status(listener.accept().unwrap().0, "429 Too Many Requests", "{}");
+            answer(listener.accept().unwrap().0, r#"{"created": true}"#);
+        });
+
+        let orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None)
+            .unwrap()
+            .with_base_url_override(&base_url)
+            .unwrap();
+        let err = orchestra.raw_request("POST", "create", Some(json!({}))).await.unwrap_err();
+        assert!(matches!(err, LLMCoreError::ApiErrorDetailed { status: 429, .. }));
+
+        let response = orchestra.raw_request_with_retries("POST", "create", Some(json!({}))).await.unwrap();
+        assert_eq!(response, json!({ "created": true }));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn json_mode_sets_format_and_instruction() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None).unwrap();
+        orchestra.set_json_mode(true).unwrap();
+        let payload = orchestra.swarm_payload("You list colors.", "Name three.");
+        assert_eq!(payload["format"], json!("json"));
+        assert_eq!(payload["messages"][0]["content"], json!(format!("You list colors.\n\n{}", JSON_MODE_INSTRUCTION)));
+
+        let mentioned = orchestra.swarm_payload("Answer in JSON.", "Name three.");
+        assert_eq!(mentioned["messages"][0]["content"], json!("Answer in JSON."));
+
+        let schema = SimpleSchema { name: "colors".to_string(), description: "Colors".to_string(), properties: vec![] };
+        let mut structured = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema), None, None).unwrap();
+        assert!(matches!(structured.set_json_mode(true), Err(LLMCoreError::ConfigError(_))));
+    }
+
+    #[test]
+    fn assistant_prefill_is_sent_natively_or_as_an_instruction() {
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.set_assistant_prefill(Some("{ ".to_string())).unwrap();
+        let payload = orchestra.swarm_payload("You list colors.", "Name three.");
+        assert_eq!(payload["messages"][0]["content"], json!("You list colors.\n\nBegin your reply with exactly: {"));
+        assert_eq!(payload["messages"].as_array().unwrap().len(), 2);
+
+        orchestra.provider_adapter = Arc::new(providers::anthropic::AnthropicAdapter);
+        orchestra.response_parser = Arc::new(providers::anthropic::AnthropicParser);
+        let payload = orchestra.swarm_payload("You list colors.", "Name three.");
+        assert_eq!(payload["system"], json!("You list colors."));
+        assert_eq!(payload["messages"][1], json!({ "role": "assistant", "content": "{" }));
+
+        let raw = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4",
+            "content": [{ "type": "text", "text": "\"colors\": [\"red\"]}" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        });
+        let reply = orchestra.parse_swarm_response(&raw.to_string()).unwrap();
+        assert_eq!(reply.choices[0].message.content.as_deref(), Some("{\"colors\": [\"red\"]}"));
+        let nested = json!({
+            "id": "msg_2",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4",
+            "content": [{ "type": "text", "text": "{\"a\": 1}}" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        });
+        let reply = orchestra.parse_swarm_response(&nested.to_string()).unwrap();
+        assert_eq!(reply.choices[0].message.content.as_deref(), Some("{{\"a\": 1}}"));
+
+        let schema = SimpleSchema { name: "colors".to_string(), description: "Colors".to_string(), properties: vec![] };
+        let mut structured = Orchestra::new("QWEN 3:0.6B", None, None, Some(schema), None, None).unwrap();
+        assert!(matches!(structured.set_assistant_prefill(Some("{".to_string())), Err(LLMCoreError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn swarm_stream_delivers_every_result() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for _ in 0..3 {
+                answer(
+                    listener.accept().unwrap().0,
+                    r#"{"reply": "ok", "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}}"#,
+                );
+            }
+        });
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let prompts = (0..3).map(|i| format!("prompt {}", i));
+        let mut seen = Vec::new();
+        let started = Instant::now();
+        let usage = orchestra
+            .swarm_stream("Be brief.", prompts, 1, |index, result| {
+                assert!(result.is_ok());
+                seen.push(index);
+            })
+            .await;
+        let elapsed = started.elapsed().as_millis() as u64;
+        server.join().unwrap();
+
+        seen.sort();
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert_eq!(usage.completion_tokens, 6);
+        assert!(usage.duration_ms.is_some_and(|ms| ms <= elapsed));
+    }
+
+    #[tokio::test]
+    async fn swarm_stream_delivers_results_in_completion_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            // Both requests are read before either is answered, then the second prompt's
+            // request is answered first.
+            let mut requests: Vec<(std::net::TcpStream, String)> = (0..2)
+                .map(|_| {
+                    let mut stream = listener.accept().unwrap().0;
+                    let request = read_request(&mut stream);
+                    (stream, request)
                 })
+                .collect();
+            requests.sort_by_key(|(_, request)| !request.contains("prompt 1"));
+            for (mut stream, _) in requests {
+                respond(&mut stream, "200 OK", "application/json", r#"{"reply": "ok"}"#);
+                // Gives the client time to deliver this result before the next is sent.
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+
+        let mut orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None).unwrap();
+        orchestra.provider_adapter = Arc::new(MockAdapter { url });
+        orchestra.response_parser = Arc::new(MockParser);
+
+        let prompts = (0..2).map(|i| format!("prompt {}", i));
+        let mut seen = Vec::new();
+        orchestra
+            .swarm_stream("Be brief.", prompts, 2, |index, result| {
+                assert!(result.is_ok());
+                seen.push(index);
             })
-            .collect()
+            .await;
+        server.join().unwrap();
+
+        assert_eq!(seen, vec![1, 0]);
     }
-} 
\ No newline at end of file
+}
@@ -1,16 +1,21 @@
-use super::{ProviderAdapter, ResponseParser};
-use crate::datam::{Choice, Message, ResponsePayload};
+use super::{has_reasoning_toggle, GenerationLimits, ProviderAdapter, ResponseParser, MIN_ANSWER_TOKENS};
+use crate::datam::{Choice, Message, ResponsePayload, extract_think_blocks};
 use crate::error::LLMCoreError;
 use crate::lucky::SimpleSchema;
 use crate::tools::{ToolCall, ToolDefinition};
 use reqwest::header;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
-use regex::Regex;
 
 pub struct AnthropicAdapter;
 pub struct AnthropicParser;
 
+/// Output cap used when the caller sets no `max_tokens`.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+/// Thinking budget used when the caller sets none. Anthropic's minimum is 1024.
+const DEFAULT_THINKING_BUDGET: u32 = 2048;
+const MIN_THINKING_BUDGET: u32 = 1024;
+
 // --- Request Structs ---
 #[derive(Serialize)]
 struct AnthropicRequestPayload {
@@ -24,6 +29,8 @@ struct AnthropicRequestPayload {
     tools: Option<Vec<AnthropicTool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_choice: Option<JsonValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<JsonValue>,
 }
 
 // A new struct for Anthropic's tool format, which omits `tool_type`.
@@ -40,8 +47,7 @@ struct AnthropicResponse {
     id: String,
     model: String,
     content: Vec<AnthropicContentBlock>,
-    #[serde(rename = "stop_reason")]
-    _stop_reason: String,
+    stop_reason: String,
     usage: AnthropicUsage,
 }
 
@@ -61,11 +67,21 @@ struct ToolUseBlock {
     input: JsonValue,
 }
 
+#[derive(Deserialize, Debug)]
+struct ThinkingBlock {
+    #[serde(rename = "type")]
+    _type: String,
+    thinking: String,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
 enum AnthropicContentBlock {
     Text(TextBlock),
     ToolUse(ToolUseBlock),
+    Thinking(ThinkingBlock),
+    // Redacted thinking and any block type we don't read.
+    Other(#[allow(dead_code)] JsonValue),
 }
 
 #[derive(Deserialize)]
@@ -86,7 +102,7 @@ impl ProviderAdapter for AnthropicAdapter {
         temperature: f32,
         schema: Option<SimpleSchema>,
         tools: Option<&Vec<ToolDefinition>>,
-        _thinking_mode: bool,
+        thinking_mode: bool,
         _debug: bool,
     ) -> JsonValue {
         // Anthropic uses a top-level `system` prompt.
@@ -186,14 +202,29 @@ impl ProviderAdapter for AnthropicAdapter {
             tool_choice = Some(json!({"type": "tool", "name": schema_as_tool.name}));
         }
 
+        // Extended thinking shares `max_tokens` with the answer, so the cap is raised to
+        // leave room after the budget. It is skipped with tools: thinking cannot be combined
+        // with a forced tool, and tool turns would need the signed thinking blocks replayed.
+        let thinking_enabled = thinking_mode && final_tools.is_none() && has_reasoning_toggle("Anthropic", model_tag);
+        let (max_tokens, temperature, thinking) = if thinking_enabled {
+            (
+                DEFAULT_MAX_TOKENS.max(DEFAULT_THINKING_BUDGET + MIN_ANSWER_TOKENS),
+                1.0, // Required by the API when thinking is on.
+                Some(json!({"type": "enabled", "budget_tokens": DEFAULT_THINKING_BUDGET})),
+            )
+        } else {
+            (DEFAULT_MAX_TOKENS, temperature, None)
+        };
+
         let payload = AnthropicRequestPayload {
             model: model_tag.to_string(),
             messages: final_messages,
             system: system_prompt,
-            max_tokens: 4096, // A sensible default
+            max_tokens,
             temperature,
             tools: final_tools,
             tool_choice,
+            thinking,
         };
 
         serde_json::to_value(payload).unwrap()
@@ -218,6 +249,79 @@ impl ProviderAdapter for AnthropicAdapter {
     fn supports_tools(&self, _model_tag: &str) -> bool {
         true // All modern Claude models support tools.
     }
+
+    fn supports_thinking_toggle(&self, model_tag: &str) -> bool {
+        has_reasoning_toggle("Anthropic", model_tag)
+    }
+
+    fn temperature_range(&self, _model_tag: &str) -> (f32, f32) {
+        (0.0, 1.0)
+    }
+
+    fn requires_alternating_roles(&self) -> bool {
+        true
+    }
+
+    fn supports_assistant_prefill(&self, model_tag: &str, thinking_mode: bool) -> bool {
+        // Prefilling is rejected while extended thinking is on.
+        !(thinking_mode && has_reasoning_toggle("Anthropic", model_tag))
+    }
+
+    fn apply_end_user_id(&self, payload: &mut JsonValue, user_id: &str) {
+        payload["metadata"]["user_id"] = json!(user_id);
+    }
+
+    fn apply_generation_limits(
+            &self,
+            payload: &mut JsonValue,
+            _model_tag: &str,
+            limits: &GenerationLimits,
+            _thinking_mode: bool,
+        ) {
+        if payload.get("thinking").is_none() {
+            if let Some(max_tokens) = limits.max_tokens {
+                payload["max_tokens"] = json!(max_tokens);
+            }
+            return;
+        }
+
+        let mut budget = limits.thinking_budget.unwrap_or(DEFAULT_THINKING_BUDGET).max(MIN_THINKING_BUDGET);
+        let max_tokens = limits.max_tokens_above_budget(DEFAULT_MAX_TOKENS, budget);
+        if max_tokens <= budget {
+            // Only reachable with an explicit `max_tokens` and the default budget; an explicit
+            // pair is validated by `Orchestra::set_generation_limits`.
+            let shrunk = max_tokens.saturating_sub(MIN_ANSWER_TOKENS);
+            if shrunk < MIN_THINKING_BUDGET {
+                eprintln!(
+                    "[WARNING] max_tokens={} leaves no room for a thinking budget; sending the request without extended thinking.",
+                    max_tokens
+                );
+                if let Some(obj) = payload.as_object_mut() {
+                    obj.remove("thinking");
+                }
+                payload["max_tokens"] = json!(max_tokens);
+                return;
+            }
+            eprintln!(
+                "[WARNING] max_tokens={} does not exceed the thinking budget of {}; lowering the budget to {}.",
+                max_tokens, budget, shrunk
+            );
+            budget = shrunk;
+        }
+        payload["max_tokens"] = json!(max_tokens);
+        payload["thinking"]["budget_tokens"] = json!(budget);
+    }
+}
+
+// Maps Anthropic's `stop_reason` onto the OpenAI finish reasons used across the crate.
+fn openai_finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "end_turn" | "stop_sequence" => "stop",
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        other => other,
+    }
+    .to_string()
 }
 
 impl ResponseParser for AnthropicParser {
@@ -237,10 +341,13 @@ impl ResponseParser for AnthropicParser {
 
         let mut final_content = String::new();
         let mut tool_calls = Vec::new();
+        let mut thinking = String::new();
 
         for block in response.content {
             match block {
                 AnthropicContentBlock::Text(text_block) => final_content.push_str(&text_block.text),
+                AnthropicContentBlock::Thinking(thinking_block) => thinking.push_str(&thinking_block.thinking),
+                AnthropicContentBlock::Other(_) => {}
                 AnthropicContentBlock::ToolUse(tool_block) => {
                     tool_calls.push(ToolCall {
                         id: tool_block.id,
@@ -254,13 +361,9 @@ impl ResponseParser for AnthropicParser {
             }
         }
 
-        let mut reasoning_content: Option<String> = None;
-        let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-        if let Some(captures) = think_re.captures(&final_content) {
-            if let Some(thought) = captures.get(1) {
-                reasoning_content = Some(thought.as_str().trim().to_string());
-            }
-            final_content = think_re.replace(&final_content, "").trim().to_string();
+        let mut reasoning_content = Some(thinking).filter(|t| !t.is_empty());
+        if let Some(thought) = extract_think_blocks(&mut final_content) {
+            reasoning_content = Some(thought);
         }
 
         // The Anthropic API sometimes returns an empty content string for tool_use stops.
@@ -271,9 +374,15 @@ impl ResponseParser for AnthropicParser {
             Some(final_content)
         };
 
+        // A `refusal` stop carries whatever text the model wrote before it stopped.
+        let refusal = (response.stop_reason == "refusal").then(|| {
+            final_content_option.clone().unwrap_or_else(|| "The model declined to respond.".to_string())
+        });
+
         let final_message = Message {
             role: "assistant".to_string(),
             content: final_content_option,
+            refusal,
             tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
             reasoning_content,
             ..Default::default()
@@ -286,6 +395,7 @@ impl ResponseParser for AnthropicParser {
             model: response.model,
             choices: vec![Choice {
                 message: final_message,
+                finish_reason: Some(openai_finish_reason(&response.stop_reason)),
             }],
             usage: {
                 let mut usage = crate::datam::Usage {
@@ -297,6 +407,92 @@ impl ResponseParser for AnthropicParser {
                 usage.calculate_cost(input_price, output_price);
                 Some(usage)
             },
+            citations: None,
+            request_id: None,
+            rate_limit: None,
+            served_by: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datam::format_user_message;
+
+    const TAG: &str = "claude-sonnet-4-20250514";
+
+    fn thinking_payload(limits: &GenerationLimits) -> JsonValue {
+        let messages = vec![format_user_message("Hi".to_string())];
+        let mut payload = AnthropicAdapter.prepare_request_payload(TAG, messages, 0.3, None, None, true, false);
+        AnthropicAdapter.apply_generation_limits(&mut payload, TAG, limits, true);
+        payload
+    }
+
+    #[test]
+    fn thinking_budget_raises_max_tokens() {
+        let payload = thinking_payload(&GenerationLimits { max_tokens: None, thinking_budget: Some(8000) });
+        assert_eq!(payload["thinking"]["budget_tokens"], json!(8000));
+        assert_eq!(payload["max_tokens"], json!(8000 + MIN_ANSWER_TOKENS));
+        assert_eq!(payload["temperature"], json!(1.0));
+
+        let defaults = thinking_payload(&GenerationLimits::default());
+        assert_eq!(defaults["thinking"]["budget_tokens"], json!(DEFAULT_THINKING_BUDGET));
+        assert_eq!(defaults["max_tokens"], json!(DEFAULT_MAX_TOKENS));
+    }
+
+    #[test]
+    fn small_max_tokens_shrinks_or_drops_the_default_budget() {
+        let shrunk = thinking_payload(&GenerationLimits { max_tokens: Some(2048), thinking_budget: None });
+        assert_eq!(shrunk["thinking"]["budget_tokens"], json!(2048 - MIN_ANSWER_TOKENS));
+        assert_eq!(shrunk["max_tokens"], json!(2048));
+
+        let dropped = thinking_payload(&GenerationLimits { max_tokens: Some(1500), thinking_budget: None });
+        assert!(dropped.get("thinking").is_none());
+        assert_eq!(dropped["max_tokens"], json!(1500));
+    }
+
+    #[test]
+    fn thinking_is_skipped_for_older_models() {
+        let messages = vec![format_user_message("Hi".to_string())];
+        let older = AnthropicAdapter.prepare_request_payload("claude-3-5-haiku-20241022", messages, 0.3, None, None, true, false);
+        assert!(older.get("thinking").is_none());
+        assert_eq!(older["max_tokens"], json!(DEFAULT_MAX_TOKENS));
+    }
+
+    #[test]
+    fn thinking_blocks_become_reasoning_content() {
+        let raw = json!({
+            "id": "msg_1",
+            "model": TAG,
+            "content": [
+                { "type": "thinking", "thinking": "Greet back.", "signature": "sig" },
+                { "type": "redacted_thinking", "data": "opaque" },
+                { "type": "text", "text": "Hello!" }
+            ],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 10, "output_tokens": 20 }
         })
+        .to_string();
+
+        let payload = AnthropicParser.parse_response(&raw, "CLAUDE SONNET 4", 3.0, 15.0).unwrap();
+        let message = &payload.choices[0].message;
+        assert_eq!(message.content.as_deref(), Some("Hello!"));
+        assert_eq!(message.reasoning_content.as_deref(), Some("Greet back."));
+    }
+
+    #[test]
+    fn a_refusal_stop_sets_the_refusal() {
+        let raw = json!({
+            "id": "msg_1",
+            "model": TAG,
+            "content": [],
+            "stop_reason": "refusal",
+            "usage": { "input_tokens": 10, "output_tokens": 0 }
+        })
+        .to_string();
+
+        let payload = AnthropicParser.parse_response(&raw, "CLAUDE SONNET 4", 3.0, 15.0).unwrap();
+        assert_eq!(payload.choices[0].message.refusal.as_deref(), Some("The model declined to respond."));
     }
 }
@@ -1,12 +1,14 @@
-use crate::datam::{Choice, Message, ResponsePayload};
+use crate::datam::{Choice, Message, ResponsePayload, extract_reasoning};
 use crate::tools::{FunctionCall, ToolCall, ToolDefinition};
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
+use crate::config::ProviderConfig;
 
-use super::{ProviderAdapter, ResponseParser};
+use super::{has_reasoning_toggle, normalize_image_b64, GeneratedImage, GenerationLimits, ImageOptions, ProviderAdapter, ResponseParser};
 use serde_json::{json, Value as JsonValue};
 use serde::{Deserialize, Serialize};
-use regex::Regex;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
 
 // --- Structs for Gemini API ---
 
@@ -16,6 +18,11 @@ pub struct GoogleAdapter;
 /// Parser for the Google Gemini API response.
 pub struct GoogleParser;
 
+// Models that accept a `thinkingConfig` (Gemini 2.5 thinking models), per `models.json`.
+fn supports_thinking_config(model_tag: &str) -> bool {
+    has_reasoning_toggle("Google", model_tag)
+}
+
 #[derive(Serialize)]
 struct GeminiContent {
     role: String,
@@ -47,12 +54,12 @@ impl ProviderAdapter for GoogleAdapter {
 
     fn prepare_request_payload(
             &self,
-            _model_tag: &str,
+            model_tag: &str,
             messages: Vec<Message>,
             temperature: f32,
             schema: Option<SimpleSchema>,
             tools: Option<&Vec<ToolDefinition>>,
-            _thinking_mode: bool,
+            thinking_mode: bool,
             debug: bool,
         ) -> JsonValue {
         let mut system_prompt = String::new();
@@ -97,12 +104,14 @@ impl ProviderAdapter for GoogleAdapter {
                  if role == "function" {
                     // Try to parse the tool result string as JSON, which Gemini expects.
                     // If it fails, fall back to wrapping the raw string.
+                    // Gemini matches a function response to its call by name; the orchestra
+                    // fills in missing names before a request is prepared.
                     let response_json = serde_json::from_str(text).unwrap_or(json!({ "content": text }));
-                     parts.push(GeminiPart {
+                    parts.push(GeminiPart {
                         text: None,
                         function_call: None,
                         function_response: Some(GeminiFunctionResponse {
-                            name: msg.name.as_ref().expect("Tool name is required for function response").clone(),
+                            name: msg.name.clone().unwrap_or_default(),
                             response: response_json,
                         }),
                     });
@@ -137,6 +146,16 @@ impl ProviderAdapter for GoogleAdapter {
         } else if schema.is_some() {
             generation_config["response_mime_type"] = json!("application/json");
         }
+
+        // Gemini 2.5 models think by default. With thinking mode on we ask for a dynamic
+        // budget and the thought summaries; with it off we disable thinking entirely.
+        if supports_thinking_config(model_tag) {
+            generation_config["thinkingConfig"] = if thinking_mode {
+                json!({ "thinkingBudget": -1, "includeThoughts": true })
+            } else {
+                json!({ "thinkingBudget": 0 })
+            };
+        }
         
         if !generation_config.as_object().unwrap().is_empty() {
             base_payload["generationConfig"] = generation_config;
@@ -149,13 +168,52 @@ impl ProviderAdapter for GoogleAdapter {
         base_payload
     }
 
-    fn get_request_url(&self, base_url: &str, model_tag: &str, api_key: &str) -> String {
-        format!(
-            "{}/{}:generateContent?key={}",
-            base_url.trim_end_matches('/'),
-            model_tag,
-            api_key
-        )
+    fn get_request_url(&self, base_url: &str, model_tag: &str, _api_key: &str) -> String {
+        format!("{}/{}:generateContent", base_url.trim_end_matches('/'), model_tag)
+    }
+
+    /// Prepares a `batchEmbedContents` payload, one request per input text.
+    fn prepare_embedding_request(&self, model_tag: &str, texts: Vec<String>) -> JsonValue {
+        let requests: Vec<JsonValue> = texts
+            .into_iter()
+            .map(|text| {
+                json!({
+                    "model": model_tag,
+                    "content": { "parts": [{ "text": text }] }
+                })
+            })
+            .collect();
+        json!({ "requests": requests })
+    }
+
+    fn get_embedding_url(
+            &self,
+            base_url: &str,
+            model_tag: &str,
+            _provider_config: &ProviderConfig,
+        ) -> String {
+        format!("{}/{}:batchEmbedContents", base_url.trim_end_matches('/'), model_tag)
+    }
+
+    fn prepare_image_request_payload(
+            &self,
+            prompt: &str,
+            _model_tag: &str,
+            options: &ImageOptions,
+        ) -> JsonValue {
+        let mut generation_config = json!({ "responseModalities": ["TEXT", "IMAGE"] });
+        if let Some(n) = options.n {
+            generation_config["candidateCount"] = json!(n);
+        }
+        // Gemini takes an aspect ratio rather than pixel dimensions. It has no
+        // quality or style settings, so those options are ignored.
+        if let Some(ratio) = options.size.as_deref().and_then(aspect_ratio) {
+            generation_config["imageConfig"] = json!({ "aspectRatio": ratio });
+        }
+        json!({
+            "contents": [{ "role": "user", "parts": [{ "text": prompt }] }],
+            "generationConfig": generation_config
+        })
     }
 
     fn get_image_request_url(&self, base_url: &str, model_tag: &str, api_key: &str) -> String {
@@ -163,12 +221,55 @@ impl ProviderAdapter for GoogleAdapter {
         self.get_request_url(base_url, model_tag, api_key)
     }
 
-    fn get_request_headers(&self, _api_key: &str) -> reqwest::header::HeaderMap {
+    /// Stores the system prompt and the content as a `cachedContents` resource. Gemini
+    /// rejects caches below a minimum size (about 1,000 to 4,000 tokens depending on the model).
+    fn prepare_cached_content_request(
+            &self,
+            model_tag: &str,
+            system_prompt: Option<&str>,
+            content: &str,
+            ttl: Duration,
+        ) -> JsonValue {
+        let model = if model_tag.starts_with("models/") {
+            model_tag.to_string()
+        } else {
+            format!("models/{}", model_tag)
+        };
+        let mut payload = json!({
+            "model": model,
+            "contents": [{ "role": "user", "parts": [{ "text": content }] }],
+            "ttl": format!("{}s", ttl.as_secs().max(1)),
+        });
+        if let Some(system_prompt) = system_prompt.filter(|s| !s.trim().is_empty()) {
+            payload["systemInstruction"] = json!({ "parts": [{ "text": system_prompt }] });
+        }
+        payload
+    }
+
+    fn get_cached_content_url(&self, base_url: &str, api_key: &str) -> String {
+        self.get_raw_request_url(base_url, "cachedContents", api_key)
+    }
+
+    /// A request that uses a cache may not set its own `systemInstruction`, so the
+    /// request's system prompt is sent as a leading user turn instead.
+    fn apply_cached_content(&self, payload: &mut JsonValue, cache_name: &str) {
+        if let Some(system) = payload.as_object_mut().and_then(|p| p.remove("systemInstruction")) {
+            if let Some(contents) = payload["contents"].as_array_mut() {
+                contents.insert(0, json!({ "role": "user", "parts": system["parts"].clone() }));
+            }
+        }
+        payload["cachedContent"] = json!(cache_name);
+    }
+
+    /// Every call sends the key in the `x-goog-api-key` header rather than the URL, so it
+    /// stays out of logged URLs.
+    fn get_request_headers(&self, api_key: &str) -> reqwest::header::HeaderMap {
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             reqwest::header::CONTENT_TYPE,
             reqwest::header::HeaderValue::from_static("application/json"),
         );
+        headers.insert("x-goog-api-key", reqwest::header::HeaderValue::from_str(api_key).unwrap());
         headers
     }
 
@@ -180,9 +281,71 @@ impl ProviderAdapter for GoogleAdapter {
         true
     }
 
+    fn supports_thinking_toggle(&self, model_tag: &str) -> bool {
+        supports_thinking_config(model_tag)
+    }
+
+    fn supports_context_caching(&self, _model_tag: &str) -> bool {
+        true
+    }
+
+    fn supports_json_mode(&self, _model_tag: &str) -> bool {
+        true
+    }
+
+    fn apply_json_mode(&self, payload: &mut JsonValue, _model_tag: &str) {
+        payload["generationConfig"]["response_mime_type"] = json!("application/json");
+    }
+
     fn supports_embeddings(&self, _model_tag: &str) -> bool {
         true
     }
+
+    /// `maxOutputTokens` includes thinking tokens, so with a fixed budget and no explicit
+    /// cap the cap is left to Gemini's default, which is far above any sensible budget.
+    // Gemini has no end-user field and rejects unknown ones.
+    fn apply_end_user_id(&self, _payload: &mut JsonValue, _user_id: &str) {}
+
+    fn apply_generation_limits(
+            &self,
+            payload: &mut JsonValue,
+            model_tag: &str,
+            limits: &GenerationLimits,
+            thinking_mode: bool,
+        ) {
+        if let Some(max_tokens) = limits.max_tokens {
+            payload["generationConfig"]["maxOutputTokens"] = json!(max_tokens);
+        }
+        if let (true, Some(budget)) = (thinking_mode, limits.thinking_budget) {
+            if supports_thinking_config(model_tag) {
+                payload["generationConfig"]["thinkingConfig"]["thinkingBudget"] = json!(budget);
+            }
+        }
+    }
+}
+
+// Cached prompt tokens are billed at a quarter of the model's input price.
+const CACHED_INPUT_PRICE_FACTOR: f32 = 0.25;
+
+// The aspect ratios Gemini image generation accepts.
+const SUPPORTED_ASPECT_RATIOS: [(u32, u32); 10] =
+    [(1, 1), (2, 3), (3, 2), (3, 4), (4, 3), (4, 5), (5, 4), (9, 16), (16, 9), (21, 9)];
+
+/// Turns an image size (`"1792x1024"`) or ratio (`"16:9"`) into the closest aspect ratio
+/// Gemini supports, so `"1792x1024"` becomes `"16:9"`. Returns `None` for anything else.
+fn aspect_ratio(size: &str) -> Option<String> {
+    let (width, height) = size.split_once(['x', 'X', ':'])?;
+    let (width, height): (u32, u32) = (width.trim().parse().ok()?, height.trim().parse().ok()?);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    // Compare on a log scale so 2:1 and 1:2 are equally far from 1:1.
+    let target = (width as f64 / height as f64).ln();
+    let distance = |(w, h): &(u32, u32)| ((*w as f64 / *h as f64).ln() - target).abs();
+    let (w, h) = SUPPORTED_ASPECT_RATIOS
+        .iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))?;
+    Some(format!("{}:{}", w, h))
 }
 
 // --- Response Structs ---
@@ -193,6 +356,18 @@ struct GeminiResponse {
     candidates: Vec<GeminiCandidate>,
     #[serde(default)]
     usage_metadata: Option<GeminiUsageMetadata>,
+    #[serde(default)]
+    response_id: Option<String>,
+    #[serde(default)]
+    model_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiCachedContent {
+    name: String,
+    #[serde(default)]
+    expire_time: Option<DateTime<Utc>>,
 }
 
 #[derive(Deserialize)]
@@ -201,11 +376,29 @@ struct GeminiUsageMetadata {
     prompt_token_count: u32,
     candidates_token_count: u32,
     total_token_count: u32,
+    // Included in `prompt_token_count`.
+    #[serde(default)]
+    cached_content_token_count: Option<u32>,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiCandidate {
     content: GeminiContentResponse,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+// Maps Gemini's `finishReason` onto the OpenAI finish reasons used across the crate.
+// Gemini reports `STOP` for function calls too, so those are told apart by the caller.
+fn openai_finish_reason(finish_reason: &str) -> String {
+    match finish_reason {
+        "STOP" => "stop",
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => "content_filter",
+        other => other,
+    }
+    .to_string()
 }
 
 #[derive(Deserialize)]
@@ -219,6 +412,9 @@ struct GeminiPartResponse {
     text: Option<String>,
     #[serde(default)]
     function_call: Option<GeminiFunctionCall>,
+    /// Set on parts that carry the model's thought summary rather than the answer.
+    #[serde(default)]
+    thought: bool,
 }
 
 // NEW STRUCT for parsing image data from the response
@@ -232,6 +428,7 @@ struct ImageGenPartResponse {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GeminiInlineData {
+    #[serde(rename = "mimeType")]
     _mime_type: String,
     data: String,
 }
@@ -242,19 +439,14 @@ struct GeminiFunctionCall {
     args: JsonValue,
 }
 
-// --- New structs for Embedding Response Parsing ---
+// --- Structs for `batchEmbedContents` Response Parsing ---
 #[derive(Deserialize)]
 struct GeminiEmbeddingResponse {
-    predictions: Vec<GeminiEmbeddingPrediction>,
+    embeddings: Vec<GeminiEmbedding>,
 }
 
 #[derive(Deserialize)]
-struct GeminiEmbeddingPrediction {
-    embeddings: GeminiEmbeddings,
-}
-
-#[derive(Deserialize)]
-struct GeminiEmbeddings {
+struct GeminiEmbedding {
     values: Vec<f32>,
 }
 
@@ -262,12 +454,23 @@ impl ResponseParser for GoogleParser {
     fn parse_response(
             &self,
             raw_response_text: &str,
-            _model_name: &str,
+            model_name: &str,
             input_price: f32,
             output_price: f32,
         ) -> Result<ResponsePayload, LLMCoreError> {
         let gemini_response: GeminiResponse = serde_json::from_str(raw_response_text)?;
 
+        // Prefer the provider's own identifiers so logs can be traced back to the
+        // Gemini console; fall back to synthetic values for older API versions.
+        let response_id = gemini_response
+            .response_id
+            .clone()
+            .unwrap_or_else(|| format!("gemini-{}", uuid::Uuid::new_v4()));
+        let served_model = gemini_response
+            .model_version
+            .clone()
+            .unwrap_or_else(|| model_name.to_string());
+
         let first_candidate = gemini_response
             .candidates
             .into_iter()
@@ -278,13 +481,22 @@ impl ResponseParser for GoogleParser {
                 )
             })?;
 
+        let mut finish_reason = first_candidate.finish_reason.as_deref().map(openai_finish_reason);
         let mut content: Option<String> = None;
+        let mut thoughts: Vec<String> = Vec::new();
         let mut tool_calls: Vec<ToolCall> = Vec::new();
         let mut tool_name_for_message: Option<String> = None;
 
         for part in first_candidate.content.parts {
             if let Some(text) = part.text {
-                content = Some(text);
+                if part.thought {
+                    thoughts.push(text);
+                } else {
+                    match &mut content {
+                        Some(existing) => existing.push_str(&text),
+                        None => content = Some(text),
+                    }
+                }
             }
             if let Some(fc) = part.function_call {
                 tool_name_for_message = Some(fc.name.clone());
@@ -299,25 +511,27 @@ impl ResponseParser for GoogleParser {
             }
         }
 
-        let mut reasoning_content: Option<String> = None;
-        if let Some(c) = &mut content {
-            let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-            if let Some(captures) = think_re.captures(c) {
-                if let Some(thought) = captures.get(1) {
-                    reasoning_content = Some(thought.as_str().trim().to_string());
-                }
-                *c = think_re.replace(c, "").trim().to_string();
-            }
+        let mut reasoning_content: Option<String> = if thoughts.is_empty() {
+            None
+        } else {
+            Some(thoughts.join("\n\n").trim().to_string())
+        };
+        // Fall back to prompt-induced `<think>` tags when there are no native thoughts.
+        if reasoning_content.is_none() {
+            reasoning_content = extract_reasoning(&mut content);
+        }
+        if !tool_calls.is_empty() && finish_reason.as_deref() == Some("stop") {
+            finish_reason = Some("tool_calls".to_string());
         }
 
         Ok(ResponsePayload {
-            id: format!("gemini-{}", uuid::Uuid::new_v4()),
+            id: response_id,
             object: "chat.completion".to_string(),
             created: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap_or_default()
                 .as_secs(),
-            model: "gemini-synthetic".to_string(),
+            model: served_model,
             choices: vec![Choice {
                 message: Message {
                     role: "assistant".to_string(),
@@ -327,17 +541,23 @@ impl ResponseParser for GoogleParser {
                     reasoning_content,
                     ..Default::default()
                 },
+                finish_reason,
             }],
             usage: gemini_response.usage_metadata.map(|meta| {
                 let mut usage = crate::datam::Usage {
                     prompt_tokens: meta.prompt_token_count,
                     completion_tokens: meta.candidates_token_count,
                     total_tokens: meta.total_token_count,
+                    cached_tokens: meta.cached_content_token_count,
                     ..Default::default()
                 };
-                usage.calculate_cost(input_price, output_price);
+                usage.calculate_cost_with_cache(input_price, input_price * CACHED_INPUT_PRICE_FACTOR, output_price);
                 usage
             }),
+            citations: None,
+            request_id: None,
+            rate_limit: None,
+            served_by: None,
         })
     }
 
@@ -347,41 +567,238 @@ impl ResponseParser for GoogleParser {
     ) -> Result<Vec<Vec<f32>>, LLMCoreError> {
         let response: GeminiEmbeddingResponse = serde_json::from_str(raw_response_text)?;
         let embeddings = response
-            .predictions
+            .embeddings
             .into_iter()
-            .map(|p| p.embeddings.values)
+            .map(|e| e.values)
             .collect();
         Ok(embeddings)
     }
 
+    fn parse_cached_content_response(
+            &self,
+            raw_response_text: &str,
+        ) -> Result<(String, Option<DateTime<Utc>>), LLMCoreError> {
+        let response: GeminiCachedContent = serde_json::from_str(raw_response_text)?;
+        Ok((response.name, response.expire_time))
+    }
+
     fn parse_image_response(
             &self,
             raw_response_text: &str,
-        ) -> Result<(Option<String>, Option<String>), LLMCoreError> {
+        ) -> Result<Vec<GeneratedImage>, LLMCoreError> {
         let response_json: JsonValue = serde_json::from_str(raw_response_text)?;
-
-        let parts: Vec<ImageGenPartResponse> = response_json
+        let candidates = response_json
             .get("candidates")
-            .and_then(|c| c.get(0))
-            .and_then(|c| c.get("content"))
-            .and_then(|c| c.get("parts"))
-            .and_then(|p| serde_json::from_value(p.clone()).ok())
+            .and_then(|c| c.as_array())
+            .filter(|c| !c.is_empty())
             .ok_or_else(|| {
-                LLMCoreError::ResponseParseError("Could not find 'parts' in Gemini image response".to_string())
+                LLMCoreError::ResponseParseError("Could not find 'candidates' in Gemini image response".to_string())
             })?;
 
-        let mut text_content = None;
-        let mut image_data = None;
-
-        for part in parts {
-            if let Some(text) = part.text {
-                text_content = Some(text);
+        // Each candidate may carry several images; every image is returned with the
+        // candidate's text, and a candidate without images still yields its text.
+        let mut results = Vec::new();
+        for candidate in candidates {
+            let parts: Vec<ImageGenPartResponse> = candidate
+                .get("content")
+                .and_then(|c| c.get("parts"))
+                .and_then(|p| serde_json::from_value(p.clone()).ok())
+                .ok_or_else(|| {
+                    LLMCoreError::ResponseParseError("Could not find 'parts' in Gemini image response".to_string())
+                })?;
+
+            let mut text_content: Option<String> = None;
+            let mut images = Vec::new();
+            for part in parts {
+                if let Some(text) = part.text {
+                    text_content.get_or_insert_with(String::new).push_str(&text);
+                }
+                if let Some(inline_data) = part.inline_data {
+                    images.push(normalize_image_b64(&inline_data.data)?);
+                }
             }
-            if let Some(inline_data) = part.inline_data {
-                image_data = Some(inline_data.data);
+
+            if images.is_empty() {
+                results.push((text_content, None));
+            } else {
+                results.extend(images.into_iter().map(|image| (text_content.clone(), Some(image))));
             }
         }
+        Ok(results)
+    }
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datam::format_user_message;
+
+    #[test]
+    fn image_options_and_candidates_are_mapped() {
+        let options = ImageOptions { n: Some(2), size: Some("1792x1024".to_string()), ..Default::default() };
+        let payload = GoogleAdapter.prepare_image_request_payload("A cat", "gemini-2.0-flash-exp", &options);
+        assert_eq!(payload["generationConfig"]["candidateCount"], json!(2));
+        assert_eq!(payload["generationConfig"]["imageConfig"]["aspectRatio"], json!("16:9"));
+        assert_eq!(aspect_ratio("16:9").as_deref(), Some("16:9"));
+        assert_eq!(aspect_ratio("1024x1792").as_deref(), Some("9:16"));
+        assert_eq!(aspect_ratio("1024x1024").as_deref(), Some("1:1"));
+        assert_eq!(aspect_ratio("large"), None);
+
+        let raw = json!({
+            "candidates": [
+                { "content": { "parts": [
+                    { "text": "Here you go." },
+                    { "inlineData": { "mimeType": "image/png", "data": "QUFB" } }
+                ] } },
+                { "content": { "parts": [
+                    { "inlineData": { "mimeType": "image/png", "data": "QkJC" } }
+                ] } }
+            ]
+        })
+        .to_string();
+        let images = GoogleParser.parse_image_response(&raw).unwrap();
+        assert_eq!(images, vec![
+            (Some("Here you go.".to_string()), Some("QUFB".to_string())),
+            (None, Some("QkJC".to_string())),
+        ]);
+    }
 
-        Ok((text_content, image_data))
+    #[test]
+    fn finish_reasons_are_mapped_to_openai_terms() {
+        let parse = |candidate: JsonValue| {
+            let raw = json!({ "candidates": [candidate] }).to_string();
+            GoogleParser.parse_response(&raw, "gemini-2.0-flash", 0.0, 0.0).unwrap().choices[0].finish_reason.clone()
+        };
+        let text = json!({ "parts": [{ "text": "Hi" }] });
+        let call = json!({ "parts": [{ "functionCall": { "name": "get_time", "args": {} } }] });
+        assert_eq!(parse(json!({ "content": text, "finishReason": "STOP" })).as_deref(), Some("stop"));
+        assert_eq!(parse(json!({ "content": text, "finishReason": "MAX_TOKENS" })).as_deref(), Some("length"));
+        assert_eq!(parse(json!({ "content": text, "finishReason": "SAFETY" })).as_deref(), Some("content_filter"));
+        assert_eq!(parse(json!({ "content": call, "finishReason": "STOP" })).as_deref(), Some("tool_calls"));
+        assert_eq!(parse(json!({ "content": text })), None);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn cached_content_is_created_and_referenced() {
+        let tag = "models/gemini-2.0-flash";
+        let request = GoogleAdapter.prepare_cached_content_request(
+            tag, Some("You answer from the manual."), "The manual.", Duration::from_secs(600),
+        );
+        assert_eq!(request["model"], json!(tag));
+        assert_eq!(request["ttl"], json!("600s"));
+        assert_eq!(request["contents"][0]["parts"][0]["text"], json!("The manual."));
+        assert_eq!(request["systemInstruction"]["parts"][0]["text"], json!("You answer from the manual."));
+
+        let messages = vec![
+            crate::datam::format_system_message("Be brief.".to_string()),
+            format_user_message("Hi".to_string()),
+        ];
+        let mut payload = GoogleAdapter.prepare_request_payload(tag, messages, 0.7, None, None, false, false);
+        GoogleAdapter.apply_cached_content(&mut payload, "cachedContents/abc");
+        assert_eq!(payload["cachedContent"], json!("cachedContents/abc"));
+        assert!(payload.get("systemInstruction").is_none());
+        assert_eq!(payload["contents"][0]["parts"][0]["text"], json!("Be brief."));
+        assert_eq!(payload["contents"][1]["parts"][0]["text"], json!("Hi"));
+
+        let raw = r#"{"name": "cachedContents/abc", "expireTime": "2026-01-01T00:10:00.000Z"}"#;
+        let (name, expires_at) = GoogleParser.parse_cached_content_response(raw).unwrap();
+        assert_eq!(name, "cachedContents/abc");
+        assert_eq!(expires_at.unwrap().to_rfc3339(), "2026-01-01T00:10:00+00:00");
+
+        let reply = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "ok" }] } }],
+            "usageMetadata": {
+                "promptTokenCount": 1_000_000,
+                "candidatesTokenCount": 0,
+                "totalTokenCount": 1_000_000,
+                "cachedContentTokenCount": 800_000
+            }
+        });
+        let usage = GoogleParser.parse_response(&reply.to_string(), tag, 1.0, 2.0).unwrap().usage.unwrap();
+        assert_eq!(usage.cached_tokens, Some(800_000));
+        assert!((usage.cost.unwrap().total - 0.4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn key_is_sent_as_a_header() {
+        let config: ProviderConfig = serde_json::from_value(json!({
+            "api_key": "GEMINI_API_KEY",
+            "base_url": "env:GEMINI_BASE_URL",
+            "models": {}
+        }))
+        .unwrap();
+        let url = GoogleAdapter.get_embedding_url(
+            "https://generativelanguage.googleapis.com/v1beta/",
+            "models/text-embedding-004",
+            &config,
+        );
+        assert_eq!(url, "https://generativelanguage.googleapis.com/v1beta/models/text-embedding-004:batchEmbedContents");
+        let headers = GoogleAdapter.get_embedding_headers("secret");
+        assert_eq!(headers["x-goog-api-key"], "secret");
+        assert_eq!(
+            GoogleAdapter.get_cached_content_url("https://generativelanguage.googleapis.com/v1beta/", "secret"),
+            "https://generativelanguage.googleapis.com/v1beta/cachedContents"
+        );
+        assert!(!GoogleAdapter.get_request_url("https://example.com", "models/gemini", "secret").contains("secret"));
+        assert!(matches!(
+            crate::providers::check_api_key("Google", "secret\n"),
+            Err(crate::error::LLMCoreError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn thought_parts_are_separated_from_the_answer() {
+        let raw = json!({
+            "candidates": [{
+                "content": {
+                    "parts": [
+                        { "text": "The user wants a greeting.", "thought": true },
+                        { "text": "Hello" },
+                        { "text": " there!" }
+                    ]
+                }
+            }],
+            "modelVersion": "gemini-2.5-flash-preview-05-20",
+            "responseId": "abc123"
+        })
+        .to_string();
+
+        let payload = GoogleParser.parse_response(&raw, "GEMINI 2.5 FLASH (PREVIEW)", 0.0, 0.0).unwrap();
+        let message = &payload.choices[0].message;
+        assert_eq!(message.content.as_deref(), Some("Hello there!"));
+        assert_eq!(message.reasoning_content.as_deref(), Some("The user wants a greeting."));
+        assert_eq!(payload.id, "abc123");
+        assert_eq!(payload.model, "gemini-2.5-flash-preview-05-20");
+    }
+
+    #[test]
+    fn thinking_config_follows_thinking_mode() {
+        let tag = "models/gemini-2.5-flash-preview-05-20";
+        let messages = vec![format_user_message("Hi".to_string())];
+
+        let on = GoogleAdapter.prepare_request_payload(tag, messages.clone(), 0.7, None, None, true, false);
+        assert_eq!(on["generationConfig"]["thinkingConfig"]["includeThoughts"], json!(true));
+
+        let off = GoogleAdapter.prepare_request_payload(tag, messages.clone(), 0.7, None, None, false, false);
+        assert_eq!(off["generationConfig"]["thinkingConfig"]["thinkingBudget"], json!(0));
+
+        let older = GoogleAdapter.prepare_request_payload("models/gemini-2.0-flash", messages, 0.7, None, None, true, false);
+        assert!(older["generationConfig"].get("thinkingConfig").is_none());
+    }
+
+    #[test]
+    fn generation_limits_set_budget_and_cap() {
+        let tag = "models/gemini-2.5-flash-preview-05-20";
+        let messages = vec![format_user_message("Hi".to_string())];
+        let limits = GenerationLimits { max_tokens: Some(8192), thinking_budget: Some(2048) };
+
+        let mut on = GoogleAdapter.prepare_request_payload(tag, messages.clone(), 0.7, None, None, true, false);
+        GoogleAdapter.apply_generation_limits(&mut on, tag, &limits, true);
+        assert_eq!(on["generationConfig"]["maxOutputTokens"], json!(8192));
+        assert_eq!(on["generationConfig"]["thinkingConfig"]["thinkingBudget"], json!(2048));
+
+        // A budget never re-enables thinking that thinking mode turned off.
+        let mut off = GoogleAdapter.prepare_request_payload(tag, messages, 0.7, None, None, false, false);
+        GoogleAdapter.apply_generation_limits(&mut off, tag, &limits, false);
+        assert_eq!(off["generationConfig"]["thinkingConfig"]["thinkingBudget"], json!(0));
+    }
+}
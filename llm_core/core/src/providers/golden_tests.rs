@@ -0,0 +1,143 @@
+//! Golden-file checks for each adapter's request payload.
+//!
+//! Every provider builds payloads for the same fixed conversation in three shapes
+//! (plain, schema, tools) and the result is compared with `tests/golden/<provider>.json`.
+//! After an intentional wire-format change, regenerate the files with
+//! `UPDATE_GOLDEN=1 cargo test golden` and review the diff.
+
+use super::{
+    anthropic::AnthropicAdapter, gemini::GoogleAdapter, grok::GrokAdapter, mercury::MercuryAdapter,
+    ollama::OllamaAdapter, openai::OpenAIAdapter, openrouter::OpenRouterAdapter, ProviderAdapter,
+};
+use crate::config::MODEL_LIBRARY;
+use crate::datam::{
+    format_assistant_message, format_system_message, format_tool_message, format_user_message, Message,
+};
+use crate::lucky::{SchemaProperty, SimpleSchema};
+use crate::tools::{FunctionCall, FunctionDefinition, ToolCall, ToolDefinition};
+use serde_json::{json, Value as JsonValue};
+use std::path::PathBuf;
+
+fn messages() -> Vec<Message> {
+    let mut tool_request = format_assistant_message(String::new());
+    tool_request.content = None;
+    tool_request.tool_calls = Some(vec![ToolCall {
+        id: "call_1".to_string(),
+        tool_type: "function".to_string(),
+        function: FunctionCall {
+            name: "get_current_time".to_string(),
+            arguments: json!({}),
+        },
+    }]);
+    vec![
+        format_system_message("You are a helpful assistant.".to_string()),
+        format_user_message("What time is it?".to_string()),
+        tool_request,
+        format_tool_message(
+            r#"{"time": "9:00 AM"}"#.to_string(),
+            "call_1".to_string(),
+            "get_current_time".to_string(),
+        ),
+        format_assistant_message("It is 9:00 AM.".to_string()),
+        format_user_message("Thanks!".to_string()),
+    ]
+}
+
+fn schema() -> SimpleSchema {
+    SimpleSchema {
+        name: "extract_user_details".to_string(),
+        description: "Extracts the user's name and age from the text.".to_string(),
+        properties: vec![
+            SchemaProperty {
+                name: "name".to_string(),
+                property_type: "string".to_string(),
+                description: "The name of the user.".to_string(),
+                items: None,
+            },
+            SchemaProperty {
+                name: "age".to_string(),
+                property_type: "number".to_string(),
+                description: "The age of the user.".to_string(),
+                items: None,
+            },
+        ],
+    }
+}
+
+fn tools() -> Vec<ToolDefinition> {
+    vec![ToolDefinition {
+        tool_type: "function".to_string(),
+        function: FunctionDefinition {
+            name: "get_current_time".to_string(),
+            description: "Get the current time.".to_string(),
+            parameters: json!({"type": "object", "properties": {}}),
+        },
+    }]
+}
+
+fn payloads(adapter: &dyn ProviderAdapter, model_name: &str) -> JsonValue {
+    let (_, _, details) = MODEL_LIBRARY.find_model(model_name).unwrap();
+    let tag = &details.model_tag;
+    let tools = tools();
+    json!({
+        "plain": adapter.prepare_request_payload(tag, messages(), 0.5, None, None, false, false),
+        "schema": adapter.prepare_request_payload(tag, messages(), 0.5, Some(schema()), None, false, false),
+        "tools": adapter.prepare_request_payload(tag, messages(), 0.5, None, Some(&tools), false, false),
+    })
+}
+
+fn assert_golden(name: &str, actual: JsonValue) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.json", name));
+    let rendered = format!("{}\n", serde_json::to_string_pretty(&actual).unwrap());
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, rendered).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!("Missing golden file {}; run with UPDATE_GOLDEN=1 to create it.", path.display())
+    });
+    assert!(
+        expected == rendered,
+        "Payload for '{}' differs from {}.\n--- expected\n{}\n--- actual\n{}\nRun with UPDATE_GOLDEN=1 if the change is intended.",
+        name,
+        path.display(),
+        expected,
+        rendered
+    );
+}
+
+#[test]
+fn golden_openai() {
+    assert_golden("openai", payloads(&OpenAIAdapter, "GPT 4.1 MINI"));
+}
+
+#[test]
+fn golden_google() {
+    assert_golden("google", payloads(&GoogleAdapter, "GEMINI 2.5 FLASH (PREVIEW)"));
+}
+
+#[test]
+fn golden_anthropic() {
+    assert_golden("anthropic", payloads(&AnthropicAdapter, "CLAUDE SONNET 4"));
+}
+
+#[test]
+fn golden_xai() {
+    assert_golden("xai", payloads(&GrokAdapter, "GROK 3"));
+}
+
+#[test]
+fn golden_inception_labs() {
+    assert_golden("inception_labs", payloads(&MercuryAdapter, "MERCURY"));
+}
+
+#[test]
+fn golden_openrouter() {
+    assert_golden("openrouter", payloads(&OpenRouterAdapter, "DEEPSEEK R1-0528:FREE"));
+}
+
+#[test]
+fn golden_ollama() {
+    assert_golden("ollama", payloads(&OllamaAdapter, "QWEN 3:0.6B"));
+}
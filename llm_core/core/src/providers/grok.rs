@@ -1,5 +1,5 @@
 use super::{ProviderAdapter, ResponseParser};
-use crate::datam::{Message, ResponsePayload};
+use crate::datam::{Message, ResponsePayload, extract_reasoning};
 use crate::tools::ToolDefinition;
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
@@ -8,7 +8,6 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 use std::collections::HashSet;
 use reqwest::header;
-use regex::Regex;
 
 /// Adapter for the xAI Grok API.
 pub struct GrokAdapter;
@@ -138,6 +137,14 @@ impl ProviderAdapter for GrokAdapter {
     fn supports_tools(&self, _model_tag: &str) -> bool {
         true
     }
+
+    fn supports_json_mode(&self, _model_tag: &str) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self, _model_tag: &str) -> bool {
+        true
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -152,6 +159,9 @@ struct GrokMessage {
 #[derive(Deserialize, Clone)]
 struct GrokChoice {
     message: GrokMessage,
+    // Grok already uses OpenAI's finish reasons.
+    #[serde(default)]
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -190,7 +200,12 @@ impl ResponseParser for GrokParser {
             input_price: f32,
             output_price: f32,
         ) -> Result<ResponsePayload, LLMCoreError> {
-        let grok_response: GrokResponse = serde_json::from_str(raw_response_text)?;
+        let grok_response: GrokResponse = serde_json::from_str(raw_response_text).map_err(|e| {
+            LLMCoreError::ResponseParseError(format!(
+                "Failed to parse Grok response: {}. Raw: {}",
+                e, raw_response_text
+            ))
+        })?;
 
         let mut choices = Vec::new();
         if let Some(candidates) = grok_response.choices {
@@ -201,15 +216,7 @@ impl ResponseParser for GrokParser {
 
                     // Handle prompt-induced reasoning tags if native reasoning is absent.
                     if reasoning_content.is_none() {
-                        if let Some(content) = &mut message_content {
-                            let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-                            if let Some(captures) = think_re.captures(content) {
-                                if let Some(thought) = captures.get(1) {
-                                    reasoning_content = Some(thought.as_str().trim().to_string());
-                                }
-                                *content = think_re.replace(content, "").trim().to_string();
-                            }
-                        }
+                        reasoning_content = extract_reasoning(&mut message_content);
                     }
                     
                     let message = Message {
@@ -235,11 +242,20 @@ impl ResponseParser for GrokParser {
                         ..Default::default()
                     };
 
-                    choices.push(crate::datam::Choice { message });
+                    choices.push(crate::datam::Choice { message, finish_reason: grok_choice.finish_reason });
                 }
             }
         }
 
+        // A missing or all-null `choices` (content filtering, error-shaped bodies) would
+        // otherwise surface later as an index panic in callers that read `choices[0]`.
+        if choices.is_empty() {
+            return Err(LLMCoreError::ResponseParseError(format!(
+                "Grok response contained no choices. Raw: {}",
+                raw_response_text
+            )));
+        }
+
         Ok(ResponsePayload {
             id: grok_response.id,
             object: grok_response.object,
@@ -256,6 +272,38 @@ impl ResponseParser for GrokParser {
                 usage.calculate_cost(input_price, output_price);
                 usage
             }),
+            citations: None,
+            request_id: None,
+            rate_limit: None,
+            served_by: None,
         })
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(choices: &str) -> String {
+        format!(
+            r#"{{"id": "g-1", "object": "chat.completion", "created": 0, "model": "grok-3", "choices": {}}}"#,
+            choices
+        )
+    }
+
+    #[test]
+    fn empty_choices_are_a_parse_error() {
+        for choices in ["null", "[]", "[null, null]"] {
+            let raw = response(choices);
+            match GrokParser.parse_response(&raw, "GROK 3", 0.0, 0.0) {
+                Err(LLMCoreError::ResponseParseError(message)) => assert!(message.contains(&raw)),
+                other => panic!("expected a parse error for {}, got {:?}", choices, other.map(|p| p.choices.len())),
+            }
+        }
+
+        let raw = response(r#"[null, {"message": {"role": "assistant", "content": "Hi"}, "finish_reason": "length"}]"#);
+        let payload = GrokParser.parse_response(&raw, "GROK 3", 0.0, 0.0).unwrap();
+        assert_eq!(payload.choices[0].message.content.as_deref(), Some("Hi"));
+        assert_eq!(payload.choices[0].finish_reason.as_deref(), Some("length"));
+    }
+}
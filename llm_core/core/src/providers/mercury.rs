@@ -1,4 +1,4 @@
-use crate::datam::{Message, ResponsePayload};
+use crate::datam::{Message, ResponsePayload, extract_reasoning};
 use crate::tools::ToolDefinition;
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
@@ -7,7 +7,6 @@ use super::{ProviderAdapter, ResponseParser};
 use serde_json::{json, Value as JsonValue};
 use std::collections::HashSet;
 use reqwest::header;
-use regex::Regex;
 
 /// Adapter for the Inception Labs Mercury API.
 pub struct MercuryAdapter;
@@ -123,14 +122,8 @@ impl ResponseParser for MercuryParser {
         })?;
 
         if let Some(choice) = payload.choices.get_mut(0) {
-            if let Some(content) = &mut choice.message.content {
-                let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-                if let Some(captures) = think_re.captures(content) {
-                    if let Some(thought) = captures.get(1) {
-                        choice.message.reasoning_content = Some(thought.as_str().trim().to_string());
-                    }
-                    *content = think_re.replace(content, "").trim().to_string();
-                }
+            if let Some(thought) = extract_reasoning(&mut choice.message.content) {
+                choice.message.reasoning_content = Some(thought);
             }
         }
 
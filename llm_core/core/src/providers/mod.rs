@@ -2,10 +2,125 @@ use crate::datam::{Message, ResponsePayload};
 use crate::tools::ToolDefinition;
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
-use crate::config::ProviderConfig;
+use crate::config::{ModelDetails, ProviderConfig, ReasoningCapability, MODEL_LIBRARY};
 
 use serde_json::{json, Value as JsonValue};
 use reqwest::header;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use base64::alphabet;
+use base64::engine::{
+    general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD as BASE64_STANDARD},
+    DecodePaddingMode, Engine as _,
+};
+
+// Providers sometimes drop the trailing `=` padding.
+const BASE64_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Checks that `api_key` can be sent in a request header, which is where adapters put it.
+pub fn check_api_key(provider_name: &str, api_key: &str) -> Result<(), LLMCoreError> {
+    header::HeaderValue::from_str(api_key).map(|_| ()).map_err(|_| {
+        LLMCoreError::ConfigError(format!(
+            "The API key for provider '{}' contains characters that cannot be sent in a header",
+            provider_name
+        ))
+    })
+}
+
+/// Returns `true` if `models.json` lists the provider's model `model_tag` with
+/// `"reasoning": "toggle"`, i.e. its native reasoning can be switched per request.
+pub fn has_reasoning_toggle(provider_name: &str, model_tag: &str) -> bool {
+    MODEL_LIBRARY
+        .find_model_by_tag(provider_name, model_tag)
+        .is_some_and(|details| details.reasoning_capability == ReasoningCapability::Toggle)
+}
+
+/// Tokens kept free for the visible answer when `max_tokens` is derived from a thinking budget.
+pub const MIN_ANSWER_TOKENS: u32 = 1024;
+
+/// Output-token limits applied to every request, set with `Orchestra::set_generation_limits`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenerationLimits {
+    /// Cap on generated tokens. For reasoning models this includes the hidden reasoning.
+    pub max_tokens: Option<u32>,
+    /// Tokens the model may spend on reasoning when thinking mode is on.
+    pub thinking_budget: Option<u32>,
+}
+
+impl GenerationLimits {
+    /// Returns the `max_tokens` to send alongside a thinking `budget`. An explicit
+    /// `max_tokens` wins; otherwise `default` is raised so the answer keeps at least
+    /// `MIN_ANSWER_TOKENS` after the budget is spent.
+    pub fn max_tokens_above_budget(&self, default: u32, budget: u32) -> u32 {
+        self.max_tokens
+            .unwrap_or_else(|| default.max(budget.saturating_add(MIN_ANSWER_TOKENS)))
+    }
+}
+
+/// What `Orchestra` does with a sampling value outside the range its provider accepts,
+/// set with `Orchestra::set_sampling_policy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SamplingPolicy {
+    /// Clamp the value into the provider's range and print a warning.
+    #[default]
+    Clamp,
+    /// Reject the configuration with a `ConfigError`.
+    Error,
+}
+
+/// Options for an image generation request. Fields a provider does not support are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageOptions {
+    /// Number of images to generate (default 1).
+    pub n: Option<u32>,
+    /// Image size as `"WIDTHxHEIGHT"` (e.g. `"1024x1024"`) or an aspect ratio such as `"16:9"`.
+    pub size: Option<String>,
+    /// Provider-specific quality level (e.g. `"hd"`, `"high"`).
+    pub quality: Option<String>,
+    /// Provider-specific style (e.g. `"vivid"`, `"natural"`).
+    pub style: Option<String>,
+}
+
+/// One generated image as returned by a parser: (accompanying text, base64 image data).
+pub type GeneratedImage = (Option<String>, Option<String>);
+
+/// Cleans up base64 image data from a provider and checks that it decodes. A
+/// `data:image/...;base64,` prefix, whitespace and URL-safe characters are accepted and
+/// the result is standard, padded base64. Truncated or otherwise invalid data is an
+/// `ImageGenerationError`.
+pub fn normalize_image_b64(data: &str) -> Result<String, LLMCoreError> {
+    let data = data.trim();
+    let data = match data.strip_prefix("data:") {
+        Some(uri) => uri.split_once(',').map_or(uri, |(_, payload)| payload),
+        None => data,
+    };
+    let cleaned: String = data
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    let bytes = BASE64_LENIENT.decode(cleaned.as_bytes()).map_err(|e| {
+        LLMCoreError::ImageGenerationError(format!(
+            "Image data is not valid base64 ({} characters, possibly truncated): {}",
+            cleaned.len(),
+            e
+        ))
+    })?;
+    if bytes.is_empty() {
+        return Err(LLMCoreError::ImageGenerationError("Image data is empty.".to_string()));
+    }
+    Ok(BASE64_STANDARD.encode(bytes))
+}
 
 /// A trait for provider-specific payload adjustments and request building.
 ///
@@ -34,26 +149,111 @@ pub trait ProviderAdapter: Send + Sync {
     }
 
     /// Prepares the payload for an image generation request.
-    fn prepare_image_request_payload(&self, _prompt: &str, _model_tag: &str) -> JsonValue {
+    fn prepare_image_request_payload(
+            &self,
+            _prompt: &str,
+            _model_tag: &str,
+            _options: &ImageOptions,
+        ) -> JsonValue {
         // Default implementation returns an empty JSON object.
         // Providers that don't support image generation will effectively do nothing.
         json!({ "error": "Image generation not supported by this provider." })
     }
 
+    /// Prepares the request that stores `system_prompt` and `content` in the provider's
+    /// context cache for `ttl`. Only called when `supports_context_caching` is `true`.
+    fn prepare_cached_content_request(
+            &self,
+            _model_tag: &str,
+            _system_prompt: Option<&str>,
+            _content: &str,
+            _ttl: Duration,
+        ) -> JsonValue {
+        json!({ "error": "Context caching not supported by this provider." })
+    }
+
+    /// Returns the URL that creates a cached context.
+    fn get_cached_content_url(&self, _base_url: &str, _api_key: &str) -> String {
+        String::new()
+    }
+
+    /// Points a prepared payload at the cached context `cache_name` in place of the
+    /// content stored in it.
+    fn apply_cached_content(&self, _payload: &mut JsonValue, _cache_name: &str) {}
+
+    /// Applies output-token limits to a payload built by `prepare_request_payload`.
+    /// The default sets the OpenAI-style `max_tokens` field; providers with their own
+    /// field or a thinking budget override it.
+    fn apply_generation_limits(
+            &self,
+            payload: &mut JsonValue,
+            _model_tag: &str,
+            limits: &GenerationLimits,
+            _thinking_mode: bool,
+        ) {
+        if let Some(max_tokens) = limits.max_tokens {
+            payload["max_tokens"] = json!(max_tokens);
+        }
+    }
+
+    /// Switches a prepared payload to the provider's schema-less JSON object mode.
+    /// Only called when `supports_json_mode` is `true`; the default sets the
+    /// OpenAI-style `response_format`.
+    fn apply_json_mode(&self, payload: &mut JsonValue, _model_tag: &str) {
+        payload["response_format"] = json!({ "type": "json_object" });
+    }
+
+    /// Asks for the response as a server-sent event stream. Only called when
+    /// `supports_streaming` is `true`; the default sets the OpenAI-style `stream` flag
+    /// and requests usage in the final chunk.
+    fn apply_streaming(&self, payload: &mut JsonValue) {
+        payload["stream"] = json!(true);
+        payload["stream_options"] = json!({ "include_usage": true });
+    }
+
+    /// Tags a prepared payload with the id of the end user it is sent for, which the
+    /// provider uses for abuse monitoring. The default sets the OpenAI-style `user`
+    /// field; providers without such a field leave the payload unchanged.
+    fn apply_end_user_id(&self, payload: &mut JsonValue, user_id: &str) {
+        payload["user"] = json!(user_id);
+    }
+
+    /// Appends `prefill` as the start of the assistant's reply. Only called when
+    /// `supports_assistant_prefill` is `true`; the default pushes a trailing
+    /// `assistant` message onto `messages`.
+    fn apply_assistant_prefill(&self, payload: &mut JsonValue, prefill: &str) {
+        if let Some(messages) = payload["messages"].as_array_mut() {
+            messages.push(json!({ "role": "assistant", "content": prefill }));
+        }
+    }
+
     /// Returns the full, provider-specific request URL.
     fn get_request_url(&self, base_url: &str, model_tag: &str, api_key: &str) -> String;
 
-    /// Returns the full URL for an embedding request.
+    /// Returns the URL for a raw request to `path` under the base URL. Providers that
+    /// authenticate through the URL rather than headers add the key here.
+    fn get_raw_request_url(&self, base_url: &str, path: &str, _api_key: &str) -> String {
+        format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+    }
+
+    /// Returns the full URL for an embedding request. The default is the OpenAI-style
+    /// `{base_url}/embeddings`; providers with another path (Gemini's
+    /// `:batchEmbedContents`) override it.
     fn get_embedding_url(
             &self,
             base_url: &str,
             _model_tag: &str,
             _provider_config: &ProviderConfig,
         ) -> String {
-        // A sensible default that works for OpenAI-like APIs.
         format!("{}/embeddings", base_url.trim_end_matches('/'))
     }
 
+    /// Returns the headers for an embedding request. Defaults to the chat headers;
+    /// providers whose embedding endpoint authenticates differently override it.
+    fn get_embedding_headers(&self, api_key: &str) -> header::HeaderMap {
+        self.get_request_headers(api_key)
+    }
+
     /// Returns the full URL for an image generation request.
     /// The default implementation can be a generic endpoint, but providers should override it.
     fn get_image_request_url(&self, base_url: &str, _model_tag: &str, api_key: &str) -> String {
@@ -73,6 +273,46 @@ pub trait ProviderAdapter: Send + Sync {
     /// Returns `true` if the provider supports native tool calling for a given model.
     fn supports_tools(&self, model_tag: &str) -> bool;
 
+    /// Returns `true` if the provider has a JSON object mode that needs no schema.
+    fn supports_json_mode(&self, _model_tag: &str) -> bool {
+        false
+    }
+
+    /// The `temperature` range the provider accepts, as `(min, max)`. Values outside it
+    /// are clamped or rejected by `Orchestra` before a request is built.
+    fn temperature_range(&self, _model_tag: &str) -> (f32, f32) {
+        (0.0, 2.0)
+    }
+
+    /// Returns `true` if the request payload can switch the model's native reasoning
+    /// on and off through `thinking_mode`.
+    fn supports_thinking_toggle(&self, _model_tag: &str) -> bool {
+        false
+    }
+
+    /// Returns `true` if the provider rejects two `user` or two `assistant` messages in a row.
+    fn requires_alternating_roles(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if the provider continues a trailing `assistant` message, so a
+    /// reply can be started for the model. The reply then omits the prefilled text.
+    fn supports_assistant_prefill(&self, _model_tag: &str, _thinking_mode: bool) -> bool {
+        false
+    }
+
+    /// Returns `true` if the provider can store a large prompt in an explicit context
+    /// cache that later requests reference by name.
+    fn supports_context_caching(&self, _model_tag: &str) -> bool {
+        false
+    }
+
+    /// Returns `true` if the provider can stream OpenAI-style `chat.completion.chunk`
+    /// events, which `sse::assemble_stream` turns back into a complete response.
+    fn supports_streaming(&self, _model_tag: &str) -> bool {
+        false
+    }
+
     /// Returns `true` if the provider supports embeddings for a given model.
     fn supports_embeddings(&self, _model_tag: &str) -> bool {
         false // Default to false for safety.
@@ -93,11 +333,23 @@ pub trait ResponseParser: Send + Sync {
             output_price: f32,
         ) -> Result<ResponsePayload, LLMCoreError>;
 
-    /// Parses the response from an image generation call into a tuple of (text, image_data).
+    /// Parses the response to a cached context request into the cache's name and, when
+    /// the provider reports one, its expiry time.
+    fn parse_cached_content_response(
+            &self,
+            _raw_response_text: &str,
+        ) -> Result<(String, Option<DateTime<Utc>>), LLMCoreError> {
+        Err(LLMCoreError::ConfigError(
+            "Context caching not supported by this provider's parser.".to_string(),
+        ))
+    }
+
+    /// Parses the response from an image generation call into one (text, image_data)
+    /// pair per generated image.
     fn parse_image_response(
             &self,
             _raw_response_text: &str,
-        ) -> Result<(Option<String>, Option<String>), LLMCoreError> {
+        ) -> Result<Vec<GeneratedImage>, LLMCoreError> {
         // Default implementation returns an error.
         Err(LLMCoreError::ImageGenerationError(
             "Image generation not supported by this provider's parser.".to_string(),
@@ -115,6 +367,73 @@ pub trait ResponseParser: Send + Sync {
     }
 }
 
+/// An adapter and parser pair registered for a provider name.
+pub type RegisteredProvider = (Arc<dyn ProviderAdapter>, Arc<dyn ResponseParser>);
+
+/// A model served by a registered provider: its name, the provider name, the
+/// provider's configuration and the model's entry in it.
+pub type RegisteredModel = (String, String, ProviderConfig, ModelDetails);
+
+// Providers registered at runtime. They take precedence over the built-in adapters.
+static PROVIDER_REGISTRY: Lazy<RwLock<HashMap<String, RegisteredProvider>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Model entries supplied with a registration, keyed by provider name. They are looked
+// up before `models.json`, so a provider that is not listed there can still be used.
+static REGISTERED_MODELS: Lazy<RwLock<HashMap<String, ProviderConfig>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Registers an adapter and parser for a provider name, replacing any earlier registration.
+/// `config` lists the provider's models in the `models.json` format; without it, models
+/// are looked up in `models.json` under `name`.
+pub fn register_provider(
+        name: &str,
+        adapter: Arc<dyn ProviderAdapter>,
+        parser: Arc<dyn ResponseParser>,
+        config: Option<ProviderConfig>,
+    ) {
+    let mut registry = PROVIDER_REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    let mut models = REGISTERED_MODELS.write().unwrap_or_else(|e| e.into_inner());
+    registry.insert(name.to_string(), (adapter, parser));
+    match config {
+        Some(config) => models.insert(name.to_string(), config),
+        None => models.remove(name),
+    };
+}
+
+/// Removes the registration for a provider name. Returns `false` if there was none.
+pub fn unregister_provider(name: &str) -> bool {
+    let mut registry = PROVIDER_REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    REGISTERED_MODELS.write().unwrap_or_else(|e| e.into_inner()).remove(name);
+    registry.remove(name).is_some()
+}
+
+/// Returns the adapter and parser registered for a provider name, if any.
+pub fn registered_provider(name: &str) -> Option<RegisteredProvider> {
+    PROVIDER_REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+}
+
+/// Finds a chat model in the configurations supplied to `register_provider`, by its
+/// name or (case-insensitively) one of its aliases.
+pub fn registered_model(model_name: &str) -> Option<RegisteredModel> {
+    let models = REGISTERED_MODELS.read().unwrap_or_else(|e| e.into_inner());
+    let find = |matches: &dyn Fn(&str, &ModelDetails) -> bool| {
+        models.iter().find_map(|(provider_name, config)| {
+            config
+                .models
+                .iter()
+                .find(|(name, details)| matches(name, details))
+                .map(|(name, details)| (name.clone(), provider_name.clone(), config.clone(), details.clone()))
+        })
+    };
+    find(&|name, _| name == model_name)
+        .or_else(|| find(&|_, details| details.aliases.iter().any(|a| a.eq_ignore_ascii_case(model_name))))
+}
+
 // We will declare the specific provider modules here as we create them.
 pub mod gemini;
 pub mod grok;
@@ -123,4 +442,9 @@ pub mod openai;
 pub mod mercury;
 pub mod ollama;
 pub mod openrouter;
+pub mod sse;
 pub mod unsupported;
+
+#[cfg(test)]
+mod golden_tests;
+
@@ -1,14 +1,13 @@
-use crate::datam::{Choice, Message, ResponsePayload};
+use crate::datam::{Choice, Message, ResponsePayload, extract_reasoning};
 use crate::tools::ToolDefinition;
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
 
-use super::{ProviderAdapter, ResponseParser};
+use super::{GenerationLimits, ProviderAdapter, ResponseParser};
 use serde_json::{json, Value as JsonValue};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet; // Added for HashSet
 use reqwest::header;
-use regex::Regex;
 
 /// Adapter for the Ollama API.
 pub struct OllamaAdapter;
@@ -23,7 +22,7 @@ fn granite_tool_supported_models() -> HashSet<&'static str> {
 
 // Helper to identify models that have a 'think' option (like Qwen)
 fn standard_ollama_think_supported_models() -> HashSet<&'static str> {
-    ["qwen3:0.6b", "deepseek-r1:free"].iter().cloned().collect()
+    ["qwen3:0.6b", "deepseek-r1:1.5b", "deepseek-r1:free"].iter().cloned().collect()
 }
 
 // Helper to identify models that support standard Ollama tool calls (like Qwen)
@@ -256,6 +255,34 @@ impl ProviderAdapter for OllamaAdapter {
         granite_tool_supported_models().contains(lower_model_tag.as_str())
             || standard_ollama_tool_supported_models().contains(lower_model_tag.as_str())
     }
+
+    fn supports_thinking_toggle(&self, model_tag: &str) -> bool {
+        standard_ollama_think_supported_models().contains(model_tag)
+    }
+
+    // Ollama runs locally and has no end-user field.
+    fn apply_end_user_id(&self, _payload: &mut JsonValue, _user_id: &str) {}
+
+    fn apply_generation_limits(
+            &self,
+            payload: &mut JsonValue,
+            _model_tag: &str,
+            limits: &GenerationLimits,
+            _thinking_mode: bool,
+        ) {
+        // Ollama takes the output cap as a model option.
+        if let Some(max_tokens) = limits.max_tokens {
+            payload["options"]["num_predict"] = json!(max_tokens);
+        }
+    }
+
+    fn supports_json_mode(&self, _model_tag: &str) -> bool {
+        true
+    }
+
+    fn apply_json_mode(&self, payload: &mut JsonValue, _model_tag: &str) {
+        payload["format"] = json!("json");
+    }
 }
 
 #[derive(Deserialize)]
@@ -265,6 +292,9 @@ struct OllamaResponse {
     message: OllamaMessage, // Use our new intermediate struct
     #[serde(rename = "done")]
     _done: bool,
+    // `"stop"` or `"length"`, like OpenAI, but also `"stop"` when the model called tools.
+    #[serde(default)]
+    done_reason: Option<String>,
     #[serde(default)]
     prompt_eval_count: u32,
     #[serde(default)]
@@ -330,20 +360,8 @@ impl ResponseParser for OllamaParser {
             }
         }
         
-        let mut reasoning_content: Option<String> = None;
-
-        // NEW (Corrected): Extract <think> blocks and place them in `reasoning_content`.
-        if let Some(content) = &mut ollama_response.message.content {
-            // Use regex to be more robust.
-            let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-            if let Some(captures) = think_re.captures(content) {
-                if let Some(thought) = captures.get(1) {
-                    reasoning_content = Some(thought.as_str().trim().to_string());
-                }
-                // Remove the entire think block from the original content, leaving the rest.
-                *content = think_re.replace(content, "").trim().to_string();
-            }
-        }
+        // Extract <think> blocks and place them in `reasoning_content`.
+        let reasoning_content = extract_reasoning(&mut ollama_response.message.content);
         
         // FINAL NORMALIZATION: If the response contains tool calls, any accompanying `content`
         // is often model chatter or garbage. We normalize it by setting it to `None` to completely
@@ -362,6 +380,11 @@ impl ResponseParser for OllamaParser {
                         .as_secs()
                 });
 
+        let finish_reason = match ollama_response.done_reason {
+            Some(reason) if reason == "stop" && ollama_response.message.tool_calls.is_some() => Some("tool_calls".to_string()),
+            other => other,
+        };
+
         let final_message = Message {
             role: ollama_response.message.role,
             content: ollama_response.message.content,
@@ -383,12 +406,15 @@ impl ResponseParser for OllamaParser {
         };
 
         Ok(ResponsePayload {
+            // Ollama does not return a response id, so one is synthesized. `model` is the
+            // model Ollama actually served, which may differ from the requested tag.
             id: format!("ollama-{}", uuid::Uuid::new_v4()),
             object: "chat.completion".to_string(),
             created: created_timestamp,
             model: ollama_response.model,
             choices: vec![Choice {
                 message: final_message,
+                finish_reason,
             }],
             usage: {
                 let mut usage = crate::datam::Usage {
@@ -400,6 +426,10 @@ impl ResponseParser for OllamaParser {
                 usage.calculate_cost(input_price, output_price);
                 Some(usage)
             },
+            citations: None,
+            request_id: None,
+            rate_limit: None,
+            served_by: None,
         })
     }
 } 
\ No newline at end of file
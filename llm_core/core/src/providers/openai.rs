@@ -1,14 +1,15 @@
-use crate::datam::{Message, ResponsePayload};
-use crate::tools::ToolDefinition;
+use crate::datam::{Choice, Message, ResponsePayload, Role, Usage, extract_reasoning};
+use crate::tools::{FunctionCall, ToolCall, ToolDefinition};
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
-use crate::config::ProviderConfig;
+use crate::client::{self, RetryPolicy};
+use crate::config::{ChatApi, ProviderConfig, MODEL_LIBRARY};
 
-use super::{ProviderAdapter, ResponseParser};
+use super::{normalize_image_b64, GeneratedImage, GenerationLimits, ImageOptions, ProviderAdapter, ResponseParser};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
-use reqwest::header;
-use regex::Regex;
+use reqwest::{header, Method};
+use std::collections::HashMap;
 
 /// Adapter for the OpenAI API.
 pub struct OpenAIAdapter;
@@ -31,11 +32,23 @@ impl ProviderAdapter for OpenAIAdapter {
             _thinking_mode: bool,
             _debug: bool,
         ) -> JsonValue {
+        // Newer models expect the system prompt under the `developer` role.
+        let system_role = MODEL_LIBRARY
+            .find_model_by_tag(self.get_provider_name(), model_tag)
+            .map(|details| details.system_role)
+            .unwrap_or_default();
+        if self.chat_api(model_tag) == ChatApi::Responses {
+            return responses_payload(model_tag, messages, temperature, schema, tools, system_role);
+        }
+
         // OpenAI expects tool_call arguments to be a string. We must re-serialize
         // our internal JSON object representation before sending it back.
         let processed_messages: Vec<JsonValue> = messages
             .into_iter()
             .map(|mut msg| {
+                if msg.role == Role::System.as_str() {
+                    msg.role = system_role.as_str().to_string();
+                }
                 if msg.role == "assistant" {
                     if let Some(tool_calls) = &mut msg.tool_calls {
                         for call in tool_calls {
@@ -61,31 +74,12 @@ impl ProviderAdapter for OpenAIAdapter {
             payload["tool_choice"] = json!("auto");
         } else if let Some(schema) = schema {
             // Convert the SimpleSchema into a ToolDefinition for OpenAI
-            let mut properties = serde_json::Map::new();
-            let mut required = Vec::new();
-
-            for prop in schema.properties {
-                let mut prop_val = json!({
-                    "type": prop.property_type,
-                    "description": prop.description,
-                });
-                if let Some(items) = prop.items {
-                    prop_val["items"] = json!({ "type": items.item_type });
-                }
-                properties.insert(prop.name.clone(), prop_val);
-                required.push(serde_json::Value::String(prop.name));
-            }
-
             let function_tool = json!({
                 "type": "function",
                 "function": {
                     "name": schema.name,
                     "description": schema.description,
-                    "parameters": {
-                        "type": "object",
-                        "properties": properties,
-                        "required": required,
-                    }
+                    "parameters": schema_parameters(&schema),
                 }
             });
 
@@ -106,13 +100,44 @@ impl ProviderAdapter for OpenAIAdapter {
         })
     }
 
-    /// Prepares the payload for an image generation request.
-    fn prepare_image_request_payload(&self, _prompt: &str, _model_tag: &str) -> JsonValue {
-        json!({ "error": "Image generation not supported by OpenAI." })
+    /// Prepares the payload for an `/images/generations` request.
+    fn prepare_image_request_payload(
+            &self,
+            prompt: &str,
+            model_tag: &str,
+            options: &ImageOptions,
+        ) -> JsonValue {
+        let mut payload = json!({
+            "model": model_tag,
+            "prompt": prompt,
+            "n": options.n.unwrap_or(1),
+        });
+        if let Some(size) = &options.size {
+            payload["size"] = json!(size);
+        }
+        if let Some(quality) = &options.quality {
+            payload["quality"] = json!(quality);
+        }
+        // DALL·E returns URLs unless asked for base64 and is the only family with a
+        // style; gpt-image models always return base64 and reject both fields.
+        if model_tag.starts_with("dall-e") {
+            payload["response_format"] = json!("b64_json");
+            if let Some(style) = &options.style {
+                payload["style"] = json!(style);
+            }
+        }
+        payload
     }
 
-    fn get_request_url(&self, base_url: &str, _model_tag: &str, _api_key: &str) -> String {
-        format!("{}/chat/completions", base_url)
+    fn get_request_url(&self, base_url: &str, model_tag: &str, _api_key: &str) -> String {
+        match self.chat_api(model_tag) {
+            ChatApi::ChatCompletions => format!("{}/chat/completions", base_url),
+            ChatApi::Responses => format!("{}/responses", base_url),
+        }
+    }
+
+    fn get_image_request_url(&self, base_url: &str, _model_tag: &str, _api_key: &str) -> String {
+        format!("{}/images/generations", base_url.trim_end_matches('/'))
     }
 
     /// Gets the URL for embedding requests.
@@ -146,11 +171,423 @@ impl ProviderAdapter for OpenAIAdapter {
         true
     }
 
+    fn supports_json_mode(&self, _model_tag: &str) -> bool {
+        true
+    }
+
+    // Responses API streams use their own event types, which `sse` does not assemble.
+    fn supports_streaming(&self, model_tag: &str) -> bool {
+        self.chat_api(model_tag) == ChatApi::ChatCompletions
+    }
+
     /// Checks if the model supports embeddings.
     fn supports_embeddings(&self, _model_tag: &str) -> bool {
         // OpenAI supports embeddings for models like text-embedding-3-small and text-embedding-3-large.
         true
     }
+
+    fn apply_generation_limits(
+            &self,
+            payload: &mut JsonValue,
+            model_tag: &str,
+            limits: &GenerationLimits,
+            _thinking_mode: bool,
+        ) {
+        // Reasoning models reject `max_tokens`; `max_completion_tokens` works for every
+        // chat model and counts reasoning tokens toward the cap.
+        if let Some(max_tokens) = limits.max_tokens {
+            let field = match self.chat_api(model_tag) {
+                ChatApi::ChatCompletions => "max_completion_tokens",
+                ChatApi::Responses => "max_output_tokens",
+            };
+            payload[field] = json!(max_tokens);
+        }
+    }
+
+    fn apply_json_mode(&self, payload: &mut JsonValue, model_tag: &str) {
+        match self.chat_api(model_tag) {
+            ChatApi::ChatCompletions => payload["response_format"] = json!({ "type": "json_object" }),
+            ChatApi::Responses => payload["text"] = json!({ "format": { "type": "json_object" } }),
+        }
+    }
+}
+
+impl OpenAIAdapter {
+    /// The API a model's chat turns use, from its `api` setting in `models.json`.
+    fn chat_api(&self, model_tag: &str) -> ChatApi {
+        MODEL_LIBRARY
+            .find_model_by_tag(self.get_provider_name(), model_tag)
+            .map(|details| details.api)
+            .unwrap_or_default()
+    }
+}
+
+/// The JSON schema of a `SimpleSchema`'s properties, all of them required.
+fn schema_parameters(schema: &SimpleSchema) -> JsonValue {
+    let mut properties = serde_json::Map::new();
+    let mut required = Vec::new();
+
+    for prop in &schema.properties {
+        let mut prop_val = json!({
+            "type": prop.property_type,
+            "description": prop.description,
+        });
+        if let Some(items) = &prop.items {
+            prop_val["items"] = json!({ "type": items.item_type });
+        }
+        properties.insert(prop.name.clone(), prop_val);
+        required.push(JsonValue::String(prop.name.clone()));
+    }
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+// --- Responses API ---
+
+/// Builds a `/responses` request. Messages become `input` items: tool calls and their
+/// results are `function_call` and `function_call_output` items, and the rest keep
+/// their role. Tools are sent in the Responses API's flat function format.
+fn responses_payload(
+        model_tag: &str,
+        messages: Vec<Message>,
+        temperature: f32,
+        schema: Option<SimpleSchema>,
+        tools: Option<&Vec<ToolDefinition>>,
+        system_role: Role,
+    ) -> JsonValue {
+    let mut input = Vec::new();
+    for message in messages {
+        if message.role == Role::Tool.as_str() {
+            input.push(json!({
+                "type": "function_call_output",
+                "call_id": message.tool_call_id.unwrap_or_default(),
+                "output": message.content.unwrap_or_default(),
+            }));
+            continue;
+        }
+        let role = if message.role == Role::System.as_str() { system_role.as_str() } else { message.role.as_str() };
+        let tool_calls = message.tool_calls.unwrap_or_default();
+        if let Some(content) = message.content.filter(|c| !c.is_empty() || tool_calls.is_empty()) {
+            input.push(json!({ "role": role, "content": content }));
+        }
+        for call in tool_calls {
+            let arguments = match call.function.arguments {
+                JsonValue::String(arguments) => arguments,
+                arguments => arguments.to_string(),
+            };
+            input.push(json!({
+                "type": "function_call",
+                "call_id": call.id,
+                "name": call.function.name,
+                "arguments": arguments,
+            }));
+        }
+    }
+
+    let mut payload = json!({
+        "model": model_tag,
+        "input": input,
+        "temperature": temperature,
+        "store": false,
+    });
+
+    // Priority: Tools > Schema, forced the same way as with chat completions.
+    if let Some(tools) = tools {
+        let tools: Vec<JsonValue> = tools
+            .iter()
+            .map(|tool| json!({
+                "type": "function",
+                "name": tool.function.name,
+                "description": tool.function.description,
+                "parameters": tool.function.parameters,
+            }))
+            .collect();
+        payload["tools"] = json!(tools);
+        payload["tool_choice"] = json!("auto");
+    } else if let Some(schema) = schema {
+        payload["tools"] = json!([{
+            "type": "function",
+            "name": schema.name,
+            "description": schema.description,
+            "parameters": schema_parameters(&schema),
+        }]);
+        payload["tool_choice"] = json!({ "type": "function", "name": schema.name });
+    }
+
+    payload
+}
+
+/// Maps a Responses API `response` object onto a chat completion payload: output text
+/// becomes `content`, `function_call` items `tool_calls`, reasoning summaries
+/// `reasoning_content` and a `refusal` part `refusal`.
+fn parse_responses_output(raw: &JsonValue, input_price: f32, output_price: f32) -> Result<ResponsePayload, LLMCoreError> {
+    if raw["status"] == "failed" {
+        return Err(LLMCoreError::ApiError(format!("The response failed: {}", raw["error"])));
+    }
+
+    let mut content = String::new();
+    let mut reasoning = Vec::new();
+    let mut refusal = None;
+    let mut tool_calls = Vec::new();
+    for item in raw["output"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    match part["type"].as_str() {
+                        Some("output_text") => content.push_str(part["text"].as_str().unwrap_or_default()),
+                        Some("refusal") => refusal = part["refusal"].as_str().map(String::from),
+                        _ => {}
+                    }
+                }
+            }
+            Some("function_call") => tool_calls.push(ToolCall {
+                id: item["call_id"].as_str().unwrap_or_default().to_string(),
+                tool_type: "function".to_string(),
+                function: FunctionCall {
+                    name: item["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: item["arguments"]
+                        .as_str()
+                        .and_then(|arguments| serde_json::from_str(arguments).ok())
+                        .unwrap_or_else(|| json!({})),
+                },
+            }),
+            Some("reasoning") => {
+                for summary in item["summary"].as_array().into_iter().flatten() {
+                    if let Some(text) = summary["text"].as_str() {
+                        reasoning.push(text.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let finish_reason = if !tool_calls.is_empty() {
+        "tool_calls"
+    } else {
+        match raw["incomplete_details"]["reason"].as_str() {
+            Some("max_output_tokens") => "length",
+            Some("content_filter") => "content_filter",
+            _ => "stop",
+        }
+    };
+
+    let mut content = if content.is_empty() && tool_calls.is_empty() && refusal.is_some() { None } else { Some(content) };
+    let mut reasoning_content = Some(reasoning.join("\n")).filter(|r| !r.is_empty());
+    if let Some(thought) = extract_reasoning(&mut content) {
+        reasoning_content = Some(thought);
+    }
+
+    let usage = raw.get("usage").filter(|u| !u.is_null()).map(|u| {
+        let count = |value: &JsonValue| value.as_u64().unwrap_or(0) as u32;
+        let mut usage = Usage {
+            prompt_tokens: count(&u["input_tokens"]),
+            completion_tokens: count(&u["output_tokens"]),
+            total_tokens: count(&u["total_tokens"]),
+            cached_tokens: u["input_tokens_details"]["cached_tokens"].as_u64().map(|c| c as u32),
+            ..Default::default()
+        };
+        usage.calculate_cost(input_price, output_price);
+        usage
+    });
+
+    Ok(ResponsePayload {
+        id: raw["id"].as_str().unwrap_or_default().to_string(),
+        object: "response".to_string(),
+        created: raw["created_at"].as_u64().unwrap_or(0),
+        model: raw["model"].as_str().unwrap_or_default().to_string(),
+        choices: vec![Choice {
+            message: Message {
+                role: "assistant".to_string(),
+                content,
+                tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+                reasoning_content,
+                refusal,
+                ..Default::default()
+            },
+            finish_reason: Some(finish_reason.to_string()),
+        }],
+        usage,
+        citations: None,
+        request_id: None,
+        rate_limit: None,
+        served_by: None,
+    })
+}
+
+// --- Batch API ---
+
+/// The state of an OpenAI batch job, as returned by the `/batches` endpoints.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpenAIBatch {
+    pub id: String,
+    /// One of `validating`, `in_progress`, `finalizing`, `completed`, `failed`,
+    /// `expired`, `cancelling` or `cancelled`.
+    pub status: String,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+}
+
+impl OpenAIBatch {
+    /// Returns `true` once the batch will not change any more.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), "completed" | "failed" | "expired" | "cancelled")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIFile {
+    id: String,
+}
+
+/// Batch requests are billed at half the regular price.
+pub const BATCH_PRICE_FACTOR: f32 = 0.5;
+
+impl OpenAIAdapter {
+    /// The batch endpoint for a model's chat turns, per its `api` setting.
+    pub fn batch_endpoint(&self, model_tag: &str) -> &'static str {
+        match self.chat_api(model_tag) {
+            ChatApi::ChatCompletions => "/v1/chat/completions",
+            ChatApi::Responses => "/v1/responses",
+        }
+    }
+
+    /// Wraps a turn payload as one line of a batch input file for `endpoint`.
+    pub fn batch_request_line(custom_id: &str, endpoint: &str, payload: JsonValue) -> JsonValue {
+        json!({
+            "custom_id": custom_id,
+            "method": "POST",
+            "url": endpoint,
+            "body": payload,
+        })
+    }
+
+    fn auth_header(api_key: &str) -> header::HeaderMap {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            header::HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap(),
+        );
+        headers
+    }
+
+    /// Uploads a JSONL batch input file and returns its file id.
+    pub async fn upload_batch_file(
+            &self,
+            base_url: &str,
+            api_key: &str,
+            jsonl: String,
+            retry_policy: &RetryPolicy,
+        ) -> Result<String, LLMCoreError> {
+        let build_form = || -> Result<reqwest::multipart::Form, reqwest::Error> {
+            let part = reqwest::multipart::Part::text(jsonl.clone())
+                .file_name("batch.jsonl")
+                .mime_str("application/jsonl")?;
+            Ok(reqwest::multipart::Form::new().text("purpose", "batch").part("file", part))
+        };
+        let (body, _) = client::execute_multipart_request(
+            format!("{}/files", base_url.trim_end_matches('/')),
+            Self::auth_header(api_key),
+            &build_form,
+            retry_policy,
+        )
+        .await?;
+        let file: OpenAIFile = serde_json::from_str(&body)?;
+        Ok(file.id)
+    }
+
+    /// Starts a batch for an uploaded input file whose lines target `endpoint`.
+    pub async fn create_batch(
+            &self,
+            base_url: &str,
+            api_key: &str,
+            input_file_id: &str,
+            endpoint: &str,
+            retry_policy: &RetryPolicy,
+        ) -> Result<OpenAIBatch, LLMCoreError> {
+        let payload = json!({
+            "input_file_id": input_file_id,
+            "endpoint": endpoint,
+            "completion_window": "24h",
+        });
+        let (body, _) = client::execute_request(
+            Method::POST,
+            format!("{}/batches", base_url.trim_end_matches('/')),
+            self.get_request_headers(api_key),
+            Some(&payload),
+            retry_policy,
+        )
+        .await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    pub async fn retrieve_batch(
+            &self,
+            base_url: &str,
+            api_key: &str,
+            batch_id: &str,
+            retry_policy: &RetryPolicy,
+        ) -> Result<OpenAIBatch, LLMCoreError> {
+        let (body, _) = client::execute_request(
+            Method::GET,
+            format!("{}/batches/{}", base_url.trim_end_matches('/'), batch_id),
+            Self::auth_header(api_key),
+            None,
+            retry_policy,
+        )
+        .await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Downloads the raw content of a file, such as a batch output file.
+    pub async fn download_file(
+            &self,
+            base_url: &str,
+            api_key: &str,
+            file_id: &str,
+            retry_policy: &RetryPolicy,
+        ) -> Result<String, LLMCoreError> {
+        let (body, _) = client::execute_request(
+            Method::GET,
+            format!("{}/files/{}/content", base_url.trim_end_matches('/'), file_id),
+            Self::auth_header(api_key),
+            None,
+            retry_policy,
+        )
+        .await?;
+        Ok(body)
+    }
+
+    /// Splits a batch output (or error) file into the raw response body of each
+    /// request, keyed by `custom_id`. Failed requests map to an error.
+    pub fn parse_batch_output(jsonl: &str) -> HashMap<String, Result<String, LLMCoreError>> {
+        let mut results = HashMap::new();
+        for line in jsonl.lines().filter(|l| !l.trim().is_empty()) {
+            let Ok(entry) = serde_json::from_str::<JsonValue>(line) else {
+                eprintln!("[WARNING] Skipping unreadable batch output line: {}", line);
+                continue;
+            };
+            let Some(custom_id) = entry["custom_id"].as_str() else {
+                continue;
+            };
+
+            let response = &entry["response"];
+            let status = response["status_code"].as_u64().unwrap_or(0) as u16;
+            let result = if !entry["error"].is_null() {
+                Err(LLMCoreError::ApiError(entry["error"].to_string()))
+            } else if (200..300).contains(&status) {
+                Ok(response["body"].to_string())
+            } else {
+                Err(LLMCoreError::ApiErrorDetailed { status, body: response["body"].to_string() })
+            };
+            results.insert(custom_id.to_string(), result);
+        }
+        results
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -209,6 +646,17 @@ pub struct OpenAIEmbeddingUsage {
     pub total_tokens: u32,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct OpenAIImageResponse {
+    data: Vec<OpenAIImageData>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OpenAIImageData {
+    b64_json: Option<String>,
+    revised_prompt: Option<String>,
+}
+
 impl ResponseParser for OpenAIParser {
     fn parse_response(
         &self,
@@ -217,18 +665,16 @@ impl ResponseParser for OpenAIParser {
         input_price: f32,
         output_price: f32,
     ) -> Result<ResponsePayload, LLMCoreError> {
-        let mut payload: ResponsePayload = serde_json::from_str(raw_response_text)?;
+        let raw: JsonValue = serde_json::from_str(raw_response_text)?;
+        if raw["object"] == "response" {
+            return parse_responses_output(&raw, input_price, output_price);
+        }
+        let mut payload: ResponsePayload = serde_json::from_value(raw)?;
 
         if let Some(choice) = payload.choices.get_mut(0) {
             // NEW: Handle prompt-induced reasoning by parsing <think> tags.
-            if let Some(content) = &mut choice.message.content {
-                let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-                if let Some(captures) = think_re.captures(content) {
-                    if let Some(thought) = captures.get(1) {
-                        choice.message.reasoning_content = Some(thought.as_str().trim().to_string());
-                    }
-                    *content = think_re.replace(content, "").trim().to_string();
-                }
+            if let Some(thought) = extract_reasoning(&mut choice.message.content) {
+                choice.message.reasoning_content = Some(thought);
             }
 
             // OpenAI returns tool arguments as a stringified JSON. We must parse it.
@@ -276,4 +722,180 @@ impl ResponseParser for OpenAIParser {
             .collect();
         Ok(embeddings)
     }
+
+    /// Parses an `/images/generations` response. DALL·E 3's rewritten prompt, when
+    /// present, is returned as the image's text.
+    fn parse_image_response(
+            &self,
+            raw_response_text: &str,
+        ) -> Result<Vec<GeneratedImage>, LLMCoreError> {
+        let response: OpenAIImageResponse = serde_json::from_str(raw_response_text).map_err(|e| {
+            LLMCoreError::ResponseParseError(format!(
+                "Failed to parse OpenAI image response: {}. Raw response: {}",
+                e, raw_response_text
+            ))
+        })?;
+        if response.data.is_empty() {
+            return Err(LLMCoreError::ImageGenerationError("OpenAI returned no images.".to_string()));
+        }
+        response
+            .data
+            .into_iter()
+            .map(|image| Ok((image.revised_prompt, image.b64_json.as_deref().map(normalize_image_b64).transpose()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datam::{format_assistant_message, format_system_message, format_tool_message, format_user_message};
+
+    fn payload_roles(model_tag: &str) -> Vec<String> {
+        let messages = vec![
+            format_system_message("Be brief.".to_string()),
+            format_user_message("Hi".to_string()),
+        ];
+        let payload = OpenAIAdapter.prepare_request_payload(model_tag, messages, 0.0, None, None, false, false);
+        payload["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn image_requests_and_responses_are_mapped() {
+        let options = ImageOptions {
+            n: Some(2),
+            size: Some("1024x1024".to_string()),
+            quality: Some("hd".to_string()),
+            style: Some("vivid".to_string()),
+        };
+        let dalle = OpenAIAdapter.prepare_image_request_payload("A cat", "dall-e-3", &options);
+        assert_eq!(dalle["response_format"], json!("b64_json"));
+        assert_eq!(dalle["style"], json!("vivid"));
+        assert_eq!(dalle["n"], json!(2));
+
+        let gpt_image = OpenAIAdapter.prepare_image_request_payload("A cat", "gpt-image-1", &options);
+        assert!(gpt_image.get("response_format").is_none());
+        assert!(gpt_image.get("style").is_none());
+        assert_eq!(gpt_image["size"], json!("1024x1024"));
+        assert_eq!(
+            OpenAIAdapter.get_image_request_url("https://api.openai.com/v1/", "gpt-image-1", "key"),
+            "https://api.openai.com/v1/images/generations"
+        );
+
+        let raw = json!({
+            "created": 1,
+            "data": [
+                { "b64_json": "QUFB", "revised_prompt": "A cat, photo" },
+                { "b64_json": "data:image/png;base64,QkJDRA\n" }
+            ]
+        })
+        .to_string();
+        assert_eq!(OpenAIParser.parse_image_response(&raw).unwrap(), vec![
+            (Some("A cat, photo".to_string()), Some("QUFB".to_string())),
+            (None, Some("QkJDRA==".to_string())),
+        ]);
+        assert!(OpenAIParser.parse_image_response(r#"{"data": []}"#).is_err());
+        let truncated = OpenAIParser.parse_image_response(r#"{"data": [{ "b64_json": "QUFBQ" }]}"#);
+        assert!(matches!(truncated, Err(LLMCoreError::ImageGenerationError(msg)) if msg.contains("truncated")));
+    }
+
+    #[test]
+    fn batch_output_is_keyed_by_custom_id() {
+        let output = [
+            json!({ "custom_id": "item-0", "response": { "status_code": 200, "body": { "id": "chatcmpl-1" } }, "error": null }),
+            json!({ "custom_id": "item-1", "response": { "status_code": 400, "body": { "error": "bad" } }, "error": null }),
+            json!({ "custom_id": "item-2", "response": null, "error": { "code": "expired" } }),
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let results = OpenAIAdapter::parse_batch_output(&output);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results["item-0"].as_ref().unwrap(), r#"{"id":"chatcmpl-1"}"#);
+        assert!(matches!(results["item-1"], Err(LLMCoreError::ApiErrorDetailed { status: 400, .. })));
+        assert!(matches!(results["item-2"], Err(LLMCoreError::ApiError(_))));
+    }
+
+    #[test]
+    fn system_role_is_remapped_per_model() {
+        // GPT 4.1 is configured with `system_role: developer` in models.json.
+        assert_eq!(payload_roles("gpt-4.1-2025-04-14"), vec!["developer", "user"]);
+        // GPT 4o keeps the default `system` role.
+        assert_eq!(payload_roles("gpt-4o-2024-08-06"), vec!["system", "user"]);
+        // Unknown tags fall back to `system`.
+        assert_eq!(payload_roles("gpt-unknown"), vec!["system", "user"]);
+    }
+
+    #[test]
+    fn responses_requests_use_input_items() {
+        let mut tool_request = format_assistant_message(String::new());
+        tool_request.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: FunctionCall { name: "get_time".to_string(), arguments: json!({}) },
+        }]);
+        let messages = vec![
+            format_system_message("Be brief.".to_string()),
+            format_user_message("What time is it?".to_string()),
+            tool_request,
+            format_tool_message("9:00".to_string(), "call_1".to_string(), "get_time".to_string()),
+        ];
+        let tools = vec![ToolDefinition::builder("get_time", "Gets the time.").build()];
+
+        let payload = responses_payload("gpt-4.1", messages, 0.5, None, Some(&tools), Role::Developer);
+        assert_eq!(payload["store"], json!(false));
+        assert_eq!(payload["input"], json!([
+            { "role": "developer", "content": "Be brief." },
+            { "role": "user", "content": "What time is it?" },
+            { "type": "function_call", "call_id": "call_1", "name": "get_time", "arguments": "{}" },
+            { "type": "function_call_output", "call_id": "call_1", "output": "9:00" },
+        ]));
+        assert_eq!(payload["tools"][0]["name"], json!("get_time"));
+        assert!(payload["tools"][0].get("function").is_none());
+    }
+
+    #[test]
+    fn responses_output_becomes_a_chat_payload() {
+        let raw = json!({
+            "id": "resp_1",
+            "object": "response",
+            "created_at": 7,
+            "model": "gpt-4.1",
+            "status": "completed",
+            "output": [
+                { "type": "reasoning", "summary": [{ "type": "summary_text", "text": "Look it up." }] },
+                { "type": "message", "role": "assistant", "content": [{ "type": "output_text", "text": "Checking." }] },
+                { "type": "function_call", "call_id": "call_1", "name": "get_time", "arguments": "{\"zone\": \"UTC\"}" }
+            ],
+            "usage": { "input_tokens": 1000000, "output_tokens": 0, "total_tokens": 1000000, "input_tokens_details": { "cached_tokens": 10 } }
+        })
+        .to_string();
+
+        let payload = OpenAIParser.parse_response(&raw, "GPT 4.1", 2.0, 8.0).unwrap();
+        let choice = &payload.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(choice.message.content.as_deref(), Some("Checking."));
+        assert_eq!(choice.message.reasoning_content.as_deref(), Some("Look it up."));
+        let calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!((calls[0].id.as_str(), calls[0].function.arguments.clone()), ("call_1", json!({ "zone": "UTC" })));
+        let usage = payload.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.cached_tokens), (1_000_000, Some(10)));
+        assert_eq!(usage.cost.unwrap().total, 2.0);
+
+        let refused = json!({
+            "object": "response",
+            "output": [{ "type": "message", "content": [{ "type": "refusal", "refusal": "No." }] }]
+        })
+        .to_string();
+        let payload = OpenAIParser.parse_response(&refused, "GPT 4.1", 0.0, 0.0).unwrap();
+        assert_eq!(payload.choices[0].message.refusal.as_deref(), Some("No."));
+        assert_eq!(payload.choices[0].message.content, None);
+    }
 }
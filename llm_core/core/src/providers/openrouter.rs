@@ -1,16 +1,41 @@
-use crate::datam::{Message, ResponsePayload};
+use crate::datam::{Message, ResponsePayload, extract_reasoning};
 use crate::tools::ToolDefinition;
 use crate::lucky::SimpleSchema;
 use crate::error::LLMCoreError;
 
 use super::{ProviderAdapter, ResponseParser};
+use serde::Serialize;
 use serde_json::{json, Value as JsonValue};
 use reqwest::header;
-use regex::Regex;
 
 /// Adapter for the OpenRouter API.
 pub struct OpenRouterAdapter;
 
+/// Whether OpenRouter may route to upstreams that store or train on prompts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataCollectionPolicy {
+    Allow,
+    Deny,
+}
+
+/// OpenRouter provider-routing preferences. Unset fields keep OpenRouter's defaults.
+/// See <https://openrouter.ai/docs/features/provider-routing>.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OpenRouterRouting {
+    /// Upstream providers to try, in order (e.g. `["Chutes", "Targon"]`).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+    /// Whether other upstreams may be used when the ones in `order` are unavailable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+    /// Only route to upstreams that support every parameter in the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_parameters: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_collection: Option<DataCollectionPolicy>,
+}
+
 /// Parser for the OpenRouter API response.
 pub struct OpenRouterParser;
 
@@ -108,6 +133,14 @@ impl ProviderAdapter for OpenRouterAdapter {
     fn supports_tools(&self, _model_tag: &str) -> bool {
         true
     }
+
+    fn supports_json_mode(&self, _model_tag: &str) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self, _model_tag: &str) -> bool {
+        true
+    }
 }
 
 impl ResponseParser for OpenRouterParser {
@@ -131,14 +164,8 @@ impl ResponseParser for OpenRouterParser {
         if let Some(raw_choices) = raw_json.get("choices").and_then(|c| c.as_array()) {
             for (i, choice) in payload.choices.iter_mut().enumerate() {
                 // First, handle prompt-induced <think> tags.
-                if let Some(content) = &mut choice.message.content {
-                    let think_re = Regex::new(r"(?is)<think>(.*)</think>").unwrap();
-                    if let Some(captures) = think_re.captures(content) {
-                        if let Some(thought) = captures.get(1) {
-                            choice.message.reasoning_content = Some(thought.as_str().trim().to_string());
-                        }
-                        *content = think_re.replace(content, "").trim().to_string();
-                    }
+                if let Some(thought) = extract_reasoning(&mut choice.message.content) {
+                    choice.message.reasoning_content = Some(thought);
                 }
                 
                 // Then, check for native reasoning content if none was found via tags.
@@ -162,4 +189,4 @@ impl ResponseParser for OpenRouterParser {
 
         Ok(payload)
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,345 @@
+use crate::datam::{Choice, Message, ResponsePayload};
+use crate::error::LLMCoreError;
+use crate::tools::{FunctionCall, ToolCall};
+
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+
+/// The `data` of the event that ends an OpenAI-style stream.
+pub const DONE_MARKER: &str = "[DONE]";
+
+/// Splits a server-sent event stream into the `data` of each event. Text can be pushed
+/// in arbitrary pieces; an event is returned once its terminating blank line arrives.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: String,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    /// Feeds more of the stream and returns the events it completed.
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.buffer.push_str(text);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=end).collect();
+            if let Some(event) = self.push_line(line.trim_end_matches(['\n', '\r'])) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Ends the stream, returning a final event that had no trailing blank line.
+    pub fn finish(mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.is_empty() {
+            self.push_line(rest.trim_end_matches('\r'));
+        }
+        self.take_event()
+    }
+
+    fn push_line(&mut self, line: &str) -> Option<String> {
+        if line.is_empty() {
+            return self.take_event();
+        }
+        // Other fields (`event`, `id`, `retry`) and `:` comments carry nothing we use.
+        if let Some(value) = line.strip_prefix("data:") {
+            self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+        }
+        None
+    }
+
+    fn take_event(&mut self) -> Option<String> {
+        if self.data.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.data).join("\n"))
+    }
+}
+
+/// Rebuilds a complete OpenAI-style chat completion from its `chat.completion.chunk`s.
+///
+/// Each chunk is read as a partial `ResponsePayload` and folded in with
+/// `ResponsePayload::merge`. Tool calls are matched by their `index`: the id, type and
+/// name arrive once, so later fragments are given the id seen for their index, and the
+/// argument string only forms valid JSON once the stream ends. Calls are therefore only
+/// emitted by `into_response`.
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    response: Option<ResponsePayload>,
+    // The id of each tool call, by choice index and tool-call index.
+    tool_call_ids: HashMap<(u64, u64), String>,
+    // Providers report the running total, so the last usage sent is kept as is.
+    usage: Option<JsonValue>,
+    done: bool,
+}
+
+impl StreamAccumulator {
+    /// Adds the `data` of one event. The `[DONE]` marker ends the stream; an `error`
+    /// object sent mid-stream is returned as an API error.
+    pub fn push_data(&mut self, data: &str) -> Result<(), LLMCoreError> {
+        let data = data.trim();
+        if data.is_empty() {
+            return Ok(());
+        }
+        if data == DONE_MARKER {
+            self.done = true;
+            return Ok(());
+        }
+        let chunk: JsonValue = serde_json::from_str(data).map_err(|e| {
+            LLMCoreError::ResponseParseError(format!("Invalid stream chunk '{}': {}", data, e))
+        })?;
+        if let Some(error) = chunk.get("error") {
+            return Err(LLMCoreError::ApiError(format!("Provider reported an error mid-stream: {}", error)));
+        }
+        self.push_chunk(&chunk);
+        Ok(())
+    }
+
+    /// Adds one parsed chunk.
+    pub fn push_chunk(&mut self, chunk: &JsonValue) {
+        if chunk.get("usage").is_some_and(|usage| !usage.is_null()) {
+            self.usage = Some(chunk["usage"].clone());
+        }
+        let delta = self.delta_payload(chunk);
+        self.response = Some(match self.response.take() {
+            Some(response) => response.merge(delta),
+            None => delta,
+        });
+    }
+
+    /// Returns `true` once the `[DONE]` marker has been seen.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the assembled response in the non-streamed `chat.completion` shape, with
+    /// tool-call arguments as the JSON strings providers normally send.
+    pub fn into_response(self) -> JsonValue {
+        let response = self.response.unwrap_or_else(|| empty_payload(JsonValue::Null));
+        let choices: Vec<JsonValue> = response
+            .choices
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut choice)| {
+                let message = &mut choice.message;
+                if message.role.is_empty() {
+                    message.role = "assistant".to_string();
+                }
+                for call in message.tool_calls.iter_mut().flatten() {
+                    if call.tool_type.is_empty() {
+                        call.tool_type = "function".to_string();
+                    }
+                    // A call without arguments streams none at all, but callers expect a JSON object.
+                    if call.function.arguments.as_str().is_none_or(|arguments| arguments.trim().is_empty()) {
+                        call.function.arguments = json!("{}");
+                    }
+                }
+                json!({
+                    "index": index,
+                    "message": choice.message,
+                    "finish_reason": choice.finish_reason,
+                })
+            })
+            .collect();
+
+        let mut response = json!({
+            "id": response.id,
+            "object": "chat.completion",
+            "created": response.created,
+            "model": response.model,
+            "choices": choices,
+        });
+        if let Some(usage) = self.usage {
+            response["usage"] = usage;
+        }
+        response
+    }
+
+    // Reads one chunk as a partial response, with its choices placed at their `index`.
+    fn delta_payload(&mut self, chunk: &JsonValue) -> ResponsePayload {
+        let mut payload = empty_payload(chunk.clone());
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            let choice_index = choice["index"].as_u64().unwrap_or(0);
+            let position = choice_index as usize;
+            while payload.choices.len() <= position {
+                payload.choices.push(Choice { message: Message::default(), finish_reason: None });
+            }
+            let delta = &mut payload.choices[position];
+            delta.finish_reason = choice["finish_reason"].as_str().map(str::to_string);
+            let Some(fields) = choice["delta"].as_object() else { continue };
+            let message = &mut delta.message;
+            for (key, value) in fields {
+                match (key.as_str(), value) {
+                    ("role", JsonValue::String(role)) => message.role = role.clone(),
+                    ("content", JsonValue::String(text)) => message.content = Some(text.clone()),
+                    ("reasoning_content" | "reasoning", JsonValue::String(text)) => {
+                        message.reasoning_content = Some(text.clone())
+                    }
+                    ("refusal", JsonValue::String(text)) => message.refusal = Some(text.clone()),
+                    ("tool_calls", JsonValue::Array(calls)) => {
+                        let calls = calls
+                            .iter()
+                            .enumerate()
+                            .map(|(position, call)| {
+                                let index = call["index"].as_u64().unwrap_or(position as u64);
+                                self.tool_call_delta(choice_index, index, call)
+                            })
+                            .collect();
+                        message.tool_calls = Some(calls);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        payload
+    }
+
+    // The id, type and name are sent once; arguments are string fragments to concatenate.
+    fn tool_call_delta(&mut self, choice_index: u64, index: u64, delta: &JsonValue) -> ToolCall {
+        let id = match delta["id"].as_str().filter(|id| !id.is_empty()) {
+            Some(id) => {
+                self.tool_call_ids.insert((choice_index, index), id.to_string());
+                id.to_string()
+            }
+            None => self.tool_call_ids.get(&(choice_index, index)).cloned().unwrap_or_default(),
+        };
+        ToolCall {
+            id,
+            tool_type: delta["type"].as_str().unwrap_or_default().to_string(),
+            function: FunctionCall {
+                name: delta["function"]["name"].as_str().unwrap_or_default().to_string(),
+                arguments: delta["function"]["arguments"].as_str().map_or(JsonValue::Null, |fragment| json!(fragment)),
+            },
+        }
+    }
+}
+
+// A response with the chunk's id, model and creation time, and no choices yet.
+fn empty_payload(chunk: JsonValue) -> ResponsePayload {
+    ResponsePayload {
+        id: chunk["id"].as_str().unwrap_or_default().to_string(),
+        object: "chat.completion".to_string(),
+        created: chunk["created"].as_u64().unwrap_or_default(),
+        model: chunk["model"].as_str().unwrap_or_default().to_string(),
+        choices: Vec::new(),
+        usage: None,
+        citations: None,
+        request_id: None,
+        rate_limit: None,
+        served_by: None,
+    }
+}
+
+/// Returns the text deltas of one event's first choice as `(field, text)` pairs, such
+/// as `("content", "Hel")` or `("reasoning_content", "First, ")`. Events that are not
+/// JSON chunks (the `[DONE]` marker) have none.
+pub fn text_deltas(data: &str) -> Vec<(String, String)> {
+    let Ok(chunk) = serde_json::from_str::<JsonValue>(data) else {
+        return Vec::new();
+    };
+    let Some(delta) = chunk["choices"][0]["delta"].as_object() else {
+        return Vec::new();
+    };
+    delta
+        .iter()
+        .filter(|(key, _)| key.as_str() != "role")
+        .filter_map(|(key, value)| Some((key.clone(), value.as_str().filter(|text| !text.is_empty())?.to_string())))
+        .collect()
+}
+
+/// Assembles a complete server-sent event body into a non-streamed `chat.completion`.
+pub fn assemble_stream(body: &str) -> Result<JsonValue, LLMCoreError> {
+    let mut decoder = SseDecoder::default();
+    let mut accumulator = StreamAccumulator::default();
+    for data in decoder.push(body) {
+        accumulator.push_data(&data)?;
+    }
+    if let Some(data) = decoder.finish() {
+        accumulator.push_data(&data)?;
+    }
+    if accumulator.response.as_ref().is_none_or(|response| response.choices.is_empty()) {
+        return Err(LLMCoreError::ResponseParseError(
+            "The stream ended without any choices.".to_string(),
+        ));
+    }
+    Ok(accumulator.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_tool_call_is_reassembled_by_index() {
+        let body = concat!(
+            "data: {\"id\":\"c1\",\"model\":\"gpt\",\"created\":7,\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[",
+            "{\"index\":0,\"id\":\"call_a\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"ci\"}}]}}]}\n\n",
+            ": keep-alive\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":1,\"id\":\"call_b\",\"function\":{\"name\":\"get_time\",\"arguments\":\"{}\"}}]}}]}\r\n\r\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"ty\\\": \\\"Paris\\\"}\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":4,\"total_tokens\":13}}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        let response = assemble_stream(body).unwrap();
+        let message = &response["choices"][0]["message"];
+        assert_eq!(response["id"], json!("c1"));
+        assert_eq!(response["choices"][0]["finish_reason"], json!("tool_calls"));
+        assert_eq!(message["tool_calls"][0]["id"], json!("call_a"));
+        assert_eq!(message["tool_calls"][0]["function"]["name"], json!("get_weather"));
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], json!("{\"city\": \"Paris\"}"));
+        assert_eq!(message["tool_calls"][1]["function"]["name"], json!("get_time"));
+        assert_eq!(response["usage"]["total_tokens"], json!(13));
+    }
+
+    #[test]
+    fn repeated_names_and_missing_arguments_are_normalized() {
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_a\",\"function\":{\"name\":\"get_time\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"name\":\"get_time\"}}]}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let response = assemble_stream(body).unwrap();
+        let call = &response["choices"][0]["message"]["tool_calls"][0];
+        assert_eq!(call["function"]["name"], json!("get_time"));
+        assert_eq!(call["function"]["arguments"], json!("{}"));
+
+        let deltas = text_deltas("{\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"Hi\",\"reasoning_content\":\"\"}}]}");
+        assert_eq!(deltas, vec![("content".to_string(), "Hi".to_string())]);
+        assert!(text_deltas(DONE_MARKER).is_empty());
+    }
+
+    #[test]
+    fn text_deltas_are_concatenated_per_field() {
+        let body = concat!(
+            "data: {\"id\":\"c2\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"reasoning\":\"Think \"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"reasoning\":\"first.\",\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+        );
+        let response = assemble_stream(body).unwrap();
+        let message = &response["choices"][0]["message"];
+        assert_eq!(response["id"], json!("c2"));
+        assert_eq!(message["reasoning_content"], json!("Think first."));
+        assert_eq!(message["content"], json!("Hello"));
+        assert_eq!(response["choices"][0]["finish_reason"], json!("stop"));
+    }
+
+    #[test]
+    fn decoder_handles_events_split_across_pushes() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push("data: {\"a\"").is_empty());
+        assert_eq!(decoder.push(":1}\n\ndata: [DO"), vec!["{\"a\":1}".to_string()]);
+        assert!(decoder.push("NE]").is_empty());
+        assert_eq!(decoder.finish().as_deref(), Some(DONE_MARKER));
+    }
+
+    #[test]
+    fn mid_stream_errors_are_reported() {
+        let body = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\ndata: {\"error\":{\"message\":\"overloaded\"}}\n\n";
+        assert!(matches!(assemble_stream(body), Err(LLMCoreError::ApiError(msg)) if msg.contains("overloaded")));
+    }
+}
@@ -69,7 +69,46 @@ use once_cell::sync::Lazy;
 use std::path::Path;
 use std::sync::Mutex;
 
-use crate::vector::KnowledgeBase;
+use crate::config::storage::DocumentChunk;
+use crate::datam::Citation;
+use crate::vector::{KnowledgeBase, SearchMode};
+
+/// Returned alongside search results so the synthesizing model cites its sources.
+pub const CITATION_INSTRUCTIONS: &str = "When using these results, cite each fact inline with the `source_id` of the result it came from, in square brackets, e.g. [https://example.com/page#2]. Only cite source ids that appear in the results.";
+
+/// Builds the stable identifier used to cite a chunk: `<url>#<chunk_number>`.
+pub fn source_id(url: &str, chunk_number: i32) -> String {
+    format!("{}#{}", url, chunk_number)
+}
+
+/// Resolves the `[source_id]` references in `text` against the sources returned by
+/// knowledge base tool calls (`tool_outputs` are the raw tool result strings).
+/// Each cited source is returned once, in order of first citation.
+pub fn extract_citations(text: &str, tool_outputs: &[String]) -> Vec<Citation> {
+    let mut known: Vec<Citation> = Vec::new();
+    for output in tool_outputs {
+        let Ok(json) = serde_json::from_str::<JsonValue>(output) else { continue };
+        // `knowledge_base_multi_search` groups its results per query under `searches`.
+        let groups = match json.get("searches").and_then(|s| s.as_array()) {
+            Some(searches) => searches.iter().filter_map(|s| s.get("results")).collect(),
+            None => json.get("results").into_iter().collect::<Vec<_>>(),
+        };
+        for result in groups.into_iter().filter_map(|r| r.as_array()).flatten() {
+            if let Ok(citation) = serde_json::from_value::<Citation>(result.clone()) {
+                if !known.iter().any(|c| c.source_id == citation.source_id) {
+                    known.push(citation);
+                }
+            }
+        }
+    }
+
+    let mut cited: Vec<(usize, Citation)> = known
+        .into_iter()
+        .filter_map(|c| text.find(&format!("[{}]", c.source_id)).map(|pos| (pos, c)))
+        .collect();
+    cited.sort_by_key(|(pos, _)| *pos);
+    cited.into_iter().map(|(_, c)| c).collect()
+}
 
 pub static KNOWLEDGE_BASE: Lazy<Mutex<KnowledgeBase>> = Lazy::new(|| {
     let db_path = Path::new("tests/output/test_chat_kb.db");
@@ -92,26 +131,100 @@ pub fn knowledge_base_search(args: JsonValue) -> Result<JsonValue, String> {
             .ok_or("Missing 'query' argument.")?
             .to_string();
         let limit = args["limit"].as_u64().unwrap_or(5) as usize;
+        let max_per_source = args["max_per_source"].as_u64().map(|max| max as usize);
+        let search_mode = search_mode_arg(&args)?;
 
         let kb = KNOWLEDGE_BASE.lock().unwrap();
-        let results = kb.search(&query, limit).await.map_err(|e| e.to_string())?;
+        let results = kb
+            .search_with_source_limit(&query, limit, max_per_source)
+            .await
+            .map_err(|e| e.to_string())?;
 
         let formatted_results: Vec<JsonValue> = results
             .into_iter()
-            .map(|chunk| {
-                json!({
-                    "title": chunk.title,
-                    "url": chunk.url,
-                    "content": chunk.content,
-                    "summary": chunk.summary
-                })
+            .map(|chunk| search_result(chunk, search_mode))
+            .collect();
+
+        Ok(json!({
+            "results": formatted_results,
+            "citation_instructions": CITATION_INSTRUCTIONS,
+        }))
+    })
+}
+
+/// Searches the knowledge base for several queries at once, embedding them in one
+/// request. Results are grouped per query under `searches`.
+// Like `knowledge_base_search`, holds the knowledge base lock for the whole search, on
+// a runtime of its own.
+#[allow(clippy::await_holding_lock)]
+pub fn knowledge_base_multi_search(args: JsonValue) -> Result<JsonValue, String> {
+    let rt = Runtime::new().map_err(|e| format!("Failed to create Tokio runtime: {}", e))?;
+    rt.block_on(async {
+        let queries: Vec<String> = args["queries"]
+            .as_array()
+            .ok_or("Missing 'queries' argument.")?
+            .iter()
+            .map(|query| query.as_str().map(String::from).ok_or("Each query must be a string."))
+            .collect::<Result<_, _>>()?;
+        if queries.is_empty() {
+            return Err("'queries' must contain at least one query.".to_string());
+        }
+        let limit = args["limit"].as_u64().unwrap_or(5) as usize;
+        let max_per_source = args["max_per_source"].as_u64().map(|max| max as usize);
+        let search_mode = search_mode_arg(&args)?;
+
+        let kb = KNOWLEDGE_BASE.lock().unwrap();
+        let results = kb
+            .multi_search(&queries, limit, max_per_source)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let searches: Vec<JsonValue> = queries
+            .into_iter()
+            .zip(results)
+            .map(|(query, chunks)| {
+                let results: Vec<JsonValue> = chunks
+                    .into_iter()
+                    .map(|chunk| search_result(chunk, search_mode))
+                    .collect();
+                json!({ "query": query, "results": results })
             })
             .collect();
 
-        Ok(json!({ "results": formatted_results }))
+        Ok(json!({
+            "searches": searches,
+            "citation_instructions": CITATION_INSTRUCTIONS,
+        }))
     })
 }
 
+fn search_mode_arg(args: &JsonValue) -> Result<SearchMode, String> {
+    match &args["search_mode"] {
+        JsonValue::Null => Ok(SearchMode::default()),
+        mode => serde_json::from_value(mode.clone()).map_err(|_| {
+            format!("Invalid 'search_mode' {}; expected \"content\", \"summary\" or \"both\".", mode)
+        }),
+    }
+}
+
+/// One `knowledge_base_search` result, without the text `search_mode` leaves out.
+pub fn search_result(mut chunk: DocumentChunk, search_mode: SearchMode) -> JsonValue {
+    search_mode.apply(&mut chunk);
+    let mut result = json!({
+        "source_id": source_id(&chunk.url, chunk.chunk_number),
+        "title": chunk.title,
+        "url": chunk.url,
+        "chunk_number": chunk.chunk_number,
+    });
+    if search_mode != SearchMode::Summary || !chunk.content.is_empty() {
+        result["content"] = json!(chunk.content);
+    }
+    if search_mode != SearchMode::Content {
+        result["summary"] = json!(chunk.summary);
+    }
+    result
+}
+
 pub fn knowledge_base_list_sources(_args: JsonValue) -> Result<JsonValue, String> {
     let kb = KNOWLEDGE_BASE.lock().unwrap();
     let sources = kb.list_sources().map_err(|e| e.to_string())?;
@@ -124,14 +237,183 @@ pub fn knowledge_base_get_full_document(args: JsonValue) -> Result<JsonValue, St
         .ok_or("Missing 'url' argument.")?
         .to_string();
 
+    let options = FullDocumentOptions {
+        separator: args["separator"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| DEFAULT_CHUNK_SEPARATOR.to_string()),
+        include_headers: args["include_headers"].as_bool().unwrap_or(false),
+        offset: args["offset"].as_u64().unwrap_or(0) as usize,
+        max_chunks: args["max_chunks"].as_u64().map(|n| n as usize),
+    };
+
     let kb = KNOWLEDGE_BASE.lock().unwrap();
     let chunks = kb.get_full_document(&url).map_err(|e| e.to_string())?;
 
-    let full_content = chunks
-        .into_iter()
-        .map(|c| c.content)
+    let mut result = render_full_document(&chunks, &options);
+    result["url"] = json!(url);
+    Ok(result)
+}
+
+pub const DEFAULT_CHUNK_SEPARATOR: &str = "\n\n---\n\n";
+
+/// Controls how `knowledge_base_get_full_document` joins a document's chunks.
+#[derive(Debug, Clone)]
+pub struct FullDocumentOptions {
+    pub separator: String,
+    /// Prefixes each chunk with its title and summary, giving a structured outline.
+    pub include_headers: bool,
+    /// Index of the first chunk to return, for paging through long documents.
+    pub offset: usize,
+    pub max_chunks: Option<usize>,
+}
+
+impl Default for FullDocumentOptions {
+    fn default() -> Self {
+        Self {
+            separator: DEFAULT_CHUNK_SEPARATOR.to_string(),
+            include_headers: false,
+            offset: 0,
+            max_chunks: None,
+        }
+    }
+}
+
+/// Joins a page of ordered chunks into one text. `next_offset` is set when more chunks remain.
+pub fn render_full_document(chunks: &[DocumentChunk], options: &FullDocumentOptions) -> JsonValue {
+    let total_chunks = chunks.len();
+    let start = options.offset.min(total_chunks);
+    let end = options
+        .max_chunks
+        .map_or(total_chunks, |max| start.saturating_add(max).min(total_chunks));
+
+    let full_content = chunks[start..end]
+        .iter()
+        .map(|c| {
+            if !options.include_headers {
+                return c.content.clone();
+            }
+            let mut section = format!("## {} (chunk {})\n\n", c.title, c.chunk_number);
+            if !c.summary.is_empty() {
+                section.push_str(&format!("_{}_\n\n", c.summary));
+            }
+            section.push_str(&c.content);
+            section
+        })
         .collect::<Vec<String>>()
-        .join("\n\n---\n\n");
+        .join(&options.separator);
+
+    json!({
+        "full_content": full_content,
+        "total_chunks": total_chunks,
+        "offset": start,
+        "returned_chunks": end - start,
+        "next_offset": if end < total_chunks { Some(end) } else { None },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn citations_resolve_in_order_of_first_use() {
+        let tool_output = json!({
+            "results": [
+                { "source_id": "doc-a#1", "title": "A", "url": "doc-a", "chunk_number": 1, "content": "", "summary": "" },
+                { "source_id": "doc-b#3", "title": "B", "url": "doc-b", "chunk_number": 3, "content": "", "summary": "" },
+                { "source_id": "doc-c#2", "title": "C", "url": "doc-c", "chunk_number": 2, "content": "", "summary": "" }
+            ],
+            "citation_instructions": CITATION_INSTRUCTIONS
+        })
+        .to_string();
+
+        let answer = "Hammers drive nails [doc-b#3]. Apples are fruit [doc-a#1][doc-b#3]. Unknown [doc-z#9].";
+        let citations = extract_citations(answer, &[tool_output]);
+
+        let ids: Vec<&str> = citations.iter().map(|c| c.source_id.as_str()).collect();
+        assert_eq!(ids, vec!["doc-b#3", "doc-a#1"]);
+        assert_eq!(citations[0].title, "B");
+        assert_eq!(citations[0].chunk_number, 3);
+    }
+
+    #[test]
+    fn citations_resolve_from_grouped_searches() {
+        let tool_output = json!({
+            "searches": [
+                { "query": "tools", "results": [{ "source_id": "doc-a#1", "title": "A", "url": "doc-a", "chunk_number": 1 }] },
+                { "query": "fruit", "results": [{ "source_id": "doc-b#2", "title": "B", "url": "doc-b", "chunk_number": 2 }] }
+            ],
+            "citation_instructions": CITATION_INSTRUCTIONS
+        })
+        .to_string();
+
+        let citations = extract_citations("Pears [doc-b#2], hammers [doc-a#1].", &[tool_output]);
+        let ids: Vec<&str> = citations.iter().map(|c| c.source_id.as_str()).collect();
+        assert_eq!(ids, vec!["doc-b#2", "doc-a#1"]);
+    }
+
+    fn chunk(chunk_number: i32, title: &str, summary: &str, content: &str) -> DocumentChunk {
+        DocumentChunk {
+            id: chunk_number as i64,
+            url: "doc".to_string(),
+            chunk_number,
+            title: title.to_string(),
+            summary: summary.to_string(),
+            content: content.to_string(),
+            metadata: json!({}),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn full_document_joins_and_pages_chunks() {
+        let chunks = vec![
+            chunk(1, "Intro", "Overview", "one"),
+            chunk(2, "Body", "", "two"),
+            chunk(3, "End", "", "three"),
+        ];
+
+        let all = render_full_document(&chunks, &FullDocumentOptions::default());
+        assert_eq!(all["full_content"], json!("one\n\n---\n\ntwo\n\n---\n\nthree"));
+        assert_eq!(all["next_offset"], JsonValue::Null);
+
+        let options = FullDocumentOptions {
+            separator: "\n".to_string(),
+            include_headers: true,
+            offset: 0,
+            max_chunks: Some(2),
+        };
+        let page = render_full_document(&chunks, &options);
+        assert_eq!(
+            page["full_content"],
+            json!("## Intro (chunk 1)\n\n_Overview_\n\none\n## Body (chunk 2)\n\ntwo")
+        );
+        assert_eq!(page["returned_chunks"], json!(2));
+        assert_eq!(page["next_offset"], json!(2));
+
+        let last = render_full_document(&chunks, &FullDocumentOptions { offset: 2, ..options });
+        assert_eq!(last["full_content"], json!("## End (chunk 3)\n\nthree"));
+        assert_eq!(last["next_offset"], JsonValue::Null);
+    }
+
+    #[test]
+    fn search_results_carry_the_text_of_their_mode() {
+        let summarized = chunk(2, "Body", "Short", "Long text");
+        let unsummarized = chunk(3, "End", "", "Only text");
+
+        let content = search_result(summarized.clone(), SearchMode::Content);
+        assert_eq!(content["content"], json!("Long text"));
+        assert!(content.get("summary").is_none());
+
+        let summary = search_result(summarized.clone(), SearchMode::Summary);
+        assert_eq!(summary["summary"], json!("Short"));
+        assert!(summary.get("content").is_none());
+        assert_eq!(summary["source_id"], json!("doc#2"));
+        // Without a summary to stand in for it, the content is kept.
+        assert_eq!(search_result(unsummarized, SearchMode::Summary)["content"], json!("Only text"));
 
-    Ok(json!({ "url": url, "full_content": full_content }))
+        let both = search_result(summarized, SearchMode::Both);
+        assert_eq!((both["content"].clone(), both["summary"].clone()), (json!("Long text"), json!("Short")));
+    }
 }
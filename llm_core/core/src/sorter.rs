@@ -3,22 +3,25 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use tokio::sync::Semaphore;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use std::io::Write; // Import the Write trait
 use uuid::Uuid;
 use std::fs;
 
 // --- Import from llm-core ---
-use crate::config::{DEFAULT_SORTER_INPUT_DIR, DEFAULT_SORTER_OUTPUT_DIR}; // Import default paths from config
+use crate::config::{DEFAULT_SORTER_INPUT_DIR, DEFAULT_SORTER_OUTPUT_DIR, MODEL_LIBRARY}; // Import default paths from config
+use crate::client;
 use crate::orchestra::Orchestra;
 use crate::datam::{
-    Message,
-    format_system_message, format_user_message, format_assistant_message, Usage,
+    Message, answer_after_reasoning, count_message_tokens, count_tokens, extract_reasoning, DEFAULT_THINKING_TAG,
+    format_system_message, format_user_message, format_assistant_message, ResponsePayload, Usage,
 };
 use crate::lucky::{SimpleSchema, SchemaProperty, SchemaItems};
 use crate::error::LLMCoreError;
+use crate::jobs::JobProgress;
 use tokio::runtime::Runtime;
 use serde_json::json;
 
@@ -31,11 +34,63 @@ pub struct SortingInstructions {
     pub item_sorting_guidelines: Vec<String>,
     #[serde(default)]
     pub provided_categories: Vec<String>,
+    /// Categories given with a definition of what belongs in them, used alongside
+    /// `provided_categories`.
+    #[serde(default)]
+    pub category_definitions: Vec<CategoryDefinition>,
+    /// When set, only the provided (or generated) categories are accepted: an item put
+    /// anywhere else is sent back to the model, and left unsorted if it still does not fit.
+    #[serde(default)]
+    pub strict_categories: bool,
+    /// When set, the model is asked to explain each classification and the rationale is
+    /// kept in the sorter's audit log, which is saved next to the sorted output.
+    #[serde(default)]
+    pub capture_reasoning: bool,
+}
+
+impl SortingInstructions {
+    /// Whether any categories were provided, bare or with definitions.
+    pub fn has_categories(&self) -> bool {
+        !self.provided_categories.is_empty() || !self.category_definitions.is_empty()
+    }
+}
+
+/// A category of a controlled taxonomy, with the description the model sorts by.
+#[derive(Deserialize, Debug, Clone, Serialize, PartialEq)]
+pub struct CategoryDefinition {
+    pub name: String,
+    pub description: String,
 }
 
 #[derive(Deserialize, Debug, Serialize)]
 pub struct SortResponse {
     pub category: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+}
+
+/// One classification decision, recorded when `capture_reasoning` is enabled.
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct SortAuditEntry {
+    pub item: String,
+    pub category: String,
+    pub reasoning: Option<String>,
+}
+
+/// The projected size and cost of a sorting run, computed without calling the model.
+#[derive(Debug, Clone, Serialize)]
+pub struct CostEstimate {
+    /// Number of API requests the run would make (category generation plus one per
+    /// distinct item).
+    pub request_count: usize,
+    /// Estimated token counts, with `cost` priced from the model's `models.json` entry.
+    pub usage: Usage,
+}
+
+impl CostEstimate {
+    pub fn total_cost(&self) -> f32 {
+        self.usage.cost.as_ref().map_or(0.0, |c| c.total)
+    }
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -52,6 +107,38 @@ Your task is to analyze the provided data items (as "Item:") and use the `sortin
 - Do not include any other text or explanations in your response, only the tool call.
 "#;
 
+/// Placeholder that a custom sorter system prompt can use to wrap the built-in
+/// `SORTER_SYSTEM_PROMPT` instead of replacing it.
+pub const DEFAULT_PROMPT_PLACEHOLDER: &str = "{default_prompt}";
+
+pub const SORTER_REASONING_PROMPT: &str = r#"
+### REASONING:
+
+Also fill in the `reasoning` field with one short sentence explaining why the item belongs in the chosen category.
+"#;
+
+pub const SORTER_STRICT_PROMPT: &str = r#"
+### STRICT CATEGORIES:
+
+Choose one of the categories listed above, spelled exactly as shown. Never use any other category.
+"#;
+
+/// Sent back with an answer outside the taxonomy in strict mode; `{category}` is the answer.
+pub const SORTER_STRICT_REPROMPT: &str = "'{category}' is not one of the allowed categories. Answer again with one of the categories listed in the instructions, spelled exactly as shown.";
+
+// How many times an item placed outside the taxonomy is sent back in strict mode.
+const STRICT_REPROMPT_LIMIT: usize = 2;
+
+// Rationales taken from thinking output can be long; only the tail is kept in the audit log.
+const MAX_AUDIT_REASONING_CHARS: usize = 500;
+
+// Number of items sent per category-generation request.
+const CATEGORY_GEN_CHUNK_SIZE: usize = 50;
+// Rough completion sizes used by `Sorter::estimate`.
+const ESTIMATED_SORT_OUTPUT_TOKENS: usize = 15;
+const ESTIMATED_REASONING_OUTPUT_TOKENS: usize = 40;
+const ESTIMATED_CATEGORY_GEN_OUTPUT_TOKENS: usize = 200;
+
 pub const CATEGORY_GEN_INITIAL_PROMPT: &str = r#"You are an AI assistant tasked with generating a concise list of categories for a given set of data items. The user is having trouble creating categories and needs your help.
 
 ### DATA ITEM NAME
@@ -85,6 +172,10 @@ Return ONLY the new categories.
 
 // --- Sorter ---
 
+/// Called with `(item, category)` as each item is classified. Returning an error stops
+/// the run: no further items are sent, and the error is returned from it.
+pub type SortResultCallback = Arc<dyn Fn(&str, &str) -> Result<(), LLMCoreError> + Send + Sync>;
+
 // This struct encapsulates all logic for sorting data items using an AI model.
 pub struct Sorter {
     orchestra: Arc<Orchestra>,
@@ -93,6 +184,12 @@ pub struct Sorter {
     sorting_instructions: SortingInstructions,
     category_set: HashSet<String>,
     debug: bool,
+    progress: Option<Arc<JobProgress>>,
+    on_result: Option<SortResultCallback>,
+    audit_log: Vec<SortAuditEntry>,
+    csv_column: Option<String>,
+    // Items answered with an undefined category in strict mode, with that answer.
+    rejected: Vec<(String, String)>,
     // Removed sorter_schema and category_gen_schema fields
 }
 impl Sorter {
@@ -100,6 +197,7 @@ impl Sorter {
             orchestra: Arc<Orchestra>,
             sorting_instructions: SortingInstructions,
             output_path: Option<PathBuf>,
+            system_prompt_override: Option<String>,
             debug: bool,
         ) -> Result<Self, LLMCoreError> {
         // Use provided output_path or default from config
@@ -113,25 +211,87 @@ impl Sorter {
 
         // Schemas are now created on-demand in the methods that use them.
 
-        let category_set: HashSet<String> =
-            sorting_instructions.provided_categories.iter().cloned().collect();
+        if let Some(definition) = sorting_instructions
+            .category_definitions
+            .iter()
+            .find(|d| d.name.trim().is_empty())
+        {
+            return Err(LLMCoreError::ConfigError(format!(
+                "The category defined as '{}' has no name.",
+                definition.description
+            )));
+        }
+        let category_set: HashSet<String> = sorting_instructions
+            .provided_categories
+            .iter()
+            .chain(sorting_instructions.category_definitions.iter().map(|d| &d.name))
+            .cloned()
+            .collect();
+
+        // A custom prompt replaces the built-in one, unless it contains `{default_prompt}`,
+        // in which case it wraps it. The guidelines, profile and categories are appended
+        // by `build_sorting_instructions_message` either way.
+        let system_message_template = match system_prompt_override {
+            Some(custom) if custom.contains(DEFAULT_PROMPT_PLACEHOLDER) => {
+                custom.replace(DEFAULT_PROMPT_PLACEHOLDER, SORTER_SYSTEM_PROMPT)
+            }
+            Some(custom) => custom,
+            None => SORTER_SYSTEM_PROMPT.to_string(),
+        };
 
         Ok(Self {
             orchestra,
             output_path: final_output_path,
-            system_message_template: SORTER_SYSTEM_PROMPT.to_string(),
+            system_message_template,
             sorting_instructions,
             category_set,
             debug,
+            progress: None,
+            on_result: None,
+            audit_log: Vec::new(),
+            csv_column: None,
+            rejected: Vec::new(),
         })
     }
 
+    /// Attaches a progress tracker that is updated as items are sorted.
+    pub fn with_progress(mut self, progress: Arc<JobProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Registers a callback that receives each `(item, category)` pair as soon as that
+    /// item is classified, rather than when the whole run finishes.
+    pub fn with_result_callback(mut self, on_result: SortResultCallback) -> Self {
+        self.on_result = Some(on_result);
+        self
+    }
+
+    /// Reads items from the named column of CSV inputs instead of the first one.
+    pub fn with_csv_column(mut self, csv_column: Option<String>) -> Self {
+        self.csv_column = csv_column;
+        self
+    }
+
     pub fn output_path(&self) -> &PathBuf {
         &self.output_path
     }
 
+    /// The classification decisions recorded by the last `sort_items` call.
+    /// Empty unless `capture_reasoning` is set in the sorting instructions.
+    pub fn audit_log(&self) -> &[SortAuditEntry] {
+        &self.audit_log
+    }
+
     // --- Input Data Collection (These will be public for library users) ---
-    pub async fn collect_items_recursively(path: &PathBuf, items_vec: &mut Vec<String>) -> Result<(), LLMCoreError> {
+    // Files are read by extension: `.json` holds an array of strings, `.txt` one item
+    // per line, and `.csv` one item per row, taken from the `with_csv_column` column (a
+    // header name) or else the first column.
+    pub async fn collect_items_recursively(
+            &self,
+            path: &PathBuf,
+            items_vec: &mut Vec<String>,
+        ) -> Result<(), LLMCoreError> {
         let mut entries = tokio::fs::read_dir(path)
             .await
             .map_err(|e| LLMCoreError::IoError(e))?;
@@ -141,66 +301,65 @@ impl Sorter {
             let entry_path = entry.path();
 
             if entry_path.is_file() {
-                if entry_path.extension().map_or(false, |ext| ext == "json") {
-                    let content = tokio::fs::read_to_string(&entry_path)
-                        .await
-                        .map_err(|e| LLMCoreError::IoError(e))?;
-                    
-                    if content.trim().is_empty() {
-                        println!("Skipping empty JSON file: '{}'", entry_path.display());
-                        continue;
-                    }
+                let Some(format) = InputFormat::from_path(&entry_path) else {
+                    println!("Skipping unsupported file: '{}'", entry_path.display());
+                    continue;
+                };
+                let content = tokio::fs::read_to_string(&entry_path)
+                    .await
+                    .map_err(|e| LLMCoreError::IoError(e))?;
+
+                if content.trim().is_empty() {
+                    println!("Skipping empty file: '{}'", entry_path.display());
+                    continue;
+                }
 
-                    match serde_json::from_str::<Vec<String>>(&content) {
-                        Ok(mut parsed_items) => {
-                            if parsed_items.is_empty() {
-                                println!("Skipping JSON file '{}' as it contains an empty list.", entry_path.display());
-                                continue;
-                            }
-                            println!("✅ Loaded {} items from '{}'", parsed_items.len(), entry_path.display());
-                            items_vec.append(&mut parsed_items);
-                        },
-                        Err(e) => {
-                            eprintln!("Error parsing JSON list from file '{}': {}", entry_path.display(), e);
-                            eprintln!("File content: {}", content);
+                match format.parse(&entry_path, &content, self.csv_column.as_deref()) {
+                    Ok(mut parsed_items) => {
+                        if parsed_items.is_empty() {
+                            println!("Skipping file '{}' as it contains no items.", entry_path.display());
+                            continue;
                         }
+                        println!("✅ Loaded {} items from '{}'", parsed_items.len(), entry_path.display());
+                        items_vec.append(&mut parsed_items);
+                    },
+                    Err(e) => {
+                        eprintln!("Error reading items from file '{}': {}", entry_path.display(), e);
                     }
-                } else {
-                    println!("Skipping non-JSON file: '{}'", entry_path.display());
                 }
             } else if entry_path.is_dir() {
-                Box::pin(Self::collect_items_recursively(&entry_path, items_vec)).await?;
+                Box::pin(self.collect_items_recursively(&entry_path, items_vec)).await?;
             }
         }
         Ok(())
     }
 
-    pub async fn collect_items_from_file(path: &PathBuf, items_vec: &mut Vec<String>) -> Result<(), LLMCoreError> {
-        if path.extension().map_or(false, |ext| ext == "json") {
-            let content = tokio::fs::read_to_string(path)
-                .await
-                .map_err(|e| LLMCoreError::IoError(e))?;
-            
-            if content.trim().is_empty() {
-                println!("Skipping empty JSON file: '{}'", path.display());
-                return Ok(());
-            }
+    pub async fn collect_items_from_file(
+            &self,
+            path: &PathBuf,
+            items_vec: &mut Vec<String>,
+        ) -> Result<(), LLMCoreError> {
+        let Some(format) = InputFormat::from_path(path) else {
+            return Err(LLMCoreError::ConfigError(format!(
+                "Unsupported input file '{}': expected a .json, .csv or .txt file.",
+                path.display()
+            )));
+        };
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| LLMCoreError::IoError(e))?;
 
-            match serde_json::from_str::<Vec<String>>(&content) {
-                Ok(mut parsed_items) => {
-                    if parsed_items.is_empty() {
-                        println!("Skipping JSON file '{}' as it contains an empty list.", path.display());
-                    } else {
-                        println!("✅ Loaded {} items from '{}'", parsed_items.len(), path.display());
-                        items_vec.append(&mut parsed_items);
-                    }
-                },
-                Err(e) => {
-                    return Err(LLMCoreError::ResponseParseError(format!("Error parsing JSON list from file '{}': {}", path.display(), e)));
-                }
-            }
+        if content.trim().is_empty() {
+            println!("Skipping empty file: '{}'", path.display());
+            return Ok(());
+        }
+
+        let mut parsed_items = format.parse(path, &content, self.csv_column.as_deref())?;
+        if parsed_items.is_empty() {
+            println!("Skipping file '{}' as it contains no items.", path.display());
         } else {
-            return Err(LLMCoreError::ConfigError(format!("Skipping non-JSON file: '{}'", path.display())));
+            println!("✅ Loaded {} items from '{}'", parsed_items.len(), path.display());
+            items_vec.append(&mut parsed_items);
         }
         Ok(())
     }
@@ -221,7 +380,7 @@ impl Sorter {
         };
 
         // Create a dedicated Orchestra for this task with the correct schema
-        let cat_gen_orchestra = Orchestra::new(
+        let mut cat_gen_orchestra = Orchestra::new(
             &self.orchestra.user_facing_model_name, // Use the same model name
             Some(0.0), // Use a low temperature for deterministic category generation
             None,
@@ -229,6 +388,7 @@ impl Sorter {
             None, // thinking_mode
             Some(self.debug),
         )?;
+        cat_gen_orchestra.set_usage_metadata(self.orchestra.usage_metadata().clone());
         
         println!("\n--- GENERATING CATEGORIES ---\n");
         let item_chunks: Vec<&[String]> = items.chunks(chunk_size).collect();
@@ -305,9 +465,24 @@ impl Sorter {
             ));
         }
 
-        if !self.category_set.is_empty() {
-            let categories = self
-                .category_set
+        if !i_sort.category_definitions.is_empty() {
+            let definitions = i_sort
+                .category_definitions
+                .iter()
+                .map(|d| format!("- {}: {}", d.name, d.description))
+                .collect::<Vec<_>>()
+                .join("\n");
+            final_message.push_str(&format!("\n### CATEGORY DEFINITIONS:\n\n{}\n", definitions));
+        }
+
+        // Defined categories are already listed with their descriptions.
+        let undefined: Vec<&String> = self
+            .category_set
+            .iter()
+            .filter(|cat| !i_sort.category_definitions.iter().any(|d| &d.name == *cat))
+            .collect();
+        if !undefined.is_empty() {
+            let categories = undefined
                 .iter()
                 .map(|cat| format!("- {}", cat))
                 .collect::<Vec<_>>()
@@ -315,11 +490,20 @@ impl Sorter {
             final_message.push_str(&format!("\n### EXISTING CATEGORIES:\n\n{}\n", categories));
         }
 
+        if i_sort.strict_categories {
+            final_message.push_str(SORTER_STRICT_PROMPT);
+        }
+
+        if i_sort.capture_reasoning {
+            final_message.push_str(SORTER_REASONING_PROMPT);
+        }
+
         final_message
     }
 
-    pub async fn sort_items(&mut self, items: &[String], swarm_size: usize) -> Result<(BTreeMap<String, Vec<String>>, Vec<String>, Usage), LLMCoreError> {
-        let sorter_schema = SimpleSchema {
+    /// Creates a dedicated low-temperature `Orchestra` that answers with the sorting schema.
+    fn build_sort_orchestra(&self) -> Result<Orchestra, LLMCoreError> {
+        let mut sorter_schema = SimpleSchema {
             name: "sorting_response".to_string(),
             description: "Sorts a data item into a category based on provided instructions.".to_string(),
             properties: vec![
@@ -331,147 +515,343 @@ impl Sorter {
                 },
             ],
         };
+        if self.sorting_instructions.capture_reasoning {
+            sorter_schema.properties.push(SchemaProperty {
+                name: "reasoning".to_string(),
+                property_type: "string".to_string(),
+                description: "One short sentence explaining why the item belongs in the chosen category.".to_string(),
+                items: None,
+            });
+        }
 
-        // Create a dedicated Orchestra for the sorting task
-        let sort_orchestra = Arc::new(Orchestra::new( // Wrap in Arc
+        let mut orchestra = Orchestra::new(
             &self.orchestra.user_facing_model_name, // Use the correct user-facing name
             Some(0.0), // Low temperature for sorting
             None,
             Some(sorter_schema),
             None, // thinking_mode
             Some(self.debug),
-        )?);
+        )?;
+        orchestra.set_usage_metadata(self.orchestra.usage_metadata().clone());
+        Ok(orchestra)
+    }
+
+    /// Passes the item's category to the result callback, if the item was sorted.
+    fn report_result(&self, item: &str, sort_results: &HashMap<String, String>) -> Result<(), LLMCoreError> {
+        match (&self.on_result, sort_results.get(item)) {
+            (Some(on_result), Some(category)) => on_result(item, category),
+            _ => Ok(()),
+        }
+    }
+
+    /// Extracts the category from one sorting response and records it.
+    fn record_sort_response(
+            &mut self,
+            item: String,
+            response: ResponsePayload,
+            sort_results: &mut HashMap<String, String>,
+            total_usage: &mut Usage,
+        ) {
+        if let Some(usage) = response.usage {
+            *total_usage += usage;
+        }
+
+        if let Some(choice) = response.choices.first() {
+            if let Some(content) = &choice.message.content {
+
+                // NEW: Optional debug log of full raw content (including thoughts) for analysis.
+                if self.debug {
+                    println!("[SORTER DEBUG] Raw content for item '{}': {}\n", item, content);
+                }
+
+                // Pre-process the content to strip out <think> blocks, which some models add.
+                let content_after_think = answer_after_reasoning(content, &[DEFAULT_THINKING_TAG]).trim();
+
+                // Thinking output is kept as a fallback rationale for the audit log.
+                let thinking = choice
+                    .message
+                    .reasoning_content
+                    .clone()
+                    .or_else(|| extract_reasoning(&mut Some(content.clone())));
+
+                // First, try to parse as the full SortResponse struct.
+                let sort_response = match serde_json::from_str::<SortResponse>(content_after_think) {
+                    Ok(res) => Ok(res),
+                    Err(_) => {
+                        // NEW: Handle cases where the model returns a tool call as a string
+                        let new_res = if let Ok(json_val) = serde_json::from_str::<JsonValue>(content_after_think) {
+                            if let Some(args) = json_val.get("arguments") {
+                                serde_json::from_value::<SortResponse>(args.clone()).map_err(|e| e.to_string())
+                            } else {
+                                Err("No arguments field found in JSON".to_string())
+                            }
+                        } else {
+                            Err("Content is not a valid JSON value".to_string())
+                        };
+
+                        if let Ok(res) = new_res {
+                            Ok(res)
+                        } else {
+                            // Try parsing as a single-element array containing the response
+                            match serde_json::from_str::<Vec<SortResponse>>(content_after_think) {
+                                Ok(mut vec) if !vec.is_empty() => Ok(vec.remove(0)),
+                                _ => {
+                                    // Fallback for generic map like {"answer": "..."}
+                                    match serde_json::from_str::<HashMap<String, String>>(content_after_think) {
+                                        Ok(map) => {
+                                            if let Some(value) = map.get("category").or_else(|| map.values().next()) {
+                                                Ok(SortResponse { category: value.clone(), reasoning: map.get("reasoning").cloned() })
+                                            } else {
+                                                Err("JSON object is empty".to_string())
+                                            }
+                                        },
+                                        Err(_) => {
+                                            // Final fallback for raw string "..."
+                                            serde_json::from_str::<String>(content_after_think)
+                                                .map(|s| SortResponse { category: s, reasoning: None })
+                                                .map_err(|e| e.to_string())
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                };
+
+                match sort_response {
+                    Ok(res) => {
+                        let category = res.category;
+                        if !self.category_set.contains(&category) {
+                            if self.sorting_instructions.strict_categories {
+                                eprintln!("Item '{}' was put in undefined category '{}'.", item, category);
+                                self.rejected.push((item, category));
+                                return;
+                            }
+                            println!("\n**NEW CATEGORY** -> {}\n", category);
+                            self.category_set.insert(category.clone());
+                        }
+                        println!("ITEM: {} -> SORT: {}", item, category);
+                        if self.sorting_instructions.capture_reasoning {
+                            let reasoning = res
+                                .reasoning
+                                .filter(|r| !r.trim().is_empty())
+                                .or(thinking)
+                                .map(|r| shorten_reasoning(&r));
+                            self.audit_log.push(SortAuditEntry {
+                                item: item.clone(),
+                                category: category.clone(),
+                                reasoning,
+                            });
+                        }
+                        sort_results.insert(item, category);
+                    }
+                    Err(e) => {
+                        eprintln!("JSON Parse Error for item '{}': {}", item, e);
+                        eprintln!("Raw Content: {}", content);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the items put in an undefined category in strict mode back to the model,
+    /// with the rejected answer, up to `STRICT_REPROMPT_LIMIT` times. Items that still do
+    /// not fit are left unsorted.
+    async fn reprompt_rejected(
+            &mut self,
+            sort_orchestra: &Orchestra,
+            system_message_content: &str,
+            sort_results: &mut HashMap<String, String>,
+            total_usage: &mut Usage,
+        ) -> Result<(), LLMCoreError> {
+        for _ in 0..STRICT_REPROMPT_LIMIT {
+            for (item, category) in std::mem::take(&mut self.rejected) {
+                let messages = vec![
+                    format_system_message(system_message_content.to_string()),
+                    format_user_message(format!("Item: {}", item)),
+                    format_assistant_message(json!({ "category": category }).to_string()),
+                    format_user_message(SORTER_STRICT_REPROMPT.replace("{category}", &category)),
+                ];
+                match sort_orchestra.call_ai(messages).await {
+                    Ok(response) => {
+                        self.record_sort_response(item.clone(), response, sort_results, total_usage);
+                        self.report_result(&item, sort_results)?;
+                    }
+                    Err(e) => eprintln!("API Error for item '{}': {}", item, e),
+                }
+            }
+        }
+        for (item, category) in self.rejected.drain(..) {
+            eprintln!("Item '{}' left unsorted: '{}' is not an allowed category.", item, category);
+        }
+        Ok(())
+    }
+
+    /// Sorts `items` with at most `swarm_size` requests in flight. Repeated items are
+    /// always sent once and share a category: results are keyed by item, so copies could
+    /// not be sorted apart anyway. Unlike `Orchestra::set_swarm_dedup`, there is no toggle.
+    pub async fn sort_items(&mut self, items: &[String], swarm_size: usize) -> Result<(BTreeMap<String, Vec<String>>, Vec<String>, Usage), LLMCoreError> {
+        let (sort_results, total_usage) = client::in_flight(self.classify_items(items, swarm_size)).await??;
+        let clean_sort_results = self.build_sorting_results(&sort_results, true)?;
+        // Return the updated category set
+        let updated_categories = self.category_set.iter().cloned().collect();
+
+        Ok((clean_sort_results, updated_categories, total_usage))
+    }
+
+    /// Sorts JSON objects by the text in their `text_field`, keeping each full object in
+    /// the output under its category. Objects without a string, number or boolean in
+    /// that field are skipped with a warning; objects sharing a text share one request.
+    pub async fn sort_records(
+            &mut self,
+            records: &[JsonValue],
+            text_field: &str,
+            swarm_size: usize,
+        ) -> Result<(BTreeMap<String, Vec<JsonValue>>, Vec<String>, Usage), LLMCoreError> {
+        let texts = record_texts(records, text_field);
+        self.sort_record_texts(records, &texts, swarm_size).await
+    }
+
+    // Sorts `records` given the text already read from each one by `record_texts`.
+    async fn sort_record_texts(
+            &mut self,
+            records: &[JsonValue],
+            texts: &[Option<String>],
+            swarm_size: usize,
+        ) -> Result<(BTreeMap<String, Vec<JsonValue>>, Vec<String>, Usage), LLMCoreError> {
+        let items: Vec<String> = texts.iter().flatten().cloned().collect();
+        let (sort_results, total_usage) = client::in_flight(self.classify_items(&items, swarm_size)).await??;
+
+        let mut categorized_records: BTreeMap<String, Vec<JsonValue>> = BTreeMap::new();
+        for (record, text) in records.iter().zip(texts) {
+            if let Some(category) = text.as_ref().and_then(|t| sort_results.get(t)) {
+                categorized_records.entry(category.clone()).or_default().push(record.clone());
+            }
+        }
+        self.save_sorted_output(&categorized_records)?;
+        let updated_categories = self.category_set.iter().cloned().collect();
+
+        Ok((categorized_records, updated_categories, total_usage))
+    }
+
+    /// Classifies each distinct item, returning the chosen category per item.
+    async fn classify_items(&mut self, items: &[String], swarm_size: usize) -> Result<(HashMap<String, String>, Usage), LLMCoreError> {
+        self.audit_log.clear();
+        self.rejected.clear();
+        // Results are keyed by item, so each distinct item only needs one request.
+        let unique = unique_items(items);
+        let items = unique.as_slice();
+        let sort_orchestra = self.build_sort_orchestra()?;
 
         let system_message_content = self.build_sorting_instructions_message();
-        
-        let semaphore = Arc::new(Semaphore::new(swarm_size));
-        let mut tasks = vec![];
-        
+
         println!("\n--- SORTING ITEMS ---\n");
 
+        let mut sort_results = HashMap::new();
+        let mut total_usage = Usage::default();
+
+        if let Some(progress) = &self.progress {
+            progress.set_total(items.len());
+        }
+
         // CRITICAL NOTE ON CONCURRENCY:
-        // This loop spawns all requests concurrently, controlled by the `swarm_size` semaphore.
+        // The items are streamed through the swarm with at most `swarm_size` requests in flight.
         // This is highly efficient for APIs with high rate limits (e.g., paid OpenAI tiers).
         // However, for free or heavily rate-limited APIs (like OpenRouter's free tier),
         // setting a `swarm_size` greater than the API's requests-per-minute limit
         // will likely result in `429 Too Many Requests` errors. For such cases,
         // a `swarm_size` of 1 is recommended to process items sequentially.
-        for item in items {
-            let orchestra_clone = Arc::clone(&sort_orchestra); // Clone the Arc, not the Orchestra
-            let system_message_clone = system_message_content.clone();
-            let semaphore_clone = Arc::clone(&semaphore);
-            let item_clone = item.clone();
-            
-            tasks.push(tokio::spawn(async move {
-                let _permit = semaphore_clone.acquire().await.unwrap();
-                let user_prompt = format!("Item: {}", item_clone);
-                let messages = vec![
-                    format_system_message(system_message_clone),
-                    format_user_message(user_prompt),
-                ];
-
-                // The Orchestra instance held by Sorter has the schema already set.
-                let result = orchestra_clone
-                    .call_ai(messages)
-                    .await;
-                (item_clone, result)
-            }));
+        // When other chats or sorters share the same account, cap the provider as a
+        // whole with `Orchestra::set_provider_concurrency_limit` instead.
+        // Results are handled in completion order so they are reported as soon as they arrive.
+        // Once the result callback fails, no further items are sent; the requests already
+        // in flight still finish and are recorded.
+        let stopped = AtomicBool::new(false);
+        let mut callback_error = None;
+        let prompts: Vec<String> = items.iter().map(|item| format!("Item: {}", item)).collect();
+        let prompts = prompts.into_iter().map_while(|prompt| (!stopped.load(Ordering::SeqCst)).then_some(prompt));
+        let swarm_usage = sort_orchestra
+            .swarm_stream(&system_message_content, prompts, swarm_size, |index, result| {
+                if let Some(progress) = &self.progress {
+                    progress.increment();
+                }
+                let item = &items[index];
+                match result {
+                    Ok(response) => {
+                        self.record_sort_response(item.clone(), response, &mut sort_results, &mut total_usage);
+                        if callback_error.is_none() {
+                            if let Err(e) = self.report_result(item, &sort_results) {
+                                stopped.store(true, Ordering::SeqCst);
+                                callback_error = Some(e);
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("API Error for item '{}': {}", item, e),
+                }
+            })
+            .await;
+        if let Some(e) = callback_error {
+            return Err(e);
         }
+        // The calls overlap, so the swarm's wall-clock time stands for the run.
+        total_usage.duration_ms = swarm_usage.duration_ms;
+        total_usage.tokens_per_second = swarm_usage.tokens_per_second;
+        self.reprompt_rejected(&sort_orchestra, &system_message_content, &mut sort_results, &mut total_usage).await?;
+
+        Ok((sort_results, total_usage))
+    }
+
+    /// Sorts items through the OpenAI Batch API instead of live calls.
+    ///
+    /// Every item is submitted in a single batch job, which is billed at a discount but
+    /// can take up to 24 hours to finish; the job is polled every `poll_interval`.
+    /// Repeated items are submitted once, as in `sort_items`.
+    pub async fn sort_items_batch(
+            &mut self,
+            items: &[String],
+            poll_interval: Duration,
+        ) -> Result<(BTreeMap<String, Vec<String>>, Vec<String>, Usage), LLMCoreError> {
+        self.audit_log.clear();
+        self.rejected.clear();
+        let unique = unique_items(items);
+        let items = unique.as_slice();
+        let sort_orchestra = self.build_sort_orchestra()?;
+        let system_message_content = self.build_sorting_instructions_message();
+
+        println!("\n--- SORTING ITEMS (BATCH) ---\n");
+
+        let requests = items
+            .iter()
+            .map(|item| vec![
+                format_system_message(system_message_content.clone()),
+                format_user_message(format!("Item: {}", item)),
+            ])
+            .collect();
+        let results = sort_orchestra.batch_call(requests, poll_interval).await?;
 
         let mut sort_results = HashMap::new();
         let mut total_usage = Usage::default();
 
-        for task in tasks {
-            let (item, result) = task.await?;
+        if let Some(progress) = &self.progress {
+            progress.set_total(items.len());
+        }
+
+        for (item, result) in items.iter().zip(results) {
+            if let Some(progress) = &self.progress {
+                progress.increment();
+            }
             match result {
                 Ok(response) => {
-                    if let Some(usage) = response.usage {
-                        total_usage += usage;
-                    }
-
-                    if let Some(choice) = response.choices.first() {
-                        if let Some(content) = &choice.message.content {
-
-                            // NEW: Optional debug log of full raw content (including thoughts) for analysis.
-                            if self.debug {
-                                println!("[SORTER DEBUG] Raw content for item '{}': {}\n", item, content);
-                            }
-
-                            // Pre-process the content to strip out <think> blocks, which some models add.
-                            let content_after_think = if let Some(end_pos) = content.rfind("</think>") {
-                                content[end_pos + "</think>".len()..].trim()
-                            } else {
-                                content.trim()
-                            };
-
-                            // First, try to parse as the full SortResponse struct.
-                            let sort_response = match serde_json::from_str::<SortResponse>(content_after_think) {
-                                Ok(res) => Ok(res),
-                                Err(_) => {
-                                    // NEW: Handle cases where the model returns a tool call as a string
-                                    let new_res = if let Ok(json_val) = serde_json::from_str::<JsonValue>(content_after_think) {
-                                        if let Some(args) = json_val.get("arguments") {
-                                            serde_json::from_value::<SortResponse>(args.clone()).map_err(|e| e.to_string())
-                                        } else {
-                                            Err("No arguments field found in JSON".to_string())
-                                        }
-                                    } else {
-                                        Err("Content is not a valid JSON value".to_string())
-                                    };
-
-                                    if let Ok(res) = new_res {
-                                        Ok(res)
-                                    } else {
-                                        // Try parsing as a single-element array containing the response
-                                        match serde_json::from_str::<Vec<SortResponse>>(content_after_think) {
-                                            Ok(mut vec) if !vec.is_empty() => Ok(vec.remove(0)),
-                                            _ => {
-                                                // Fallback for generic map like {"answer": "..."}
-                                                match serde_json::from_str::<HashMap<String, String>>(content_after_think) {
-                                                    Ok(map) => {
-                                                        if let Some(value) = map.values().next() {
-                                                            Ok(SortResponse { category: value.clone() })
-                                                        } else {
-                                                            Err("JSON object is empty".to_string())
-                                                        }
-                                                    },
-                                                    Err(_) => {
-                                                        // Final fallback for raw string "..."
-                                                        serde_json::from_str::<String>(content_after_think)
-                                                            .map(|s| SortResponse { category: s })
-                                                            .map_err(|e| e.to_string())
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            };
-
-                            match sort_response {
-                                Ok(res) => {
-                                    let category = res.category;
-                                    if !self.category_set.contains(&category) {
-                                        println!("\n**NEW CATEGORY** -> {}\n", category);
-                                        self.category_set.insert(category.clone());
-                                    }
-                                    println!("ITEM: {} -> SORT: {}", item, category);
-                                    sort_results.insert(item, category);
-                                }
-                                Err(e) => {
-                                    eprintln!("JSON Parse Error for item '{}': {}", item, e);
-                                    eprintln!("Raw Content: {}", content);
-                                }
-                            }
-                        }
-                    }
+                    self.record_sort_response(item.clone(), response, &mut sort_results, &mut total_usage);
+                    self.report_result(item, &sort_results)?;
                 }
                 Err(e) => eprintln!("API Error for item '{}': {}", item, e),
             }
         }
+        // Corrections are few, so they are made with live calls rather than another batch.
+        self.reprompt_rejected(&sort_orchestra, &system_message_content, &mut sort_results, &mut total_usage).await?;
 
         let clean_sort_results = self.build_sorting_results(&sort_results, true)?;
-        // Return the updated category set
         let updated_categories = self.category_set.iter().cloned().collect();
 
         Ok((clean_sort_results, updated_categories, total_usage))
@@ -488,77 +868,107 @@ impl Sorter {
         }
 
         if save {
-            let final_path: PathBuf;
-
-            // NEW: Check if the provided path is a file or a directory.
-            if self.output_path.extension().is_some() && self.output_path.file_name().is_some() {
-                // It's a full file path. Use it directly.
-                // Ensure its parent directory exists.
-                if let Some(parent) = self.output_path.parent() {
-                    fs::create_dir_all(parent).map_err(|e| {
-                        LLMCoreError::IoError(std::io::Error::new(
-                            e.kind(),
-                            format!("Failed to create parent directory '{}': {}", parent.display(), e),
-                        ))
-                    })?;
-                }
-                final_path = self.output_path.clone();
-            } else {
-                // It's a directory. Create a unique filename inside it.
-                fs::create_dir_all(&self.output_path).map_err(|e| {
-                     LLMCoreError::IoError(std::io::Error::new(
-                        e.kind(),
-                        format!("Failed to create output directory '{}': {}", self.output_path.display(), e),
-                    ))
-                })?;
-                let file_name = format!("sorted-data-{}.json", Uuid::new_v4().to_string().split('-').next().unwrap_or(""));
-                final_path = self.output_path.join(file_name);
-            }
+            self.save_sorted_output(&categorized_items)?;
+        }
 
-            let json_str = serde_json::to_string_pretty(&categorized_items)?;
-            
-            // Use OpenOptions for a more robust file write/overwrite operation.
-            let mut file = std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true) // This will clear the file if it exists, achieving overwrite.
-                .open(&final_path)
-                .map_err(|e| {
+        Ok(categorized_items)
+    }
+
+    /// Writes the sorted output (and the audit log, when reasoning is captured) to the
+    /// output path, which may be a file or a directory.
+    fn save_sorted_output<T: Serialize>(&self, categorized_items: &BTreeMap<String, Vec<T>>) -> Result<(), LLMCoreError> {
+        let final_path: PathBuf;
+
+        // NEW: Check if the provided path is a file or a directory.
+        if self.output_path.extension().is_some() && self.output_path.file_name().is_some() {
+            // It's a full file path. Use it directly.
+            // Ensure its parent directory exists.
+            if let Some(parent) = self.output_path.parent() {
+                fs::create_dir_all(parent).map_err(|e| {
                     LLMCoreError::IoError(std::io::Error::new(
                         e.kind(),
-                        format!("Failed to open or create file '{}': {}", final_path.display(), e),
+                        format!("Failed to create parent directory '{}': {}", parent.display(), e),
                     ))
                 })?;
-
-            file.write_all(json_str.as_bytes()).map_err(|e| {
+            }
+            final_path = self.output_path.clone();
+        } else {
+            // It's a directory. Create a unique filename inside it.
+            fs::create_dir_all(&self.output_path).map_err(|e| {
                  LLMCoreError::IoError(std::io::Error::new(
                     e.kind(),
-                    format!("Failed to write to final path '{}': {}", final_path.display(), e),
+                    format!("Failed to create output directory '{}': {}", self.output_path.display(), e),
                 ))
             })?;
-            println!("\n✅ Results saved: '{}'", final_path.display());
+            let file_name = format!("sorted-data-{}.json", Uuid::new_v4().to_string().split('-').next().unwrap_or(""));
+            final_path = self.output_path.join(file_name);
         }
 
-        Ok(categorized_items)
+        let json_str = serde_json::to_string_pretty(categorized_items)?;
+        
+        // Use OpenOptions for a more robust file write/overwrite operation.
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true) // This will clear the file if it exists, achieving overwrite.
+            .open(&final_path)
+            .map_err(|e| {
+                LLMCoreError::IoError(std::io::Error::new(
+                    e.kind(),
+                    format!("Failed to open or create file '{}': {}", final_path.display(), e),
+                ))
+            })?;
+
+        file.write_all(json_str.as_bytes()).map_err(|e| {
+             LLMCoreError::IoError(std::io::Error::new(
+                e.kind(),
+                format!("Failed to write to final path '{}': {}", final_path.display(), e),
+            ))
+        })?;
+        println!("\n✅ Results saved: '{}'", final_path.display());
+
+        if self.sorting_instructions.capture_reasoning {
+            let mut audit_log = self.audit_log.clone();
+            audit_log.sort_by(|a, b| a.category.cmp(&b.category).then_with(|| a.item.cmp(&b.item)));
+            let audit_path = final_path.with_extension("audit.json");
+            fs::write(&audit_path, serde_json::to_string_pretty(&audit_log)?).map_err(|e| {
+                LLMCoreError::IoError(std::io::Error::new(
+                    e.kind(),
+                    format!("Failed to write audit log '{}': {}", audit_path.display(), e),
+                ))
+            })?;
+            println!("✅ Audit log saved: '{}'", audit_path.display());
+        }
+
+        Ok(())
     }
 
     // --- Public API for library users ---
+    #[allow(clippy::too_many_arguments)]
     pub async fn run_sorting_task(
             orchestra: Arc<Orchestra>,
             input_path: Option<PathBuf>,
             items_list: Option<Vec<String>>,
             output_path: Option<PathBuf>,
             sorting_instructions: SortingInstructions,
+            system_prompt_override: Option<String>,
             swarm_size: usize,
             debug: bool,
         ) -> Result<(BTreeMap<String, Vec<String>>, Vec<String>, Usage, usize), LLMCoreError> {
-        let mut sorter = Self::new(orchestra, sorting_instructions, output_path, debug)?;
-        let items_to_process: Vec<String>;
-        let mut total_usage = Usage::default();
+        let mut sorter = Self::new(orchestra, sorting_instructions, output_path, system_prompt_override, debug)?;
+        sorter.run(input_path, items_list, swarm_size).await
+    }
 
+    /// Reads the items to sort from `items_list`, or else from `input_path` (a file or a
+    /// folder searched recursively), defaulting to the sorter input directory.
+    pub async fn collect_items(
+            &self,
+            input_path: Option<PathBuf>,
+            items_list: Option<Vec<String>>,
+        ) -> Result<Vec<String>, LLMCoreError> {
         if let Some(items) = items_list {
             println!("📚 Reading {} items provided directly in a list...", items.len());
-            items_to_process = items;
+            Ok(items)
         } else {
             let path_to_process = if let Some(path) = input_path {
                 path
@@ -572,43 +982,347 @@ impl Sorter {
             if path_to_process.is_file() {
                 println!("📚 Reading items from file: '{}'...", path_to_process.display());
                 let mut items_from_file = Vec::new();
-                Self::collect_items_from_file(&path_to_process, &mut items_from_file).await?;
-                items_to_process = items_from_file;
+                self.collect_items_from_file(&path_to_process, &mut items_from_file).await?;
+                Ok(items_from_file)
             } else if path_to_process.is_dir() {
                 println!("📚 Reading items from folder: '{}' (including subfolders)...", path_to_process.display());
                 let mut items_from_dir = Vec::new();
-                Self::collect_items_recursively(&path_to_process, &mut items_from_dir).await?;
-                items_to_process = items_from_dir;
+                self.collect_items_recursively(&path_to_process, &mut items_from_dir).await?;
+                Ok(items_from_dir)
             } else {
-                return Err(LLMCoreError::ConfigError(format!("Provided path '{}' is neither a file nor a directory.", path_to_process.display())));
+                Err(LLMCoreError::ConfigError(format!("Provided path '{}' is neither a file nor a directory.", path_to_process.display())))
+            }
+        }
+    }
+
+    /// Projects the requests, tokens and cost of sorting `items` without calling the
+    /// model. Token counts use the same rough heuristic as `datam::count_tokens`, and
+    /// category generation is included when no categories were provided.
+    pub fn estimate(&self, items: &[String]) -> Result<CostEstimate, LLMCoreError> {
+        let model_name = &self.orchestra.user_facing_model_name;
+        let (_, _, details) = MODEL_LIBRARY.find_model(model_name)?;
+
+        let unique = unique_items(items);
+        let system_message = format_system_message(self.build_sorting_instructions_message());
+        let mut request_count = unique.len();
+        let mut input_tokens: usize = unique
+            .iter()
+            .map(|item| count_message_tokens(&[system_message.clone(), format_user_message(format!("Item: {}", item))]))
+            .sum();
+        let per_item_output = if self.sorting_instructions.capture_reasoning {
+            ESTIMATED_SORT_OUTPUT_TOKENS + ESTIMATED_REASONING_OUTPUT_TOKENS
+        } else {
+            ESTIMATED_SORT_OUTPUT_TOKENS
+        };
+        let mut output_tokens = per_item_output * unique.len();
+
+        if !self.sorting_instructions.has_categories() {
+            let category_prompt = format_system_message(
+                CATEGORY_GEN_INITIAL_PROMPT
+                    .replace("{data_item_name}", &self.sorting_instructions.data_item_name)
+                    .replace("{data_profile_description}", &self.sorting_instructions.data_profile_description),
+            );
+            for (i, chunk) in items.chunks(CATEGORY_GEN_CHUNK_SIZE).enumerate() {
+                input_tokens += count_message_tokens(&[category_prompt.clone(), format_user_message(chunk.join("\n"))]);
+                if i > 0 {
+                    // Later chunks also resend the categories found so far.
+                    input_tokens += count_tokens(CATEGORY_GEN_NEXT_PROMPT) + ESTIMATED_CATEGORY_GEN_OUTPUT_TOKENS;
+                }
+                output_tokens += ESTIMATED_CATEGORY_GEN_OUTPUT_TOKENS;
+                request_count += 1;
             }
         }
 
+        let mut usage = Usage {
+            prompt_tokens: input_tokens as u32,
+            completion_tokens: output_tokens as u32,
+            total_tokens: (input_tokens + output_tokens) as u32,
+            ..Default::default()
+        };
+        usage.calculate_cost(details.input_price, details.output_price);
+        Ok(CostEstimate { request_count, usage })
+    }
+
+    /// Collects the items, generates categories if none were provided, and sorts them.
+    /// The run is refused once a `client::Shutdown` drain has begun; a run already under
+    /// way is finished.
+    pub async fn run(
+            &mut self,
+            input_path: Option<PathBuf>,
+            items_list: Option<Vec<String>>,
+            swarm_size: usize,
+        ) -> Result<(BTreeMap<String, Vec<String>>, Vec<String>, Usage, usize), LLMCoreError> {
+        client::in_flight(self.run_items(input_path, items_list, swarm_size)).await?
+    }
+
+    async fn run_items(
+            &mut self,
+            input_path: Option<PathBuf>,
+            items_list: Option<Vec<String>>,
+            swarm_size: usize,
+        ) -> Result<(BTreeMap<String, Vec<String>>, Vec<String>, Usage, usize), LLMCoreError> {
+        let items_to_process = self.collect_items(input_path, items_list).await?;
+
         let item_count = items_to_process.len();
         if item_count == 0 {
             return Err(LLMCoreError::ConfigError(format!("No valid items found to process.")));
         }
 
-        // If no categories are provided, generate them before sorting.
-        if sorter.sorting_instructions.provided_categories.is_empty() {
-            println!("\nNo categories provided. Attempting to generate categories from data items...");
-            let (new_categories, cat_gen_usage) = sorter.generate_categories(&items_to_process, 50).await?;
-            total_usage += cat_gen_usage;
+        let mut total_usage = self.ensure_categories(&items_to_process).await?;
+        let (sorted_items, updated_categories, sort_usage) = self.sort_items(&items_to_process, swarm_size).await?;
+        total_usage += sort_usage;
+        Ok((sorted_items, updated_categories, total_usage, item_count))
+    }
+
+    /// Like `run`, but sorts JSON objects by their `text_field` and returns the full
+    /// objects grouped by category (see `sort_records`).
+    pub async fn run_records(
+            &mut self,
+            records: &[JsonValue],
+            text_field: &str,
+            swarm_size: usize,
+        ) -> Result<(BTreeMap<String, Vec<JsonValue>>, Vec<String>, Usage, usize), LLMCoreError> {
+        client::in_flight(self.run_record_items(records, text_field, swarm_size)).await?
+    }
 
-            if new_categories.is_empty() {
-                return Err(LLMCoreError::ChatError(
-                    "Category generation resulted in an empty list. Cannot proceed with sorting."
-                        .to_string(),
-                ));
-            }
-            println!("\n✅ Generated {} new categories.", new_categories.len());
-            // The sorter's internal category_set is already updated by generate_categories
+    async fn run_record_items(
+            &mut self,
+            records: &[JsonValue],
+            text_field: &str,
+            swarm_size: usize,
+        ) -> Result<(BTreeMap<String, Vec<JsonValue>>, Vec<String>, Usage, usize), LLMCoreError> {
+        // Read once, so records without text are only warned about once.
+        let texts = record_texts(records, text_field);
+        let items: Vec<String> = texts.iter().flatten().cloned().collect();
+        if items.is_empty() {
+            return Err(LLMCoreError::ConfigError(format!(
+                "No records with a '{}' field found to process.",
+                text_field
+            )));
         }
 
-        let (sorted_items, updated_categories, sort_usage) = sorter.sort_items(&items_to_process, swarm_size).await?;
+        let mut total_usage = self.ensure_categories(&items).await?;
+        let (sorted_records, updated_categories, sort_usage) = self.sort_record_texts(records, &texts, swarm_size).await?;
         total_usage += sort_usage;
-        Ok((sorted_items, updated_categories, total_usage, item_count))
+        Ok((sorted_records, updated_categories, total_usage, items.len()))
     }
+
+    /// Generates categories from `items` if none were provided.
+    async fn ensure_categories(&mut self, items: &[String]) -> Result<Usage, LLMCoreError> {
+        if self.sorting_instructions.has_categories() {
+            return Ok(Usage::default());
+        }
+        println!("\nNo categories provided. Attempting to generate categories from data items...");
+        let (new_categories, cat_gen_usage) = self.generate_categories(items, CATEGORY_GEN_CHUNK_SIZE).await?;
+
+        if new_categories.is_empty() {
+            return Err(LLMCoreError::ChatError(
+                "Category generation resulted in an empty list. Cannot proceed with sorting."
+                    .to_string(),
+            ));
+        }
+        println!("\n✅ Generated {} new categories.", new_categories.len());
+        // The sorter's internal category_set is already updated by generate_categories
+        Ok(cat_gen_usage)
+    }
+}
+
+/// The text to classify for each record, or `None` (with a warning) when its
+/// `text_field` is missing or not a scalar.
+fn record_texts(records: &[JsonValue], text_field: &str) -> Vec<Option<String>> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let text = match record.get(text_field) {
+                Some(JsonValue::String(s)) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+                Some(value @ (JsonValue::Number(_) | JsonValue::Bool(_))) => Some(value.to_string()),
+                _ => None,
+            };
+            if text.is_none() {
+                eprintln!("[WARNING] Skipping record {}: no text in field '{}'.", i, text_field);
+            }
+            text
+        })
+        .collect()
+}
+
+/// The input file formats the sorter reads items from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+    Json,
+    Csv,
+    Text,
+}
+
+impl InputFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "txt" => Some(Self::Text),
+            _ => None,
+        }
+    }
+
+    fn parse(self, path: &Path, content: &str, csv_column: Option<&str>) -> Result<Vec<String>, LLMCoreError> {
+        // Files saved by spreadsheet tools often start with a UTF-8 byte order mark.
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        match self {
+            Self::Json => serde_json::from_str::<Vec<String>>(content).map_err(|e| {
+                LLMCoreError::ResponseParseError(format!("Error parsing JSON list from file '{}': {}", path.display(), e))
+            }),
+            Self::Csv => parse_csv_items(path, content, csv_column),
+            Self::Text => Ok(content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect()),
+        }
+    }
+}
+
+/// Reads one item per CSV row from `column` (matched against the header row) or the
+/// first column. Rows that are too short or have an unclosed quote are skipped.
+fn parse_csv_items(path: &Path, content: &str, column: Option<&str>) -> Result<Vec<String>, LLMCoreError> {
+    let mut records = split_csv_records(content).into_iter();
+    let header = match records.next() {
+        Some((_, Some(header))) => header,
+        _ => {
+            return Err(LLMCoreError::ResponseParseError(format!(
+                "Could not read the header row of CSV file '{}'.",
+                path.display()
+            )))
+        }
+    };
+    let index = match column {
+        Some(name) => header.iter().position(|h| h.trim() == name).ok_or_else(|| {
+            LLMCoreError::ConfigError(format!(
+                "Column '{}' not found in CSV file '{}'. Available columns: {}",
+                name,
+                path.display(),
+                header.join(", ")
+            ))
+        })?,
+        None => 0,
+    };
+
+    let mut items = Vec::new();
+    for (line, record) in records {
+        match record {
+            Some(fields) if fields.len() == 1 && fields[0].trim().is_empty() => {}
+            Some(fields) if index < fields.len() => {
+                let value = fields[index].trim();
+                if !value.is_empty() {
+                    items.push(value.to_string());
+                }
+            }
+            Some(fields) => eprintln!(
+                "[WARNING] Skipping row {} of '{}': expected at least {} fields, found {}.",
+                line,
+                path.display(),
+                index + 1,
+                fields.len()
+            ),
+            None => eprintln!(
+                "[WARNING] Skipping row {} of '{}': unclosed quote.",
+                line,
+                path.display()
+            ),
+        }
+    }
+    Ok(items)
+}
+
+/// Splits CSV text into records, each tagged with the line it starts on. Quoted fields
+/// may contain commas, doubled quotes and line breaks. A record whose quote is never
+/// closed comes back as `None`, and reading resumes on the line after it starts.
+fn split_csv_records(content: &str) -> Vec<(usize, Option<Vec<String>>)> {
+    let mut records = Vec::new();
+    let mut rest = content;
+    let mut first_line = 1;
+    while let Some((start, line)) = split_closed_csv_records(rest, first_line, &mut records) {
+        records.push((line, None));
+        match rest[start..].find('\n') {
+            Some(end) => {
+                rest = &rest[start + end + 1..];
+                first_line = line + 1;
+            }
+            None => break,
+        }
+    }
+    records
+}
+
+// Appends the records of `content` (starting on `first_line`) to `records`. If a quote is
+// still open at the end, returns the byte offset and line where its record starts.
+fn split_closed_csv_records(
+        content: &str,
+        first_line: usize,
+        records: &mut Vec<(usize, Option<Vec<String>>)>,
+    ) -> Option<(usize, usize)> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = first_line;
+    let mut record_line = first_line;
+    let mut record_start = 0;
+    let mut chars = content.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek().map(|&(_, next)| next) == Some('"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                in_quotes = true;
+            }
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes && chars.peek().map(|&(_, next)| next) == Some('\n') => {}
+            '\n' if !in_quotes => {
+                fields.push(std::mem::take(&mut field));
+                records.push((record_line, Some(std::mem::take(&mut fields))));
+                line += 1;
+                record_line = line;
+                record_start = offset + 1;
+            }
+            _ => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Some((record_start, record_line));
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((record_line, Some(fields)));
+    }
+    None
+}
+
+// Keeps the last `MAX_AUDIT_REASONING_CHARS` characters, where thinking output usually concludes.
+fn shorten_reasoning(reasoning: &str) -> String {
+    let trimmed = reasoning.trim();
+    let char_count = trimmed.chars().count();
+    if char_count <= MAX_AUDIT_REASONING_CHARS {
+        return trimmed.to_string();
+    }
+    let tail: String = trimmed.chars().skip(char_count - MAX_AUDIT_REASONING_CHARS).collect();
+    format!("...{}", tail.trim_start())
+}
+
+// Drops repeated items, keeping the first occurrence of each in input order.
+fn unique_items(items: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    items.iter().filter(|item| seen.insert(item.as_str())).cloned().collect()
 }
 
 // --- Tool Entry Point ---
@@ -635,11 +1349,16 @@ pub fn sort_data_items_tool(args: JsonValue) -> Result<JsonValue, String> {
             data_profile_description: description,
             item_sorting_guidelines: guidelines,
             provided_categories: vec![], // No longer required
+            category_definitions: vec![],
+            strict_categories: false,
+            capture_reasoning: args["capture_reasoning"].as_bool().unwrap_or(false),
         };
 
+        let system_prompt = args["system_prompt"].as_str().map(String::from);
         let model_name = args["model_name"].as_str().unwrap_or("GPT 4o MINI");
         let output_path = args["output_path"].as_str().map(PathBuf::from);
         let swarm_size = args["swarm_size"].as_u64().unwrap_or(5) as usize;
+        let csv_column = args["csv_column"].as_str().map(String::from);
         
         let orchestra = Arc::new(
             Orchestra::new(model_name, None, None, None, None, Some(true))
@@ -652,16 +1371,15 @@ pub fn sort_data_items_tool(args: JsonValue) -> Result<JsonValue, String> {
 
         let input_path = input_path_str.map(PathBuf::from);
 
-        let initial_result = Sorter::run_sorting_task(
-            Arc::clone(&orchestra),
-            input_path.clone(),
-            items_list.clone(),
-            output_path.clone(),
-            instructions.clone(),
-            swarm_size,
-            true, // debug
-        )
-        .await;
+        // Both attempts read CSV inputs from the same column.
+        let run_sort = |output_path: Option<PathBuf>| {
+            let sorter = Sorter::new(Arc::clone(&orchestra), instructions.clone(), output_path, system_prompt.clone(), true)
+                .map(|sorter| sorter.with_csv_column(csv_column.clone()));
+            let (input_path, items_list) = (input_path.clone(), items_list.clone());
+            async move { sorter?.run(input_path, items_list, swarm_size).await }
+        };
+
+        let initial_result = run_sort(output_path.clone()).await;
 
         match initial_result {
             Ok((sorted_items, updated_categories, total_usage, item_count)) => {
@@ -683,17 +1401,9 @@ pub fn sort_data_items_tool(args: JsonValue) -> Result<JsonValue, String> {
                         );
 
                         let (sorted_items, updated_categories, total_usage, item_count) =
-                            Sorter::run_sorting_task(
-                                orchestra,
-                                input_path,
-                                items_list,
-                                None, // Fallback to default path
-                                instructions,
-                                swarm_size,
-                                true, // debug
-                            )
-                            .await
-                            .map_err(|e| e.to_string())?;
+                            run_sort(None) // Fallback to default path
+                                .await
+                                .map_err(|e| e.to_string())?;
 
                         return Ok(json!({
                             "message": "Sorting completed, but failed to save to the specified path. The file was saved to the default application directory instead.",
@@ -710,3 +1420,201 @@ pub fn sort_data_items_tool(args: JsonValue) -> Result<JsonValue, String> {
     })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorter(provided_categories: Vec<String>) -> Sorter {
+        let orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, None, None).unwrap();
+        let instructions = SortingInstructions {
+            data_item_name: "Word".to_string(),
+            data_profile_description: "Common nouns.".to_string(),
+            item_sorting_guidelines: vec![],
+            provided_categories,
+            category_definitions: vec![],
+            strict_categories: false,
+            capture_reasoning: false,
+        };
+        let output = std::env::temp_dir().join("llm-core-estimate-test.json");
+        Sorter::new(Arc::new(orchestra), instructions, Some(output), None, false).unwrap()
+    }
+
+    #[test]
+    fn estimate_counts_distinct_items_and_category_generation() {
+        let items: Vec<String> = (0..120).map(|i| format!("item {}", i % 100)).collect();
+
+        let with_categories = sorter(vec!["a".to_string(), "b".to_string()]).estimate(&items).unwrap();
+        assert_eq!(with_categories.request_count, 100);
+        assert_eq!(with_categories.usage.completion_tokens as usize, 100 * ESTIMATED_SORT_OUTPUT_TOKENS);
+        assert_eq!(with_categories.total_cost(), 0.0);
+
+        // 120 items are sent for category generation in chunks of 50.
+        let generated = sorter(vec![]).estimate(&items).unwrap();
+        assert_eq!(generated.request_count, 103);
+        assert!(generated.usage.prompt_tokens > with_categories.usage.prompt_tokens);
+    }
+
+    #[test]
+    fn result_callback_only_reports_sorted_items() {
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reported);
+        let sorter = sorter(vec!["animal".to_string()]).with_result_callback(Arc::new(move |item: &str, category: &str| {
+            sink.lock().unwrap().push((item.to_string(), category.to_string()));
+            Ok(())
+        }));
+
+        let sort_results = HashMap::from([("cat".to_string(), "animal".to_string())]);
+        sorter.report_result("cat", &sort_results).unwrap();
+        sorter.report_result("rock", &sort_results).unwrap();
+
+        assert_eq!(*reported.lock().unwrap(), vec![("cat".to_string(), "animal".to_string())]);
+    }
+
+    #[test]
+    fn strict_taxonomies_are_described_and_enforced() {
+        let mut sorter = sorter(vec!["mineral".to_string()]);
+        sorter.sorting_instructions.category_definitions = vec![CategoryDefinition {
+            name: "animal".to_string(),
+            description: "Living creatures, wild or domestic.".to_string(),
+        }];
+        sorter.sorting_instructions.strict_categories = true;
+        sorter.category_set.insert("animal".to_string());
+
+        let message = sorter.build_sorting_instructions_message();
+        assert!(message.contains("### CATEGORY DEFINITIONS:\n\n- animal: Living creatures, wild or domestic.\n"));
+        assert!(message.contains("### EXISTING CATEGORIES:\n\n- mineral\n"));
+        assert!(message.contains(SORTER_STRICT_PROMPT));
+
+        let response = |category: &str| -> ResponsePayload {
+            serde_json::from_value(json!({
+                "id": "1", "object": "chat.completion", "created": 0, "model": "m",
+                "choices": [{ "message": { "role": "assistant", "content": json!({ "category": category }).to_string() } }]
+            }))
+            .unwrap()
+        };
+        let (mut sort_results, mut usage) = (HashMap::new(), Usage::default());
+        sorter.record_sort_response("cat".to_string(), response("animal"), &mut sort_results, &mut usage);
+        sorter.record_sort_response("dog".to_string(), response("pets"), &mut sort_results, &mut usage);
+
+        assert_eq!(sort_results, HashMap::from([("cat".to_string(), "animal".to_string())]));
+        assert_eq!(sorter.rejected, vec![("dog".to_string(), "pets".to_string())]);
+        assert!(!sorter.category_set.contains("pets"));
+    }
+
+    // Answers one Ollama chat request per category in turn, returning the request bodies.
+    fn serve_categories(listener: std::net::TcpListener, categories: Vec<&'static str>) -> std::thread::JoinHandle<Vec<String>> {
+        use std::io::Read;
+        std::thread::spawn(move || {
+            categories
+                .into_iter()
+                .map(|category| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 4096];
+                    let body_start = loop {
+                        let n = stream.read(&mut buf).unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        if let Some(end) = text.find("\r\n\r\n") {
+                            let length = text[..end]
+                                .lines()
+                                .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                                .unwrap_or(0);
+                            if request.len() >= end + 4 + length {
+                                break end + 4;
+                            }
+                        }
+                    };
+                    let body = json!({
+                        "model": "qwen3:0.6b",
+                        "created_at": "2026-01-01T00:00:00Z",
+                        "message": { "role": "assistant", "content": json!({ "category": category }).to_string() },
+                        "done": true
+                    })
+                    .to_string();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).unwrap();
+                    String::from_utf8_lossy(&request[body_start..]).to_string()
+                })
+                .collect()
+        })
+    }
+
+    #[tokio::test]
+    async fn rejected_items_are_reprompted_until_the_limit() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        // "dog" fits on its first reprompt; "rock" is rejected on every attempt.
+        let server = serve_categories(listener, vec!["animal", "pets", "pets"]);
+        let orchestra = Orchestra::new("QWEN 3:0.6B", None, None, None, Some(false), None)
+            .unwrap()
+            .with_base_url_override(&base_url)
+            .unwrap();
+
+        let mut sorter = sorter(vec!["animal".to_string()]);
+        sorter.sorting_instructions.strict_categories = true;
+        let (mut sort_results, mut usage) = (HashMap::new(), Usage::default());
+
+        sorter.rejected = vec![("dog".to_string(), "pets".to_string())];
+        sorter.reprompt_rejected(&orchestra, "Sort it.", &mut sort_results, &mut usage).await.unwrap();
+        assert_eq!(sort_results.get("dog").map(String::as_str), Some("animal"));
+
+        sorter.rejected = vec![("rock".to_string(), "gems".to_string())];
+        sorter.reprompt_rejected(&orchestra, "Sort it.", &mut sort_results, &mut usage).await.unwrap();
+        assert!(!sort_results.contains_key("rock"));
+        assert!(sorter.rejected.is_empty());
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1 + STRICT_REPROMPT_LIMIT);
+        let first: JsonValue = serde_json::from_str(&requests[0]).unwrap();
+        let messages = first["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["content"], json!("Item: dog"));
+        assert_eq!(messages[2]["content"], json!(json!({ "category": "pets" }).to_string()));
+        assert_eq!(messages[3]["content"], json!(SORTER_STRICT_REPROMPT.replace("{category}", "pets")));
+        // Each further attempt quotes the category the model gave last.
+        let last: JsonValue = serde_json::from_str(&requests[2]).unwrap();
+        assert_eq!(last["messages"][1]["content"], json!("Item: rock"));
+        assert_eq!(last["messages"][3]["content"], json!(SORTER_STRICT_REPROMPT.replace("{category}", "pets")));
+    }
+
+    #[test]
+    fn csv_and_text_inputs_are_read_by_extension() {
+        let csv = "\u{feff}id,title,notes\n1,Red apple,fresh\n2,\"Pear, green\",\"says \"\"hi\"\"\"\n3\n4,\"Plum\nsweet\",x\n\n5,\"Fig,x\n6,Kiwi,y\n";
+        let path = Path::new("items.csv");
+        let format = InputFormat::from_path(path).unwrap();
+        assert_eq!(format, InputFormat::Csv);
+        assert_eq!(
+            format.parse(path, csv, Some("title")).unwrap(),
+            vec!["Red apple", "Pear, green", "Plum\nsweet", "Kiwi"]
+        );
+        assert_eq!(format.parse(path, csv, None).unwrap(), vec!["1", "2", "3", "4", "6"]);
+        assert_eq!(format.parse(path, csv, Some("id")).unwrap(), vec!["1", "2", "3", "4", "6"]);
+        assert!(matches!(format.parse(path, csv, Some("name")), Err(LLMCoreError::ConfigError(_))));
+
+        let text = Path::new("items.TXT");
+        assert_eq!(
+            InputFormat::from_path(text).unwrap().parse(text, "one\n\n  two \r\n", None).unwrap(),
+            vec!["one", "two"]
+        );
+        assert_eq!(InputFormat::from_path(Path::new("items.xlsx")), None);
+    }
+
+    #[test]
+    fn record_texts_read_the_text_field() {
+        let records = vec![
+            json!({ "name": "Apple", "price": 1.2 }),
+            json!({ "name": 42 }),
+            json!({ "name": ["not", "text"] }),
+            json!({ "title": "Pear" }),
+            json!({ "name": "  " }),
+        ];
+        assert_eq!(
+            record_texts(&records, "name"),
+            vec![Some("Apple".to_string()), Some("42".to_string()), None, None, None]
+        );
+    }
+}
@@ -1,8 +1,16 @@
 use pyo3::prelude::*;
 
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::client;
+use crate::config::get_env_var;
+use crate::error::LLMCoreError;
 
 /// Represents a tool call requested by the model in its response.
 #[pyclass]
@@ -61,6 +69,123 @@ pub struct FunctionDefinition {
     pub parameters: JsonValue, // JSON Schema object
 }
 
+impl ToolDefinition {
+    /// Starts a function tool definition. Parameters are described with a `ParametersBuilder`.
+    pub fn builder(name: &str, description: &str) -> ToolDefinitionBuilder {
+        ToolDefinitionBuilder {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters: ParametersBuilder::new(),
+        }
+    }
+}
+
+/// Builds a `ToolDefinition`; created by `ToolDefinition::builder`.
+#[derive(Debug, Clone)]
+pub struct ToolDefinitionBuilder {
+    name: String,
+    description: String,
+    parameters: ParametersBuilder,
+}
+
+impl ToolDefinitionBuilder {
+    pub fn parameters(mut self, parameters: ParametersBuilder) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    pub fn build(self) -> ToolDefinition {
+        ToolDefinition {
+            tool_type: "function".to_string(),
+            function: FunctionDefinition {
+                name: self.name,
+                description: self.description,
+                parameters: self.parameters.build(),
+            },
+        }
+    }
+}
+
+/// Builds the JSON Schema object for `FunctionDefinition::parameters`, so a tool's
+/// parameters are a valid schema by construction:
+///
+/// ```ignore
+/// let parameters = ParametersBuilder::new()
+///     .string("query", "The text to search for.", true)
+///     .number("limit", "Optional. Maximum results.", false)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParametersBuilder {
+    properties: serde_json::Map<String, JsonValue>,
+    required: Vec<String>,
+}
+
+impl ParametersBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn string(self, name: &str, description: &str, required: bool) -> Self {
+        self.primitive(name, "string", description, required)
+    }
+
+    pub fn number(self, name: &str, description: &str, required: bool) -> Self {
+        self.primitive(name, "number", description, required)
+    }
+
+    pub fn integer(self, name: &str, description: &str, required: bool) -> Self {
+        self.primitive(name, "integer", description, required)
+    }
+
+    pub fn boolean(self, name: &str, description: &str, required: bool) -> Self {
+        self.primitive(name, "boolean", description, required)
+    }
+
+    /// An array whose items are all of the JSON Schema type `item_type` (e.g. `"string"`).
+    pub fn array(self, name: &str, item_type: &str, description: &str, required: bool) -> Self {
+        let schema = serde_json::json!({
+            "type": "array",
+            "items": { "type": item_type },
+            "description": description,
+        });
+        self.property(name, schema, required)
+    }
+
+    /// A nested object described by its own builder.
+    pub fn object(self, name: &str, description: &str, properties: ParametersBuilder, required: bool) -> Self {
+        let mut schema = properties.build();
+        schema["description"] = JsonValue::String(description.to_string());
+        self.property(name, schema, required)
+    }
+
+    /// Returns the `{"type": "object", "properties": ..., "required": ...}` schema.
+    /// `required` is omitted when no parameter is required.
+    pub fn build(self) -> JsonValue {
+        let mut schema = serde_json::json!({
+            "type": "object",
+            "properties": self.properties,
+        });
+        if !self.required.is_empty() {
+            schema["required"] = serde_json::json!(self.required);
+        }
+        schema
+    }
+
+    fn primitive(self, name: &str, property_type: &str, description: &str, required: bool) -> Self {
+        let schema = serde_json::json!({ "type": property_type, "description": description });
+        self.property(name, schema, required)
+    }
+
+    fn property(mut self, name: &str, schema: JsonValue, required: bool) -> Self {
+        self.properties.insert(name.to_string(), schema);
+        if required && !self.required.iter().any(|r| r == name) {
+            self.required.push(name.to_string());
+        }
+        self
+    }
+}
+
 /// A self-contained, executable tool including its definition and function.
 /// This is what the user will create and provide to the library.
 pub enum Tool {
@@ -73,17 +198,884 @@ pub enum Tool {
         definition: ToolDefinition,
         function: PyObject, // This will hold the Python callable
     },
+    /// Runs an external program. Each entry of `args_template` is one argument and may
+    /// contain `{name}` placeholders that are filled from the model's JSON arguments.
+    /// An entry that is only a placeholder is dropped when that argument is absent.
+    Command {
+        definition: ToolDefinition,
+        program: String,
+        args_template: Vec<String>,
+        // Defaults to `DEFAULT_COMMAND_TIMEOUT` when not set.
+        timeout: Option<Duration>,
+    },
+    /// Calls an HTTP endpoint. `url` may contain `{name}` placeholders, each filled with
+    /// one percent-encoded path segment; the remaining arguments are sent as query
+    /// parameters for `GET`/`DELETE` and as a JSON body otherwise. Header values may be
+    /// `env:VAR_NAME` references, resolved at call time.
+    Http {
+        definition: ToolDefinition,
+        method: String,
+        url: String,
+        headers: HashMap<String, String>,
+        // Defaults to `DEFAULT_HTTP_TOOL_TIMEOUT` when not set.
+        timeout: Option<Duration>,
+    },
 }
 
+pub const DEFAULT_HTTP_TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
 impl Tool {
     /// A helper method to get the definition from any tool variant.
     pub fn definition(&self) -> &ToolDefinition {
         match self {
             Tool::Rust { definition, .. } => definition,
             Tool::Python { definition, .. } => definition,
+            Tool::Command { definition, .. } => definition,
+            Tool::Http { definition, .. } => definition,
+        }
+    }
+
+    fn definition_mut(&mut self) -> &mut ToolDefinition {
+        match self {
+            Tool::Rust { definition, .. } => definition,
+            Tool::Python { definition, .. } => definition,
+            Tool::Command { definition, .. } => definition,
+            Tool::Http { definition, .. } => definition,
         }
     }
 }
 
+/// Fills the `{name}` placeholders of a command tool's argument template. Each entry
+/// stays one argument whatever its value holds, as no shell is involved, but a value
+/// that would start an argument with `-` is refused so the model cannot add options.
+pub fn render_command_args(args_template: &[String], args: &JsonValue) -> Result<Vec<String>, String> {
+    let lookup = |name: &str| -> Result<Option<String>, String> {
+        let value = match args.get(name) {
+            None | Some(JsonValue::Null) => return Ok(None),
+            Some(JsonValue::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        };
+        if value.contains('\0') {
+            return Err(format!("Argument '{}' contains a NUL byte", name));
+        }
+        Ok(Some(value))
+    };
+    let check_leading = |name: &str, value: &str| {
+        if value.starts_with('-') {
+            Err(format!("Argument '{}' cannot start with '-': '{}' would be read as an option", name, value))
+        } else {
+            Ok(())
+        }
+    };
+
+    let mut rendered = Vec::with_capacity(args_template.len());
+    for part in args_template {
+        // A lone placeholder for a missing argument is treated as an omitted optional flag.
+        if let Some(name) = part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            if !name.contains(['{', '}']) {
+                if let Some(value) = lookup(name)? {
+                    check_leading(name, &value)?;
+                    rendered.push(value);
+                }
+                continue;
+            }
+        }
+
+        let mut out = String::new();
+        let mut rest = part.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else { break };
+            let name = &rest[start + 1..start + len];
+            let value = lookup(name)?
+                .ok_or_else(|| format!("Missing argument '{}' for command template '{}'", name, part))?;
+            if out.is_empty() && start == 0 {
+                check_leading(name, &value)?;
+            }
+            out.push_str(&rest[..start]);
+            out.push_str(&value);
+            rest = &rest[start + len + 1..];
+        }
+        out.push_str(rest);
+        rendered.push(out);
+    }
+    Ok(rendered)
+}
+
+/// Runs a `Tool::Command` and returns its exit code, stdout and stderr as a JSON object.
+/// The arguments are passed directly to the program, never through a shell.
+pub async fn run_command_tool(
+        program: &str,
+        args_template: &[String],
+        timeout: Option<Duration>,
+        args: &JsonValue,
+    ) -> Result<JsonValue, String> {
+    let rendered_args = render_command_args(args_template, args)?;
+    let timeout = timeout.unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+
+    let child = tokio::process::Command::new(program)
+        .args(&rendered_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start '{}': {}", program, e))?;
+
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(result) => result.map_err(|e| format!("Failed to run '{}': {}", program, e))?,
+        Err(_) => return Err(format!("Command '{}' timed out after {:?}", program, timeout)),
+    };
+
+    Ok(serde_json::json!({
+        "exit_code": output.status.code(),
+        "success": output.status.success(),
+        "stdout": String::from_utf8_lossy(&output.stdout),
+        "stderr": String::from_utf8_lossy(&output.stderr),
+    }))
+}
+
+// Everything but RFC 3986 unreserved characters, so a value can never add a `/`, `?`
+// or `#` to the URL it is substituted into.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Fills the `{name}` placeholders of an HTTP tool's URL, percent-encoding each value as
+/// a single path segment. Returns the URL and the names of the arguments it used.
+pub fn render_url(url_template: &str, args: &JsonValue) -> Result<(String, HashSet<String>), String> {
+    let mut url = String::new();
+    let mut used = HashSet::new();
+    let mut rest = url_template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else { break };
+        let name = &rest[start + 1..start + len];
+        let value = match args.get(name) {
+            None | Some(JsonValue::Null) => {
+                return Err(format!("Missing argument '{}' for URL '{}'", name, url_template));
+            }
+            Some(JsonValue::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        };
+        url.push_str(&rest[..start]);
+        url.extend(utf8_percent_encode(&value, PATH_SEGMENT));
+        used.insert(name.to_string());
+        rest = &rest[start + len + 1..];
+    }
+    url.push_str(rest);
+    Ok((url, used))
+}
+
+/// Runs a `Tool::Http` and returns the response status and body (parsed as JSON when
+/// possible) as a JSON object. Non-success statuses are returned as errors. Calls share
+/// the host's concurrency cap (see `client::set_host_concurrency_limit`).
+pub async fn run_http_tool(
+        method: &str,
+        url_template: &str,
+        headers: &HashMap<String, String>,
+        timeout: Option<Duration>,
+        args: &JsonValue,
+    ) -> Result<JsonValue, String> {
+    let (url, used) = render_url(url_template, args)?;
+    let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+        .map_err(|_| format!("Invalid HTTP method '{}'", method))?;
+    let client = reqwest::Client::builder()
+        .timeout(timeout.unwrap_or(DEFAULT_HTTP_TOOL_TIMEOUT))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    // Arguments already placed in the path are not sent a second time.
+    let remaining: serde_json::Map<String, JsonValue> = args
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(k, _)| !used.contains(k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    let mut request = client.request(method.clone(), &url);
+    for (name, value) in headers {
+        request = request.header(name, get_env_var(value).map_err(|e| e.to_string())?);
+    }
+    request = if method == reqwest::Method::GET || method == reqwest::Method::DELETE {
+        let query: Vec<(String, String)> = remaining
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), v.as_str().map_or_else(|| v.to_string(), str::to_string)))
+            .collect();
+        request.query(&query)
+    } else {
+        request.json(&remaining)
+    };
+
+    let _slot = client::acquire_host_slot(&url).await;
+    let response = request.send().await.map_err(|e| format!("Request to '{}' failed: {}", url, e))?;
+    let status = response.status();
+    let text = response.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("'{}' returned {}: {}", url, status, text));
+    }
+    let body = serde_json::from_str(&text).unwrap_or(JsonValue::String(text));
+    Ok(serde_json::json!({ "status": status.as_u16(), "body": body }))
+}
+
 /// A collection of executable tools, searchable by name, to be passed to the Orchestra.
 pub type ToolLibrary = HashMap<String, Tool>;
+
+/// Collision-aware combining and introspection of tool libraries.
+pub trait ToolLibraryExt: Sized {
+    /// Adds `other`'s tools. A tool whose name is already taken is not added, so neither
+    /// tool silently replaces the other; the taken names are returned, sorted.
+    fn merge(&mut self, other: ToolLibrary) -> Result<(), Vec<String>>;
+
+    /// Renames every tool to `{prefix}_{name}`, e.g. to keep a group of tools clear of
+    /// the built-in ones before merging.
+    fn namespaced(self, prefix: &str) -> Self;
+
+    /// The definition of every tool, sorted by name: what the model is shown, e.g. to
+    /// list the available tools in a UI or check that an expected tool is registered.
+    fn descriptions(&self) -> Vec<ToolDefinition>;
+}
+
+impl ToolLibraryExt for ToolLibrary {
+    fn merge(&mut self, other: ToolLibrary) -> Result<(), Vec<String>> {
+        let mut collisions = Vec::new();
+        for (name, tool) in other {
+            match self.entry(name) {
+                Entry::Occupied(taken) => collisions.push(taken.key().clone()),
+                Entry::Vacant(slot) => {
+                    slot.insert(tool);
+                }
+            }
+        }
+        if collisions.is_empty() {
+            Ok(())
+        } else {
+            collisions.sort();
+            Err(collisions)
+        }
+    }
+
+    fn namespaced(self, prefix: &str) -> Self {
+        self.into_iter()
+            .map(|(name, mut tool)| {
+                let name = format!("{}_{}", prefix, name);
+                tool.definition_mut().function.name = name.clone();
+                (name, tool)
+            })
+            .collect()
+    }
+
+    fn descriptions(&self) -> Vec<ToolDefinition> {
+        let mut definitions: Vec<ToolDefinition> = self.values().map(|tool| tool.definition().clone()).collect();
+        definitions.sort_by(|a, b| a.function.name.cmp(&b.function.name));
+        definitions
+    }
+}
+
+// --- Tool Manifests ---
+
+#[derive(Deserialize)]
+struct ToolManifest {
+    tools: Vec<ManifestTool>,
+}
+
+#[derive(Deserialize)]
+struct ManifestTool {
+    name: String,
+    description: String,
+    #[serde(default = "empty_parameters")]
+    parameters: JsonValue,
+    endpoint: Option<ManifestEndpoint>,
+    command: Option<ManifestCommand>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEndpoint {
+    url: String,
+    #[serde(default = "default_http_method")]
+    method: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ManifestCommand {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    timeout_secs: Option<u64>,
+}
+
+fn empty_parameters() -> JsonValue {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+fn default_http_method() -> String {
+    "POST".to_string()
+}
+
+/// Loads tools from a manifest: `{"tools": [{"name", "description", "parameters",
+/// "endpoint": {"url", "method", "headers", "timeout_secs"}}]}`, where `parameters` is
+/// a JSON Schema object. A tool may give a `"command": {"program", "args",
+/// "timeout_secs"}` instead of an endpoint to become a `Tool::Command`. Files ending in `.yaml` or `.yml` are read as YAML (see
+/// `parse_yaml_manifest`), anything else as JSON.
+pub fn load_manifest(path: &Path) -> Result<ToolLibrary, LLMCoreError> {
+    let contents = std::fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml") {
+        parse_yaml_manifest(&contents)
+    } else {
+        parse_manifest(&contents)
+    }
+}
+
+/// Parses the contents of a JSON tool manifest (see `load_manifest`).
+pub fn parse_manifest(manifest: &str) -> Result<ToolLibrary, LLMCoreError> {
+    let manifest: JsonValue = serde_json::from_str(manifest)
+        .map_err(|e| LLMCoreError::ConfigError(format!("Invalid tool manifest: {}", e)))?;
+    library_from_manifest(manifest)
+}
+
+/// Parses the contents of a YAML tool manifest (see `load_manifest`).
+///
+/// Only the block-style subset manifests need is understood: nested mappings and `-`
+/// sequences, plain, quoted and `|`/`>` block scalars, `#` comments, and JSON-style
+/// `[...]`/`{...}` flow values. Anchors, tags and multiple documents are rejected.
+pub fn parse_yaml_manifest(manifest: &str) -> Result<ToolLibrary, LLMCoreError> {
+    let manifest = yaml_to_json(manifest)
+        .map_err(|e| LLMCoreError::ConfigError(format!("Invalid tool manifest: {}", e)))?;
+    library_from_manifest(manifest)
+}
+
+fn library_from_manifest(manifest: JsonValue) -> Result<ToolLibrary, LLMCoreError> {
+    let manifest: ToolManifest = serde_json::from_value(manifest)
+        .map_err(|e| LLMCoreError::ConfigError(format!("Invalid tool manifest: {}", e)))?;
+
+    let mut library = ToolLibrary::new();
+    for tool in manifest.tools {
+        let definition = ToolDefinition {
+            tool_type: "function".to_string(),
+            function: FunctionDefinition {
+                name: tool.name.clone(),
+                description: tool.description,
+                parameters: tool.parameters,
+            },
+        };
+        let entry = match (tool.endpoint, tool.command) {
+            (Some(endpoint), None) => {
+                if reqwest::Method::from_bytes(endpoint.method.to_uppercase().as_bytes()).is_err() {
+                    return Err(LLMCoreError::ConfigError(format!(
+                        "Tool '{}' has an invalid HTTP method '{}'.",
+                        tool.name, endpoint.method
+                    )));
+                }
+                if !(endpoint.url.starts_with("http://") || endpoint.url.starts_with("https://")) {
+                    return Err(LLMCoreError::ConfigError(format!(
+                        "Tool '{}' endpoint '{}' must be an http(s) URL.",
+                        tool.name, endpoint.url
+                    )));
+                }
+                Tool::Http {
+                    definition,
+                    method: endpoint.method.to_uppercase(),
+                    url: endpoint.url,
+                    headers: endpoint.headers,
+                    timeout: endpoint.timeout_secs.map(Duration::from_secs),
+                }
+            }
+            (None, Some(command)) => Tool::Command {
+                definition,
+                program: command.program,
+                args_template: command.args,
+                timeout: command.timeout_secs.map(Duration::from_secs),
+            },
+            _ => {
+                return Err(LLMCoreError::ConfigError(format!(
+                    "Tool '{}' must have exactly one of `endpoint` or `command`.",
+                    tool.name
+                )));
+            }
+        };
+        if library.insert(tool.name.clone(), entry).is_some() {
+            return Err(LLMCoreError::ConfigError(format!("Tool '{}' is defined twice in the manifest.", tool.name)));
+        }
+    }
+    Ok(library)
+}
+
+// --- YAML Subset ---
+
+struct YamlLine<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+fn yaml_to_json(yaml: &str) -> Result<JsonValue, String> {
+    let raw: Vec<&str> = yaml.lines().collect();
+    let mut lines = Vec::new();
+    for (index, line) in raw.iter().enumerate() {
+        let text = strip_yaml_comment(line).trim_end();
+        if text.trim().is_empty() {
+            continue;
+        }
+        if text.starts_with('\t') {
+            return Err(format!("line {}: tabs cannot indent YAML", index + 1));
+        }
+        if text == "---" && lines.is_empty() {
+            continue;
+        }
+        let trimmed = text.trim_start();
+        if trimmed.starts_with(['&', '*', '!']) || trimmed == "---" || trimmed == "..." {
+            return Err(format!("line {}: unsupported YAML syntax '{}'", index + 1, trimmed));
+        }
+        lines.push(YamlLine { number: index + 1, indent: text.len() - trimmed.len(), text: trimmed });
+    }
+    if lines.is_empty() {
+        return Ok(JsonValue::Null);
+    }
+
+    let mut pos = 0;
+    let indent = lines[0].indent;
+    let value = parse_yaml_block(&raw, &mut lines, &mut pos, indent)?;
+    match lines.get(pos) {
+        Some(line) => Err(format!("line {}: unexpected indentation", line.number)),
+        None => Ok(value),
+    }
+}
+
+// Drops a `#` comment that starts the line or follows whitespace, outside quotes.
+fn strip_yaml_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+// Parses the mapping or sequence whose entries start at column `indent`.
+fn parse_yaml_block(raw: &[&str], lines: &mut [YamlLine], pos: &mut usize, indent: usize) -> Result<JsonValue, String> {
+    if is_sequence_item(lines[*pos].text) {
+        let mut items = Vec::new();
+        while *pos < lines.len() && lines[*pos].indent == indent && is_sequence_item(lines[*pos].text) {
+            let rest = lines[*pos].text[1..].trim_start();
+            if rest.is_empty() {
+                *pos += 1;
+                items.push(parse_yaml_nested(raw, lines, pos, indent)?);
+            } else if split_yaml_key(rest).is_some() {
+                // `- key: value` opens a mapping whose keys line up with `key`.
+                let column = indent + (lines[*pos].text.len() - rest.len());
+                lines[*pos].indent = column;
+                lines[*pos].text = rest;
+                items.push(parse_yaml_block(raw, lines, pos, column)?);
+            } else {
+                let number = lines[*pos].number;
+                *pos += 1;
+                items.push(parse_yaml_scalar(rest).map_err(|e| format!("line {}: {}", number, e))?);
+            }
+        }
+        return Ok(JsonValue::Array(items));
+    }
+
+    let mut map = serde_json::Map::new();
+    while *pos < lines.len() && lines[*pos].indent == indent {
+        let line = &lines[*pos];
+        let number = line.number;
+        let (key, value) = split_yaml_key(line.text)
+            .ok_or_else(|| format!("line {}: expected `key: value`, found '{}'", number, line.text))?;
+        let key = match parse_yaml_scalar(key).map_err(|e| format!("line {}: {}", number, e))? {
+            JsonValue::String(key) => key,
+            other => other.to_string(),
+        };
+        *pos += 1;
+        let value = match value {
+            "" => match lines.get(*pos) {
+                // A sequence may sit at the same indentation as its key.
+                Some(next) if next.indent == indent && is_sequence_item(next.text) => {
+                    parse_yaml_block(raw, lines, pos, indent)?
+                }
+                _ => parse_yaml_nested(raw, lines, pos, indent)?,
+            },
+            "|" | "|-" | ">" | ">-" => parse_yaml_block_scalar(raw, lines, pos, indent, number, value),
+            _ => parse_yaml_scalar(value).map_err(|e| format!("line {}: {}", number, e))?,
+        };
+        if map.insert(key.clone(), value).is_some() {
+            return Err(format!("line {}: duplicate key '{}'", number, key));
+        }
+    }
+    Ok(JsonValue::Object(map))
+}
+
+// Parses the block nested under a line at `parent` indentation, or null if there is none.
+fn parse_yaml_nested(raw: &[&str], lines: &mut [YamlLine], pos: &mut usize, parent: usize) -> Result<JsonValue, String> {
+    match lines.get(*pos) {
+        Some(next) if next.indent > parent => {
+            let indent = next.indent;
+            parse_yaml_block(raw, lines, pos, indent)
+        }
+        _ => Ok(JsonValue::Null),
+    }
+}
+
+// Collects the more-indented raw lines of a `|` (literal) or `>` (folded) scalar.
+fn parse_yaml_block_scalar(
+        raw: &[&str],
+        lines: &[YamlLine],
+        pos: &mut usize,
+        parent: usize,
+        header_line: usize,
+        style: &str,
+    ) -> JsonValue {
+    let mut end = header_line;
+    while *pos < lines.len() && lines[*pos].indent > parent {
+        end = lines[*pos].number;
+        *pos += 1;
+    }
+    let body = &raw[header_line..end];
+    let indent = body
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let body: Vec<&str> = body.iter().map(|l| l.get(indent..).unwrap_or("").trim_end()).collect();
+    let mut text = if style.starts_with('|') { body.join("\n") } else { body.join(" ") };
+    if !style.ends_with('-') && !text.is_empty() {
+        text.push('\n');
+    }
+    JsonValue::String(text)
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+// Splits `key: value` at the first `:` that ends the line or precedes a space, outside
+// quotes and flow collections.
+fn split_yaml_key(text: &str) -> Option<(&str, &str)> {
+    if text.starts_with(['[', '{']) {
+        return None;
+    }
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ':' && matches!(text[i + 1..].chars().next(), None | Some(' ')) => {
+                return Some((text[..i].trim_end(), text[i + 1..].trim()));
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+fn parse_yaml_scalar(text: &str) -> Result<JsonValue, String> {
+    let text = text.trim();
+    if text.starts_with('"') {
+        // Double-quoted YAML uses the same escapes as JSON for everything manifests need.
+        return serde_json::from_str(text).map_err(|_| format!("invalid quoted string {}", text));
+    }
+    if let Some(inner) = text.strip_prefix('\'') {
+        let inner = inner.strip_suffix('\'').ok_or_else(|| format!("unterminated string {}", text))?;
+        return Ok(JsonValue::String(inner.replace("''", "'")));
+    }
+    if text.starts_with(['[', '{']) {
+        return parse_yaml_flow(text);
+    }
+    Ok(match text {
+        "" | "~" | "null" | "Null" | "NULL" => JsonValue::Null,
+        "true" | "True" | "TRUE" => JsonValue::Bool(true),
+        "false" | "False" | "FALSE" => JsonValue::Bool(false),
+        _ => match text.parse::<i64>() {
+            Ok(n) => JsonValue::from(n),
+            Err(_) => match text.parse::<f64>() {
+                Ok(n) if n.is_finite() => JsonValue::from(n),
+                _ => JsonValue::String(text.to_string()),
+            },
+        },
+    })
+}
+
+// Flow collections are accepted as JSON, or as a flat `[a, b]` list of plain scalars.
+fn parse_yaml_flow(text: &str) -> Result<JsonValue, String> {
+    if let Ok(value) = serde_json::from_str(text) {
+        return Ok(value);
+    }
+    match text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Some(inner) if !inner.contains(['[', '{', '"', '\'']) => {
+            let items = inner.split(',').map(str::trim).filter(|item| !item.is_empty());
+            Ok(JsonValue::Array(items.map(parse_yaml_scalar).collect::<Result<_, _>>()?))
+        }
+        _ => Err(format!("unsupported flow value {}", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn http_tool(name: &str) -> JsonValue {
+        json!({ "name": name, "description": "d", "endpoint": { "url": "https://tools.example/run" } })
+    }
+
+    #[test]
+    fn merging_reports_collisions_and_namespaces_avoid_them() {
+        let manifest = |names: &[&str]| {
+            let tools: Vec<_> = names.iter().map(|name| http_tool(name)).collect();
+            parse_manifest(&json!({ "tools": tools }).to_string()).unwrap()
+        };
+        let mut library = manifest(&["generate_image", "get_current_time"]);
+
+        let err = library.merge(manifest(&["generate_image", "search"])).unwrap_err();
+        assert_eq!(err, vec!["generate_image".to_string()]);
+        assert_eq!(library.len(), 3);
+
+        library.merge(manifest(&["generate_image"]).namespaced("user")).unwrap();
+        assert_eq!(library["user_generate_image"].definition().function.name, "user_generate_image");
+
+        let names: Vec<_> = library.descriptions().into_iter().map(|d| d.function.name).collect();
+        assert_eq!(names, ["generate_image", "get_current_time", "search", "user_generate_image"]);
+    }
+
+    fn template(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn manifest_builds_http_and_command_tools() {
+        let library = parse_manifest(&json!({
+            "tools": [
+                {
+                    "name": "get_order",
+                    "description": "Looks up an order.",
+                    "parameters": { "type": "object", "properties": { "id": { "type": "string" } }, "required": ["id"] },
+                    "endpoint": { "url": "https://orders.internal/orders/{id}", "method": "get", "headers": { "Authorization": "env:ORDERS_TOKEN" } }
+                },
+                http_tool("ping"),
+                {
+                    "name": "disk_usage",
+                    "description": "Shows disk usage.",
+                    "command": { "program": "df", "args": ["-h"] }
+                }
+            ]
+        }).to_string())
+        .unwrap();
+
+        match &library["get_order"] {
+            Tool::Http { method, url, headers, definition, .. } => {
+                assert_eq!(method, "GET");
+                assert_eq!(url, "https://orders.internal/orders/{id}");
+                assert_eq!(headers["Authorization"], "env:ORDERS_TOKEN");
+                assert_eq!(definition.function.parameters["required"], json!(["id"]));
+            }
+            _ => panic!("expected an HTTP tool"),
+        }
+        assert!(matches!(&library["ping"], Tool::Http { method, .. } if method == "POST"));
+        assert_eq!(library["ping"].definition().function.parameters, empty_parameters());
+        assert!(matches!(&library["disk_usage"], Tool::Command { program, .. } if program == "df"));
+
+        let neither = json!({ "tools": [{ "name": "x", "description": "y" }] }).to_string();
+        assert!(matches!(parse_manifest(&neither), Err(LLMCoreError::ConfigError(_))));
+        let bad_url = json!({ "tools": [{ "name": "x", "description": "y", "endpoint": { "url": "ftp://host" } }] }).to_string();
+        assert!(matches!(parse_manifest(&bad_url), Err(LLMCoreError::ConfigError(_))));
+    }
+
+    #[test]
+    fn yaml_manifest_matches_its_json_form() {
+        let yaml = r#"
+# Order service tools
+tools:
+  - name: get_order
+    description: >
+      Looks up an order
+      by its id.
+    parameters:
+      type: object
+      properties:
+        id: { "type": "string", "description": "Order id" }
+        verbose:
+          type: boolean
+      required: [id]
+    endpoint:
+      url: "https://orders.internal/orders/{id}"   # the id is a path segment
+      method: get
+      timeout_secs: 10
+      headers:
+        Authorization: 'env:ORDERS_TOKEN'
+  - name: ping
+    description: "Checks the service: \"up\" or not."
+    endpoint:
+      url: https://orders.internal/ping
+"#;
+        let value = yaml_to_json(yaml).unwrap();
+        assert_eq!(
+            value["tools"][0],
+            json!({
+                "name": "get_order",
+                "description": "Looks up an order by its id.\n",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "description": "Order id" },
+                        "verbose": { "type": "boolean" }
+                    },
+                    "required": ["id"]
+                },
+                "endpoint": {
+                    "url": "https://orders.internal/orders/{id}",
+                    "method": "get",
+                    "timeout_secs": 10,
+                    "headers": { "Authorization": "env:ORDERS_TOKEN" }
+                }
+            })
+        );
+        assert_eq!(value["tools"][1]["description"], json!("Checks the service: \"up\" or not."));
+
+        let library = parse_yaml_manifest(yaml).unwrap();
+        assert!(matches!(&library["get_order"], Tool::Http { timeout: Some(t), .. } if *t == Duration::from_secs(10)));
+        assert!(matches!(parse_yaml_manifest("tools:\n  - name: x\n   description: y"), Err(LLMCoreError::ConfigError(_))));
+        assert!(matches!(parse_yaml_manifest("tools: &anchor []"), Err(LLMCoreError::ConfigError(_))));
+    }
+
+    #[test]
+    fn parameters_builder_produces_json_schema() {
+        let definition = ToolDefinition::builder("lookup", "Looks things up.")
+            .parameters(
+                ParametersBuilder::new()
+                    .string("query", "What to look up.", true)
+                    .array("tags", "string", "Tags to filter by.", false)
+                    .object(
+                        "range",
+                        "Date range.",
+                        ParametersBuilder::new().string("from", "Start date.", true),
+                        false,
+                    ),
+            )
+            .build();
+
+        assert_eq!(definition.tool_type, "function");
+        assert_eq!(
+            definition.function.parameters,
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "What to look up." },
+                    "tags": { "type": "array", "items": { "type": "string" }, "description": "Tags to filter by." },
+                    "range": {
+                        "type": "object",
+                        "description": "Date range.",
+                        "properties": { "from": { "type": "string", "description": "Start date." } },
+                        "required": ["from"]
+                    }
+                },
+                "required": ["query"]
+            })
+        );
+        assert_eq!(ParametersBuilder::new().build(), json!({ "type": "object", "properties": {} }));
+    }
+
+    #[test]
+    fn url_placeholders_are_single_encoded_segments() {
+        let (url, used) = render_url(
+            "https://orders.internal/orders/{id}/items/{item}",
+            &json!({ "id": "../admin?x=1#", "item": 7, "note": "kept" }),
+        )
+        .unwrap();
+        assert_eq!(url, "https://orders.internal/orders/..%2Fadmin%3Fx%3D1%23/items/7");
+        assert_eq!(used, HashSet::from(["id".to_string(), "item".to_string()]));
+
+        let err = render_url("https://orders.internal/orders/{id}", &json!({})).unwrap_err();
+        assert!(err.contains("'id'"));
+    }
+
+    #[tokio::test]
+    async fn http_tool_sends_unused_arguments_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for body in [r#"{"ok": true}"#, "not found"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 4096];
+                let mut request = String::new();
+                // Read until the headers and the announced body have arrived.
+                loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buf[..n]));
+                    if let Some(end) = request.find("\r\n\r\n") {
+                        let length = request[..end]
+                            .lines()
+                            .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                let status = if body == "not found" { "404 Not Found" } else { "200 OK" };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let url = format!("{}/orders/{{id}}", base);
+        let result = run_http_tool("post", &url, &HashMap::new(), None, &json!({ "id": "a b", "qty": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(result, json!({ "status": 200, "body": { "ok": true } }));
+        let err = run_http_tool("GET", &url, &HashMap::new(), None, &json!({ "id": "x", "page": 2 }))
+            .await
+            .unwrap_err();
+        assert!(err.contains("404"), "{}", err);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /orders/a%20b HTTP/1.1"), "{}", requests[0]);
+        assert!(requests[0].ends_with(r#"{"qty":2}"#), "{}", requests[0]);
+        assert!(requests[1].starts_with("GET /orders/x?page=2 HTTP/1.1"), "{}", requests[1]);
+    }
+
+    #[test]
+    fn command_args_are_rendered_from_json() {
+        let args = json!({ "path": "src/main.rs", "width": 80, "flag": "--delete", "spaced": "a b; rm -rf ~" });
+        let rendered = render_command_args(
+            &template(&["--width={width}", "{path}", "{missing_flag}", "--check", "{spaced}"]),
+            &args,
+        )
+        .unwrap();
+        assert_eq!(rendered, vec!["--width=80", "src/main.rs", "--check", "a b; rm -rf ~"]);
+
+        let err = render_command_args(&template(&["--out={output}"]), &args).unwrap_err();
+        assert!(err.contains("output"));
+        // A value may not turn into an option of its own, but may follow one.
+        assert!(render_command_args(&template(&["{flag}"]), &args).unwrap_err().contains("option"));
+        assert!(render_command_args(&template(&["{flag}.txt"]), &args).is_err());
+        assert_eq!(render_command_args(&template(&["--name={flag}"]), &args).unwrap(), vec!["--name=--delete"]);
+    }
+
+    #[tokio::test]
+    async fn command_tool_captures_output() {
+        let result = run_command_tool("echo", &template(&["{text}"]), None, &json!({ "text": "hello" }))
+            .await
+            .unwrap();
+        assert_eq!(result["exit_code"], json!(0));
+        assert_eq!(result["stdout"], json!("hello\n"));
+
+        let err = run_command_tool("sleep", &template(&["5"]), Some(Duration::from_millis(50)), &json!({}))
+            .await
+            .unwrap_err();
+        assert!(err.contains("timed out"));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::datam::{ResponsePayload, Usage};
+use crate::error::LLMCoreError;
+use crate::failures::{redact, redact_headers};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+tokio::task_local! {
+    // The trace of the turn running on this task, set by `Orchestra` while tracing is on.
+    static CURRENT: Arc<Mutex<JobTrace>>;
+}
+
+/// Everything one turn sent and received, as written by `Orchestra::set_trace_dir` to
+/// `<job_id>.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobTrace {
+    pub job_id: Uuid,
+    pub model: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub requests: Vec<TracedRequest>,
+    pub tool_calls: Vec<TracedToolCall>,
+    pub response: Option<ResponsePayload>,
+    pub usage: Option<Usage>,
+    pub error: Option<String>,
+}
+
+/// One request of a turn (the first call, a retry, the synthesis after tools).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TracedRequest {
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub payload: JsonValue,
+    pub raw_response: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A tool the model called during the turn and what it returned.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TracedToolCall {
+    pub name: String,
+    pub args: JsonValue,
+    pub result: Option<String>,
+}
+
+impl JobTrace {
+    pub fn new(job_id: Uuid, model: &str) -> Self {
+        JobTrace {
+            job_id,
+            model: model.to_string(),
+            started_at: Utc::now(),
+            finished_at: None,
+            requests: Vec::new(),
+            tool_calls: Vec::new(),
+            response: None,
+            usage: None,
+            error: None,
+        }
+    }
+
+    pub fn tool_call_started(&mut self, name: &str, args: &JsonValue) {
+        self.tool_calls.push(TracedToolCall { name: name.to_string(), args: args.clone(), result: None });
+    }
+
+    pub fn tool_result(&mut self, name: &str, result: &str) {
+        if let Some(call) = self.tool_calls.iter_mut().rev().find(|c| c.name == name && c.result.is_none()) {
+            call.result = Some(result.to_string());
+        }
+    }
+
+    /// Records how the turn ended.
+    pub fn finish(&mut self, result: &Result<ResponsePayload, LLMCoreError>) {
+        self.finished_at = Some(Utc::now());
+        match result {
+            Ok(payload) => {
+                self.usage = payload.usage.clone();
+                self.response = Some(payload.clone());
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Writes the trace to `<dir>/<job_id>.json` and returns its path.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, LLMCoreError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.json", self.job_id));
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+/// Runs `future` with `trace` receiving the requests it makes through `record_request`.
+pub(crate) async fn scope<F: std::future::Future>(trace: Arc<Mutex<JobTrace>>, future: F) -> F::Output {
+    CURRENT.scope(trace, future).await
+}
+
+/// Whether a trace is being recorded on this task.
+pub(crate) fn is_active() -> bool {
+    CURRENT.try_with(|_| ()).is_ok()
+}
+
+/// Adds a request to the current trace, if any, with `api_key` redacted.
+pub(crate) fn record_request(
+        url: &str,
+        headers: &HeaderMap,
+        payload: &JsonValue,
+        result: &Result<(String, HeaderMap), LLMCoreError>,
+        api_key: &str,
+    ) {
+    let _ = CURRENT.try_with(|trace| {
+        let (raw_response, error) = match result {
+            Ok((body, _)) => (Some(body.clone()), None),
+            Err(e) => {
+                let body = match e {
+                    LLMCoreError::ApiErrorDetailed { body, .. } | LLMCoreError::AuthenticationFailed { body, .. } => {
+                        Some(body.clone())
+                    }
+                    _ => None,
+                };
+                (body, Some(e.to_string()))
+            }
+        };
+        trace.lock().unwrap_or_else(|e| e.into_inner()).requests.push(TracedRequest {
+            url: redact(url, api_key),
+            headers: redact_headers(headers, api_key),
+            payload: payload.clone(),
+            raw_response,
+            error,
+        });
+    });
+}
@@ -3,7 +3,7 @@ use crate::error::LLMCoreError;
 use crate::datam::Usage;
 
 use serde_json::Value as JsonValue;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::ErrorKind;
 use serde_json::json;
 use chrono::Utc;
@@ -15,24 +15,21 @@ use std::fs;
 /// This function maintains a `usage.json` file, creating it if it doesn't exist.
 /// It structures the data hierarchically: Day -> Hour -> ID -> {task_label, model_name, events}.
 /// This makes it easy to track token consumption over time and by session.
+///
+/// Non-empty `metadata` (e.g. `tenant`, `feature`, `user_id`) is stored on the event, so
+/// spend can later be grouped with `aggregate_usage_by_metadata`.
 pub fn log_usage_turn(
         id: Uuid,
         turn_usage: &Usage,
         label: &str,
         model_name: &str,
+        metadata: &HashMap<String, String>,
     ) -> Result<(), LLMCoreError> {
     let usage_dir = &*USAGE_DATA_DIR;
     fs::create_dir_all(usage_dir)?;
 
     let usage_file_path = usage_dir.join("usage.json");
-
-    // Read the existing file or create a new BTreeMap if it doesn't exist.
-    // We use BTreeMap to maintain sorted keys (days, hours).
-    let mut all_usage: BTreeMap<String, JsonValue> = match fs::read_to_string(&usage_file_path) {
-        Ok(contents) => serde_json::from_str(&contents)?,
-        Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
-        Err(e) => return Err(e.into()),
-    };
+    let mut all_usage = read_usage_file()?;
 
     let now = Utc::now();
     let day_key = now.format("%Y-%m-%d").to_string();
@@ -68,10 +65,14 @@ pub fn log_usage_turn(
     usage_entry["model_name"] = JsonValue::String(model_name.to_string());
 
     // Append the new usage event for this turn to the "events" array.
+    let mut event = serde_json::to_value(turn_usage)?;
+    if !metadata.is_empty() {
+        event["metadata"] = json!(metadata);
+    }
     usage_entry["events"]
         .as_array_mut()
         .unwrap()
-        .push(serde_json::to_value(turn_usage)?);
+        .push(event);
 
     // Write the updated data back to the file.
     let updated_json = serde_json::to_string_pretty(&all_usage)?;
@@ -79,3 +80,76 @@ pub fn log_usage_turn(
 
     Ok(())
 }
+
+/// The group that events without the requested metadata key are counted under.
+pub const UNTAGGED: &str = "untagged";
+
+/// Sums the logged usage per value of the metadata `key` (e.g. per tenant), across the
+/// whole usage log. Events logged without that key are summed under `UNTAGGED`.
+pub fn aggregate_usage_by_metadata(key: &str) -> Result<BTreeMap<String, Usage>, LLMCoreError> {
+    Ok(aggregate_by_metadata(&read_usage_file()?, key))
+}
+
+// Reads `usage.json`, or returns an empty log if it does not exist yet. BTreeMap keeps
+// the day and hour keys sorted.
+fn read_usage_file() -> Result<BTreeMap<String, JsonValue>, LLMCoreError> {
+    let usage_file_path = USAGE_DATA_DIR.join("usage.json");
+    match fs::read_to_string(&usage_file_path) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn aggregate_by_metadata(all_usage: &BTreeMap<String, JsonValue>, key: &str) -> BTreeMap<String, Usage> {
+    let mut totals: BTreeMap<String, Usage> = BTreeMap::new();
+    let events = all_usage
+        .values()
+        .filter_map(JsonValue::as_object)
+        .flat_map(|hours| hours.values())
+        .filter_map(JsonValue::as_object)
+        .flat_map(|entries| entries.values())
+        .filter_map(|entry| entry["events"].as_array())
+        .flatten();
+    for event in events {
+        let Ok(usage) = serde_json::from_value::<Usage>(event.clone()) else { continue };
+        let group = event["metadata"][key].as_str().unwrap_or(UNTAGGED).to_string();
+        *totals.entry(group).or_default() += usage;
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_grouped_by_metadata_value() {
+        let event = |tokens: u32, tenant: Option<&str>| {
+            let mut event = json!({ "prompt_tokens": tokens, "completion_tokens": 0, "total_tokens": tokens });
+            if let Some(tenant) = tenant {
+                event["metadata"] = json!({ "tenant": tenant, "feature": "search" });
+            }
+            event
+        };
+        let all_usage: BTreeMap<String, JsonValue> = serde_json::from_value(json!({
+            "2026-10-15": {
+                "09:00": {
+                    "a": { "task_label": "convo", "model_name": "m", "events": [event(10, Some("acme")), event(5, None)] },
+                    "b": { "task_label": "convo", "model_name": "m", "events": [event(7, Some("globex"))] }
+                },
+                "10:00": {
+                    "c": { "task_label": "convo", "model_name": "m", "events": [event(3, Some("acme"))] }
+                }
+            }
+        }))
+        .unwrap();
+
+        let totals = aggregate_by_metadata(&all_usage, "tenant");
+        assert_eq!(totals.len(), 3);
+        assert_eq!(totals["acme"].total_tokens, 13);
+        assert_eq!(totals["globex"].total_tokens, 7);
+        assert_eq!(totals[UNTAGGED].total_tokens, 5);
+        assert_eq!(aggregate_by_metadata(&all_usage, "feature")["search"].total_tokens, 20);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
@@ -6,6 +7,7 @@ use arroy::{Database, Reader, Writer};
 use heed::EnvOpenOptions;
 use rand::rngs::StdRng;
 use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
 
 use crate::embed::Embedder;
 use crate::error::LLMCoreError;
@@ -26,6 +28,17 @@ impl VectorIndex {
         wtxn.commit()?;
         Ok(Self { env, db, dimensions })
     }
+
+    /// Reads `(dimensions, item count)` from the built index metadata.
+    /// Returns `None` if the index has never been built.
+    fn metadata(&self) -> Result<Option<(usize, u64)>, LLMCoreError> {
+        let rtxn = self.env.read_txn()?;
+        match Reader::<DotProduct>::open(&rtxn, 0, self.db) {
+            Ok(reader) => Ok(Some((reader.dimensions(), reader.n_items()))),
+            Err(arroy::Error::MissingMetadata(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 pub struct KnowledgeBase {
@@ -34,6 +47,47 @@ pub struct KnowledgeBase {
     embedder: Embedder,
 }
 
+/// A snapshot of a knowledge base's size and health, from `KnowledgeBase::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KbStats {
+    /// Chunks stored in the database.
+    pub chunk_count: usize,
+    /// Distinct documents (URLs or file paths) the chunks come from.
+    pub source_count: usize,
+    /// Vectors in the index; 0 when it has never been built.
+    pub vector_count: usize,
+    /// Size of the vector index on disk, in bytes.
+    pub index_size_bytes: u64,
+    /// Size of the database on disk, in bytes.
+    pub db_size_bytes: u64,
+    /// `true` when the index holds exactly one vector per stored chunk. `false` means
+    /// the two have drifted apart, e.g. after chunks were removed through `Storage`.
+    pub consistency_ok: bool,
+}
+
+/// Which text of each chunk a search returns. `Summary` keeps results small, leaving
+/// the model to fetch the documents it needs with `knowledge_base_get_full_document`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    Content,
+    Summary,
+    #[default]
+    Both,
+}
+
+impl SearchMode {
+    /// Clears the text `chunk` should not carry in this mode. A chunk ingested without
+    /// a summary keeps its content in `Summary` mode, so it is not returned empty.
+    pub fn apply(self, chunk: &mut DocumentChunk) {
+        match self {
+            SearchMode::Content => chunk.summary.clear(),
+            SearchMode::Summary if !chunk.summary.is_empty() => chunk.content.clear(),
+            SearchMode::Summary | SearchMode::Both => {}
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct DocumentSource {
     pub url: String,
@@ -54,7 +108,62 @@ impl KnowledgeBase {
         let embedder = Embedder::new(embedding_model, None)?;
         let storage = Storage::new(db_path)?;
         let vector_index = VectorIndex::new(index_path, embedder.dimensions)?;
-        Ok(Self { storage, vector_index, embedder })
+        let kb = Self { storage, vector_index, embedder };
+
+        if let Some(stored) = kb.embedding_dimensions() {
+            if stored != kb.embedder.dimensions {
+                eprintln!(
+                    "[WARNING] Index at '{}' stores {}-dimensional vectors but '{}' produces {}. Searches will fail until the index is rebuilt with a matching model.",
+                    index_path.display(),
+                    stored,
+                    embedding_model,
+                    kb.embedder.dimensions
+                );
+            }
+        }
+        Ok(kb)
+    }
+
+    /// The dimensionality of the vectors stored in the index, or `None` if nothing
+    /// has been indexed yet.
+    pub fn embedding_dimensions(&self) -> Option<usize> {
+        match self.vector_index.metadata() {
+            Ok(meta) => meta.map(|(dimensions, _)| dimensions),
+            Err(e) => {
+                eprintln!("[WARNING] Failed to read vector index metadata: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The number of vectors in the index.
+    pub fn len(&self) -> usize {
+        match self.vector_index.metadata() {
+            Ok(meta) => meta.map_or(0, |(_, n_items)| n_items as usize),
+            Err(e) => {
+                eprintln!("[WARNING] Failed to read vector index metadata: {}", e);
+                0
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reports chunk, source and vector counts, sizes on disk, and whether the index
+    /// and the database agree.
+    pub fn stats(&self) -> Result<KbStats, LLMCoreError> {
+        let (chunk_count, source_count) = self.storage.chunk_and_source_counts()?;
+        let vector_count = self.vector_index.metadata()?.map_or(0, |(_, n_items)| n_items as usize);
+        Ok(KbStats {
+            chunk_count,
+            source_count,
+            vector_count,
+            index_size_bytes: self.vector_index.env.real_disk_size()?,
+            db_size_bytes: self.storage.size_on_disk()?,
+            consistency_ok: vector_count == chunk_count,
+        })
     }
 
     pub async fn add_documents_and_build(
@@ -91,28 +200,178 @@ impl KnowledgeBase {
         Ok(())
     }
 
+    /// Returns the `limit` best-matching chunks, with the text `search_mode` selects.
     pub async fn search(
             &self,
             query: &str,
             limit: usize,
+            search_mode: SearchMode,
         ) -> Result<Vec<DocumentChunk>, LLMCoreError> {
+        let mut chunks = self.search_with_source_limit(query, limit, None).await?;
+        chunks.iter_mut().for_each(|chunk| search_mode.apply(chunk));
+        Ok(chunks)
+    }
+
+    /// Searches like `search`, but returns at most `max_per_source` chunks from any one
+    /// document (`url`), fetching further candidates to fill the remaining slots from
+    /// other documents. `None` places no cap. Fewer than `limit` chunks come back only
+    /// when the index runs out of candidates.
+    pub async fn search_with_source_limit(
+            &self,
+            query: &str,
+            limit: usize,
+            max_per_source: Option<usize>,
+        ) -> Result<Vec<DocumentChunk>, LLMCoreError> {
+        if max_per_source == Some(0) {
+            return Err(LLMCoreError::ConfigError("max_per_source must be at least 1.".to_string()));
+        }
         let embeddings = self.embedder.get_embeddings(vec![query.to_string()]).await?;
         let query_vector = embeddings.get(0).ok_or_else(|| {
             LLMCoreError::RetrievalError("Failed to generate embedding for query".to_string())
         })?;
+        self.nearest(query_vector, limit, max_per_source)
+    }
 
+    /// Searches like `search_with_source_limit` for each of `queries`, embedding them all
+    /// in one request. Returns one result list per query, in the order given.
+    pub async fn multi_search(
+            &self,
+            queries: &[String],
+            limit: usize,
+            max_per_source: Option<usize>,
+        ) -> Result<Vec<Vec<DocumentChunk>>, LLMCoreError> {
+        if max_per_source == Some(0) {
+            return Err(LLMCoreError::ConfigError("max_per_source must be at least 1.".to_string()));
+        }
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+        let embeddings = self.embedder.get_embeddings(queries.to_vec()).await?;
+        if embeddings.len() != queries.len() {
+            return Err(LLMCoreError::RetrievalError(format!(
+                "Expected {} query embeddings, got {}",
+                queries.len(),
+                embeddings.len()
+            )));
+        }
+        embeddings
+            .iter()
+            .map(|query_vector| self.nearest(query_vector, limit, max_per_source))
+            .collect()
+    }
+
+    /// The `limit` chunks nearest to `query_vector`, at most `max_per_source` per document.
+    fn nearest(
+            &self,
+            query_vector: &[f32],
+            limit: usize,
+            max_per_source: Option<usize>,
+        ) -> Result<Vec<DocumentChunk>, LLMCoreError> {
         let rtxn = self.vector_index.env.read_txn()?;
         let reader = Reader::<DotProduct>::open(&rtxn, 0, self.vector_index.db)?;
-        
-        let query_builder = reader.nns(limit);
-        let result = query_builder.by_vector(&rtxn, query_vector)?;
-        let ids: Vec<i64> = result.into_iter().map(|(id, _)| id as i64).collect();
-        
-        self.storage.get_chunks_by_ids(&ids)
+
+        // A capped search starts with extra candidates, since some will be dropped.
+        let mut candidates = match max_per_source {
+            Some(_) => limit.saturating_mul(4),
+            None => limit,
+        };
+        let mut warned = false;
+        loop {
+            let result = reader.nns(candidates).by_vector(&rtxn, query_vector)?;
+            let ids: Vec<i64> = result.into_iter().map(|(id, _)| id as i64).collect();
+            let chunks = self.storage.get_chunks_by_ids(&ids)?;
+
+            // An id can be in the index without a row in the database (e.g. a chunk removed
+            // through `Storage` directly). Those are skipped and the search widened to fill the gap.
+            if chunks.len() < ids.len() && !warned {
+                eprintln!(
+                    "[WARNING] {} search result(s) have no matching database row; skipping.",
+                    ids.len() - chunks.len()
+                );
+                warned = true;
+            }
+
+            let exhausted = ids.len() < candidates;
+            let results = cap_per_source(chunks, max_per_source, limit);
+            if results.len() >= limit || exhausted {
+                return Ok(results);
+            }
+            candidates = candidates.saturating_mul(2);
+        }
+    }
+
+    /// Searches like `search_with_source_limit`, then adds the chunks within
+    /// `neighbor_window` positions of each hit in the same document. Chunks are
+    /// deduplicated and returned grouped by document (best-ranked document first), in
+    /// chunk order within each document.
+    pub async fn search_with_context(
+            &self,
+            query: &str,
+            limit: usize,
+            neighbor_window: usize,
+            max_per_source: Option<usize>,
+        ) -> Result<Vec<DocumentChunk>, LLMCoreError> {
+        let hits = self.search_with_source_limit(query, limit, max_per_source).await?;
+        if neighbor_window == 0 {
+            return Ok(hits);
+        }
+        expand_with_neighbors(hits, neighbor_window, |url| self.storage.get_full_document(url))
+    }
+
+    /// Removes a chunk from both the database and the vector index.
+    /// Returns `true` if the chunk existed in either store.
+    pub fn remove_chunk(&self, id: i64) -> Result<bool, LLMCoreError> {
+        Ok(self.remove_chunks(&[id])? > 0)
+    }
+
+    /// Removes several chunks from both the database and the vector index, returning
+    /// how many ids were found in either store.
+    ///
+    /// The index changes are only committed after the database delete succeeds, so a
+    /// failure leaves the index untouched rather than pointing at deleted rows.
+    pub fn remove_chunks(&self, ids: &[i64]) -> Result<usize, LLMCoreError> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut wtxn = self.vector_index.env.write_txn()?;
+        let writer = Writer::<DotProduct>::new(self.vector_index.db, 0, self.vector_index.dimensions);
+
+        let mut removed_from_index = HashSet::new();
+        for &id in ids {
+            if writer.del_item(&mut wtxn, id as u32)? {
+                removed_from_index.insert(id);
+            }
+        }
+
+        if !removed_from_index.is_empty() {
+            let mut rng = StdRng::seed_from_u64(42);
+            writer.builder(&mut rng).build(&mut wtxn)?;
+        }
+
+        let removed_from_db = self.storage.remove_chunks(ids)?;
+        wtxn.commit()?;
+
+        Ok(ids
+            .iter()
+            .filter(|id| removed_from_index.contains(id) || removed_from_db.contains(id))
+            .count())
+    }
+
+    /// Removes every chunk of the document at `url` from the database and the index,
+    /// returning how many were removed.
+    pub fn remove_source(&self, url: &str) -> Result<usize, LLMCoreError> {
+        let ids = self.storage.chunk_ids_for_url(url)?;
+        self.remove_chunks(&ids)
     }
 
     // --- Pass-through methods to Storage ---
 
+    /// Returns `true` if any chunk of the document at `url` is stored.
+    pub fn contains_source(&self, url: &str) -> Result<bool, LLMCoreError> {
+        Ok(!self.storage.chunk_ids_for_url(url)?.is_empty())
+    }
+
     pub fn list_sources(&self) -> Result<Vec<String>, LLMCoreError> {
         self.storage.list_sources()
     }
@@ -121,3 +380,99 @@ impl KnowledgeBase {
         self.storage.get_full_document(url)
     }
 }
+
+/// Keeps the first `limit` chunks, skipping any beyond `max_per_source` from one `url`.
+fn cap_per_source(
+        chunks: Vec<DocumentChunk>,
+        max_per_source: Option<usize>,
+        limit: usize,
+    ) -> Vec<DocumentChunk> {
+    let mut per_source: HashMap<String, usize> = HashMap::new();
+    chunks
+        .into_iter()
+        .filter(|chunk| {
+            let Some(max) = max_per_source else { return true };
+            let count = per_source.entry(chunk.url.clone()).or_default();
+            *count += 1;
+            *count <= max
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Replaces each hit with the chunks of its document whose `chunk_number` is within
+/// `window` of a hit. Documents keep the order of their best hit.
+fn expand_with_neighbors<F>(
+        hits: Vec<DocumentChunk>,
+        window: usize,
+        mut full_document: F,
+    ) -> Result<Vec<DocumentChunk>, LLMCoreError>
+where
+    F: FnMut(&str) -> Result<Vec<DocumentChunk>, LLMCoreError>,
+{
+    let window = window.min(i32::MAX as usize) as i32;
+    let mut urls: Vec<&str> = Vec::new();
+    for hit in &hits {
+        if !urls.contains(&hit.url.as_str()) {
+            urls.push(&hit.url);
+        }
+    }
+
+    let mut expanded = Vec::new();
+    for url in urls {
+        let hit_numbers: Vec<i32> = hits
+            .iter()
+            .filter(|hit| hit.url == url)
+            .map(|hit| hit.chunk_number)
+            .collect();
+        expanded.extend(full_document(url)?.into_iter().filter(|chunk| {
+            hit_numbers
+                .iter()
+                .any(|&number| (chunk.chunk_number - number).abs() <= window)
+        }));
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn chunk(url: &str, chunk_number: i32) -> DocumentChunk {
+        DocumentChunk {
+            id: chunk_number as i64,
+            url: url.to_string(),
+            chunk_number,
+            title: String::new(),
+            summary: String::new(),
+            content: format!("{} #{}", url, chunk_number),
+            metadata: serde_json::Value::Null,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn results_are_capped_per_source() {
+        let ranked = vec![chunk("a", 1), chunk("a", 2), chunk("a", 3), chunk("b", 1), chunk("a", 4), chunk("c", 1)];
+        let found = |chunks: Vec<DocumentChunk>| -> Vec<(String, i32)> {
+            chunks.into_iter().map(|c| (c.url, c.chunk_number)).collect()
+        };
+
+        let capped = cap_per_source(ranked.clone(), Some(2), 4);
+        assert_eq!(found(capped), vec![("a".into(), 1), ("a".into(), 2), ("b".into(), 1), ("c".into(), 1)]);
+        let uncapped = cap_per_source(ranked, None, 3);
+        assert_eq!(found(uncapped), vec![("a".into(), 1), ("a".into(), 2), ("a".into(), 3)]);
+    }
+
+    #[test]
+    fn neighbors_are_merged_in_document_order() {
+        let documents = |url: &str| Ok((1..=10).map(|n| chunk(url, n)).collect());
+        // Two overlapping hits in "b" (ranked first) and one at the start of "a".
+        let hits = vec![chunk("b", 5), chunk("a", 1), chunk("b", 6)];
+
+        let expanded = expand_with_neighbors(hits, 1, documents).unwrap();
+        let found: Vec<(&str, i32)> = expanded.iter().map(|c| (c.url.as_str(), c.chunk_number)).collect();
+        assert_eq!(found, vec![("b", 4), ("b", 5), ("b", 6), ("b", 7), ("a", 1), ("a", 2)]);
+    }
+}
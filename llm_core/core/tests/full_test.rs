@@ -14,16 +14,17 @@
 use _llm_core::{
     config::get_rust_tool_library,
     config::storage::Storage,
-    orchestra::Orchestra,
+    orchestra::{self, Orchestra},
     convo::Chat,
     embed::Embedder,
-    vector::{KnowledgeBase, DocumentSource},
+    vector::{KnowledgeBase, DocumentSource, SearchMode},
     retrieval::KNOWLEDGE_BASE,
     datam::{format_system_message, format_user_message},
     lucky::{SchemaProperty, SimpleSchema},
-    sorter::{Sorter, SortingInstructions},
-    ingest::Ingestor,
-    config::{get_env_var, MODEL_LIBRARY},
+    sorter::{SortAuditEntry, Sorter, SortingInstructions},
+    ingest::{Ingestor, OnConflict},
+    jobs,
+    config::{get_env_var, DEFAULT_MODEL_ENV_VAR, MODEL_LIBRARY},
 };
 use serde::Deserialize;
 use serde_json::Value as JsonValue;
@@ -35,8 +36,16 @@ use tempfile::tempdir;
 use std::io::Write; // Import Write trait for writeln!
 use reqwest::Client;
 use serde_json::json;
+use once_cell::sync::Lazy;
 
-const MODEL_NAME: &str = "GPT 4o MINI";
+// Resolved from `LLM_CORE_DEFAULT_MODEL`, so the suite can be run against any model
+// without editing it; "GPT 4o MINI" when the variable is unset.
+static MODEL_NAME: Lazy<String> = Lazy::new(|| {
+    env::var(DEFAULT_MODEL_ENV_VAR)
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "GPT 4o MINI".to_string())
+});
 
 // CONCLUSIONS:
 // All models here have been tested. Of them, only deepseek (OpenRouter & Ollama) have failed.
@@ -88,10 +97,10 @@ const MODEL_NAME: &str = "GPT 4o MINI";
 #[tokio::test]
 #[ignore]
 async fn test_normal_mode() {
-    println!("\n--- Running Test: Normal Chat Mode ({}) ---\n", MODEL_NAME);
+    println!("\n--- Running Test: Normal Chat Mode ({}) ---\n", MODEL_NAME.as_str());
 
     let orchestra = Orchestra::new(
-        MODEL_NAME, 
+        MODEL_NAME.as_str(), 
         Some(0.7), 
         None, 
         None,
@@ -128,11 +137,11 @@ async fn test_normal_mode() {
 #[tokio::test]
 #[ignore]
 async fn test_thinking_mode() {
-    println!("\n--- Running Test: Thinking Mode ({}) ---\n", MODEL_NAME);
+    println!("\n--- Running Test: Thinking Mode ({}) ---\n", MODEL_NAME.as_str());
 
     // 1. Create a chat session with thinking_mode explicitly enabled.
     let mut chat = Chat::new(
-        MODEL_NAME,
+        MODEL_NAME.as_str(),
         Some("You are a helpful assistant.".to_string()),
         None,
         None,
@@ -167,7 +176,7 @@ async fn test_thinking_mode() {
 #[tokio::test]
 #[ignore]
 async fn test_schema_mode() {
-    println!("\n--- Running Test: Native Schema Mode ({}) ---\n", MODEL_NAME);
+    println!("\n--- Running Test: Native Schema Mode ({}) ---\n", MODEL_NAME.as_str());
 
     #[derive(Deserialize, Debug)]
     struct UserDetails {
@@ -195,7 +204,7 @@ async fn test_schema_mode() {
     };
 
     let orchestra = Orchestra::new(
-        MODEL_NAME, 
+        MODEL_NAME.as_str(), 
         Some(0.0), 
         None, 
         Some(schema),
@@ -213,16 +222,52 @@ async fn test_schema_mode() {
     assert_eq!(details.age, 34);
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_extract() {
+    #[derive(Deserialize, Debug)]
+    struct UserDetails {
+        name: String,
+        age: u8,
+    }
+
+    let schema = SimpleSchema {
+        name: "extract_user_details".to_string(),
+        description: "Extracts the user's name and age from the text.".to_string(),
+        properties: vec![
+            SchemaProperty {
+                name: "name".to_string(),
+                property_type: "string".to_string(),
+                description: "The name of the user.".to_string(),
+                items: None,
+            },
+            SchemaProperty {
+                name: "age".to_string(),
+                property_type: "number".to_string(),
+                description: "The age of the user.".to_string(),
+                items: None,
+            },
+        ],
+    };
+
+    let details: UserDetails = orchestra::extract(MODEL_NAME.as_str(), "My name is Alex and I'm 34 years old.", schema)
+        .await
+        .unwrap();
+    println!("Extracted: {:?}", details);
+    assert_eq!(details.name.to_lowercase(), "alex");
+    assert_eq!(details.age, 34);
+}
+
 // --- Test: Native Tool Mode ---
 // Goal: Verify that the Orchestra can use a model's native tool-calling ability.
 #[tokio::test]
 #[ignore]
 async fn test_tooler_mode() {
-    println!("\n--- Running Test: Native Tool Mode ({}) ---\n", MODEL_NAME);
+    println!("\n--- Running Test: Native Tool Mode ({}) ---\n", MODEL_NAME.as_str());
     let tool_library = get_rust_tool_library();
 
     let orchestra = Orchestra::new(
-        MODEL_NAME, 
+        MODEL_NAME.as_str(), 
         Some(0.0), 
         Some(tool_library), 
         None,
@@ -269,7 +314,7 @@ async fn test_sorter_mode() {
     // Use a model known not to have native schema support to force the Lucky fallback.
     // The Orchestra should print a warning that it's falling back.
     let orchestra = Orchestra::new(
-        MODEL_NAME, 
+        MODEL_NAME.as_str(), 
         Some(0.0), 
         None, 
         Some(sorter_schema),
@@ -288,6 +333,9 @@ async fn test_sorter_mode() {
             "technology".to_string(),
             "vehicle".to_string(),
         ],
+        category_definitions: vec![],
+        strict_categories: false,
+        capture_reasoning: false,
     };
     
     // Use the actual orchestra instance to build the prompt.
@@ -295,6 +343,7 @@ async fn test_sorter_mode() {
         Arc::new(orchestra.clone()), // We clone the orchestra for the prompt builder
         sorting_instructions,
         None,
+        None,
         false
     ).unwrap();
     
@@ -327,13 +376,13 @@ async fn test_sorter_mode() {
 #[tokio::test]
 #[ignore]
 async fn test_conversation_mode() {
-    println!("\n--- Running Test: Conversation Mode ({}) ---\n", MODEL_NAME);
+    println!("\n--- Running Test: Conversation Mode ({}) ---\n", MODEL_NAME.as_str());
 
     // 1. Create a new chat session
     println!("Phase 1: Starting new chat session...");
     
     let mut chat = Chat::new(
-        MODEL_NAME,
+        MODEL_NAME.as_str(),
         Some("You are a helpful assistant who remembers details from the conversation.".to_string()),
         None,
         None,
@@ -443,6 +492,52 @@ async fn test_resume_conversation() {
     println!("\nTest complete. Context was successfully maintained.");
 }
 
+// --- Test: Clear Conversation ---
+// Goal: Verify that clearing a chat wipes history but keeps the session usable.
+#[tokio::test]
+#[ignore]
+async fn test_clear_conversation() {
+    let mut chat = Chat::new(
+        MODEL_NAME.as_str(),
+        Some("You are a helpful assistant.".to_string()),
+        None,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+
+    chat.send("My name is Ace.").await.unwrap();
+    let old_id = chat.conversation.id;
+    let (used, window) = chat.context_usage();
+    assert!(used > 0);
+    assert!(window.is_some_and(|w| w > used));
+
+    chat.clear(true);
+    assert_ne!(chat.conversation.id, old_id, "Clearing should start a new conversation id.");
+    assert_eq!(chat.conversation.messages.len(), 1, "Only the system prompt should remain.");
+    assert_eq!(chat.conversation.usage.total_tokens, 0);
+
+    chat.send("Do you remember my name?").await.unwrap();
+    let sent: Vec<(&str, Option<&str>)> = chat
+        .conversation
+        .messages
+        .iter()
+        .take(2)
+        .map(|m| (m.role.as_str(), m.content.as_deref()))
+        .collect();
+    assert_eq!(
+        sent,
+        vec![("system", Some("You are a helpful assistant.")), ("user", Some("Do you remember my name?"))],
+        "Only the new prompt should follow the system prompt after clearing."
+    );
+    assert_eq!(chat.conversation.messages.len(), 3);
+
+    chat.clear(false);
+    assert!(chat.conversation.messages.is_empty());
+    assert_eq!(chat.context_usage().0, 0);
+}
+
 
 
 
@@ -456,7 +551,7 @@ async fn test_resume_conversation() {
 async fn test_chat_image_gen() {
     // 1. Set up a chat session with a capable model (GPT 4o Mini) and provide it with our Rust tool library.
     let mut chat = Chat::new(
-        MODEL_NAME,
+        MODEL_NAME.as_str(),
         Some("You are a helpful assistant with access to tools. Your goal is to use the tools to help the user, and then report the results of the tool use.".to_string()),
         Some(get_rust_tool_library()),
         None,
@@ -513,17 +608,16 @@ async fn test_raww_image_gen() {
 
     // This logic is now self-contained, mirroring the working tool implementation.
     let (_provider_name, provider_data, model_details) =
-        MODEL_LIBRARY.find_model(model_name)
-        .unwrap_or_else(|| panic!("Model '{}' not found", model_name));
+        MODEL_LIBRARY.find_image_model(model_name)
+        .unwrap_or_else(|e| panic!("{}", e));
 
     let api_key = get_env_var(&provider_data.api_key).unwrap();
     let base_url = get_env_var(&provider_data.base_url).unwrap();
 
     let url = format!(
-        "{}/{}:generateContent?key={}",
+        "{}/{}:generateContent",
         base_url.trim_end_matches('/'),
-        &model_details.model_tag,
-        api_key
+        &model_details.model_tag
     );
 
     let payload = json!({
@@ -534,6 +628,7 @@ async fn test_raww_image_gen() {
     let client = Client::new();
     let res = client.post(&url)
         .header("Content-Type", "application/json")
+        .header("x-goog-api-key", api_key)
         .json(&payload)
         .send()
         .await
@@ -570,7 +665,7 @@ async fn test_raww_image_gen() {
 #[ignore]
 async fn test_sorter_in_chat() {
     let mut chat = Chat::new(
-        MODEL_NAME,
+        MODEL_NAME.as_str(),
         Some("You are a helpful assistant with access to tools. Your goal is to use the tools to help the user, and then report the results of the tool use.".to_string()),
         Some(get_rust_tool_library()),
         None,
@@ -622,6 +717,45 @@ async fn test_sorter_in_chat() {
     assert!(sorted_json.get("fruit").is_some() || sorted_json.get("fruits").is_some(), "Category 'fruit(s)' should exist.");
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_sorting_job_handle() {
+    let orchestra = Orchestra::new(MODEL_NAME.as_str(), None, None, None, None, None).unwrap();
+    let instructions = SortingInstructions {
+        data_item_name: "Word".to_string(),
+        data_profile_description: "Common nouns.".to_string(),
+        item_sorting_guidelines: vec!["Sort by what kind of thing it is.".to_string()],
+        provided_categories: vec!["fruit".to_string(), "animal".to_string()],
+        category_definitions: vec![],
+        strict_categories: false,
+        capture_reasoning: false,
+    };
+    let items = vec!["apple".to_string(), "cat".to_string(), "banana".to_string()];
+    let dir = tempdir().unwrap();
+
+    let handle = orchestra.spawn_sorting_job(
+        instructions,
+        None,
+        Some(items),
+        Some(dir.path().to_path_buf()),
+        2,
+    );
+    let job_id = handle.id();
+    assert!(jobs::job_info(job_id).is_some());
+
+    while !handle.is_finished() {
+        let (done, total) = handle.progress();
+        println!("Job {}: {:?} ({}/{})", job_id, handle.status(), done, total);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+
+    let (sorted, _, _, item_count) = handle.wait().await.unwrap();
+    assert_eq!(item_count, 3);
+    assert_eq!(jobs::job_info(job_id).unwrap().status, jobs::JobStatus::Completed);
+    assert_eq!(jobs::take_job_result(job_id).unwrap().unwrap().0, sorted);
+    assert!(jobs::job_info(job_id).is_none(), "Taking the result should remove the job.");
+}
+
 
 
 
@@ -653,6 +787,20 @@ async fn test_embeddings() {
     assert_eq!(embeddings[0].len(), 1536);
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_gemini_embeddings() {
+    let embedder = Embedder::new("TEXT-EMB 004", Some(true)).unwrap();
+    let embeddings = embedder
+        .get_embeddings(vec!["Hello, world!".to_string(), "Goodbye, world!".to_string()])
+        .await
+        .unwrap();
+
+    // One vector per input, each with text-embedding-004's 768 dimensions.
+    assert_eq!(embeddings.len(), 2);
+    assert_eq!(embeddings[0].len(), embedder.dimensions);
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_create_database() {
@@ -663,6 +811,34 @@ async fn test_create_database() {
     assert!(db_path.exists());
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_storage_shared_connection() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("shared.db");
+    let storage = Storage::new(&db_path).unwrap();
+
+    // Many inserts and lookups reuse the same connection and cached statements.
+    let metadata = json!({ "source": "test" });
+    let ids: Vec<i64> = (1..=20)
+        .map(|i| storage.insert_chunk("doc", i, "Title", "Summary", "Content", &metadata).unwrap())
+        .collect();
+    assert_eq!(storage.get_chunks_by_ids(&ids).unwrap().len(), 20);
+    assert_eq!(storage.get_chunk_by_id(ids[0]).unwrap().unwrap().chunk_number, 1);
+    assert_eq!(storage.chunk_ids_for_url("doc").unwrap(), ids);
+    assert_eq!(storage.chunk_and_source_counts().unwrap(), (20, 1));
+    assert!(storage.size_on_disk().unwrap() > 0);
+    assert!(storage.chunk_ids_for_url("missing").unwrap().is_empty());
+
+    // A second Storage on the same file can read while the first is still open (WAL).
+    let reader = Storage::new(&db_path).unwrap();
+    assert_eq!(reader.list_sources().unwrap(), vec!["doc".to_string()]);
+    drop(reader);
+
+    storage.delete_database().unwrap();
+    assert!(!db_path.exists());
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_knowledge_base_end_to_end() {
@@ -678,6 +854,8 @@ async fn test_knowledge_base_end_to_end() {
     // 2. Initialize the KnowledgeBase.
     let knowledge_base = KnowledgeBase::new(&db_path, &index_path, "TEXT-EMB 3 SMALL")
         .expect("Failed to create KnowledgeBase");
+    assert_eq!(knowledge_base.embedding_dimensions(), None, "A fresh index has no metadata.");
+    assert!(knowledge_base.is_empty());
 
     // 3. Add documents in a batch.
     let metadata = serde_json::json!({});
@@ -712,11 +890,13 @@ async fn test_knowledge_base_end_to_end() {
         .add_documents_and_build(documents)
         .await
         .expect("Failed to add documents and build index");
+    assert_eq!(knowledge_base.embedding_dimensions(), Some(1536));
+    assert_eq!(knowledge_base.len(), 3);
 
     // 4. Perform a search.
     let search_query = "What is a hammer?";
     let results = knowledge_base
-        .search(search_query, 1)
+        .search(search_query, 1, SearchMode::Both)
         .await
         .expect("Search failed");
 
@@ -730,6 +910,19 @@ async fn test_knowledge_base_end_to_end() {
         "Search for '{}' returned: '{}'",
         search_query, retrieved_doc.content
     );
+
+    // 6. Remove the matched chunk; it must disappear from both the DB and the index.
+    let removed_id = retrieved_doc.id;
+    assert!(knowledge_base.remove_chunk(removed_id).unwrap());
+    let results = knowledge_base.search(search_query, 1, SearchMode::Both).await.unwrap();
+    assert_eq!(results.len(), 1, "Search should still fill the limit after removal.");
+    assert_ne!(results[0].id, removed_id);
+    assert_eq!(knowledge_base.len(), 2);
+
+    let stats = knowledge_base.stats().unwrap();
+    assert_eq!((stats.chunk_count, stats.source_count, stats.vector_count), (2, 2, 2));
+    assert!(stats.consistency_ok);
+    assert!(stats.index_size_bytes > 0 && stats.db_size_bytes > 0);
 }
 
 #[tokio::test]
@@ -761,7 +954,7 @@ async fn test_knowledge_base_in_chat() {
 
     // --- Phase 2: Interact with the Chat Agent ---
     let mut chat = Chat::new(
-        MODEL_NAME,
+        MODEL_NAME.as_str(),
         Some("You are a helpful assistant. Use the available tools to answer questions.".to_string()),
         Some(get_rust_tool_library()),
         None, None, Some(true),
@@ -805,14 +998,16 @@ async fn test_ingestor_from_file() {
             &db_path,
             &index_path,
             "TEXT-EMB 3 SMALL",
-            MODEL_NAME,
+            MODEL_NAME.as_str(),
+            None,
+            None,
         ).unwrap();
         ingestor.ingest_from_file(&file_path, "local_file").await.unwrap();
     } // Ingestor is dropped here, releasing the database lock.
 
     // 4. Verify that the document was added correctly.
     let kb = KnowledgeBase::new(&db_path, &index_path, "TEXT-EMB 3 SMALL").unwrap();
-    let results = kb.search("Rust performance", 1).await.unwrap();
+    let results = kb.search("Rust performance", 1, SearchMode::Both).await.unwrap();
 
     assert_eq!(results.len(), 1);
     assert!(results[0].content.contains("Rust performance"));
@@ -820,6 +1015,73 @@ async fn test_ingestor_from_file() {
     println!("Successfully ingested from file and found relevant content via search.");
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_ingestor_reingest_conflicts() {
+    pyo3::prepare_freethreaded_python();
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("conflict.md");
+    std::fs::write(&file_path, "# Conflicts\n\nRe-ingesting a file must not duplicate it.").unwrap();
+    let db_path = dir.path().join("test_ingest_conflict.db");
+    let index_path = dir.path().join("test_ingest_conflict_index");
+
+    let mut ingestor = Ingestor::new(&db_path, &index_path, "TEXT-EMB 3 SMALL", MODEL_NAME.as_str(), None, None).unwrap();
+    let first = ingestor.ingest_from_file(&file_path, "local_file").await.unwrap();
+    assert!(first.chunk_count > 0);
+
+    // The default refuses before fetching anything.
+    assert!(ingestor.ingest_from_file(&file_path, "local_file").await.is_err());
+
+    ingestor.set_on_conflict(OnConflict::Skip);
+    let skipped = ingestor.ingest_from_file(&file_path, "local_file").await.unwrap();
+    assert!(skipped.skipped);
+    assert_eq!(skipped.chunk_count, 0);
+
+    ingestor.set_on_conflict(OnConflict::Replace);
+    let replaced = ingestor.ingest_from_file(&file_path, "local_file").await.unwrap();
+    assert_eq!(replaced.replaced_chunk_count, first.chunk_count);
+    drop(ingestor);
+
+    let kb = KnowledgeBase::new(&db_path, &index_path, "TEXT-EMB 3 SMALL").unwrap();
+    assert_eq!(kb.len(), first.chunk_count);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_ingestor_custom_system_prompt() {
+    pyo3::prepare_freethreaded_python();
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_es.md");
+    let mut temp_file = std::fs::File::create(&file_path).unwrap();
+    writeln!(temp_file, "# Test Document\n\nThis is a test about Rust performance and memory safety.").unwrap();
+
+    let db_path = dir.path().join("test_ingest_es.db");
+    let index_path = dir.path().join("test_ingest_es_index");
+
+    {
+        let ingestor = Ingestor::new(
+            &db_path,
+            &index_path,
+            "TEXT-EMB 3 SMALL",
+            MODEL_NAME.as_str(),
+            Some("You are a technical writer. Always write the title and summary in Spanish.".to_string()),
+            None,
+        ).unwrap();
+        ingestor.ingest_from_file(&file_path, "local_file").await.unwrap();
+    }
+
+    let kb = KnowledgeBase::new(&db_path, &index_path, "TEXT-EMB 3 SMALL").unwrap();
+    let results = kb.search("Rust performance", 1, SearchMode::Both).await.unwrap();
+    assert_eq!(results.len(), 1);
+
+    println!("Title: {}\nSummary: {}", results[0].title, results[0].summary);
+    let summary_lower = results[0].summary.to_lowercase();
+    assert!(
+        [" el ", " la ", " de ", " y "].iter().any(|w| summary_lower.contains(w)),
+        "Summary should be written in Spanish."
+    );
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_ingestor_from_url() {
@@ -841,14 +1103,16 @@ async fn test_ingestor_from_url() {
             &db_path,
             &index_path,
             "TEXT-EMB 3 SMALL",
-            MODEL_NAME,
+            MODEL_NAME.as_str(),
+            None,
+            None,
         ).unwrap();
         ingestor.ingest_from_url(test_url, "wikipedia_rust").await.unwrap();
     } // Ingestor is dropped here, releasing the database lock.
 
     // 2. Verify that the document was added correctly.
     let kb = KnowledgeBase::new(&db_path, &index_path, "TEXT-EMB 3 SMALL").unwrap();
-    let results = kb.search("systems programming", 5).await.unwrap();
+    let results = kb.search("systems programming", 5, SearchMode::Both).await.unwrap();
 
     assert!(!results.is_empty(), "Search should return at least one result.");
     
@@ -864,3 +1128,57 @@ async fn test_ingestor_from_url() {
     println!("Successfully ingested from URL and found relevant content via search.");
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_sorter_reasoning_audit() {
+    let orchestra = Orchestra::new(MODEL_NAME.as_str(), None, None, None, None, None).unwrap();
+    let instructions = SortingInstructions {
+        data_item_name: "Word".to_string(),
+        data_profile_description: "Common nouns.".to_string(),
+        item_sorting_guidelines: vec!["Sort by what kind of thing it is.".to_string()],
+        provided_categories: vec!["fruit".to_string(), "animal".to_string()],
+        category_definitions: vec![],
+        strict_categories: false,
+        capture_reasoning: true,
+    };
+    let items = vec!["apple".to_string(), "cat".to_string()];
+    let dir = tempdir().unwrap();
+    let output_file = dir.path().join("sorted.json");
+
+    let mut sorter = Sorter::new(Arc::new(orchestra), instructions, Some(output_file.clone()), None, false).unwrap();
+    let (sorted, _, _) = sorter.sort_items(&items, 2).await.unwrap();
+    assert!(!sorted.is_empty());
+
+    let audit_log = sorter.audit_log();
+    assert_eq!(audit_log.len(), items.len());
+    for entry in audit_log {
+        println!("{} -> {}: {:?}", entry.item, entry.category, entry.reasoning);
+        assert!(entry.reasoning.as_deref().is_some_and(|r| !r.is_empty()));
+    }
+
+    let audit_file = dir.path().join("sorted.audit.json");
+    let saved: Vec<SortAuditEntry> = serde_json::from_str(&std::fs::read_to_string(audit_file).unwrap()).unwrap();
+    assert_eq!(saved.len(), items.len());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_sorter_batch() {
+    let orchestra = Orchestra::new(MODEL_NAME.as_str(), None, None, None, None, None).unwrap();
+    let instructions = SortingInstructions {
+        data_item_name: "Word".to_string(),
+        data_profile_description: "Common nouns.".to_string(),
+        item_sorting_guidelines: vec!["Sort by what kind of thing it is.".to_string()],
+        provided_categories: vec!["fruit".to_string(), "animal".to_string()],
+        category_definitions: vec![],
+        strict_categories: false,
+        capture_reasoning: false,
+    };
+    let items = vec!["apple".to_string(), "cat".to_string()];
+    let dir = tempdir().unwrap();
+
+    let mut sorter = Sorter::new(Arc::new(orchestra), instructions, Some(dir.path().join("sorted.json")), None, false).unwrap();
+    let (sorted, _, usage) = sorter.sort_items_batch(&items, std::time::Duration::from_secs(30)).await.unwrap();
+    println!("Batch sort results: {:?} (cost: {:?})", sorted, usage.cost);
+    assert_eq!(sorted.values().map(Vec::len).sum::<usize>(), items.len());
+}